@@ -5,6 +5,8 @@
 
 extern crate cortex_m;
 extern crate cortex_m_rt as rt;
+#[macro_use]
+extern crate nb;
 extern crate panic_halt;
 extern crate stm32g0xx_hal as hal;
 
@@ -30,8 +32,8 @@ fn main() -> ! {
     vtemp.enable(&mut adc);
 
     loop {
-        let u_raw: u32 = adc.read(&mut adc_pin).expect("adc read failed");
-        let temp: u32 = adc.read(&mut vtemp).expect("temperature read failed");
+        let u_raw: u32 = block!(adc.read(&mut adc_pin)).expect("adc read failed");
+        let temp: u32 = block!(adc.read(&mut vtemp)).expect("temperature read failed");
 
         let u = u_raw.saturating_sub(32) as f32 / 4_096_f32 * 3.3;
         let temp = temp / 42;
@@ -0,0 +1,45 @@
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use cortex_m_semihosting::hprintln;
+use hal::analog::adc::{SampleTime, Sequence, Trigger};
+use hal::prelude::*;
+use hal::stm32;
+use hal::timer::sync::MasterMode;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+    let mut rcc = dp.RCC.constrain();
+
+    let gpioa = dp.GPIOA.split(&mut rcc);
+    let dma = dp.DMA.split(&mut rcc);
+
+    let mut adc = dp.ADC.constrain(&mut rcc);
+    adc.set_sample_time(SampleTime::T_40);
+
+    let current = gpioa.pa0.into_analog();
+    let voltage = gpioa.pa1.into_analog();
+    let sequence = Sequence::new().channel(&current).channel(&voltage);
+
+    // sample both channels every millisecond
+    let mut timer = dp.TIM3.timer(&mut rcc);
+    timer.set_master_mode(MasterMode::Update);
+    timer.start(1.ms());
+
+    let buffer = cortex_m::singleton!(: [u16; 32] = [0; 32]).unwrap();
+    let adc = adc.start_triggered(&sequence, Trigger::Tim3Trgo, dma.1, buffer);
+
+    let mut samples = [0; 2];
+    loop {
+        adc.read_samples(&mut samples);
+        hprintln!("current: {} voltage: {}", samples[0], samples[1]).unwrap();
+    }
+}
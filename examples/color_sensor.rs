@@ -95,7 +95,8 @@ const APP: () = {
             .device
             .USART2
             .usart(tx, rx, serial::Config::default(), &mut rcc)
-            .unwrap();
+            .unwrap()
+            .erase_pins();
 
         init::LateResources {
             uart,
@@ -41,5 +41,16 @@ fn main() -> ! {
             hash_sum ^ 0xffffffff
         )
         .unwrap();
+
+        crc.configure(&Config::crc16_modbus());
+        let modbus = crc.checksum(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        hprintln!("modbus: 0x{:04x}", modbus).unwrap();
+
+        crc.configure(&Config::crc32());
+        crc.update(b"123456789");
+        hprintln!("crc32: 0x{:x}", crc.finalize()).unwrap();
+
+        crc.reverse_input(InputReverse::Word);
+        crc.reverse_output(true);
     }
 }
@@ -0,0 +1,74 @@
+//! HIL test firmware for NUCLEO-G071RB
+//!
+//! Wiring: PA9-PA10 (USART1 loopback), PB4-PB5 (SPI1 loopback),
+//! 24C02 EEPROM at 0x50 on PB6/PB7. Results are reported on USART2 (VCP).
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use hal::factory_test::FactoryTest;
+use hal::hil;
+use hal::i2c::Config as I2cConfig;
+use hal::prelude::*;
+use hal::serial::Config;
+use hal::spi;
+use hal::stm32;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+    let mut cp = cortex_m::Peripherals::take().expect("cannot take core peripherals");
+    let mut rcc = dp.RCC.constrain();
+    let gpioa = dp.GPIOA.split(&mut rcc);
+    let gpiob = dp.GPIOB.split(&mut rcc);
+
+    let report = dp
+        .USART2
+        .usart(gpioa.pa2, gpioa.pa3, Config::default(), &mut rcc)
+        .unwrap();
+    let mut runner = FactoryTest::new(report);
+
+    let mut loopback = dp
+        .USART1
+        .usart(gpioa.pa9, gpioa.pa10, Config::default(), &mut rcc)
+        .unwrap();
+    let result = hil::uart_loopback(&mut loopback);
+    hil::report(&mut runner, "uart_loopback", result);
+
+    let mut spi = dp.SPI1.spi(
+        (gpiob.pb3, gpiob.pb4, gpiob.pb5),
+        spi::MODE_0,
+        1.mhz(),
+        &mut rcc,
+    );
+    let result = hil::spi_loopback(&mut spi);
+    hil::report(&mut runner, "spi_loopback", result);
+
+    let sda = gpiob.pb7.into_open_drain_output();
+    let scl = gpiob.pb6.into_open_drain_output();
+    let mut i2c = dp
+        .I2C1
+        .i2c(sda, scl, I2cConfig::with_timing(0x2020151b), &mut rcc);
+    let result = hil::i2c_eeprom(&mut i2c, 0x50, 0x10, b"stm32g0xx-hal");
+    hil::report(&mut runner, "i2c_eeprom", result);
+
+    let mut timer = dp.TIM2.timer(&mut rcc);
+    let result = hil::timer_accuracy(
+        &mut timer,
+        &mut cp.SYST,
+        rcc.clocks.core_clk,
+        100.ms(),
+        1_000,
+    );
+    hil::report(&mut runner, "timer_accuracy", result);
+
+    runner.finish();
+    loop {}
+}
@@ -0,0 +1,39 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use hal::prelude::*;
+use hal::stm32;
+use hal::timer::irtim::IrTx;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+    let cp = cortex_m::Peripherals::take().expect("cannot take core peripherals");
+    let mut rcc = dp.RCC.constrain();
+    let mut delay = cp.SYST.delay(&mut rcc);
+
+    let gpiob = dp.GPIOB.split(&mut rcc);
+    let mut syscfg = dp.SYSCFG_VREFBUF.constrain(&mut rcc);
+    let mut ir = IrTx::new(
+        dp.TIM16,
+        dp.TIM17,
+        gpiob.pb9,
+        38.khz(),
+        &mut syscfg,
+        &mut rcc,
+    );
+
+    loop {
+        // Power button of a common NEC remote
+        ir.send_nec(0x00, 0x45);
+        delay.delay(1000.ms());
+    }
+}
@@ -0,0 +1,61 @@
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate nb;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use cortex_m::interrupt;
+use cortex_m::peripheral::NVIC;
+use hal::exti::Event as ExtiEvent;
+use hal::gpio::gpioa::{PA2, PA3};
+use hal::gpio::DefaultMode;
+use hal::power::StopMode;
+use hal::prelude::*;
+use hal::rcc::{KernelClock, LseDrive};
+use hal::serial::{Config, Event, Serial};
+use hal::stm32;
+use nb::block;
+use rt::entry;
+
+type Lpuart = Serial<stm32::LPUART, (PA2<DefaultMode>, PA3<DefaultMode>)>;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+    let mut cp = cortex_m::Peripherals::take().expect("cannot take core peripherals");
+    let mut rcc = dp.RCC.constrain();
+    rcc.start_lse(LseDrive::MediumHigh, false);
+
+    let gpioa = dp.GPIOA.split(&mut rcc);
+    let config = Config::default()
+        .baudrate(9600.bps())
+        .kernel_clock(KernelClock::Lse);
+    let mut lpuart: Lpuart = dp
+        .LPUART
+        .usart(gpioa.pa2, gpioa.pa3, config, &mut rcc)
+        .unwrap();
+    lpuart.listen(Event::Rxne);
+    lpuart.set_stop_mode_wakeup(true);
+
+    let exti = dp.EXTI;
+    exti.wakeup(ExtiEvent::LPUART1);
+    let mut power = dp.PWR.constrain(&mut rcc);
+
+    // The interrupt only wakes the core, it is never taken
+    unsafe { NVIC::unmask(Lpuart::INTERRUPT) };
+    loop {
+        let byte = interrupt::free(|_| {
+            power.stop(&mut cp.SCB, StopMode::Stop1);
+            let byte = block!(lpuart.read());
+            NVIC::unpend(Lpuart::INTERRUPT);
+            byte
+        });
+        if let Ok(byte) = byte {
+            block!(lpuart.write(byte)).ok();
+        }
+    }
+}
@@ -0,0 +1,35 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use hal::prelude::*;
+use hal::rcc::ResetMode;
+use hal::stm32;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+    let mut rcc = dp.RCC.constrain();
+
+    // Resets once to apply the option bytes on the first start-up
+    rcc.set_reset_mode(ResetMode::GPIO);
+
+    let gpiof = dp.GPIOF.split(&mut rcc);
+    let mut led = gpiof.pf2.into_push_pull_output();
+
+    loop {
+        for _ in 0..1_000_000 {
+            led.set_low().unwrap();
+        }
+        for _ in 0..1_000_000 {
+            led.set_high().unwrap();
+        }
+    }
+}
@@ -0,0 +1,38 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use hal::prelude::*;
+use hal::stm32;
+use hal::timer::pwm::{Alignment, BreakConfig, BreakPolarity};
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+
+    let mut rcc = dp.RCC.constrain();
+    let gpioa = dp.GPIOA.split(&mut rcc);
+    let gpiob = dp.GPIOB.split(&mut rcc);
+
+    let mut pwm = dp.TIM1.pwm(20.khz(), &mut rcc);
+    pwm.set_alignment(Alignment::Center1);
+    pwm.set_dead_time(500);
+    pwm.enable_break(
+        gpiob.pb12,
+        BreakConfig::new(BreakPolarity::ActiveLow).filter(4),
+    );
+
+    let mut ch1 = pwm.bind_pin(gpioa.pa8).with_complementary(gpioa.pa7);
+    let max = ch1.get_max_duty();
+    ch1.set_duty(max / 2);
+    ch1.enable();
+
+    loop {}
+}
@@ -0,0 +1,36 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use cortex_m_semihosting::hprintln;
+use hal::prelude::*;
+use hal::stm32;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+
+    let mut rcc = dp.RCC.constrain();
+    let gpioa = dp.GPIOA.split(&mut rcc);
+
+    let pwm_input = dp.TIM3.pwm_input(gpioa.pa6, 1.mhz(), &mut rcc);
+
+    loop {
+        if let (Some(freq), Some(duty)) = (pwm_input.frequency(), pwm_input.duty_cycle()) {
+            hprintln!(
+                "freq: {} Hz | pulse: {} us | duty: {}‰",
+                freq.0,
+                pwm_input.pulse_width().0,
+                duty
+            )
+            .unwrap();
+        }
+    }
+}
@@ -0,0 +1,57 @@
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use core::pin::Pin;
+use hal::prelude::*;
+use hal::stm32;
+use rt::{entry, exception, ExceptionFrame};
+
+const LEDS: usize = 3;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+
+    let mut rcc = dp.RCC.constrain();
+    let gpioa = dp.GPIOA.split(&mut rcc);
+    let dma = dp.DMA.split(&mut rcc);
+
+    // WS2812 bit period is 1.25us
+    let pwm = dp.TIM1.pwm(800.khz(), &mut rcc);
+    let mut led_data = pwm.bind_pin(gpioa.pa8);
+    let max = led_data.get_max_duty() as u16;
+    led_data.set_duty(0);
+    led_data.enable();
+
+    // 24 bits per LED, trailing zero sample keeps the line low for reset
+    let buffer = cortex_m::singleton!(: [u16; LEDS * 24 + 1] = [0; LEDS * 24 + 1]).unwrap();
+    let colors: [u32; LEDS] = [0x00ff00, 0xff0000, 0x0000ff];
+    for (led, grb) in colors.iter().enumerate() {
+        for bit in 0..24 {
+            let one = grb & (1 << (23 - bit)) != 0;
+            buffer[led * 24 + bit] = if one { max * 9 / 16 } else { max * 9 / 32 };
+        }
+    }
+
+    let buffer: &'static [u16] = buffer;
+    let transfer = led_data.dma_waveform(dma.1, Pin::new(buffer));
+    while !transfer.channel.is_done() {}
+
+    loop {}
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("Hard fault {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
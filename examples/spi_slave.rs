@@ -0,0 +1,47 @@
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use hal::exti::Event;
+use hal::gpio::SignalEdge;
+use hal::prelude::*;
+use hal::spi;
+use hal::stm32;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+
+    let mut rcc = dp.RCC.constrain();
+    let mut exti = dp.EXTI;
+    let gpioa = dp.GPIOA.split(&mut rcc);
+    let dma = dp.DMA.split(&mut rcc);
+
+    // frame ends on NSS rising edge
+    let nss = gpioa.pa4.listen(SignalEdge::Rising, &mut exti);
+    let pins = (gpioa.pa5, gpioa.pa6, gpioa.pa7, nss);
+    let slave = dp.SPI1.spi_slave(pins, spi::MODE_0, &mut rcc);
+
+    let rx0 = cortex_m::singleton!(: [u8; 64] = [0; 64]).unwrap();
+    let rx1 = cortex_m::singleton!(: [u8; 64] = [0; 64]).unwrap();
+    let tx0 = cortex_m::singleton!(: [u8; 64] = [0; 64]).unwrap();
+    let tx1 = cortex_m::singleton!(: [u8; 64] = [0; 64]).unwrap();
+    let mut slave = slave.frames(dma.1, dma.2, [rx0, rx1], [tx0, tx1]);
+
+    loop {
+        if exti.is_pending(Event::GPIO4, SignalEdge::Rising) {
+            exti.unpend(Event::GPIO4);
+            // length and checksum are clocked out after the next frame
+            let frame = slave.end_of_frame();
+            let sum = frame.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            let len = frame.len() as u8;
+            slave.set_reply(&[len, sum]);
+        }
+    }
+}
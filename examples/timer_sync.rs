@@ -0,0 +1,48 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use hal::prelude::*;
+use hal::stm32;
+use hal::timer::sync::{MasterMode, SlaveMode};
+use rt::{entry, exception, ExceptionFrame};
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+
+    let mut rcc = dp.RCC.constrain();
+    let gpioa = dp.GPIOA.split(&mut rcc);
+    let gpiob = dp.GPIOB.split(&mut rcc);
+
+    // TIM2 channel 1 gates TIM3: 10kHz bursts every 10ms
+    let mut gate = dp.TIM2.pwm(100.hz(), &mut rcc);
+    let mut burst = dp.TIM3.pwm(10.khz(), &mut rcc);
+    gate.set_master_mode(MasterMode::Compare1);
+    burst.set_slave_mode::<stm32::TIM2>(SlaveMode::Gated);
+
+    let mut gate_ch = gate.bind_pin(gpioa.pa0);
+    let mut burst_ch = burst.bind_pin(gpiob.pb4);
+    gate_ch.set_duty(gate_ch.get_max_duty() / 4);
+    burst_ch.set_duty(burst_ch.get_max_duty() / 2);
+    gate_ch.enable();
+    burst_ch.enable();
+
+    loop {}
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("Hard fault {:#?}", ef);
+}
+
+#[exception]
+fn DefaultHandler(irqn: i16) {
+    panic!("Unhandled exception (IRQn = {})", irqn);
+}
@@ -0,0 +1,43 @@
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32g0xx_hal as hal;
+
+use cortex_m_semihosting::hprintln;
+use hal::prelude::*;
+use hal::stm32;
+use hal::ucpd::{SinkEvent, SinkPolicy};
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+    let mut rcc = dp.RCC.constrain();
+
+    let mut port = dp.UCPD1.constrain(&mut rcc);
+    port.sink();
+
+    // wait for a source on either CC line
+    let cc = loop {
+        if let Some(cc) = port.attached() {
+            break cc;
+        }
+    };
+    port.enable_phy(cc);
+
+    // ask for 9V at up to 2A
+    let mut policy = SinkPolicy::new(9000, 2000);
+    loop {
+        match policy.poll(&mut port) {
+            Some(SinkEvent::Contract(contract)) => {
+                hprintln!("{}mV {}mA", contract.millivolts, contract.milliamps).unwrap();
+            }
+            Some(SinkEvent::Rejected) => hprintln!("rejected").unwrap(),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,738 @@
+//! # AES hardware accelerator
+//!
+//! Encrypts and decrypts with 128 or 256 bit keys in ECB, CBC, CTR, GCM and
+//! CCM modes. Data is processed in place, byte order as in the standards.
+//!
+//! For DMA, `start_dma()` configures a mode, then `Aes` is the sink of a
+//! `MemToPeriph<u32>` transfer and the source of a `PeriphToMem<u32>`
+//! transfer of the same length in whole blocks. The output channel must be
+//! started first.
+//!
+//! With the `aead` feature `AesGcm` implements `aead::AeadInPlace`. With the
+//! `cipher` feature `AesEcb` is a `cipher::BlockEncrypt`/`BlockDecrypt` block
+//! cipher, `AesCbc` and `AesCtr` implement `BlockEncryptMut`/`BlockDecryptMut`
+//! and chain across calls.
+use crate::dma::descriptor::{DmaSink, DmaSource};
+use crate::dma::DmaMuxIndex;
+use crate::rcc::Rcc;
+use crate::stm32::AES;
+
+/// Block size in bytes
+pub const BLOCK_SIZE: usize = 16;
+
+// CR
+const EN: u32 = 1 << 0;
+const DATATYPE_BYTE: u32 = 0b10 << 1;
+const MODE_SHIFT: u32 = 3;
+const CCFC: u32 = 1 << 7;
+const ERRC: u32 = 1 << 8;
+const DMAINEN: u32 = 1 << 11;
+const DMAOUTEN: u32 = 1 << 12;
+const GCMPH_SHIFT: u32 = 13;
+const KEYSIZE: u32 = 1 << 18;
+const NPBLB_SHIFT: u32 = 20;
+
+// SR
+const CCF: u32 = 1 << 0;
+
+// Register offsets in words
+const KEYR0: usize = 0x10 / 4;
+const IVR0: usize = 0x20 / 4;
+const KEYR4: usize = 0x30 / 4;
+
+/// AES errors
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Data is not a whole number of blocks
+    Alignment,
+    /// Unsupported nonce or tag length
+    InvalidLength,
+    /// Authentication tag does not match
+    TagMismatch,
+}
+
+/// Chaining mode for `encrypt()` and `decrypt()`
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    Ecb,
+    /// Cipher block chaining with initialization vector
+    Cbc([u8; BLOCK_SIZE]),
+    /// Counter mode with initial counter block
+    Ctr([u8; BLOCK_SIZE]),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    Encrypt,
+    Decrypt,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Chaining {
+    Ecb = 0b000,
+    Cbc = 0b001,
+    Ctr = 0b010,
+    Gcm = 0b011,
+    Ccm = 0b100,
+}
+
+#[derive(Copy, Clone)]
+enum Phase {
+    Init = 0b00,
+    Header = 0b01,
+    Payload = 0b10,
+    Final = 0b11,
+}
+
+/// AES accelerator
+pub struct Aes {
+    rb: AES,
+    key: [u32; 8],
+    key_words: usize,
+}
+
+impl Aes {
+    /// Sets a 16 or 32 byte key
+    pub fn set_key(&mut self, key: &[u8]) {
+        assert!(key.len() == 16 || key.len() == 32);
+        for (word, bytes) in self.key.iter_mut().zip(key.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        self.key_words = key.len() / 4;
+    }
+
+    /// Encrypts `data` in place
+    ///
+    /// ECB and CBC need whole blocks, CTR takes any length.
+    pub fn encrypt(&mut self, mode: Mode, data: &mut [u8]) -> Result<(), Error> {
+        self.process(mode, Direction::Encrypt, data)
+    }
+
+    /// Decrypts `data` in place
+    pub fn decrypt(&mut self, mode: Mode, data: &mut [u8]) -> Result<(), Error> {
+        self.process(mode, Direction::Decrypt, data)
+    }
+
+    /// Encrypts `data` in place with GCM and returns the tag
+    pub fn gcm_encrypt(&mut self, iv: &[u8; 12], aad: &[u8], data: &mut [u8]) -> [u8; 16] {
+        self.gcm(Direction::Encrypt, iv, aad, data)
+    }
+
+    /// Decrypts `data` in place with GCM and checks `tag`
+    ///
+    /// `data` holds garbage on a tag mismatch.
+    pub fn gcm_decrypt(
+        &mut self,
+        iv: &[u8; 12],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), Error> {
+        let expected = self.gcm(Direction::Decrypt, iv, aad, data);
+        if tag.is_empty() || tag.len() > BLOCK_SIZE || !equal(&expected[..tag.len()], tag) {
+            return Err(Error::TagMismatch);
+        }
+        Ok(())
+    }
+
+    /// Encrypts `data` in place with CCM and fills `tag`
+    ///
+    /// `nonce` is 7 to 13 bytes, `tag` 4 to 16 bytes of even length.
+    pub fn ccm_encrypt(
+        &mut self,
+        nonce: &[u8],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &mut [u8],
+    ) -> Result<(), Error> {
+        let mac = self.ccm(Direction::Encrypt, nonce, aad, data, tag.len())?;
+        tag.copy_from_slice(&mac[..tag.len()]);
+        Ok(())
+    }
+
+    /// Decrypts `data` in place with CCM and checks `tag`
+    pub fn ccm_decrypt(
+        &mut self,
+        nonce: &[u8],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), Error> {
+        let mac = self.ccm(Direction::Decrypt, nonce, aad, data, tag.len())?;
+        if !equal(&mac[..tag.len()], tag) {
+            return Err(Error::TagMismatch);
+        }
+        Ok(())
+    }
+
+    /// Configures `mode` and enables DMA requests
+    ///
+    /// Every word written to the input is a big endian word of the block in
+    /// memory order, so byte buffers can be transferred as `u32`.
+    pub fn start_dma(&mut self, mode: Mode, dir: Direction) {
+        self.setup(mode, dir);
+        self.rb
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | DMAINEN | DMAOUTEN | EN) });
+    }
+
+    /// Disables DMA requests once the output transfer completed
+    pub fn stop_dma(&mut self) {
+        self.rb
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(DMAINEN | DMAOUTEN | EN)) });
+    }
+
+    pub fn release(self) -> AES {
+        self.rb
+    }
+
+    /// Calls `f` with a backend processing blocks in `mode`, returns the
+    /// chaining state after the last block
+    #[cfg(feature = "cipher")]
+    fn process_blocks(
+        &mut self,
+        mode: Mode,
+        dir: Direction,
+        f: impl cipher::BlockClosure<BlockSize = cipher::consts::U16>,
+    ) -> Mode {
+        self.setup(mode, dir);
+        self.enable();
+        let mut backend = Backend {
+            aes: self,
+            mode,
+            dir,
+        };
+        f.call(&mut backend);
+        let mode = backend.mode;
+        self.disable();
+        mode
+    }
+
+    fn process(&mut self, mode: Mode, dir: Direction, data: &mut [u8]) -> Result<(), Error> {
+        if !matches!(mode, Mode::Ctr(_)) && data.len() & (BLOCK_SIZE - 1) != 0 {
+            return Err(Error::Alignment);
+        }
+        self.setup(mode, dir);
+        self.enable();
+        self.payload(data, None);
+        self.disable();
+        Ok(())
+    }
+
+    /// Loads key and IV, derives the decryption key for ECB and CBC
+    fn setup(&mut self, mode: Mode, dir: Direction) {
+        let (chaining, iv) = match mode {
+            Mode::Ecb => (Chaining::Ecb, None),
+            Mode::Cbc(iv) => (Chaining::Cbc, Some(iv)),
+            Mode::Ctr(iv) => (Chaining::Ctr, Some(iv)),
+        };
+        let derive = chaining != Chaining::Ctr && dir == Direction::Decrypt;
+        if derive {
+            self.configure(chaining, 0b01, Phase::Init);
+            self.enable();
+            self.wait();
+            self.disable();
+        }
+        let mode = match dir {
+            Direction::Decrypt if chaining != Chaining::Ctr => 0b10,
+            _ => 0b00,
+        };
+        // The derived key stays in the key registers
+        self.configure_cr(chaining, mode, Phase::Init);
+        if !derive {
+            self.load_key();
+        }
+        if let Some(iv) = iv {
+            self.load_iv(&iv);
+        }
+    }
+
+    fn gcm(&mut self, dir: Direction, iv: &[u8; 12], aad: &[u8], data: &mut [u8]) -> [u8; 16] {
+        let mode = if dir == Direction::Decrypt { 0b10 } else { 0b00 };
+        self.configure(Chaining::Gcm, mode, Phase::Init);
+        let mut counter = [0; BLOCK_SIZE];
+        counter[..12].copy_from_slice(iv);
+        counter[15] = 2;
+        self.load_iv(&counter);
+        // Hash key calculation
+        self.enable();
+        self.wait();
+
+        if !aad.is_empty() {
+            self.set_phase(Phase::Header);
+            self.header(&[], aad);
+        }
+        if !data.is_empty() {
+            self.set_phase(Phase::Payload);
+            let npblb = dir == Direction::Encrypt;
+            self.payload(data, Some(npblb));
+        }
+
+        self.set_phase(Phase::Final);
+        let mut lengths = [0; BLOCK_SIZE];
+        lengths[..8].copy_from_slice(&(aad.len() as u64 * 8).to_be_bytes());
+        lengths[8..].copy_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+        let tag = self.block(&lengths);
+        self.disable();
+        tag
+    }
+
+    fn ccm(
+        &mut self,
+        dir: Direction,
+        nonce: &[u8],
+        aad: &[u8],
+        data: &mut [u8],
+        tag_len: usize,
+    ) -> Result<[u8; 16], Error> {
+        if !(7..=13).contains(&nonce.len()) || !(4..=16).contains(&tag_len) || tag_len & 1 != 0 {
+            return Err(Error::InvalidLength);
+        }
+        // Length field size
+        let q = 15 - nonce.len();
+        if q < 8 && data.len() as u64 >= 1 << (8 * q) {
+            return Err(Error::InvalidLength);
+        }
+
+        let mut b0 = [0; BLOCK_SIZE];
+        let adata = if aad.is_empty() { 0 } else { 0x40 };
+        b0[0] = adata | ((tag_len as u8 - 2) / 2) << 3 | (q as u8 - 1);
+        b0[1..=nonce.len()].copy_from_slice(nonce);
+        let len = (data.len() as u64).to_be_bytes();
+        b0[16 - q..].copy_from_slice(&len[8 - q..]);
+
+        let mode = if dir == Direction::Decrypt { 0b10 } else { 0b00 };
+        self.configure(Chaining::Ccm, mode, Phase::Init);
+        self.load_iv(&b0);
+        self.enable();
+        self.wait();
+
+        if !aad.is_empty() {
+            self.set_phase(Phase::Header);
+            let len = aad.len();
+            if len < 0xff00 {
+                self.header(&(len as u16).to_be_bytes(), aad);
+            } else {
+                let mut prefix = [0xff, 0xfe, 0, 0, 0, 0];
+                prefix[2..].copy_from_slice(&(len as u32).to_be_bytes());
+                self.header(&prefix, aad);
+            }
+        }
+        if !data.is_empty() {
+            self.set_phase(Phase::Payload);
+            let npblb = dir == Direction::Decrypt;
+            self.payload(data, Some(npblb));
+        }
+
+        // The final phase encrypts the MAC with counter block 0
+        self.set_phase(Phase::Final);
+        let mut ctr0 = [0; BLOCK_SIZE];
+        ctr0[0] = q as u8 - 1;
+        ctr0[1..=nonce.len()].copy_from_slice(nonce);
+        let tag = self.block(&ctr0);
+        self.disable();
+        Ok(tag)
+    }
+
+    /// Feeds `prefix` followed by `aad`, zero padded to whole blocks
+    fn header(&mut self, prefix: &[u8], aad: &[u8]) {
+        let mut block = [0; BLOCK_SIZE];
+        let mut fill = 0;
+        for byte in prefix.iter().chain(aad.iter()) {
+            block[fill] = *byte;
+            fill += 1;
+            if fill == BLOCK_SIZE {
+                self.write_block(&block);
+                self.wait();
+                fill = 0;
+            }
+        }
+        if fill != 0 {
+            block[fill..].iter_mut().for_each(|byte| *byte = 0);
+            self.write_block(&block);
+            self.wait();
+        }
+    }
+
+    /// Processes `data` in place, zero padding a partial last block
+    ///
+    /// With `npblb` set the padding is excluded from the authentication.
+    fn payload(&mut self, data: &mut [u8], npblb: Option<bool>) {
+        for chunk in data.chunks_mut(BLOCK_SIZE) {
+            let mut block = [0; BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+            if chunk.len() < BLOCK_SIZE && npblb == Some(true) {
+                let padding = (BLOCK_SIZE - chunk.len()) as u32;
+                self.rb.cr.modify(|r, w| unsafe {
+                    w.bits(r.bits() & !(0xf << NPBLB_SHIFT) | padding << NPBLB_SHIFT)
+                });
+            }
+            let out = self.block(&block);
+            chunk.copy_from_slice(&out[..chunk.len()]);
+        }
+    }
+
+    fn block(&mut self, input: &[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+        self.write_block(input);
+        while self.rb.sr.read().bits() & CCF == 0 {}
+        let mut out = [0; BLOCK_SIZE];
+        for chunk in out.chunks_mut(4) {
+            chunk.copy_from_slice(&self.rb.doutr.read().bits().to_le_bytes());
+        }
+        self.clear_ccf();
+        out
+    }
+
+    fn write_block(&mut self, block: &[u8; BLOCK_SIZE]) {
+        // DATATYPE swaps the bytes of every word
+        for chunk in block.chunks(4) {
+            let word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            self.rb.dinr.write(|w| unsafe { w.bits(word) });
+        }
+    }
+
+    fn wait(&mut self) {
+        while self.rb.sr.read().bits() & CCF == 0 {}
+        self.clear_ccf();
+    }
+
+    fn clear_ccf(&mut self) {
+        self.rb
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | CCFC) });
+    }
+
+    /// Disables the unit and loads the key for a new operation
+    fn configure(&mut self, chaining: Chaining, mode: u32, phase: Phase) {
+        self.configure_cr(chaining, mode, phase);
+        self.load_key();
+    }
+
+    fn configure_cr(&mut self, chaining: Chaining, mode: u32, phase: Phase) {
+        let chmod = chaining as u32;
+        let keysize = if self.key_words == 8 { KEYSIZE } else { 0 };
+        self.rb.cr.write(|w| unsafe { w.bits(0) });
+        self.rb.cr.write(|w| unsafe {
+            w.bits(
+                DATATYPE_BYTE
+                    | mode << MODE_SHIFT
+                    | (chmod & 0b11) << 5
+                    | (chmod >> 2) << 16
+                    | (phase as u32) << GCMPH_SHIFT
+                    | keysize
+                    | CCFC
+                    | ERRC,
+            )
+        });
+    }
+
+    fn set_phase(&mut self, phase: Phase) {
+        self.rb.cr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b11 << GCMPH_SHIFT) | (phase as u32) << GCMPH_SHIFT | EN)
+        });
+    }
+
+    fn enable(&mut self) {
+        self.rb.cr.modify(|r, w| unsafe { w.bits(r.bits() | EN) });
+    }
+
+    fn disable(&mut self) {
+        self.rb.cr.modify(|r, w| unsafe { w.bits(r.bits() & !EN) });
+    }
+
+    fn load_key(&mut self) {
+        assert!(self.key_words != 0, "AES key not set");
+        // The first key word goes to the highest key register
+        for (idx, word) in self.key[..self.key_words].iter().enumerate() {
+            let reg = self.key_words - 1 - idx;
+            let offset = if reg < 4 { KEYR0 + reg } else { KEYR4 + reg - 4 };
+            unsafe { core::ptr::write_volatile(register(offset), *word) };
+        }
+    }
+
+    fn load_iv(&mut self, iv: &[u8; BLOCK_SIZE]) {
+        for (idx, bytes) in iv.chunks(4).enumerate() {
+            let word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            unsafe { core::ptr::write_volatile(register(IVR0 + 3 - idx), word) };
+        }
+    }
+}
+
+unsafe impl DmaSink for Aes {
+    type Word = u32;
+
+    fn address(&self) -> u32 {
+        &self.rb.dinr as *const _ as u32
+    }
+
+    fn request(&self) -> DmaMuxIndex {
+        DmaMuxIndex::AES_IN
+    }
+}
+
+unsafe impl DmaSource for Aes {
+    type Word = u32;
+
+    fn address(&self) -> u32 {
+        &self.rb.doutr as *const _ as u32
+    }
+
+    fn request(&self) -> DmaMuxIndex {
+        DmaMuxIndex::AES_OUT
+    }
+}
+
+pub trait AesExt {
+    fn constrain(self, rcc: &mut Rcc) -> Aes;
+}
+
+impl AesExt for AES {
+    fn constrain(self, rcc: &mut Rcc) -> Aes {
+        rcc.rb.ahbenr.modify(|_, w| w.aesen().set_bit());
+        rcc.rb.ahbrstr.modify(|_, w| w.aesrst().set_bit());
+        rcc.rb.ahbrstr.modify(|_, w| w.aesrst().clear_bit());
+        Aes {
+            rb: self,
+            key: [0; 8],
+            key_words: 0,
+        }
+    }
+}
+
+/// AES-GCM with a 96 bit nonce and 128 bit tag
+#[cfg(feature = "aead")]
+pub struct AesGcm {
+    aes: core::cell::RefCell<Aes>,
+}
+
+#[cfg(feature = "aead")]
+impl AesGcm {
+    pub fn new(mut aes: Aes, key: &[u8]) -> Self {
+        aes.set_key(key);
+        AesGcm {
+            aes: core::cell::RefCell::new(aes),
+        }
+    }
+
+    pub fn release(self) -> Aes {
+        self.aes.into_inner()
+    }
+}
+
+#[cfg(feature = "aead")]
+impl aead::AeadCore for AesGcm {
+    type NonceSize = aead::consts::U12;
+    type TagSize = aead::consts::U16;
+    type CiphertextOverhead = aead::consts::U0;
+}
+
+#[cfg(feature = "aead")]
+impl aead::AeadInPlace for AesGcm {
+    fn encrypt_in_place_detached(
+        &self,
+        nonce: &aead::Nonce<Self>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> aead::Result<aead::Tag<Self>> {
+        let mut iv = [0; 12];
+        iv.copy_from_slice(nonce);
+        let tag = self
+            .aes
+            .borrow_mut()
+            .gcm_encrypt(&iv, associated_data, buffer);
+        Ok(tag.into())
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        nonce: &aead::Nonce<Self>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &aead::Tag<Self>,
+    ) -> aead::Result<()> {
+        let mut iv = [0; 12];
+        iv.copy_from_slice(nonce);
+        self.aes
+            .borrow_mut()
+            .gcm_decrypt(&iv, associated_data, buffer, tag)
+            .map_err(|_| aead::Error)
+    }
+}
+
+/// AES-ECB block cipher
+#[cfg(feature = "cipher")]
+pub struct AesEcb {
+    aes: core::cell::RefCell<Aes>,
+}
+
+#[cfg(feature = "cipher")]
+impl AesEcb {
+    pub fn new(mut aes: Aes, key: &[u8]) -> Self {
+        aes.set_key(key);
+        AesEcb {
+            aes: core::cell::RefCell::new(aes),
+        }
+    }
+
+    pub fn release(self) -> Aes {
+        self.aes.into_inner()
+    }
+}
+
+#[cfg(feature = "cipher")]
+impl cipher::BlockSizeUser for AesEcb {
+    type BlockSize = cipher::consts::U16;
+}
+
+#[cfg(feature = "cipher")]
+impl cipher::BlockCipher for AesEcb {}
+
+#[cfg(feature = "cipher")]
+impl cipher::BlockEncrypt for AesEcb {
+    fn encrypt_with_backend(&self, f: impl cipher::BlockClosure<BlockSize = Self::BlockSize>) {
+        self.aes
+            .borrow_mut()
+            .process_blocks(Mode::Ecb, Direction::Encrypt, f);
+    }
+}
+
+#[cfg(feature = "cipher")]
+impl cipher::BlockDecrypt for AesEcb {
+    fn decrypt_with_backend(&self, f: impl cipher::BlockClosure<BlockSize = Self::BlockSize>) {
+        self.aes
+            .borrow_mut()
+            .process_blocks(Mode::Ecb, Direction::Decrypt, f);
+    }
+}
+
+/// AES-CBC, the IV chains across calls
+#[cfg(feature = "cipher")]
+pub struct AesCbc {
+    aes: Aes,
+    iv: [u8; BLOCK_SIZE],
+}
+
+/// AES-CTR, the counter chains across calls
+///
+/// Encryption and decryption are the same operation. The hardware increments
+/// the last 32 bits of the counter block.
+#[cfg(feature = "cipher")]
+pub struct AesCtr {
+    aes: Aes,
+    counter: [u8; BLOCK_SIZE],
+}
+
+macro_rules! block_mode {
+    ($($Cipher:ident: ($state:ident, $Mode:ident),)+) => {
+        $(
+            #[cfg(feature = "cipher")]
+            impl $Cipher {
+                pub fn new(mut aes: Aes, key: &[u8], $state: [u8; BLOCK_SIZE]) -> Self {
+                    aes.set_key(key);
+                    $Cipher { aes, $state }
+                }
+
+                pub fn release(self) -> Aes {
+                    self.aes
+                }
+            }
+
+            #[cfg(feature = "cipher")]
+            impl cipher::BlockSizeUser for $Cipher {
+                type BlockSize = cipher::consts::U16;
+            }
+
+            #[cfg(feature = "cipher")]
+            impl cipher::BlockEncryptMut for $Cipher {
+                fn encrypt_with_backend_mut(
+                    &mut self,
+                    f: impl cipher::BlockClosure<BlockSize = Self::BlockSize>,
+                ) {
+                    let mode = Mode::$Mode(self.$state);
+                    let mode = self.aes.process_blocks(mode, Direction::Encrypt, f);
+                    if let Mode::$Mode(state) = mode {
+                        self.$state = state;
+                    }
+                }
+            }
+
+            #[cfg(feature = "cipher")]
+            impl cipher::BlockDecryptMut for $Cipher {
+                fn decrypt_with_backend_mut(
+                    &mut self,
+                    f: impl cipher::BlockClosure<BlockSize = Self::BlockSize>,
+                ) {
+                    let mode = Mode::$Mode(self.$state);
+                    let mode = self.aes.process_blocks(mode, Direction::Decrypt, f);
+                    if let Mode::$Mode(state) = mode {
+                        self.$state = state;
+                    }
+                }
+            }
+        )+
+    };
+}
+
+block_mode! {
+    AesCbc: (iv, Cbc),
+    AesCtr: (counter, Ctr),
+}
+
+/// Processes blocks on a configured unit, tracking the chaining state
+#[cfg(feature = "cipher")]
+struct Backend<'a> {
+    aes: &'a mut Aes,
+    mode: Mode,
+    dir: Direction,
+}
+
+#[cfg(feature = "cipher")]
+impl cipher::BlockSizeUser for Backend<'_> {
+    type BlockSize = cipher::consts::U16;
+}
+
+#[cfg(feature = "cipher")]
+impl cipher::ParBlocksSizeUser for Backend<'_> {
+    type ParBlocksSize = cipher::consts::U1;
+}
+
+#[cfg(feature = "cipher")]
+impl cipher::BlockBackend for Backend<'_> {
+    fn proc_block(&mut self, mut block: cipher::inout::InOut<'_, '_, cipher::Block<Self>>) {
+        let mut input = [0; BLOCK_SIZE];
+        input.copy_from_slice(block.get_in());
+        let output = self.aes.block(&input);
+        block.get_out().copy_from_slice(&output);
+
+        match &mut self.mode {
+            Mode::Ecb => {}
+            Mode::Cbc(iv) => {
+                // The next block chains with the ciphertext of this one
+                *iv = if self.dir == Direction::Encrypt {
+                    output
+                } else {
+                    input
+                };
+            }
+            Mode::Ctr(counter) => {
+                let mut low = [0; 4];
+                low.copy_from_slice(&counter[12..]);
+                let low = u32::from_be_bytes(low).wrapping_add(1);
+                counter[12..].copy_from_slice(&low.to_be_bytes());
+            }
+        }
+    }
+}
+
+fn register(offset: usize) -> *mut u32 {
+    unsafe { (AES::ptr() as *mut u32).add(offset) }
+}
+
+/// Compares without an early exit
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
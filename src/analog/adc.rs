@@ -1,11 +1,67 @@
 //! # Analog to Digital converter
+use crate::dma::double_buffer::DmaRestart;
+use crate::dma::{DmaChannel, DmaMuxIndex, TransferDirection, WordSize};
 use crate::gpio::*;
-use crate::rcc::Rcc;
-use crate::stm32::ADC;
-use hal::adc::{Channel, OneShot};
+use crate::rcc::{Enable, KernelClockUser, Rcc};
+use crate::stm32::{Interrupt, ADC};
+use crate::time::Hertz;
+use core::{mem, ptr};
+use core::sync::atomic::{self, Ordering};
+use hal::adc::Channel;
+
+/// VREFINT raw reading at 30°C and 3.0V VDDA
+pub(crate) const VREFINT_CAL: *const u16 = 0x1fff_75aa as *const u16;
+/// Temperature sensor raw reading at 30°C and 3.0V VDDA
+const TS_CAL1: *const u16 = 0x1fff_75a8 as *const u16;
+const VDDA_CALIB_MV: u32 = 3_000;
+const TS_CAL1_TEMP: i32 = 30;
+/// Typical temperature sensor slope in uV/°C
+const TS_AVG_SLOPE_UV: i32 = 2_500;
+const SENSOR_STARTUP_CYCLES: u32 = 5_200;
+
+// ADC_CCR enable bits
+const VREFEN: u32 = 1 << 22;
+const TSEN: u32 = 1 << 23;
+const VBATEN: u32 = 1 << 24;
+// ADC_CCR asynchronous clock prescaler
+const PRESC_SHIFT: u32 = 18;
+const PRESC_MASK: u32 = 0b1111 << PRESC_SHIFT;
+/// Asynchronous clock dividers, indexed by PRESC
+const PRESC_DIV: [u32; 12] = [1, 2, 4, 6, 8, 10, 12, 16, 32, 64, 128, 256];
+// CFGR1
+const DMAEN: u32 = 1 << 0;
+const DMACFG: u32 = 1 << 1;
+const EXTSEL_SHIFT: u32 = 6;
+const EXTSEL_MASK: u32 = 0b111 << EXTSEL_SHIFT;
+const EXTEN_SHIFT: u32 = 10;
+const EXTEN_MASK: u32 = 0b11 << EXTEN_SHIFT;
+const OVRMOD: u32 = 1 << 12;
+const CONT: u32 = 1 << 13;
+const WAIT: u32 = 1 << 14;
+const AUTOFF: u32 = 1 << 15;
+const DISCEN: u32 = 1 << 16;
+const CHSELRMOD: u32 = 1 << 21;
+// CFGR2
+const CKMODE_SHIFT: u32 = 30;
+const CKMODE_MASK: u32 = 0b11 << CKMODE_SHIFT;
+// ISR
+const EOC: u32 = 1 << 2;
+const EOS: u32 = 1 << 3;
+const OVR: u32 = 1 << 4;
+const EOCAL: u32 = 1 << 11;
+const CCRDY: u32 = 1 << 13;
+/// Voltage regulator start-up time (tADCVREG_STUP) in microseconds
+const REGULATOR_STARTUP_US: u32 = 20;
+/// VDDA drift calling for a new calibration, in permille
+const VDDA_DRIFT: u32 = 100;
+/// Internal VBAT divider ratio
+const VBAT_DIV: u32 = 3;
+/// Fastest ADC clock reaching the 12us VBAT sampling time in 160.5 cycles
+const VBAT_MAX_CLK: u32 = 13_000_000;
 
 /// ADC Result Alignment
 #[derive(PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Align {
     /// Right aligned results (least significant bits)
     ///
@@ -22,6 +78,7 @@ pub enum Align {
 
 /// ADC Sampling Precision
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Precision {
     /// 12 bit precision
     B_12 = 0b00,
@@ -33,8 +90,23 @@ pub enum Precision {
     B_6 = 0b11,
 }
 
+/// ADC clock (CKMODE)
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockMode {
+    /// Kernel clock selected with `Rcc::set_kernel_clock`
+    Async = 0b00,
+    /// PCLK / 2
+    PclkDiv2 = 0b01,
+    /// PCLK / 4
+    PclkDiv4 = 0b10,
+    /// PCLK, needs a 50% duty cycle, i.e. an undivided APB clock
+    Pclk = 0b11,
+}
+
 /// ADC Sampling time
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SampleTime {
     T_2 = 0b000,
     T_4 = 0b001,
@@ -46,12 +118,194 @@ pub enum SampleTime {
     T_160 = 0b111,
 }
 
+/// ADC Oversampling ratio
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OversamplingRatio {
+    X_2 = 0b000,
+    X_4 = 0b001,
+    X_8 = 0b010,
+    X_16 = 0b011,
+    X_32 = 0b100,
+    X_64 = 0b101,
+    X_128 = 0b110,
+    X_256 = 0b111,
+}
+
+/// ADC analog watchdog
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AnalogWatchdog {
+    /// Watchdog 1, guards a single channel or all channels
+    AWD1 = 1,
+    /// Watchdog 2, guards any selection of channels
+    AWD2 = 2,
+    /// Watchdog 3, guards any selection of channels
+    AWD3 = 3,
+}
+
+/// ADC events
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// End of a (continuous or triggered) conversion
+    EndOfConversion = 1 << 2,
+    /// End of the conversions of a sequence
+    EndOfSequence = 1 << 3,
+    /// A result was overwritten before it was read
+    Overrun = 1 << 4,
+    /// Converted value outside of the analog watchdog 1 thresholds
+    AnalogWatchdog1 = 1 << 7,
+    /// Converted value outside of the analog watchdog 2 thresholds
+    AnalogWatchdog2 = 1 << 8,
+    /// Converted value outside of the analog watchdog 3 thresholds
+    AnalogWatchdog3 = 1 << 9,
+}
+
+/// ADC error
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A result was overwritten before it was read
+    Overrun,
+}
+
+/// ADC external trigger (EXTSEL)
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    /// TIM1 TRGO2
+    Tim1Trgo2 = 0b000,
+    /// TIM1 capture/compare 4
+    Tim1Cc4 = 0b001,
+    /// TIM2 TRGO
+    Tim2Trgo = 0b010,
+    /// TIM3 TRGO
+    Tim3Trgo = 0b011,
+    /// TIM15 TRGO
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    Tim15Trgo = 0b100,
+    /// TIM6 TRGO
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    Tim6Trgo = 0b101,
+    /// EXTI line 11
+    Exti11 = 0b111,
+}
+
+/// ADC external trigger polarity (EXTEN)
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerEdge {
+    Rising = 0b01,
+    Falling = 0b10,
+    Both = 0b11,
+}
+
+/// Channels converted as a group, results are returned in sequence order
+///
+/// Channels added in ascending order are selected with a bitmask. Any other
+/// order uses the sequencer, limited to 8 conversions of channels 0-14.
+#[derive(Copy, Clone, Default)]
+pub struct Sequence {
+    order: [u8; 8],
+    len: usize,
+    mask: u32,
+    sequencer: bool,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Sequence {
+            order: [0; 8],
+            len: 0,
+            mask: 0,
+            sequencer: false,
+        }
+    }
+
+    /// Appends a channel to the sequence
+    pub fn channel<PIN>(mut self, _pin: &PIN) -> Self
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        let channel = PIN::channel();
+        if self.len > 0 && channel <= self.last() {
+            self.sequencer = true;
+        }
+        if self.len < self.order.len() {
+            self.order[self.len] = channel;
+        }
+        self.len += 1;
+        self.mask |= 1 << channel;
+        assert!(
+            !self.sequencer || (self.len <= 8 && self.order[..self.len].iter().all(|ch| *ch <= 14)),
+            "sequence out of sequencer range"
+        );
+        self
+    }
+
+    /// Number of conversions in the sequence
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn last(&self) -> u8 {
+        if self.len <= self.order.len() {
+            self.order[self.len - 1]
+        } else {
+            31 - self.mask.leading_zeros() as u8
+        }
+    }
+
+    /// CHSELR contents
+    fn chselr(&self) -> u32 {
+        if self.sequencer {
+            // SQx nibbles, terminated by 0xf
+            let mut bits = 0xffff_ffff;
+            for (idx, channel) in self.order[..self.len].iter().enumerate() {
+                bits &= !(0xf << (idx * 4));
+                bits |= (*channel as u32) << (idx * 4);
+            }
+            bits
+        } else {
+            self.mask
+        }
+    }
+}
+
+/// Conversions started one at a time, the ADC is only powered while converting
+pub struct SingleShot;
+
+/// Conversions of a sequence repeat without software intervention
+pub struct Continuous;
+
+/// Every `convert_next` converts the next channel of a sequence
+pub struct Discontinuous;
+
+/// Conversions start on a hardware trigger, `CHANNEL` writes the results
+pub struct Triggered<CHANNEL> {
+    channel: CHANNEL,
+    buffer: &'static mut [u16],
+}
+
 /// Analog to Digital converter interface
-pub struct Adc {
+pub struct Adc<MODE = SingleShot> {
     rb: ADC,
     sample_time: SampleTime,
+    sample_time2: SampleTime,
+    sample_select: u32,
     align: Align,
     precision: Precision,
+    oversampling: Option<(OversamplingRatio, u8)>,
+    triggered_oversampling: bool,
+    calibration_vdda: u16,
+    apb_clk: Hertz,
+    kernel_clk: Hertz,
+    mode: MODE,
 }
 
 impl Adc {
@@ -59,20 +313,91 @@ impl Adc {
         // Enable ADC clocks
         rcc.rb.apbenr2.modify(|_, w| w.adcen().set_bit());
         adc.cr.modify(|_, w| w.advregen().set_bit());
+        cortex_m::asm::delay(rcc.clocks.sys_clk.0 / 1_000_000 * REGULATOR_STARTUP_US);
 
-        Self {
+        let mut adc = Self {
             rb: adc,
             sample_time: SampleTime::T_2,
+            sample_time2: SampleTime::T_2,
+            sample_select: 0,
             align: Align::Right,
             precision: Precision::B_12,
+            oversampling: None,
+            triggered_oversampling: false,
+            calibration_vdda: 0,
+            apb_clk: rcc.clocks.apb_clk,
+            kernel_clk: rcc.kernel_clock(KernelClockUser::Adc),
+            mode: SingleShot,
+        };
+        adc.calibrate();
+        adc
+    }
+
+    /// Calibrates the ADC, leaving it disabled
+    ///
+    /// Runs in `new` and `set_clock_mode`. Calibrate again after large
+    /// supply voltage or temperature changes, see `check_calibration`.
+    pub fn calibrate(&mut self) {
+        self.disable();
+        // DMAEN must be cleared while calibrating
+        let cfgr1 = self.rb.cfgr1.read().bits();
+        self.rb.cfgr1.write(|w| unsafe { w.bits(cfgr1 & !1) });
+        self.rb.cr.modify(|_, w| w.adcal().set_bit());
+        while self.rb.cr.read().adcal().bit_is_set() {}
+        self.rb.isr.write(|w| unsafe { w.bits(EOCAL) });
+        self.rb.cfgr1.write(|w| unsafe { w.bits(cfgr1) });
+        // VDDA is only measured by `check_calibration`
+        self.calibration_vdda = 0;
+    }
+
+    /// Calibrates again if VDDA drifted by more than 10% since the last
+    /// calibration, returns `true` if it did
+    ///
+    /// The first call after a calibration measures the reference VDDA.
+    pub fn check_calibration(&mut self) -> bool {
+        let vdda = self.read_vdda() as u32;
+        let cal = self.calibration_vdda as u32;
+        if cal == 0 {
+            self.calibration_vdda = vdda as u16;
+            false
+        } else if vdda.abs_diff(cal) * 1_000 > cal * VDDA_DRIFT {
+            self.calibrate();
+            true
+        } else {
+            false
         }
     }
 
+    /// Selects the ADC clock and calibrates again
+    pub fn set_clock_mode(&mut self, mode: ClockMode) {
+        self.disable();
+        self.rb.cfgr2.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b11 << CKMODE_SHIFT) | (mode as u32) << CKMODE_SHIFT)
+        });
+        self.calibrate();
+    }
+
     /// Set the Adc sampling time
     pub fn set_sample_time(&mut self, t_samp: SampleTime) {
         self.sample_time = t_samp;
     }
 
+    /// Set the sampling time of a single channel
+    ///
+    /// The ADC supports two sampling times: channels not using the common
+    /// `set_sample_time` value share the last time set here.
+    pub fn set_channel_sample_time<PIN>(&mut self, _pin: &PIN, t_samp: SampleTime)
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        if t_samp == self.sample_time {
+            self.sample_select &= !(1 << PIN::channel());
+        } else {
+            self.sample_time2 = t_samp;
+            self.sample_select |= 1 << PIN::channel();
+        }
+    }
+
     /// Set the Adc result alignment
     pub fn set_align(&mut self, align: Align) {
         self.align = align;
@@ -83,42 +408,408 @@ impl Adc {
         self.precision = precision;
     }
 
-    fn power_up(&mut self) {
+    /// Enable hardware oversampling
+    ///
+    /// Accumulates `ratio` conversions and shifts the sum right by `shift`
+    /// bits (0-8), e.g. 256x with a shift of 4 yields 16 bit results from
+    /// the 12 bit converter. Results are always right aligned.
+    pub fn set_oversampling(&mut self, ratio: OversamplingRatio, shift: u8) {
+        assert!(shift <= 8);
+        self.oversampling = Some((ratio, shift));
+    }
+
+    /// Disable hardware oversampling
+    pub fn disable_oversampling(&mut self) {
+        self.oversampling = None;
+    }
+
+    /// Powers the ADC only while converting (AUTOFF)
+    ///
+    /// The ADC powers up on every start or trigger and down once the sequence
+    /// is converted, adding the start-up time to each conversion. Suits
+    /// triggered conversions at low rates, the ADC draws almost nothing
+    /// between triggers. Stops running conversions.
+    pub fn set_auto_off(&mut self, auto_off: bool) {
+        self.set_cfgr1(AUTOFF, auto_off);
+    }
+
+    /// Delays the next conversion until the last result was read (WAIT)
+    ///
+    /// Prevents overruns without DMA, hardware triggers arriving before the
+    /// result was read are ignored. With AUTOFF the ADC stays powered down
+    /// until then. Stops running conversions.
+    pub fn set_wait(&mut self, wait: bool) {
+        self.set_cfgr1(WAIT, wait);
+    }
+
+    /// Adds a channel to the analog watchdog
+    ///
+    /// Watchdog 1 guards a single channel, the last one added.
+    pub fn watchdog_channel<PIN>(&mut self, awd: AnalogWatchdog, _pin: &PIN)
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        let channel = PIN::channel() as u32;
+        match awd {
+            AnalogWatchdog::AWD1 => self.rb.cfgr1.modify(|r, w| unsafe {
+                // AWD1CH, AWD1EN, AWD1SGL
+                w.bits(r.bits() & !(0x1f << 26) | channel << 26 | 0b11 << 22)
+            }),
+            AnalogWatchdog::AWD2 => self
+                .rb
+                .awd2cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << channel) }),
+            AnalogWatchdog::AWD3 => self
+                .rb
+                .awd3cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << channel) }),
+        }
+    }
+
+    /// Makes analog watchdog 1 guard all channels
+    pub fn watchdog_all_channels(&mut self) {
+        self.rb
+            .cfgr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 22) | 1 << 23) });
+    }
+
+    /// Stops the analog watchdog guarding any channels
+    pub fn watchdog_disable(&mut self, awd: AnalogWatchdog) {
+        match awd {
+            AnalogWatchdog::AWD1 => self
+                .rb
+                .cfgr1
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 23)) }),
+            AnalogWatchdog::AWD2 => self.rb.awd2cr.reset(),
+            AnalogWatchdog::AWD3 => self.rb.awd3cr.reset(),
+        }
+    }
+
+    /// Sets the analog watchdog thresholds
+    ///
+    /// Thresholds are 12 bit right aligned values, compared against the
+    /// conversion result before any oversampling shift.
+    pub fn set_watchdog_thresholds(&mut self, awd: AnalogWatchdog, low: u16, high: u16) {
+        let bits = (high as u32 & 0xfff) << 16 | low as u32 & 0xfff;
+        match awd {
+            AnalogWatchdog::AWD1 => self.rb.awd1tr.write(|w| unsafe { w.bits(bits) }),
+            AnalogWatchdog::AWD2 => self.rb.awd2tr.write(|w| unsafe { w.bits(bits) }),
+            AnalogWatchdog::AWD3 => self.rb.awd3tr.write(|w| unsafe { w.bits(bits) }),
+        }
+    }
+
+    /// Starts continuous conversions of `sequence`
+    ///
+    /// `read` returns the results in sequence order, `Event::EndOfSequence`
+    /// follows the last channel. Unread results are kept, later results are
+    /// lost and raise `Event::Overrun`.
+    pub fn into_continuous(mut self, sequence: &Sequence) -> Adc<Continuous> {
+        assert!(!sequence.is_empty());
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(OVRMOD | CONT | DISCEN) | CONT)
+        });
+        self.configure();
+        self.select(sequence);
+        self.rb.isr.write(|w| unsafe { w.bits(EOC | EOS | OVR) });
+        self.rb.cr.modify(|_, w| w.adstart().set_bit());
+        self.into_mode(Continuous)
+    }
+
+    /// Prepares conversions of `sequence`, one channel at a time
+    ///
+    /// Each `convert_next` converts the next channel, the sequence restarts
+    /// after `Event::EndOfSequence`.
+    pub fn into_discontinuous(mut self, sequence: &Sequence) -> Adc<Discontinuous> {
+        assert!(!sequence.is_empty());
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(OVRMOD | CONT | DISCEN) | DISCEN)
+        });
+        self.configure();
+        self.select(sequence);
+        self.rb.isr.write(|w| unsafe { w.bits(EOC | EOS | OVR) });
+        self.into_mode(Discontinuous)
+    }
+
+    /// Starts continuous conversions of a single channel
+    ///
+    /// The analog watchdogs guard the conversions without CPU intervention,
+    /// new results overwrite unread ones. Stop with `into_single_shot`.
+    pub fn start_continuous<PIN>(mut self, _pin: &mut PIN) -> Adc<Continuous>
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        self.start(1 << PIN::channel());
+        self.into_mode(Continuous)
+    }
+
+    /// Starts conversions synchronized to the TIM1 PWM period
+    ///
+    /// Every TIM1 channel 4 compare event, placed with
+    /// `Pwm::<TIM1>::set_adc_trigger_phase`, samples `pin` once. The oversampler
+    /// accumulates one sample per PWM period over `periods` periods, which
+    /// cancels switching ripple. Results are available with `latest()` after
+    /// `Event::EndOfConversion`, stop with `into_single_shot()`.
+    pub fn start_pwm_synced<PIN>(
+        mut self,
+        _pin: &mut PIN,
+        periods: OversamplingRatio,
+        shift: u8,
+    ) -> Adc<Continuous>
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        assert!(shift <= 8);
+        self.oversampling = Some((periods, shift));
+        self.triggered_oversampling = true;
+        // EXTSEL TIM1_CC4, EXTEN rising edge, single conversion
+        let cfgr1 = OVRMOD
+            | (Trigger::Tim1Cc4 as u32) << EXTSEL_SHIFT
+            | (TriggerEdge::Rising as u32) << EXTEN_SHIFT;
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(DMAEN | DMACFG | EXTSEL_MASK | EXTEN_MASK | CONT) | cfgr1)
+        });
+        self.configure();
+        self.select_channels(1 << PIN::channel(), false);
+        self.rb.cr.modify(|_, w| w.adstart().set_bit());
+        self.into_mode(Continuous)
+    }
+
+    /// Starts conversions of `sequence` on every `trigger` rising edge
+    ///
+    /// Results are written by `channel` into `buffer` in circular mode, one
+    /// sample per channel in sequence order. The buffer length should be a
+    /// multiple of the sequence length. `stop_triggered` returns both.
+    pub fn start_triggered<CHANNEL>(
+        self,
+        sequence: &Sequence,
+        trigger: Trigger,
+        channel: CHANNEL,
+        buffer: &'static mut [u16],
+    ) -> Adc<Triggered<CHANNEL>>
+    where
+        CHANNEL: DmaChannel,
+    {
+        self.start_triggered_on(sequence, trigger, TriggerEdge::Rising, channel, buffer)
+    }
+
+    /// Starts conversions of `sequence` on every `trigger` `edge`
+    pub fn start_triggered_on<CHANNEL>(
+        mut self,
+        sequence: &Sequence,
+        trigger: Trigger,
+        edge: TriggerEdge,
+        mut channel: CHANNEL,
+        buffer: &'static mut [u16],
+    ) -> Adc<Triggered<CHANNEL>>
+    where
+        CHANNEL: DmaChannel,
+    {
+        assert!(!sequence.is_empty() && !buffer.is_empty());
+
+        channel.set_direction(TransferDirection::PeriphToMemory);
+        channel.set_circular_mode(true);
+        channel.set_word_size(WordSize::Bits16);
+        channel.set_peripheral_address(&self.rb.dr as *const _ as u32, false);
+        channel.set_memory_address(buffer.as_ptr() as u32, true);
+        channel.set_transfer_length(buffer.len());
+        channel.select_peripheral(DmaMuxIndex::ADC);
+        channel.start();
+
+        // DMA circular mode, single conversion
+        let cfgr1 =
+            DMAEN | DMACFG | (trigger as u32) << EXTSEL_SHIFT | (edge as u32) << EXTEN_SHIFT;
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(DMAEN | DMACFG | EXTSEL_MASK | EXTEN_MASK | CONT) | cfgr1)
+        });
+        self.configure();
+        self.select(sequence);
+        // Waits for the first trigger
+        self.rb.cr.modify(|_, w| w.adstart().set_bit());
+        self.into_mode(Triggered { channel, buffer })
+    }
+
+    /// Converts all channels of the `sequence`, results are written in order
+    ///
+    /// Returns the number of results written.
+    pub fn read_sequence(&mut self, sequence: &Sequence, results: &mut [u16]) -> usize {
+        self.configure();
+        self.select(sequence);
+
+        self.rb.isr.write(|w| unsafe { w.bits(EOC | EOS | OVR) });
+        self.rb.cr.modify(|_, w| w.adstart().set_bit());
+        let mut count = 0;
+        loop {
+            let isr = self.rb.isr.read().bits();
+            if isr & EOC != 0 {
+                // Reading DR clears EOC
+                let value = self.rb.dr.read().bits() as u16;
+                if let Some(result) = results.get_mut(count) {
+                    *result = value;
+                    count += 1;
+                }
+            } else if isr & EOS != 0 {
+                break;
+            }
+        }
+        self.disable();
+        count
+    }
+
+    /// Enables the ADC and waits until it is ready
+    ///
+    /// Conversions enable the ADC on their own, enabling it up front saves
+    /// the start-up time of the next conversion.
+    pub fn enable(&mut self) {
+        if self.is_enabled() {
+            return;
+        }
         self.rb.isr.modify(|_, w| w.adrdy().set_bit());
         self.rb.cr.modify(|_, w| w.aden().set_bit());
-        while self.rb.isr.read().adrdy().bit_is_clear() {}
+        // With AUTOFF the ADC stays powered down until a conversion starts,
+        // ADRDY is never set
+        if self.rb.cfgr1.read().bits() & AUTOFF == 0 {
+            while self.rb.isr.read().adrdy().bit_is_clear() {}
+        }
     }
 
-    fn power_down(&mut self) {
-        self.rb.cr.modify(|_, w| w.addis().set_bit());
-        self.rb.isr.modify(|_, w| w.adrdy().set_bit());
-        while self.rb.cr.read().aden().bit_is_set() {}
+    /// Stops ongoing conversions and disables the ADC
+    pub fn disable(&mut self) {
+        self.power_off();
     }
 
-    pub fn release(self) -> ADC {
+    /// Reads the internal reference voltage in millivolts
+    ///
+    /// Returns the factory calibrated VREFINT voltage, measured at 3.0V VDDA.
+    pub fn read_vref(&mut self) -> u16 {
+        let cal = unsafe { ptr::read_volatile(VREFINT_CAL) } as u32;
+        (cal * VDDA_CALIB_MV / 4095) as u16
+    }
+
+    /// Measures the analog supply voltage in millivolts using VREFINT
+    pub fn read_vdda(&mut self) -> u16 {
+        let raw = self.read_internal(VRef::channel(), VREFEN) as u32;
+        let cal = unsafe { ptr::read_volatile(VREFINT_CAL) } as u32;
+        (VDDA_CALIB_MV * cal / raw.max(1)) as u16
+    }
+
+    /// Measures the junction temperature in degrees Celsius
+    ///
+    /// Uses the TS_CAL1 factory calibration point at 30°C and the typical
+    /// sensor slope of 2.5mV/°C. The reading is compensated for VDDA.
+    pub fn read_temperature(&mut self) -> i16 {
+        let vdda = self.read_vdda() as i32;
+        let raw = self.read_internal(VTemp::channel(), TSEN) as i32;
+        let cal = unsafe { ptr::read_volatile(TS_CAL1) } as i32;
+
+        // Scale the reading to the 3.0V calibration supply
+        let raw = raw * vdda / VDDA_CALIB_MV as i32;
+        let delta_uv = (raw - cal) * VDDA_CALIB_MV as i32 * 1_000 / 4095;
+        (TS_CAL1_TEMP + delta_uv / TS_AVG_SLOPE_UV) as i16
+    }
+
+    /// Measures the backup battery voltage in millivolts
+    ///
+    /// Compensates the internal 1/3 divider and VDDA. The conversion runs from
+    /// the divided asynchronous clock to reach the 12us VBAT sampling time,
+    /// the clock settings are restored afterwards.
+    pub fn read_vbat(&mut self) -> u16 {
+        let vdda = self.read_vdda() as u32;
+
+        self.disable();
+        let cfgr2 = self.rb.cfgr2.read().bits();
+        let ccr = self.rb.ccr.read().bits();
+        let presc = PRESC_DIV
+            .iter()
+            .position(|div| self.kernel_clk.0 / div <= VBAT_MAX_CLK)
+            .unwrap_or(PRESC_DIV.len() - 1) as u32;
+        self.rb
+            .cfgr2
+            .write(|w| unsafe { w.bits(cfgr2 & !CKMODE_MASK) });
+        self.rb
+            .ccr
+            .write(|w| unsafe { w.bits(ccr & !PRESC_MASK | presc << PRESC_SHIFT) });
+
+        let raw = self.read_internal(VBat::channel(), VBATEN) as u32;
+
         self.rb
+            .ccr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !PRESC_MASK | ccr & PRESC_MASK) });
+        self.rb.cfgr2.write(|w| unsafe { w.bits(cfgr2) });
+        (raw * vdda * VBAT_DIV / 4095) as u16
     }
-}
 
-pub trait AdcExt {
-    fn constrain(self, rcc: &mut Rcc) -> Adc;
-}
+    fn convert(&mut self, channel: u8) -> u16 {
+        self.start_conversion(channel);
+        while self.rb.isr.read().bits() & EOS == 0 {}
 
-impl AdcExt for ADC {
-    fn constrain(self, rcc: &mut Rcc) -> Adc {
-        Adc::new(self, rcc)
+        let res = self.rb.dr.read().bits() as u16;
+        self.disable();
+        self.aligned(res)
     }
-}
 
-impl<WORD, PIN> OneShot<Adc, WORD, PIN> for Adc
-where
-    WORD: From<u16>,
-    PIN: Channel<Adc, ID = u8>,
-{
-    type Error = ();
+    /// Starts a single conversion of `channel`
+    fn start_conversion(&mut self, channel: u8) {
+        self.configure();
+        self.select_channels(1 << channel, false);
+
+        self.rb.isr.write(|w| unsafe { w.bits(EOC | EOS | OVR) });
+        self.rb.cr.modify(|_, w| w.adstart().set_bit());
+    }
+
+    /// Moves left aligned 6-bit results to the upper byte
+    fn aligned(&self, res: u16) -> u16 {
+        if self.oversampling.is_none()
+            && self.align == Align::Left
+            && self.precision == Precision::B_6
+        {
+            res << 8
+        } else {
+            res
+        }
+    }
+
+    /// Starts continuous conversions of the selected channels
+    fn start(&mut self, channels: u32) {
+        self.rb
+            .cfgr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | CONT | OVRMOD) });
+        self.configure();
+        self.select_channels(channels, false);
+        self.rb.cr.modify(|_, w| w.adstart().set_bit());
+    }
+
+    fn select(&mut self, sequence: &Sequence) {
+        self.select_channels(sequence.chselr(), sequence.sequencer);
+    }
 
-    fn read(&mut self, _pin: &mut PIN) -> nb::Result<WORD, Self::Error> {
-        self.power_up();
+    /// Writes CHSELR and waits for the channel configuration to be applied
+    fn select_channels(&mut self, chselr: u32, sequencer: bool) {
+        let chselrmod = if sequencer { CHSELRMOD } else { 0 };
+        self.rb
+            .cfgr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !CHSELRMOD | chselrmod) });
+        self.rb.isr.write(|w| unsafe { w.bits(CCRDY) });
+        self.rb.chselr().write(|w| unsafe { w.bits(chselr) });
+        while self.rb.isr.read().bits() & CCRDY == 0 {}
+    }
+
+    /// Applies settings and powers up the ADC
+    fn configure(&mut self) {
+        // OVSE, OVSR and OVSS fields can only be written while the ADC is disabled
+        self.disable();
+        let ovs = match self.oversampling {
+            Some((ratio, shift)) => {
+                // TOVS: every oversampled conversion needs a trigger
+                let tovs = (self.triggered_oversampling as u32) << 9;
+                1 | (ratio as u32) << 2 | (shift as u32) << 5 | tovs
+            }
+            None => 0,
+        };
+        self.rb
+            .cfgr2
+            .modify(|r, w| unsafe { w.bits(r.bits() & !0x3ff | ovs) });
+
+        self.enable();
         self.rb.cfgr1.modify(|_, w| unsafe {
             w.res()
                 .bits(self.precision as u8)
@@ -126,27 +817,329 @@ where
                 .bit(self.align == Align::Left)
         });
 
+        // SMP1, SMP2 and per channel SMPSEL
+        self.rb.smpr.write(|w| unsafe {
+            w.bits(
+                self.sample_time as u32
+                    | (self.sample_time2 as u32) << 4
+                    | (self.sample_select & 0x7_ffff) << 8,
+            )
+        });
+    }
+
+    /// CFGR1 can only be written while no conversion is running
+    fn set_cfgr1(&mut self, mask: u32, set: bool) {
+        self.disable();
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            w.bits(if set {
+                r.bits() | mask
+            } else {
+                r.bits() & !mask
+            })
+        });
+    }
+
+    /// Converts an internal channel at 12 bit with the longest sampling time
+    fn read_internal(&mut self, channel: u8, enable: u32) -> u16 {
+        let enabled = self.rb.ccr.read().bits() & enable != 0;
+        if !enabled {
+            self.rb.ccr.modify(|r, w| unsafe { w.bits(r.bits() | enable) });
+            // Sensor and buffer start-up time, up to 80us at 64MHz
+            cortex_m::asm::delay(SENSOR_STARTUP_CYCLES);
+        }
+
+        let sample_time = self.sample_time;
+        let precision = self.precision;
+        let align = core::mem::replace(&mut self.align, Align::Right);
+        let oversampling = self.oversampling.take();
+        let sample_select = core::mem::replace(&mut self.sample_select, 0);
+        self.sample_time = SampleTime::T_160;
+        self.precision = Precision::B_12;
+
+        let raw = self.convert(channel);
+
+        self.sample_time = sample_time;
+        self.precision = precision;
+        self.align = align;
+        self.oversampling = oversampling;
+        self.sample_select = sample_select;
+        if !enabled {
+            self.rb.ccr.modify(|r, w| unsafe { w.bits(r.bits() & !enable) });
+        }
+        raw
+    }
+}
+
+impl<MODE> Adc<MODE> {
+    /// NVIC interrupt line of the ADC, shared with the comparators
+    pub const INTERRUPT: Interrupt = Interrupt::ADC_COMP;
+
+    /// Calibration factor applied to the conversions
+    pub fn calibration_factor(&self) -> u8 {
+        (self.rb.calfact.read().bits() & 0x7f) as u8
+    }
+
+    /// ADC clock frequency
+    pub fn clock(&self) -> Hertz {
+        let presc = (self.rb.ccr.read().bits() & PRESC_MASK) >> PRESC_SHIFT;
+        match self.rb.cfgr2.read().bits() >> CKMODE_SHIFT {
+            0b01 => Hertz(self.apb_clk.0 / 2),
+            0b10 => Hertz(self.apb_clk.0 / 4),
+            0b11 => self.apb_clk,
+            _ => Hertz(self.kernel_clk.0 / PRESC_DIV[(presc as usize).min(PRESC_DIV.len() - 1)]),
+        }
+    }
+
+    /// Starts listening for an `event`
+    pub fn listen(&mut self, event: Event) {
         self.rb
-            .smpr
-            .modify(|_, w| unsafe { w.smp1().bits(self.sample_time as u8) });
+            .ier
+            .modify(|r, w| unsafe { w.bits(r.bits() | event as u32) });
+    }
 
+    /// Stops listening for an `event`
+    pub fn unlisten(&mut self, event: Event) {
         self.rb
-            .chselr()
-            .modify(|_, w| unsafe { w.chsel().bits(1 << PIN::channel()) });
+            .ier
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(event as u32)) });
+    }
 
-        self.rb.isr.modify(|_, w| w.eos().set_bit());
-        self.rb.cr.modify(|_, w| w.adstart().set_bit());
-        while self.rb.isr.read().eos().bit_is_clear() {}
+    /// Returns `true` if the `event` is pending
+    pub fn is_pending(&self, event: Event) -> bool {
+        self.rb.isr.read().bits() & event as u32 != 0
+    }
 
-        let res = self.rb.dr.read().bits() as u16;
-        let val = if self.align == Align::Left && self.precision == Precision::B_6 {
-            res << 8
+    /// Clears the pending `event`
+    pub fn clear_interrupt(&mut self, event: Event) {
+        self.rb.isr.write(|w| unsafe { w.bits(event as u32) });
+    }
+
+    /// Clears the pending `event`, same as `clear_interrupt`
+    pub fn unpend(&mut self, event: Event) {
+        self.clear_interrupt(event);
+    }
+
+    /// Returns the NVIC interrupt line, shared with the comparators on some parts
+    pub fn interrupt(&self) -> Interrupt {
+        Self::INTERRUPT
+    }
+
+    /// Returns the latest continuous conversion result
+    pub fn latest(&self) -> u16 {
+        self.rb.dr.read().bits() as u16
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rb.cr.read().aden().bit_is_set()
+    }
+
+    pub fn release(self) -> ADC {
+        self.rb
+    }
+
+    /// Stops conversions, powers down the ADC and disables its clock
+    pub fn release_and_disable(mut self, rcc: &mut Rcc) -> ADC {
+        self.power_off();
+        self.rb.cr.modify(|_, w| w.advregen().clear_bit());
+        ADC::disable(rcc);
+        self.rb
+    }
+
+    fn stop_conversion(&mut self) {
+        if self.rb.cr.read().adstart().bit_is_set() {
+            self.rb.cr.modify(|_, w| w.adstp().set_bit());
+            while self.rb.cr.read().adstart().bit_is_set() {}
+        }
+    }
+
+    fn power_off(&mut self) {
+        self.stop_conversion();
+        if !self.is_enabled() {
+            return;
+        }
+        self.rb.cr.modify(|_, w| w.addis().set_bit());
+        self.rb.isr.modify(|_, w| w.adrdy().set_bit());
+        while self.rb.cr.read().aden().bit_is_set() {}
+    }
+
+    /// Returns the next result of a running sequence
+    fn read_result(&mut self) -> nb::Result<u16, Error> {
+        let isr = self.rb.isr.read().bits();
+        if isr & OVR != 0 {
+            self.rb.isr.write(|w| unsafe { w.bits(OVR) });
+            Err(nb::Error::Other(Error::Overrun))
+        } else if isr & EOC != 0 {
+            // Reading DR clears EOC
+            Ok(self.rb.dr.read().bits() as u16)
         } else {
-            res
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Stops conversions and returns to single conversions
+    fn into_single_shot_mode(mut self) -> Adc {
+        self.power_off();
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(CONT | DISCEN | OVRMOD | EXTSEL_MASK | EXTEN_MASK))
+        });
+        self.triggered_oversampling = false;
+        self.into_mode(SingleShot)
+    }
+
+    fn into_mode<M>(self, mode: M) -> Adc<M> {
+        self.replace_mode(mode).0
+    }
+
+    /// Switches to `mode`, returns the previous mode state as well
+    fn replace_mode<M>(self, mode: M) -> (Adc<M>, MODE) {
+        let adc = Adc {
+            rb: self.rb,
+            sample_time: self.sample_time,
+            sample_time2: self.sample_time2,
+            sample_select: self.sample_select,
+            align: self.align,
+            precision: self.precision,
+            oversampling: self.oversampling,
+            triggered_oversampling: self.triggered_oversampling,
+            calibration_vdda: self.calibration_vdda,
+            apb_clk: self.apb_clk,
+            kernel_clk: self.kernel_clk,
+            mode,
         };
+        (adc, self.mode)
+    }
+}
 
-        self.power_down();
-        Ok(val.into())
+impl Adc<Continuous> {
+    /// Returns the next result in sequence order
+    pub fn read(&mut self) -> nb::Result<u16, Error> {
+        self.read_result()
+    }
+
+    /// Stops the conversions
+    pub fn into_single_shot(self) -> Adc {
+        self.into_single_shot_mode()
+    }
+}
+
+impl<CHANNEL> Adc<Triggered<CHANNEL>>
+where
+    CHANNEL: DmaChannel,
+{
+    /// Copies the latest samples of the DMA buffer into `results`
+    ///
+    /// Samples are read while the DMA keeps writing, the copy may mix two
+    /// sequences. Returns the number of samples copied.
+    pub fn read_samples(&self, results: &mut [u16]) -> usize {
+        let buffer = &self.mode.buffer;
+        let count = results.len().min(buffer.len());
+        atomic::compiler_fence(Ordering::SeqCst);
+        for (idx, result) in results[..count].iter_mut().enumerate() {
+            *result = unsafe { ptr::read_volatile(buffer.as_ptr().add(idx)) };
+        }
+        count
+    }
+
+    /// Stops triggered conversions, returns the ADC, DMA channel and buffer
+    ///
+    /// The buffer is handed back once the channel is disabled. It is empty
+    /// if `DmaRestart::stop` already took it.
+    pub fn stop_triggered(mut self) -> (Adc, CHANNEL, &'static mut [u16]) {
+        self.power_off();
+        self.mode.channel.stop();
+        self.mode.channel.set_circular_mode(false);
+        atomic::compiler_fence(Ordering::SeqCst);
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(DMAEN | DMACFG | EXTSEL_MASK | EXTEN_MASK))
+        });
+        let (adc, Triggered { channel, buffer }) = self.replace_mode(SingleShot);
+        (adc, channel, buffer)
+    }
+}
+
+/// Fills one buffer per restart in DMA one-shot mode
+///
+/// Each restart begins a new sequence, so buffer lengths should be multiples
+/// of the sequence length.
+impl<CHANNEL> DmaRestart for Adc<Triggered<CHANNEL>>
+where
+    CHANNEL: DmaChannel,
+{
+    type Word = u16;
+
+    fn restart(&mut self, buffer: &'static mut [u16]) -> Option<&'static mut [u16]> {
+        assert!(!buffer.is_empty());
+        self.stop_conversion();
+        self.mode.channel.stop();
+        self.mode.channel.set_circular_mode(false);
+        atomic::compiler_fence(Ordering::SeqCst);
+        // DMA requests stop once the buffer is full
+        self.rb.cfgr1.modify(|r, w| unsafe { w.bits(r.bits() & !DMACFG) });
+        self.mode.channel.set_memory_address(buffer.as_ptr() as u32, true);
+        self.mode.channel.set_transfer_length(buffer.len());
+        let previous = mem::replace(&mut self.mode.buffer, buffer);
+        atomic::compiler_fence(Ordering::SeqCst);
+        self.mode.channel.start();
+        self.rb.cr.modify(|_, w| w.adstart().set_bit());
+        Some(previous).filter(|previous| !previous.is_empty())
+    }
+
+    fn stop(&mut self) -> Option<&'static mut [u16]> {
+        self.stop_conversion();
+        self.mode.channel.stop();
+        atomic::compiler_fence(Ordering::SeqCst);
+        Some(mem::take(&mut self.mode.buffer)).filter(|buffer| !buffer.is_empty())
+    }
+}
+
+impl Adc<Discontinuous> {
+    /// Starts the conversion of the next channel in the sequence
+    pub fn convert_next(&mut self) {
+        self.rb.cr.modify(|_, w| w.adstart().set_bit());
+    }
+
+    /// Returns the result of the last `convert_next`
+    pub fn read(&mut self) -> nb::Result<u16, Error> {
+        self.read_result()
+    }
+
+    /// Returns to single conversions
+    pub fn into_single_shot(self) -> Adc {
+        self.into_single_shot_mode()
+    }
+}
+
+pub trait AdcExt {
+    fn constrain(self, rcc: &mut Rcc) -> Adc;
+}
+
+impl AdcExt for ADC {
+    fn constrain(self, rcc: &mut Rcc) -> Adc {
+        Adc::new(self, rcc)
+    }
+}
+
+impl<WORD, PIN> hal::adc::OneShot<Adc, WORD, PIN> for Adc
+where
+    WORD: From<u16>,
+    PIN: Channel<Adc, ID = u8>,
+{
+    type Error = Error;
+
+    /// Starts a conversion of `PIN` on the first call, then polls for its result
+    fn read(&mut self, _pin: &mut PIN) -> nb::Result<WORD, Error> {
+        let idle = self.rb.cr.read().adstart().bit_is_clear();
+        if idle && self.rb.isr.read().bits() & (EOC | OVR) == 0 {
+            self.start_conversion(PIN::channel());
+            return Err(nb::Error::WouldBlock);
+        }
+        match self.read_result() {
+            Err(nb::Error::WouldBlock) => Err(nb::Error::WouldBlock),
+            result => {
+                self.disable();
+                result.map(|res| self.aligned(res).into())
+            }
+        }
     }
 }
 
@@ -222,3 +1215,13 @@ adc_pin! {
     Channel17: (gpioc::PC4<Analog>, 17u8),
     Channel18: (gpioc::PC5<Analog>, 18u8),
 }
+
+// Additional inputs of the G030, G031 and G041
+#[cfg(any(feature = "stm32g030", feature = "stm32g031", feature = "stm32g041"))]
+adc_pin! {
+    Channel11: (gpiob::PB7<Analog>, 11u8),
+    Channel15: (gpioa::PA11<Analog>, 15u8),
+    Channel16: (gpioa::PA12<Analog>, 16u8),
+    Channel17: (gpioa::PA13<Analog>, 17u8),
+    Channel18: (gpioa::PA14<Analog>, 18u8),
+}
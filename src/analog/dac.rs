@@ -1,8 +1,10 @@
 //! DAC
 use core::mem::MaybeUninit;
+use core::sync::atomic::{self, Ordering};
 
 use hal::blocking::delay::DelayUs;
 use crate::gpio::gpioa::{PA4, PA5};
+use crate::dma::{DmaChannel, DmaMuxIndex, TransferDirection, WordSize};
 use crate::gpio::DefaultMode;
 use crate::rcc::Rcc;
 use crate::stm32::DAC;
@@ -134,3 +136,196 @@ dac!(
             dacc2dhr
         ),
 );
+
+/// DAC conversion trigger (TSEL)
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    Software = 0b0000,
+    Tim1Trgo = 0b0001,
+    Tim2Trgo = 0b0010,
+    Tim3Trgo = 0b0011,
+    Tim6Trgo = 0b0101,
+    Tim7Trgo = 0b0110,
+    Tim15Trgo = 0b1000,
+    Lptim1Out = 0b1011,
+    Lptim2Out = 0b1100,
+    Exti9 = 0b1101,
+}
+
+/// Triangle amplitude or noise LFSR mask (MAMP), `2^(n+1) - 1`
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Amplitude {
+    A1 = 0,
+    A3 = 1,
+    A7 = 2,
+    A15 = 3,
+    A31 = 4,
+    A63 = 5,
+    A127 = 6,
+    A255 = 7,
+    A511 = 8,
+    A1023 = 9,
+    A2047 = 10,
+    A4095 = 11,
+}
+
+/// Output connection and buffer mode (MODE)
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Buffered output to the pin
+    Buffered = 0b000,
+    /// Buffered output to the pin and on-chip peripherals
+    BufferedInternal = 0b001,
+    /// Unbuffered output to the pin
+    Unbuffered = 0b010,
+    /// Unbuffered output to on-chip peripherals only
+    Internal = 0b011,
+    /// Sample and hold, buffered output to the pin
+    SampleHold = 0b100,
+    /// Sample and hold, buffered output to the pin and on-chip peripherals
+    SampleHoldInternal = 0b101,
+    /// Sample and hold, unbuffered output to the pin and on-chip peripherals
+    SampleHoldUnbuffered = 0b110,
+    /// Sample and hold, unbuffered output to on-chip peripherals only
+    SampleHoldInternalOnly = 0b111,
+}
+
+/// DMA waveform output in progress
+pub struct Waveform<CX, CHANNEL> {
+    dac: CX,
+    channel: CHANNEL,
+    buffer: &'static [u16],
+}
+
+macro_rules! dac_wave {
+    ($($CX:ident: ($shift:expr, $swtrig:expr, $shsr:ident, $dhrx:ident, $req:ident),)+) => {
+        $(
+            impl $CX {
+                /// Selects the conversion trigger
+                ///
+                /// Without a trigger, values are output one APB clock after `set_value`.
+                pub fn set_trigger(&mut self, trigger: Option<Trigger>) {
+                    // TEN, TSEL
+                    let bits = match trigger {
+                        Some(trigger) => 1 << 1 | (trigger as u32) << 2,
+                        None => 0,
+                    };
+                    modify_cr(0b1_1111 << 1, bits, $shift);
+                }
+
+                /// Triggers a conversion when the software trigger is selected
+                pub fn trigger(&mut self) {
+                    let dac = unsafe { &(*DAC::ptr()) };
+                    dac.dac_swtrgr.write(|w| unsafe { w.bits($swtrig) });
+                }
+
+                /// Adds a triangle wave of `amplitude` to the output value
+                ///
+                /// Every trigger steps the triangle by one, a trigger must be selected.
+                pub fn enable_triangle(&mut self, amplitude: Amplitude) {
+                    modify_cr(0b11_1111 << 6, 0b10 << 6 | (amplitude as u32) << 8, $shift);
+                }
+
+                /// Adds LFSR noise masked to `amplitude` bits to the output value
+                ///
+                /// Every trigger advances the LFSR, a trigger must be selected.
+                pub fn enable_noise(&mut self, amplitude: Amplitude) {
+                    modify_cr(0b11_1111 << 6, 0b01 << 6 | (amplitude as u32) << 8, $shift);
+                }
+
+                /// Stops triangle or noise generation
+                pub fn disable_wave(&mut self) {
+                    modify_cr(0b11_1111 << 6, 0, $shift);
+                }
+
+                /// Selects output buffer and sample and hold mode
+                pub fn set_mode(&mut self, mode: Mode) {
+                    let dac = unsafe { &(*DAC::ptr()) };
+                    let en = dac.dac_cr.read().bits() & (1 << $shift);
+                    dac.dac_cr.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $shift)) });
+                    dac.dac_mcr.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0b111 << $shift) | (mode as u32) << $shift)
+                    });
+                    dac.dac_cr.modify(|r, w| unsafe { w.bits(r.bits() | en) });
+                }
+
+                /// Configures sample and hold timing in LSI/LSE clock cycles
+                ///
+                /// The output capacitor is charged for `sample` cycles, then held
+                /// for `hold` cycles and refreshed for `refresh` cycles.
+                pub fn set_sample_hold_timing(&mut self, sample: u16, hold: u16, refresh: u8) {
+                    let dac = unsafe { &(*DAC::ptr()) };
+                    dac.$shsr.write(|w| unsafe { w.bits(sample as u32 & 0x3ff) });
+                    // BWST: sample time register write in progress
+                    while dac.dac_sr.read().bits() & (1 << (15 + $shift)) != 0 {}
+                    dac.dac_shhr.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0x3ff << $shift) | (hold as u32 & 0x3ff) << $shift)
+                    });
+                    dac.dac_shrr.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0xff << $shift) | (refresh as u32) << $shift)
+                    });
+                }
+
+                /// Plays `buffer` in a loop, one sample per `trigger`
+                pub fn waveform<CHANNEL>(
+                    mut self,
+                    trigger: Trigger,
+                    mut channel: CHANNEL,
+                    buffer: &'static [u16],
+                ) -> Waveform<$CX, CHANNEL>
+                where
+                    CHANNEL: DmaChannel,
+                {
+                    assert!(!buffer.is_empty());
+                    let dac = unsafe { &(*DAC::ptr()) };
+                    channel.set_direction(TransferDirection::MemoryToPeriph);
+                    channel.set_circular_mode(true);
+                    channel.set_word_size(WordSize::Bits16);
+                    channel.set_peripheral_address(&dac.$dhrx as *const _ as u32, false);
+                    channel.set_memory_address(buffer.as_ptr() as u32, true);
+                    channel.set_transfer_length(buffer.len());
+                    channel.select_peripheral(DmaMuxIndex::$req);
+                    atomic::compiler_fence(Ordering::SeqCst);
+                    channel.start();
+
+                    self.set_trigger(Some(trigger));
+                    // DMAEN
+                    modify_cr(1 << 12, 1 << 12, $shift);
+                    self.enable();
+                    Waveform {
+                        dac: self,
+                        channel,
+                        buffer,
+                    }
+                }
+            }
+
+            impl<CHANNEL: DmaChannel> Waveform<$CX, CHANNEL> {
+                /// Stops waveform output, returning DAC channel, DMA channel and buffer
+                pub fn release(mut self) -> ($CX, CHANNEL, &'static [u16]) {
+                    modify_cr(1 << 12, 0, $shift);
+                    self.channel.stop();
+                    self.channel.set_circular_mode(false);
+                    (self.dac, self.channel, self.buffer)
+                }
+            }
+        )+
+    };
+}
+
+/// Modifies a channel's `DAC_CR` field that requires the channel disabled
+fn modify_cr(mask: u32, bits: u32, shift: u32) {
+    let dac = unsafe { &(*DAC::ptr()) };
+    let en = dac.dac_cr.read().bits() & (1 << shift);
+    dac.dac_cr.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << shift)) });
+    dac.dac_cr.modify(|r, w| unsafe { w.bits(r.bits() & !(mask << shift) | bits << shift) });
+    dac.dac_cr.modify(|r, w| unsafe { w.bits(r.bits() | en) });
+}
+
+dac_wave!(
+    Channel1: (0, 0b01, dac_shsr1, dac_dhr12r1, DAC_CH1),
+    Channel2: (16, 0b10, dac_shsr2, dac_dhr12r2, DAC_CH2),
+);
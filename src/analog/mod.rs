@@ -1,3 +1,5 @@
 pub mod adc;
 #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 pub mod dac;
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pub mod vrefbuf;
@@ -0,0 +1,97 @@
+//! # Voltage reference buffer
+//!
+//! VREFBUF drives the VREF+ pin with an internal reference, usable by the
+//! ADC, DAC and external converters.
+use crate::rcc::Rcc;
+use crate::stm32::SYSCFG_VREFBUF;
+
+/// Reference voltage scale (VRS)
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VoltageScale {
+    /// 2.048V
+    V2_048 = 0,
+    /// 2.5V
+    V2_5 = 1,
+}
+
+/// Voltage reference buffer
+pub struct VrefBuf {
+    rb: SYSCFG_VREFBUF,
+}
+
+impl VrefBuf {
+    pub fn new(vrefbuf: SYSCFG_VREFBUF, rcc: &mut Rcc) -> Self {
+        rcc.rb.apbenr2.modify(|_, w| w.syscfgen().set_bit());
+        VrefBuf { rb: vrefbuf }
+    }
+
+    /// Enables the buffer driving VREF+ with `scale`
+    ///
+    /// Blocks until the output voltage is stable. VDDA must be at least
+    /// 2.4V for the 2.048V and 2.8V for the 2.5V reference.
+    pub fn enable(&mut self, scale: VoltageScale) {
+        // ENVR, HIZ cleared, VRS
+        self.rb
+            .vrefbuf_csr
+            .write(|w| unsafe { w.bits(1 | (scale as u32) << 2) });
+        while !self.is_ready() {}
+    }
+
+    /// Disables the buffer
+    ///
+    /// With `hi_z` set VREF+ is left floating for an external reference,
+    /// otherwise it is pulled down to VSSA.
+    pub fn disable(&mut self, hi_z: bool) {
+        self.rb
+            .vrefbuf_csr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !0b11 | (hi_z as u32) << 1) });
+    }
+
+    /// Returns `true` once the reference voltage is stable (VRR)
+    pub fn is_ready(&self) -> bool {
+        self.rb.vrefbuf_csr.read().bits() & (1 << 3) != 0
+    }
+
+    /// Selected voltage scale
+    pub fn scale(&self) -> VoltageScale {
+        if self.rb.vrefbuf_csr.read().bits() & (1 << 2) != 0 {
+            VoltageScale::V2_5
+        } else {
+            VoltageScale::V2_048
+        }
+    }
+
+    /// Reference voltage in millivolts
+    pub fn millivolts(&self) -> u16 {
+        match self.scale() {
+            VoltageScale::V2_048 => 2048,
+            VoltageScale::V2_5 => 2500,
+        }
+    }
+
+    /// Adjusts the factory trimming code (0-63)
+    pub fn set_trim(&mut self, trim: u8) {
+        self.rb
+            .vrefbuf_ccr
+            .write(|w| unsafe { w.bits(trim as u32 & 0x3f) });
+    }
+
+    pub fn trim(&self) -> u8 {
+        (self.rb.vrefbuf_ccr.read().bits() & 0x3f) as u8
+    }
+
+    pub fn release(self) -> SYSCFG_VREFBUF {
+        self.rb
+    }
+}
+
+pub trait VrefBufExt {
+    fn constrain(self, rcc: &mut Rcc) -> VrefBuf;
+}
+
+impl VrefBufExt for SYSCFG_VREFBUF {
+    fn constrain(self, rcc: &mut Rcc) -> VrefBuf {
+        VrefBuf::new(self, rcc)
+    }
+}
@@ -0,0 +1,83 @@
+//! # Async support
+//!
+//! Async drivers enable a peripheral interrupt and suspend until it fires.
+//! The application forwards the peripheral interrupts to the HAL:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn USART2() {
+//!     hal::asynch::on_interrupt::<stm32::USART2>();
+//! }
+//! ```
+//!
+//! Interrupt enable bits used by a pending future are cleared by the handler.
+use core::cell::RefCell;
+use core::task::Waker;
+
+use cortex_m::interrupt::{self, Mutex};
+
+/// Storage for the waker of a pending future
+pub struct WakerSlot {
+    waker: Mutex<RefCell<Option<Waker>>>,
+}
+
+impl WakerSlot {
+    pub const fn new() -> Self {
+        WakerSlot {
+            waker: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Registers waker of the current task
+    pub fn register(&self, waker: &Waker) {
+        interrupt::free(|cs| {
+            let mut slot = self.waker.borrow(cs).borrow_mut();
+            match &*slot {
+                Some(current) if current.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        });
+    }
+
+    /// Wakes registered task, if any
+    pub fn wake(&self) {
+        if let Some(waker) = interrupt::free(|cs| self.waker.borrow(cs).borrow_mut().take()) {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for WakerSlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Peripheral interrupt used by async drivers
+pub trait AsyncInterrupt {
+    /// Masks the interrupt sources that fired and wakes pending futures
+    fn on_interrupt();
+}
+
+/// Waker slot owned by a peripheral or peripheral half
+pub(crate) trait AsyncWaker {
+    fn waker() -> &'static WakerSlot;
+}
+
+/// Forwards peripheral interrupt to the async drivers
+pub fn on_interrupt<P: AsyncInterrupt>() {
+    P::on_interrupt()
+}
+
+macro_rules! async_waker {
+    ($($T:ty,)+) => {
+        $(
+            impl crate::asynch::AsyncWaker for $T {
+                fn waker() -> &'static crate::asynch::WakerSlot {
+                    static WAKER: crate::asynch::WakerSlot = crate::asynch::WakerSlot::new();
+                    &WAKER
+                }
+            }
+        )+
+    };
+}
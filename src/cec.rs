@@ -0,0 +1,435 @@
+//! # HDMI-CEC controller
+//!
+//! Sends and receives CEC frames on the open-drain CEC line. The own
+//! logical address is acknowledged by hardware, frames to other followers
+//! can be captured in listen mode. The kernel clock is HSI16/488 or LSE.
+use crate::gpio::gpioa::PA5;
+use crate::gpio::gpiob::PB10;
+use crate::gpio::{AltFunction, Alternate, OpenDrain, Output, AF0, AF1};
+use crate::rcc::Rcc;
+use crate::stm32::HDMI_CEC;
+
+// CR
+const CECEN: u32 = 1 << 0;
+const TXSOM: u32 = 1 << 1;
+const TXEOM: u32 = 1 << 2;
+
+// CFGR
+const RXTOL: u32 = 1 << 3;
+const BRESTP: u32 = 1 << 4;
+const BREGEN: u32 = 1 << 5;
+const LBPEGEN: u32 = 1 << 6;
+const LSTN: u32 = 1 << 31;
+
+// ISR
+const RXBR: u32 = 1 << 0;
+const RXEND: u32 = 1 << 1;
+const RXOVR: u32 = 1 << 2;
+const BRE: u32 = 1 << 3;
+const SBPE: u32 = 1 << 4;
+const LBPE: u32 = 1 << 5;
+const RXACKE: u32 = 1 << 6;
+const ARBLST: u32 = 1 << 7;
+const TXBR: u32 = 1 << 8;
+const TXEND: u32 = 1 << 9;
+const TXUDR: u32 = 1 << 10;
+const TXERR: u32 = 1 << 11;
+const TXACKE: u32 = 1 << 12;
+
+const RX_ERRORS: u32 = RXOVR | BRE | SBPE | LBPE | RXACKE;
+const TX_DONE: u32 = TXEND | TXUDR | TXERR | TXACKE | ARBLST;
+
+/// Header plus opcode and up to 14 operands
+pub const MAX_FRAME_LEN: usize = 16;
+
+/// Broadcast / unregistered logical address
+pub const BROADCAST: u8 = 0xf;
+
+/// CEC pin, an open drain output or an open drain `Alternate` pin
+pub trait CecPin {
+    fn setup(&self);
+}
+
+impl CecPin for PA5<Output<OpenDrain>> {
+    fn setup(&self) {
+        self.set_alt_mode(AltFunction::AF1);
+    }
+}
+
+impl CecPin for PB10<Output<OpenDrain>> {
+    fn setup(&self) {
+        self.set_alt_mode(AltFunction::AF0);
+    }
+}
+
+impl CecPin for PA5<Alternate<AF1>> {
+    fn setup(&self) {}
+}
+
+impl CecPin for PB10<Alternate<AF0>> {
+    fn setup(&self) {}
+}
+
+/// CEC kernel clock
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CecClkSource {
+    /// HSI16 divided by 488
+    HSI = 0,
+    LSE = 1,
+}
+
+/// CEC errors
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Received data was not read in time
+    Overrun,
+    /// Bit rising error, the line rose outside the allowed window
+    BitRising,
+    /// Short bit period
+    ShortBitPeriod,
+    /// Long bit period
+    LongBitPeriod,
+    /// Frame was not acknowledged
+    Nack,
+    /// Another initiator won the bus
+    ArbitrationLost,
+    /// Transmit data was not written in time
+    Underrun,
+    /// Transmission error, the line was held low
+    Tx,
+    /// Frame has no header or more than 16 bytes
+    InvalidLength,
+}
+
+/// CEC interrupt events
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// Byte received
+    RxByte = 1 << 0,
+    /// Frame received
+    RxEnd = 1 << 1,
+    /// Receive errors
+    RxError = (RXOVR | BRE | SBPE | LBPE | RXACKE) as isize,
+    /// Transmit data register empty
+    TxByte = 1 << 8,
+    /// Frame sent
+    TxEnd = 1 << 9,
+    /// Transmit errors
+    TxError = (ARBLST | TXUDR | TXERR | TXACKE) as isize,
+}
+
+/// CEC configuration
+pub struct Config {
+    clk_src: CecClkSource,
+    address: u8,
+    signal_free_time: u8,
+    tolerant: bool,
+    bit_rising_stop: bool,
+    error_bit: bool,
+}
+
+impl Config {
+    /// Follower with logical `address`
+    pub fn new(address: u8) -> Self {
+        Config::default().address(address)
+    }
+
+    pub fn clock_src(mut self, clk_src: CecClkSource) -> Self {
+        self.clk_src = clk_src;
+        self
+    }
+
+    /// Own logical address, 0-14
+    pub fn address(mut self, address: u8) -> Self {
+        assert!(address < BROADCAST);
+        self.address = address;
+        self
+    }
+
+    /// Signal free time in nominal bit periods, 0 selects the time from
+    /// the standard
+    pub fn signal_free_time(mut self, bits: u8) -> Self {
+        self.signal_free_time = bits.min(7);
+        self
+    }
+
+    /// Extended receive tolerance for non-compliant initiators
+    pub fn tolerant(mut self, tolerant: bool) -> Self {
+        self.tolerant = tolerant;
+        self
+    }
+
+    /// Stops reception on a bit rising error
+    pub fn bit_rising_stop(mut self, stop: bool) -> Self {
+        self.bit_rising_stop = stop;
+        self
+    }
+
+    /// Generates an error bit on bit rising and long bit period errors
+    pub fn error_bit(mut self, enable: bool) -> Self {
+        self.error_bit = enable;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            clk_src: CecClkSource::HSI,
+            address: BROADCAST - 1,
+            signal_free_time: 0,
+            tolerant: false,
+            bit_rising_stop: false,
+            error_bit: true,
+        }
+    }
+}
+
+/// CEC frame
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame {
+    bytes: [u8; MAX_FRAME_LEN],
+    len: usize,
+}
+
+impl Frame {
+    /// Frame from `initiator` to `destination` with opcode and operands
+    /// in `data`, an empty `data` makes a polling message
+    pub fn new(initiator: u8, destination: u8, data: &[u8]) -> Result<Self, Error> {
+        if data.len() >= MAX_FRAME_LEN {
+            return Err(Error::InvalidLength);
+        }
+        let mut frame = Frame {
+            bytes: [0; MAX_FRAME_LEN],
+            len: data.len() + 1,
+        };
+        frame.bytes[0] = (initiator & 0xf) << 4 | destination & 0xf;
+        frame.bytes[1..frame.len].copy_from_slice(data);
+        Ok(frame)
+    }
+
+    pub fn initiator(&self) -> u8 {
+        self.bytes[0] >> 4
+    }
+
+    pub fn destination(&self) -> u8 {
+        self.bytes[0] & 0xf
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.destination() == BROADCAST
+    }
+
+    pub fn opcode(&self) -> Option<u8> {
+        self.operands_from(1).first().copied()
+    }
+
+    pub fn operands(&self) -> &[u8] {
+        self.operands_from(2)
+    }
+
+    /// Raw frame bytes, starting with the header
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    fn operands_from(&self, idx: usize) -> &[u8] {
+        &self.bytes[idx.min(self.len)..self.len]
+    }
+}
+
+/// HDMI-CEC controller
+pub struct Cec<PIN> {
+    rb: HDMI_CEC,
+    pin: PIN,
+    rx: [u8; MAX_FRAME_LEN],
+    rx_len: usize,
+}
+
+pub trait CecExt {
+    fn cec<PIN: CecPin>(self, pin: PIN, config: Config, rcc: &mut Rcc) -> Cec<PIN>;
+}
+
+impl CecExt for HDMI_CEC {
+    fn cec<PIN: CecPin>(self, pin: PIN, config: Config, rcc: &mut Rcc) -> Cec<PIN> {
+        rcc.rb.apbenr1.modify(|_, w| w.cecen().set_bit());
+        rcc.rb.apbrstr1.modify(|_, w| w.cecrst().set_bit());
+        rcc.rb.apbrstr1.modify(|_, w| w.cecrst().clear_bit());
+        rcc.rb
+            .ccipr
+            .modify(|_, w| w.cecsel().bit(config.clk_src == CecClkSource::LSE));
+
+        pin.setup();
+
+        let mut cfgr = config.signal_free_time as u32 | 1 << (16 + config.address as u32);
+        if config.tolerant {
+            cfgr |= RXTOL;
+        }
+        if config.bit_rising_stop {
+            cfgr |= BRESTP;
+        }
+        if config.error_bit {
+            cfgr |= BREGEN | LBPEGEN;
+        }
+        // CFGR is writable only while the controller is disabled
+        self.cec_cfgr.write(|w| unsafe { w.bits(cfgr) });
+        self.cec_cr.write(|w| unsafe { w.bits(CECEN) });
+
+        Cec {
+            rb: self,
+            pin,
+            rx: [0; MAX_FRAME_LEN],
+            rx_len: 0,
+        }
+    }
+}
+
+impl<PIN> Cec<PIN> {
+    /// Changes the own logical address
+    pub fn set_address(&mut self, address: u8) {
+        assert!(address < BROADCAST);
+        self.reconfigure(|cfgr| cfgr & !(0x7fff << 16) | 1 << (16 + address as u32));
+    }
+
+    /// In listen mode frames addressed to other followers are received
+    /// too, without being acknowledged
+    pub fn set_listen_mode(&mut self, listen: bool) {
+        self.reconfigure(|cfgr| if listen { cfgr | LSTN } else { cfgr & !LSTN });
+    }
+
+    /// Sends `frame`, blocking until it is acknowledged or fails
+    ///
+    /// Broadcast frames are acknowledged unless a follower rejects them.
+    pub fn transmit(&mut self, frame: &Frame) -> Result<(), Error> {
+        let bytes = frame.as_bytes();
+        self.rb.cec_isr.write(|w| unsafe { w.bits(TXBR | TX_DONE) });
+        self.rb
+            .cec_txdr
+            .write(|w| unsafe { w.bits(bytes[0] as u32) });
+        let eom = if bytes.len() == 1 { TXEOM } else { 0 };
+        self.rb
+            .cec_cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | TXSOM | eom) });
+
+        let mut idx = 1;
+        loop {
+            let isr = self.rb.cec_isr.read().bits();
+            if isr & TX_DONE != 0 {
+                self.rb
+                    .cec_isr
+                    .write(|w| unsafe { w.bits(isr & (TXBR | TX_DONE)) });
+                return if isr & ARBLST != 0 {
+                    Err(Error::ArbitrationLost)
+                } else if isr & TXACKE != 0 {
+                    Err(Error::Nack)
+                } else if isr & TXUDR != 0 {
+                    Err(Error::Underrun)
+                } else if isr & TXERR != 0 {
+                    Err(Error::Tx)
+                } else {
+                    Ok(())
+                };
+            }
+            if isr & TXBR != 0 && idx < bytes.len() {
+                if idx == bytes.len() - 1 {
+                    // End of message is flagged before the last byte is written
+                    self.rb
+                        .cec_cr
+                        .modify(|r, w| unsafe { w.bits(r.bits() | TXEOM) });
+                }
+                self.rb.cec_isr.write(|w| unsafe { w.bits(TXBR) });
+                self.rb
+                    .cec_txdr
+                    .write(|w| unsafe { w.bits(bytes[idx] as u32) });
+                idx += 1;
+            }
+        }
+    }
+
+    /// Receives a frame
+    ///
+    /// Must be polled at least once per received byte (~24ms), or from the
+    /// `RxByte` interrupt.
+    pub fn receive(&mut self) -> nb::Result<Frame, Error> {
+        let isr = self.rb.cec_isr.read().bits();
+        if isr & RX_ERRORS != 0 {
+            self.rb
+                .cec_isr
+                .write(|w| unsafe { w.bits(isr & (RX_ERRORS | RXEND)) });
+            self.rx_len = 0;
+            let err = if isr & RXOVR != 0 {
+                Error::Overrun
+            } else if isr & BRE != 0 {
+                Error::BitRising
+            } else if isr & SBPE != 0 {
+                Error::ShortBitPeriod
+            } else if isr & LBPE != 0 {
+                Error::LongBitPeriod
+            } else {
+                Error::Nack
+            };
+            return Err(nb::Error::Other(err));
+        }
+        if isr & RXBR != 0 {
+            let byte = self.rb.cec_rxdr.read().bits() as u8;
+            self.rb.cec_isr.write(|w| unsafe { w.bits(RXBR) });
+            if self.rx_len < MAX_FRAME_LEN {
+                self.rx[self.rx_len] = byte;
+                self.rx_len += 1;
+            }
+        }
+        if isr & RXEND == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.rb.cec_isr.write(|w| unsafe { w.bits(RXEND) });
+        let len = core::mem::replace(&mut self.rx_len, 0);
+        if len == 0 {
+            return Err(nb::Error::Other(Error::InvalidLength));
+        }
+        let mut frame = Frame {
+            bytes: [0; MAX_FRAME_LEN],
+            len,
+        };
+        frame.bytes[..len].copy_from_slice(&self.rx[..len]);
+        Ok(frame)
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        self.rb
+            .cec_ier
+            .modify(|r, w| unsafe { w.bits(r.bits() | event as u32) });
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        self.rb
+            .cec_ier
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(event as u32)) });
+    }
+
+    /// Returns `true` if `event` is pending
+    pub fn is_pending(&self, event: Event) -> bool {
+        self.rb.cec_isr.read().bits() & event as u32 != 0
+    }
+
+    /// Disables the controller, returning the peripheral and pin
+    pub fn release(self) -> (HDMI_CEC, PIN) {
+        self.rb.cec_cr.write(|w| unsafe { w.bits(0) });
+        (self.rb, self.pin)
+    }
+
+    fn reconfigure<F: FnOnce(u32) -> u32>(&mut self, f: F) {
+        self.rb.cec_cr.write(|w| unsafe { w.bits(0) });
+        self.rb
+            .cec_cfgr
+            .modify(|r, w| unsafe { w.bits(f(r.bits())) });
+        self.rb.cec_cr.write(|w| unsafe { w.bits(CECEN) });
+        self.rx_len = 0;
+    }
+}
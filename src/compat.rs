@@ -0,0 +1,186 @@
+//! # API compatibility layer
+//!
+//! Redesigned APIs live next to the ones they replace for at least one
+//! release. Each `vX_Y` module freezes the API surface of release `X.Y`:
+//! firmware importing from it keeps building while migrating piece by piece
+//! to the new types, e.g. from byte oriented `ReadDma`/`WriteDma` to the typed
+//! descriptors in `dma::descriptor`, or from embedded-hal 0.2 traits to 1.0.
+//!
+//! Items whose form changed since are `#[deprecated]`, the note names the
+//! replacement. Modules are removed one release after they stop matching the
+//! crate API.
+
+/// API of the 0.0 releases
+#[allow(deprecated)]
+pub mod v0_0 {
+    /// ADC with the `OneShot` mode names
+    pub mod adc {
+        use crate::analog::adc::{Continuous, Discontinuous};
+
+        pub use crate::analog::adc::{Adc, AdcExt};
+
+        /// Conversions started one at a time
+        #[deprecated(note = "renamed to `analog::adc::SingleShot`")]
+        pub type OneShot = crate::analog::adc::SingleShot;
+
+        /// Returns to single conversions under the 0.0 name
+        #[deprecated(note = "renamed to `Adc::into_single_shot`")]
+        pub trait IntoOneShot {
+            fn into_one_shot(self) -> Adc;
+        }
+
+        impl IntoOneShot for Adc<Continuous> {
+            fn into_one_shot(self) -> Adc {
+                self.into_single_shot()
+            }
+        }
+
+        impl IntoOneShot for Adc<Discontinuous> {
+            fn into_one_shot(self) -> Adc {
+                self.into_single_shot()
+            }
+        }
+    }
+
+    /// Byte oriented DMA transfers
+    pub mod dma {
+        pub use crate::dma::{
+            CopyDma, DmaChannel, DmaExt, DmaMuxIndex, Event, Priority, ReadDma, Transfer,
+            TransferDirection, WordSize, WriteDma,
+        };
+    }
+
+    /// embedded-hal 0.2 traits
+    pub mod hal {
+        pub use crate::hal::adc::{Channel, OneShot};
+        pub use crate::hal::blocking::delay::{DelayMs, DelayUs};
+        pub use crate::hal::digital::v2::{
+            InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin,
+        };
+        pub use crate::hal::serial::{Read, Write};
+        pub use crate::hal::spi::FullDuplex;
+        pub use crate::hal::timer::{CountDown, Periodic};
+    }
+
+    /// Peripheral pin traits
+    ///
+    /// Pins in the modes accepted by the 0.0 releases still implement them,
+    /// `Alternate<AF>` pins were added next to them. `rcc::Lsco` defaults its
+    /// pin type to `rcc::LscoPin`, the 0.0 output pin.
+    pub mod pins {
+        pub use crate::i2c::{SCLPin, SDAPin};
+        pub use crate::rcc::{Lsco, LscoPin};
+        pub use crate::serial::{RxPin, TxPin};
+        pub use crate::spi::{PinMiso, PinMosi, PinSck};
+        pub use crate::timer::pins::TimerPin;
+    }
+
+    /// Serial ports without a pins type
+    pub mod serial {
+        use crate::rcc::Rcc;
+        use crate::serial::{Config, InvalidConfig, RxPin, TxPin};
+        use crate::stm32::{LPUART, USART1, USART2};
+        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        use crate::stm32::{USART3, USART4};
+
+        /// Serial port of the 0.0 releases
+        ///
+        /// `release` takes `&mut Rcc`, disables the USART clock and hands
+        /// back `(USART, ())` since the pins were erased.
+        #[deprecated(note = "use `serial::Serial<USART, (TX, RX)>` or `Serial::erase_pins`")]
+        pub type Serial<USART> = crate::serial::Serial<USART>;
+
+        /// Constructor returning a port without a pins type
+        #[deprecated(note = "use `serial::SerialExt`, the port now owns its pins")]
+        pub trait SerialExt<USART> {
+            fn usart<TX, RX>(
+                self,
+                tx: TX,
+                rx: RX,
+                config: Config,
+                rcc: &mut Rcc,
+            ) -> Result<Serial<USART>, InvalidConfig>
+            where
+                TX: TxPin<USART>,
+                RX: RxPin<USART>;
+        }
+
+        macro_rules! serial_ext {
+            ($($USARTX:ident),+) => {
+                $(
+                    impl SerialExt<$USARTX> for $USARTX {
+                        fn usart<TX, RX>(
+                            self,
+                            tx: TX,
+                            rx: RX,
+                            config: Config,
+                            rcc: &mut Rcc,
+                        ) -> Result<Serial<$USARTX>, InvalidConfig>
+                        where
+                            TX: TxPin<$USARTX>,
+                            RX: RxPin<$USARTX>,
+                        {
+                            crate::serial::SerialExt::usart(self, tx, rx, config, rcc)
+                                .map(|serial| serial.erase_pins())
+                        }
+                    }
+                )+
+            };
+        }
+
+        serial_ext!(LPUART, USART1, USART2);
+        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        serial_ext!(USART3, USART4);
+    }
+
+    /// Prelude of the 0.0 releases
+    pub mod prelude {
+        pub use hal::adc::OneShot as _;
+        pub use hal::digital::v2::*;
+        pub use hal::prelude::*;
+        pub use hal::watchdog::Watchdog as _;
+        pub use hal::watchdog::WatchdogEnable as _;
+
+        pub use super::serial::SerialExt as _;
+        pub use crate::analog::adc::AdcExt as _;
+        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        pub use crate::analog::dac::DacExt as _;
+        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        pub use crate::analog::dac::DacOut as _;
+        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        pub use crate::analog::dac::DacPin as _;
+        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        pub use crate::comparator::ComparatorExt as _;
+        pub use crate::crc::CrcExt as _;
+        pub use crate::delay::DelayExt as _;
+        pub use crate::dma::CopyDma as _;
+        pub use crate::dma::DmaExt as _;
+        pub use crate::dma::ReadDma as _;
+        pub use crate::dma::WriteDma as _;
+        pub use crate::exti::ExtiExt as _;
+        pub use crate::gpio::GpioExt as _;
+        pub use crate::i2c::I2cExt as _;
+        pub use crate::rcc::LSCOExt as _;
+        pub use crate::rcc::MCOExt as _;
+        pub use crate::rcc::RccExt as _;
+        pub use crate::rng::RngCore as _;
+        pub use crate::rng::RngExt as _;
+        pub use crate::rtc::RtcExt as _;
+        pub use crate::spi::SpiExt as _;
+        pub use crate::time::U32Ext as _;
+        pub use crate::timer::opm::OpmExt as _;
+        pub use crate::timer::pwm::PwmExt as _;
+        pub use crate::timer::qei::QeiExt as _;
+        pub use crate::timer::stopwatch::StopwatchExt as _;
+        pub use crate::timer::TimerExt as _;
+        pub use crate::watchdog::IWDGExt as _;
+        pub use crate::watchdog::WWDGExt as _;
+    }
+
+    pub use self::adc::{Adc, AdcExt};
+    pub use self::serial::{Serial, SerialExt};
+    pub use crate::delay::{Delay, DelayExt};
+    pub use crate::i2c::{I2c, I2cExt};
+    pub use crate::spi::{Spi, SpiExt};
+    pub use crate::timer::{Timer, TimerExt};
+}
@@ -1,7 +1,13 @@
 //! # Cyclic redundancy check calculation unit
+//!
+//! The unit calculates CRCs with 7, 8, 16 or 32 bit polynomials. `Config`
+//! holds the parameters of a CRC algorithm, `update()` feeds a byte stream
+//! and `finalize()` returns the CRC. The final XOR is applied in software.
 use crate::rcc::Rcc;
 use crate::stm32::CRC;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum InputReverse {
     None = 0b00,
     Byte = 0b01,
@@ -9,6 +15,8 @@ pub enum InputReverse {
     Word = 0b11,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum PolySize {
     CRC32 = 0b00,
     CRC16 = 0b01,
@@ -16,17 +24,162 @@ pub enum PolySize {
     CRC7 = 0b11,
 }
 
+impl PolySize {
+    fn mask(self) -> u32 {
+        match self {
+            PolySize::CRC32 => 0xffff_ffff,
+            PolySize::CRC16 => 0xffff,
+            PolySize::CRC8 => 0xff,
+            PolySize::CRC7 => 0x7f,
+        }
+    }
+}
+
+/// CRC algorithm parameters
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub size: PolySize,
+    pub poly: u32,
+    pub init: u32,
+    /// Reflect input bytes
+    pub reflect_in: bool,
+    /// Reflect the result
+    pub reflect_out: bool,
+    pub xor_out: u32,
+}
+
+impl Config {
+    /// Non-reflected CRC with `size` and `poly`, zero seed
+    pub fn new(size: PolySize, poly: u32) -> Self {
+        Config {
+            size,
+            poly,
+            init: 0,
+            reflect_in: false,
+            reflect_out: false,
+            xor_out: 0,
+        }
+    }
+
+    /// CRC-32 as used by Ethernet, zlib and PNG
+    pub fn crc32() -> Self {
+        Config::new(PolySize::CRC32, 0x04c1_1db7)
+            .init(0xffff_ffff)
+            .reflect(true, true)
+            .xor_out(0xffff_ffff)
+    }
+
+    /// CRC-32/MPEG-2, the reset configuration of the unit
+    pub fn crc32_mpeg2() -> Self {
+        Config::new(PolySize::CRC32, 0x04c1_1db7).init(0xffff_ffff)
+    }
+
+    /// CRC-16/MODBUS
+    pub fn crc16_modbus() -> Self {
+        Config::new(PolySize::CRC16, 0x8005)
+            .init(0xffff)
+            .reflect(true, true)
+    }
+
+    /// CRC-16/CCITT-FALSE
+    pub fn crc16_ccitt() -> Self {
+        Config::new(PolySize::CRC16, 0x1021).init(0xffff)
+    }
+
+    /// CRC-8/SMBUS
+    pub fn crc8() -> Self {
+        Config::new(PolySize::CRC8, 0x07)
+    }
+
+    pub fn init(mut self, init: u32) -> Self {
+        self.init = init;
+        self
+    }
+
+    pub fn reflect(mut self, reflect_in: bool, reflect_out: bool) -> Self {
+        self.reflect_in = reflect_in;
+        self.reflect_out = reflect_out;
+        self
+    }
+
+    pub fn xor_out(mut self, xor_out: u32) -> Self {
+        self.xor_out = xor_out;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::crc32()
+    }
+}
+
 pub struct Crc {
     rb: CRC,
+    mask: u32,
+    xor_out: u32,
 }
 
 impl Crc {
+    /// Applies `config` and starts a new calculation
+    pub fn configure(&mut self, config: &Config) {
+        let rev_in = if config.reflect_in {
+            InputReverse::Byte
+        } else {
+            InputReverse::None
+        };
+        self.rb.pol.write(|w| unsafe { w.bits(config.poly) });
+        self.rb.init.write(|w| unsafe { w.bits(config.init) });
+        self.rb.cr.write(|w| unsafe {
+            w.polysize()
+                .bits(config.size as u8)
+                .rev_in()
+                .bits(rev_in as u8)
+                .rev_out()
+                .bit(config.reflect_out)
+        });
+        self.mask = config.size.mask();
+        self.xor_out = config.xor_out;
+        self.reset();
+    }
+
+    /// Feeds `data` to the running calculation
+    pub fn update(&mut self, data: &[u8]) {
+        // Words are processed MSB first, so the first byte goes on top
+        let mut words = data.chunks_exact(4);
+        for word in &mut words {
+            let word = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+            self.rb.dr.write(|w| unsafe { w.dr().bits(word) });
+        }
+        for byte in words.remainder() {
+            unsafe {
+                core::ptr::write_volatile(core::ptr::addr_of!(self.rb.dr) as *mut u8, *byte);
+            }
+        }
+    }
+
+    /// Returns the CRC of the data fed since the last reset
+    ///
+    /// The calculation keeps running, more data can be fed afterwards.
+    pub fn finalize(&self) -> u32 {
+        (self.rb.dr.read().bits() ^ self.xor_out) & self.mask
+    }
+
+    /// Calculates the CRC of `data` in one go
+    pub fn checksum(&mut self, data: &[u8]) -> u32 {
+        self.reset();
+        self.update(data);
+        self.finalize()
+    }
+
     pub fn polynomial(&mut self, size: PolySize, poly: u32) {
         self.reset();
         self.rb.pol.modify(|_, w| unsafe { w.bits(poly) });
         self.rb
             .cr
             .modify(|_, w| unsafe { w.polysize().bits(size as u8) });
+        self.mask = size.mask();
     }
 
     pub fn seed(&mut self, value: u32) {
@@ -52,6 +205,17 @@ impl Crc {
     }
 }
 
+/// Hashes a byte stream, `finish()` returns the CRC
+impl core::hash::Hasher for Crc {
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.finalize() as u64
+    }
+}
+
 pub trait Digest<W> {
     fn digest(&mut self, data: W) -> u32;
 }
@@ -74,7 +238,7 @@ impl Digest<&[u16]> for Crc {
     fn digest(&mut self, data: &[u16]) -> u32 {
         data.iter()
             .map(|v| unsafe {
-                core::ptr::write_volatile(&self.rb.dr as *const _ as *mut u16, *v);
+                core::ptr::write_volatile(core::ptr::addr_of!(self.rb.dr) as *mut u16, *v);
             })
             .last();
         self.rb.dr.read().bits()
@@ -91,7 +255,7 @@ impl Digest<&[u8]> for Crc {
         data[words * 4..]
             .iter()
             .map(|v| unsafe {
-                core::ptr::write_volatile(&self.rb.dr as *const _ as *mut u8, *v);
+                core::ptr::write_volatile(core::ptr::addr_of!(self.rb.dr) as *mut u8, *v);
             })
             .last();
         self.rb.dr.read().bits()
@@ -113,6 +277,58 @@ impl CrcExt for CRC {
         rcc.rb.ahbenr.modify(|_, w| w.crcen().set_bit());
         rcc.rb.ahbrstr.modify(|_, w| w.crcrst().set_bit());
         rcc.rb.ahbrstr.modify(|_, w| w.crcrst().clear_bit());
-        Crc { rb: self }
+        Crc {
+            rb: self,
+            mask: PolySize::CRC32.mask(),
+            xor_out: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK: &[u8] = b"123456789";
+
+    /// Bitwise model of the unit, MSB first like the hardware
+    fn model(config: &Config, data: &[u8]) -> u32 {
+        let width = 32 - config.size.mask().leading_zeros();
+        let mask = config.size.mask();
+        let top = 1 << (width - 1);
+        let mut crc = config.init & mask;
+        for byte in data {
+            let byte = if config.reflect_in {
+                byte.reverse_bits()
+            } else {
+                *byte
+            };
+            crc ^= (byte as u32) << (width - 8);
+            for _ in 0..8 {
+                let carry = crc & top != 0;
+                crc = (crc << 1) & mask;
+                if carry {
+                    crc ^= config.poly;
+                }
+            }
+        }
+        if config.reflect_out {
+            crc = crc.reverse_bits() >> (32 - width);
+        }
+        (crc ^ config.xor_out) & mask
+    }
+
+    #[test]
+    fn preset_check_values() {
+        assert_eq!(model(&Config::crc32(), CHECK), 0xcbf4_3926);
+        assert_eq!(model(&Config::crc32_mpeg2(), CHECK), 0x0376_e6e7);
+        assert_eq!(model(&Config::crc16_modbus(), CHECK), 0x4b37);
+        assert_eq!(model(&Config::crc16_ccitt(), CHECK), 0x29b1);
+        assert_eq!(model(&Config::crc8(), CHECK), 0xf4);
+    }
+
+    #[test]
+    fn default_is_crc32() {
+        assert_eq!(Config::default(), Config::crc32());
     }
 }
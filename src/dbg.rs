@@ -0,0 +1,182 @@
+//! # Debug support
+//!
+//! Keeps the debug connection alive in Stop and Standby modes, and freezes
+//! timers and watchdogs while the core is halted by a debugger, so stepping
+//! through code doesn't trigger a watchdog reset.
+use crate::rcc::{Enable, Rcc};
+use crate::stm32::DBG;
+
+// CR
+const DBG_STOP: u32 = 1 << 1;
+const DBG_STANDBY: u32 = 1 << 2;
+
+/// Peripheral stopped while the core is halted
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Freeze {
+    Tim1,
+    Tim2,
+    Tim3,
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    Tim6,
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    Tim7,
+    Tim14,
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    Tim15,
+    Tim16,
+    Tim17,
+    Rtc,
+    Wwdg,
+    Iwdg,
+    /// SMBus timeout of I2C1
+    I2c1,
+    #[cfg(any(
+        feature = "stm32g031",
+        feature = "stm32g041",
+        feature = "stm32g07x",
+        feature = "stm32g081"
+    ))]
+    Lptim1,
+    #[cfg(any(
+        feature = "stm32g031",
+        feature = "stm32g041",
+        feature = "stm32g07x",
+        feature = "stm32g081"
+    ))]
+    Lptim2,
+}
+
+impl Freeze {
+    /// Freeze register, `false` for APB_FZ1, and bit
+    fn bit(self) -> (bool, u32) {
+        match self {
+            Freeze::Tim2 => (false, 0),
+            Freeze::Tim3 => (false, 1),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            Freeze::Tim6 => (false, 4),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            Freeze::Tim7 => (false, 5),
+            Freeze::Rtc => (false, 10),
+            Freeze::Wwdg => (false, 11),
+            Freeze::Iwdg => (false, 12),
+            Freeze::I2c1 => (false, 21),
+            #[cfg(any(
+                feature = "stm32g031",
+                feature = "stm32g041",
+                feature = "stm32g07x",
+                feature = "stm32g081"
+            ))]
+            Freeze::Lptim2 => (false, 30),
+            #[cfg(any(
+                feature = "stm32g031",
+                feature = "stm32g041",
+                feature = "stm32g07x",
+                feature = "stm32g081"
+            ))]
+            Freeze::Lptim1 => (false, 31),
+            Freeze::Tim1 => (true, 11),
+            Freeze::Tim14 => (true, 15),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            Freeze::Tim15 => (true, 16),
+            Freeze::Tim16 => (true, 17),
+            Freeze::Tim17 => (true, 18),
+        }
+    }
+}
+
+/// Debug support
+pub struct Dbg {
+    rb: DBG,
+}
+
+impl Dbg {
+    pub fn new(dbg: DBG, rcc: &mut Rcc) -> Self {
+        DBG::enable(rcc);
+        Dbg { rb: dbg }
+    }
+
+    /// Keeps the core clock running for the debugger in Stop mode
+    pub fn set_stop_debug(&mut self, enable: bool) {
+        self.set_cr(DBG_STOP, enable);
+    }
+
+    /// Keeps the debug connection in Standby mode, at the cost of a higher
+    /// Standby current
+    pub fn set_standby_debug(&mut self, enable: bool) {
+        self.set_cr(DBG_STANDBY, enable);
+    }
+
+    /// Stops `periph` while the core is halted
+    pub fn freeze(&mut self, periph: Freeze, freeze: bool) {
+        set_freeze(&self.rb, periph, freeze);
+    }
+
+    /// Returns `true` if `periph` is stopped while the core is halted
+    pub fn is_frozen(&self, periph: Freeze) -> bool {
+        let (apb2, bit) = periph.bit();
+        let bits = if apb2 {
+            self.rb.apb_fz2.read().bits()
+        } else {
+            self.rb.apb_fz1.read().bits()
+        };
+        bits & 1 << bit != 0
+    }
+
+    /// Device identifier (DEV_ID)
+    pub fn device_id(&self) -> u16 {
+        (self.rb.idcode.read().bits() & 0xfff) as u16
+    }
+
+    /// Silicon revision (REV_ID)
+    pub fn revision_id(&self) -> u16 {
+        (self.rb.idcode.read().bits() >> 16) as u16
+    }
+
+    pub fn release(self) -> DBG {
+        self.rb
+    }
+
+    fn set_cr(&mut self, mask: u32, enable: bool) {
+        self.rb.cr.modify(|r, w| unsafe {
+            w.bits(if enable {
+                r.bits() | mask
+            } else {
+                r.bits() & !mask
+            })
+        });
+    }
+}
+
+/// Stops `periph` while the core is halted, for drivers not owning the `Dbg`
+pub(crate) fn freeze_on_debug(periph: Freeze, rcc: &mut Rcc) {
+    DBG::enable(rcc);
+    let dbg = unsafe { &(*DBG::ptr()) };
+    set_freeze(dbg, periph, true);
+}
+
+fn set_freeze(dbg: &crate::stm32::dbg::RegisterBlock, periph: Freeze, freeze: bool) {
+    let (apb2, bit) = periph.bit();
+    let set = |bits: u32| {
+        if freeze {
+            bits | 1 << bit
+        } else {
+            bits & !(1 << bit)
+        }
+    };
+    if apb2 {
+        dbg.apb_fz2.modify(|r, w| unsafe { w.bits(set(r.bits())) });
+    } else {
+        dbg.apb_fz1.modify(|r, w| unsafe { w.bits(set(r.bits())) });
+    }
+}
+
+pub trait DbgExt {
+    fn constrain(self, rcc: &mut Rcc) -> Dbg;
+}
+
+impl DbgExt for DBG {
+    fn constrain(self, rcc: &mut Rcc) -> Dbg {
+        Dbg::new(self, rcc)
+    }
+}
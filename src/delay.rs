@@ -1,5 +1,6 @@
 //! Delays
 use core::cmp;
+use cortex_m::peripheral::syst::SystClkSource;
 use cortex_m::peripheral::SYST;
 use hal::blocking::delay::{DelayMs, DelayUs};
 
@@ -7,6 +8,7 @@ use crate::prelude::*;
 use crate::rcc::Rcc;
 use crate::stm32::*;
 use crate::time::{Hertz, MicroSecond};
+use crate::timer::Timer;
 
 /// Delay provider
 pub struct Delay<TIM> {
@@ -21,9 +23,21 @@ pub trait DelayExt<TIM> {
 impl Delay<SYST> {
     /// Configures the system timer (SysTick) as a delay provider
     pub fn syst(syst: SYST, rcc: &Rcc) -> Self {
+        Delay::syst_with_clock(syst, rcc.clocks.core_clk)
+    }
+
+    /// Configures SysTick as a delay provider clocked at `clk`
+    ///
+    /// For use when the `Rcc` is not at hand, e.g. inside a driver. SysTick
+    /// runs from HCLK / 8, `clk` is `rcc.clocks.core_clk`.
+    pub fn syst_with_clock<T>(mut syst: SYST, clk: T) -> Self
+    where
+        T: Into<Hertz>,
+    {
+        syst.set_clock_source(SystClkSource::External);
         Delay {
             tim: syst,
-            clk: rcc.clocks.core_clk,
+            clk: clk.into(),
         }
     }
 
@@ -31,21 +45,33 @@ impl Delay<SYST> {
     where
         T: Into<MicroSecond>,
     {
-        let mut cycles = delay.into().cycles(self.clk);
+        let cycles = self.clk.0 as u64 * delay.into().0 as u64 / 1_000_000;
+        self.delay_cycles(cycles);
+    }
+
+    /// Releases the system timer (SysTick) resource
+    pub fn release(self) -> SYST {
+        self.tim
+    }
+
+    fn delay_cycles(&mut self, mut cycles: u64) {
         while cycles > 0 {
             let reload = cmp::min(cycles, 0x00ff_ffff);
             cycles -= reload;
-            self.tim.set_reload(reload);
+            self.tim.set_reload(reload as u32);
             self.tim.clear_current();
             self.tim.enable_counter();
             while !self.tim.has_wrapped() {}
             self.tim.disable_counter();
         }
     }
+}
 
-    /// Releases the system timer (SysTick) resource
-    pub fn release(self) -> SYST {
-        self.tim
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::delay::DelayNs for Delay<SYST> {
+    fn delay_ns(&mut self, ns: u32) {
+        let cycles = (self.clk.0 as u64 * ns as u64).div_ceil(1_000_000_000);
+        self.delay_cycles(cycles);
     }
 }
 
@@ -92,7 +118,7 @@ impl DelayExt<SYST> for SYST {
 }
 
 macro_rules! delays {
-    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $max_arr:expr),)+) => {
         $(
             impl Delay<$TIM> {
                 /// Configures $TIM timer as a delay provider
@@ -110,11 +136,27 @@ macro_rules! delays {
                 where
                     T: Into<MicroSecond>,
                 {
-                    let mut cycles = delay.into().cycles(self.clk);
+                    let cycles = self.clk.0 as u64 * delay.into().0 as u64 / 1_000_000;
+                    self.delay_cycles(cycles);
+                }
+
+                pub fn release(self) -> $TIM {
+                    self.tim
+                }
+
+                /// Turns the delay provider back into a count down timer
+                pub fn timer(self) -> Timer<$TIM> {
+                    Timer {
+                        tim: self.tim,
+                        clk: self.clk,
+                    }
+                }
+
+                fn delay_cycles(&mut self, mut cycles: u64) {
                     while cycles > 0 {
-                        let reload = cmp::min(cycles, 0xffff);
+                        let reload = cmp::min(cycles, $max_arr);
                         cycles -= reload;
-                        self.tim.arr.write(|w| unsafe { w.bits(reload) });
+                        self.tim.arr.write(|w| unsafe { w.bits(reload as u32) });
                         self.tim.cnt.reset();
                         self.tim.cr1.modify(|_, w| w.cen().set_bit().urs().set_bit());
                         while self.tim.sr.read().uif().bit_is_clear() {}
@@ -122,9 +164,13 @@ macro_rules! delays {
                         self.tim.cr1.modify(|_, w| w.cen().clear_bit());
                     }
                 }
+            }
 
-                pub fn release(self) -> $TIM {
-                    self.tim
+            #[cfg(feature = "embedded-hal-1")]
+            impl embedded_hal_1::delay::DelayNs for Delay<$TIM> {
+                fn delay_ns(&mut self, ns: u32) {
+                    let cycles = (self.clk.0 as u64 * ns as u64).div_ceil(1_000_000_000);
+                    self.delay_cycles(cycles);
                 }
             }
 
@@ -164,6 +210,21 @@ macro_rules! delays {
                 }
             }
 
+            impl Timer<$TIM> {
+                /// Turns the timer into a blocking delay provider
+                ///
+                /// Leaves SysTick free, e.g. for the RTIC scheduler.
+                pub fn delay(self) -> Delay<$TIM> {
+                    let tim = self.tim;
+                    tim.cr1.modify(|_, w| w.cen().clear_bit().urs().set_bit());
+                    tim.dier.modify(|_, w| w.uie().clear_bit());
+                    tim.psc.reset();
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.sr.modify(|_, w| w.uif().clear_bit());
+                    Delay { tim, clk: self.clk }
+                }
+            }
+
             impl DelayExt<$TIM> for $TIM {
                 fn delay(self, rcc: &mut Rcc) -> Delay<$TIM> {
                     Delay::$tim(self, rcc)
@@ -174,17 +235,17 @@ macro_rules! delays {
 }
 
 delays! {
-    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2),
-    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1),
-    TIM3: (tim3, tim3en, tim3rst, apbenr1, apbrstr1),
-    TIM14: (tim14, tim14en, tim14rst, apbenr2, apbrstr2),
-    TIM16: (tim16, tim16en, tim16rst, apbenr2, apbrstr2),
-    TIM17: (tim17, tim17en, tim17rst, apbenr2, apbrstr2),
+    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2, 0xffff),
+    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1, 0xffff_ffff),
+    TIM3: (tim3, tim3en, tim3rst, apbenr1, apbrstr1, 0xffff),
+    TIM14: (tim14, tim14en, tim14rst, apbenr2, apbrstr2, 0xffff),
+    TIM16: (tim16, tim16en, tim16rst, apbenr2, apbrstr2, 0xffff),
+    TIM17: (tim17, tim17en, tim17rst, apbenr2, apbrstr2, 0xffff),
 }
 
 #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 delays! {
-    TIM6: (tim6, tim6en, tim6rst, apbenr1, apbrstr1),
-    TIM7: (tim7, tim7en, tim7rst, apbenr1, apbrstr1),
-    TIM15: (tim15, tim15en, tim15rst, apbenr2, apbrstr2),
+    TIM6: (tim6, tim6en, tim6rst, apbenr1, apbrstr1, 0xffff),
+    TIM7: (tim7, tim7en, tim7rst, apbenr1, apbrstr1, 0xffff),
+    TIM15: (tim15, tim15en, tim15rst, apbenr2, apbrstr2, 0xffff),
 }
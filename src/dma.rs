@@ -1,30 +1,116 @@
 //! Direct Memory Access Engine
 use crate::rcc::Rcc;
-use crate::stm32::DMA;
+use crate::stm32::{Interrupt, DMA, DMAMUX};
 use as_slice::{AsMutSlice, AsSlice};
 use core::ops;
 use core::pin::Pin;
 use core::sync::atomic::{self, Ordering};
 
+pub mod arbiter;
+pub mod descriptor;
+pub mod double_buffer;
+
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     Overrun,
     BufferError,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Event {
     HalfTransfer,
     TransferComplete,
+    TransferError,
+}
+
+/// Channel flags in ISR and IFCR
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChannelEvent {
+    /// Set with any of the other flags, clearing it clears all of them
+    Global = 0b0001,
+    TransferComplete = 0b0010,
+    HalfTransfer = 0b0100,
+    TransferError = 0b1000,
+}
+
+impl From<Event> for ChannelEvent {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::HalfTransfer => ChannelEvent::HalfTransfer,
+            Event::TransferComplete => ChannelEvent::TransferComplete,
+            Event::TransferError => ChannelEvent::TransferError,
+        }
+    }
+}
+
+/// Number of channels
+#[cfg(any(
+    feature = "stm32g030",
+    feature = "stm32g031",
+    feature = "stm32g041"
+))]
+pub const CHANNELS: u8 = 5;
+/// Number of channels
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pub const CHANNELS: u8 = 7;
+
+/// Snapshot of the ISR flags of all channels
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status(u32);
+
+impl Status {
+    /// Reads ISR
+    pub fn read() -> Self {
+        let dma = unsafe { &(*DMA::ptr()) };
+        Status(dma.isr.read().bits())
+    }
+
+    /// Returns `true` if `event` is flagged for channel `index` (0 - Channel1)
+    pub fn is_set(self, index: u8, event: ChannelEvent) -> bool {
+        (self.0 >> (index * 4)) & event as u32 != 0
+    }
+
+    /// Channels with any flag set, bit 0 - Channel1
+    pub fn pending_channels(self) -> u8 {
+        (0..CHANNELS)
+            .filter(|index| self.is_set(*index, ChannelEvent::Global))
+            .fold(0, |pending, index| pending | 1 << index)
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+/// Clears the `event` flag of channel `index` in IFCR
+pub fn clear(index: u8, event: ChannelEvent) {
+    let dma = unsafe { &(*DMA::ptr()) };
+    dma.ifcr
+        .write(|w| unsafe { w.bits((event as u32) << (index * 4)) });
+}
+
+/// NVIC interrupt line of channel `index`, shared by channels 2-3 and 4-7
+pub fn interrupt(index: u8) -> Interrupt {
+    match index {
+        0 => Interrupt::DMA_CHANNEL1,
+        1 | 2 => Interrupt::DMA_CHANNEL2_3,
+        _ => Interrupt::DMA_CHANNEL4_5_6_7,
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Half {
     First,
     Second,
 }
 
 #[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum TransferDirection {
     MemoryToMemory,
     MemoryToPeriph,
@@ -32,6 +118,7 @@ pub enum TransferDirection {
 }
 
 #[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Priority {
     Low = 0b00,
     Medium = 0b01,
@@ -39,6 +126,80 @@ pub enum Priority {
     VeryHigh = 0b11,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WordSize {
+    Bits8 = 0b00,
+    Bits16 = 0b01,
+    Bits32 = 0b10,
+}
+
+/// DMAMUX request line
+#[derive(Clone, Copy, PartialEq)]
+pub enum DmaMuxIndex {
+    DmaMuxReqGen0 = 1,
+    DmaMuxReqGen1 = 2,
+    DmaMuxReqGen2 = 3,
+    DmaMuxReqGen3 = 4,
+    ADC = 5,
+    AES_IN = 6,
+    AES_OUT = 7,
+    DAC_CH1 = 8,
+    DAC_CH2 = 9,
+    I2C1_RX = 10,
+    I2C1_TX = 11,
+    I2C2_RX = 12,
+    I2C2_TX = 13,
+    LPUART_RX = 14,
+    LPUART_TX = 15,
+    SPI1_RX = 16,
+    SPI1_TX = 17,
+    SPI2_RX = 18,
+    SPI2_TX = 19,
+    TIM1_CH1 = 20,
+    TIM1_CH2 = 21,
+    TIM1_CH3 = 22,
+    TIM1_CH4 = 23,
+    TIM1_TRIG_COM = 24,
+    TIM1_UP = 25,
+    TIM2_CH1 = 26,
+    TIM2_CH2 = 27,
+    TIM2_CH3 = 28,
+    TIM2_CH4 = 29,
+    TIM2_TRIG = 30,
+    TIM2_UP = 31,
+    TIM3_CH1 = 32,
+    TIM3_CH2 = 33,
+    TIM3_CH3 = 34,
+    TIM3_CH4 = 35,
+    TIM3_TRIG = 36,
+    TIM3_UP = 37,
+    TIM6_UP = 38,
+    TIM7_UP = 39,
+    TIM15_CH1 = 40,
+    TIM15_CH2 = 41,
+    TIM15_TRIG_COM = 42,
+    TIM15_UP = 43,
+    TIM16_CH1 = 44,
+    TIM16_COM = 45,
+    TIM16_UP = 46,
+    TIM17_CH1 = 47,
+    TIM17_COM = 48,
+    TIM17_UP = 49,
+    USART1_RX = 50,
+    USART1_TX = 51,
+    USART2_RX = 52,
+    USART2_TX = 53,
+    USART3_RX = 54,
+    USART3_TX = 55,
+    USART4_RX = 56,
+    USART4_TX = 57,
+    UCPD1_RX = 58,
+    UCPD1_TX = 59,
+    UCPD2_RX = 60,
+    UCPD2_TX = 61,
+}
+
 pub struct Transfer<CHANNEL, BUFFER> {
     pub channel: CHANNEL,
     pub buffer: BUFFER,
@@ -94,15 +255,38 @@ pub trait DmaChannel {
     fn set_transfer_length(&mut self, len: usize);
     fn set_direction(&mut self, dir: TransferDirection);
     fn set_priority(&mut self, priority: Priority);
+
+    fn set_word_size(&mut self, size: WordSize);
+    fn set_circular_mode(&mut self, circular: bool);
+    fn select_peripheral(&mut self, index: DmaMuxIndex);
+    fn get_transfer_remaining(&self) -> u16;
+    fn clear_interrupts(&mut self);
+
     fn start(&mut self);
     fn stop(&mut self);
     fn listen(&mut self, event: Event);
     fn unlisten(&mut self, event: Event);
 }
 
+/// Flags and interrupt line of a channel of the DMA controller
+pub trait DmaChannelExt: DmaChannel {
+    fn is_pending(&self, event: ChannelEvent) -> bool;
+    fn clear_event(&mut self, event: ChannelEvent);
+    fn interrupt(&self) -> Interrupt;
+}
+
+/// Sets up a memory-to-memory copy, CMAR is the source and CPAR the destination
+fn configure_copy<C: DmaChannel>(channel: &mut C, from: u32, to: u32, len: usize) {
+    channel.set_direction(TransferDirection::MemoryToMemory);
+    channel.set_memory_address(from, true);
+    channel.set_peripheral_address(to, true);
+    channel.set_transfer_length(len);
+}
+
 macro_rules! dma {
     ($($DMAX:ident: ($dmaXen:ident, $dmaXrst:ident, {
-        $($CX:ident: ($ccrX:ident, $cndtrX:ident, $cparX:ident, $cmarX:ident, $cgifX:ident),)+
+        $($CX:ident: ($ccrX:ident, $cndtrX:ident, $cparX:ident, $cmarX:ident, $cgifX:ident,
+            $cXcr:ident, $index:expr),)+
     }),)+) => {
         $(
             impl DmaExt for $DMAX {
@@ -119,9 +303,29 @@ macro_rules! dma {
 
             pub struct Channels((), $(pub $CX),+);
 
+            /// Restores the CCR and DMAMUX CxCR reset values of channel `index`
+            pub(crate) fn reset(index: u8) {
+                let dma = unsafe { &(*$DMAX::ptr()) };
+                let dmamux = unsafe { &(*DMAMUX::ptr()) };
+                match index {
+                    $(
+                        $index => {
+                            dma.$ccrX.reset();
+                            dmamux.$cXcr.reset();
+                        }
+                    )+
+                    _ => unreachable!(),
+                }
+            }
+
             $(
                 pub struct $CX;
 
+                impl $CX {
+                    /// Index of the channel (0 - Channel1)
+                    pub const INDEX: u8 = $index;
+                }
+
                 impl DmaChannel for $CX {
                     /// Associated peripheral `address`
                     ///
@@ -151,8 +355,9 @@ macro_rules! dma {
                     fn set_direction(&mut self, dir: TransferDirection) {
                         let dma = unsafe { &(*$DMAX::ptr()) };
                         match dir {
+                            // Memory (CMAR) is the source, peripheral (CPAR) the destination
                             TransferDirection::MemoryToMemory => dma.$ccrX.modify(|_, w| {
-                                w.mem2mem().set_bit().circ().clear_bit()
+                                w.mem2mem().set_bit().circ().clear_bit().dir().set_bit()
                             }),
                             TransferDirection::MemoryToPeriph => dma.$ccrX.modify(|_, w| {
                                 w.mem2mem().clear_bit().circ().clear_bit().dir().set_bit()
@@ -169,6 +374,42 @@ macro_rules! dma {
                         dma.$ccrX.modify(|_, w| unsafe { w.pl().bits(priority as u8) });
                     }
 
+                    /// Set peripheral and memory word size
+                    fn set_word_size(&mut self, size: WordSize) {
+                        let dma = unsafe { &(*$DMAX::ptr()) };
+                        dma.$ccrX.modify(|_, w| unsafe {
+                            w.psize().bits(size as u8).msize().bits(size as u8)
+                        });
+                    }
+
+                    /// Restart transfer from the beginning once completed
+                    ///
+                    /// Must be set after the transfer direction
+                    fn set_circular_mode(&mut self, circular: bool) {
+                        let dma = unsafe { &(*$DMAX::ptr()) };
+                        dma.$ccrX.modify(|_, w| w.circ().bit(circular));
+                    }
+
+                    /// Route DMAMUX request line to the channel
+                    fn select_peripheral(&mut self, index: DmaMuxIndex) {
+                        let dmamux = unsafe { &(*DMAMUX::ptr()) };
+                        dmamux.$cXcr.modify(|r, w| unsafe {
+                            w.bits((r.bits() & !0x3f) | index as u32)
+                        });
+                    }
+
+                    /// Number of data items left to transfer
+                    fn get_transfer_remaining(&self) -> u16 {
+                        let dma = unsafe { &(*$DMAX::ptr()) };
+                        dma.$cndtrX.read().ndt().bits()
+                    }
+
+                    /// Clears all channel interrupt flags
+                    fn clear_interrupts(&mut self) {
+                        let dma = unsafe { &(*$DMAX::ptr()) };
+                        dma.ifcr.write(|w| w.$cgifX().set_bit());
+                    }
+
                     /// Starts the DMA transfer
                     fn start(&mut self) {
                         let dma = unsafe { &(*$DMAX::ptr()) };
@@ -189,6 +430,7 @@ macro_rules! dma {
                             Event::TransferComplete => {
                                 dma.$ccrX.modify(|_, w| w.tcie().set_bit())
                             }
+                            Event::TransferError => dma.$ccrX.modify(|_, w| w.teie().set_bit()),
                         }
                     }
 
@@ -201,10 +443,30 @@ macro_rules! dma {
                             Event::TransferComplete => {
                                 dma.$ccrX.modify(|_, w| w.tcie().clear_bit())
                             }
+                            Event::TransferError => {
+                                dma.$ccrX.modify(|_, w| w.teie().clear_bit())
+                            }
                         }
                     }
                 }
 
+                impl DmaChannelExt for $CX {
+                    /// Returns `true` if the `event` flag is set in ISR
+                    fn is_pending(&self, event: ChannelEvent) -> bool {
+                        Status::read().is_set($index, event)
+                    }
+
+                    /// Clears the `event` flag in IFCR
+                    fn clear_event(&mut self, event: ChannelEvent) {
+                        clear($index, event)
+                    }
+
+                    /// NVIC interrupt line, shared with other channels except Channel1
+                    fn interrupt(&self) -> Interrupt {
+                        interrupt($index)
+                    }
+                }
+
                 impl<F, T> CopyDma<F, T> for $CX
                 where
                     F: ops::Deref + 'static,
@@ -220,10 +482,7 @@ macro_rules! dma {
                         let (ptr_to, len_to) = (slice_to.as_ptr(), slice_to.len());
                         assert!(len_from == len_to);
 
-                        self.set_direction(TransferDirection::MemoryToMemory);
-                        self.set_memory_address(ptr_from as u32, true);
-                        self.set_peripheral_address(ptr_to as u32, false);
-                        self.set_transfer_length(len_from);
+                        configure_copy(&mut self, ptr_from as u32, ptr_to as u32, len_from);
 
                         atomic::compiler_fence(Ordering::SeqCst);
                         self.start();
@@ -239,12 +498,106 @@ macro_rules! dma {
     }
 }
 
+#[cfg(any(
+    feature = "stm32g030",
+    feature = "stm32g031",
+    feature = "stm32g041"
+))]
+dma! {
+    DMA: (dmaen, dmarst, {
+        Channel1: ( ccr1, cndtr1, cpar1, cmar1, cgif0, c0cr, 0 ),
+        Channel2: ( ccr2, cndtr2, cpar2, cmar2, cgif4, c1cr, 1 ),
+        Channel3: ( ccr3, cndtr3, cpar3, cmar3, cgif8, c2cr, 2 ),
+        Channel4: ( ccr4, cndtr4, cpar4, cmar4, cgif12, c3cr, 3 ),
+        Channel5: ( ccr5, cndtr5, cpar5, cmar5, cgif16, c4cr, 4 ),
+    }),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 dma! {
-    DMA: (dmaen, dma1rst, {
-        Channel1: ( ccr1, cndtr1, cpar1, cmar1, cgif0 ),
-        Channel2: ( ccr2, cndtr2, cpar2, cmar2, cgif4 ),
-        Channel3: ( ccr3, cndtr3, cpar3, cmar3, cgif8 ),
-        Channel4: ( ccr4, cndtr4, cpar4, cmar4, cgif12 ),
-        Channel5: ( ccr5, cndtr5, cpar5, cmar5, cgif16 ),
+    DMA: (dmaen, dmarst, {
+        Channel1: ( ccr1, cndtr1, cpar1, cmar1, cgif0, dmamux_c0cr, 0 ),
+        Channel2: ( ccr2, cndtr2, cpar2, cmar2, cgif4, dmamux_c1cr, 1 ),
+        Channel3: ( ccr3, cndtr3, cpar3, cmar3, cgif8, dmamux_c2cr, 2 ),
+        Channel4: ( ccr4, cndtr4, cpar4, cmar4, cgif12, dmamux_c3cr, 3 ),
+        Channel5: ( ccr5, cndtr5, cpar5, cmar5, cgif16, dmamux_c4cr, 4 ),
+        Channel6: ( ccr6, cndtr6, cpar6, cmar6, cgif20, dmamux_c5cr, 5 ),
+        Channel7: ( ccr7, cndtr7, cpar7, cmar7, cgif24, dmamux_c6cr, 6 ),
     }),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_flags() {
+        // Channel1 half transfer, Channel3 transfer complete and error
+        let status = Status(0b0101 | 0b1011 << 8);
+        assert!(status.is_set(0, ChannelEvent::Global));
+        assert!(status.is_set(0, ChannelEvent::HalfTransfer));
+        assert!(!status.is_set(0, ChannelEvent::TransferComplete));
+        assert!(!status.is_set(1, ChannelEvent::Global));
+        assert!(status.is_set(2, ChannelEvent::TransferComplete));
+        assert!(status.is_set(2, ChannelEvent::TransferError));
+        assert_eq!(status.pending_channels(), 0b101);
+    }
+
+    #[derive(Default)]
+    struct MockChannel {
+        peripheral: Option<(u32, bool)>,
+        memory: Option<(u32, bool)>,
+        len: usize,
+        mem2mem: bool,
+    }
+
+    impl DmaChannel for MockChannel {
+        fn set_peripheral_address(&mut self, address: u32, inc: bool) {
+            self.peripheral = Some((address, inc));
+        }
+        fn set_memory_address(&mut self, address: u32, inc: bool) {
+            self.memory = Some((address, inc));
+        }
+        fn set_transfer_length(&mut self, len: usize) {
+            self.len = len;
+        }
+        fn set_direction(&mut self, dir: TransferDirection) {
+            self.mem2mem = matches!(dir, TransferDirection::MemoryToMemory);
+        }
+        fn set_priority(&mut self, _priority: Priority) {}
+        fn set_word_size(&mut self, _size: WordSize) {}
+        fn set_circular_mode(&mut self, _circular: bool) {}
+        fn select_peripheral(&mut self, _index: DmaMuxIndex) {}
+        fn get_transfer_remaining(&self) -> u16 {
+            0
+        }
+        fn clear_interrupts(&mut self) {}
+        fn start(&mut self) {}
+        fn stop(&mut self) {}
+        fn listen(&mut self, _event: Event) {}
+        fn unlisten(&mut self, _event: Event) {}
+    }
+
+    #[test]
+    fn copy_reads_memory_address() {
+        let mut channel = MockChannel::default();
+        configure_copy(&mut channel, 0x2000_0000, 0x2000_0100, 16);
+        assert!(channel.mem2mem);
+        // DIR set: CMAR is read, CPAR written, both buffers are walked
+        assert_eq!(channel.memory, Some((0x2000_0000, true)));
+        assert_eq!(channel.peripheral, Some((0x2000_0100, true)));
+        assert_eq!(channel.len, 16);
+    }
+
+    #[test]
+    fn event_flags() {
+        assert_eq!(
+            ChannelEvent::from(Event::TransferComplete),
+            ChannelEvent::TransferComplete
+        );
+        assert_eq!(
+            ChannelEvent::from(Event::HalfTransfer),
+            ChannelEvent::HalfTransfer
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Cooperative DMA channel allocator
+//!
+//! `DmaArbiter` owns all DMA channels and lends them to drivers at runtime, so
+//! more peripherals than channels can share the DMA by time-multiplexing it.
+//! Requests carry a priority hint: while a higher priority request is waiting,
+//! lower priority requests are not granted a channel.
+use core::cell::Cell;
+
+use cortex_m::interrupt::{self, Mutex};
+use void::Void;
+
+use crate::dma::*;
+use crate::stm32::Interrupt;
+
+/// DMA channel arbiter
+pub struct DmaArbiter {
+    busy: Mutex<Cell<u8>>,
+    waiting: Mutex<Cell<[u8; 4]>>,
+    channels: Channels,
+}
+
+/// Pending channel request
+pub struct Request<'a> {
+    arbiter: &'a DmaArbiter,
+    priority: Priority,
+    pending: bool,
+}
+
+/// Channel granted by the arbiter, returned back on drop
+pub struct Grant<'a> {
+    arbiter: &'a DmaArbiter,
+    index: u8,
+}
+
+impl DmaArbiter {
+    /// Takes ownership of all DMA channels
+    pub fn new(channels: Channels) -> Self {
+        DmaArbiter {
+            busy: Mutex::new(Cell::new(0)),
+            waiting: Mutex::new(Cell::new([0; 4])),
+            channels,
+        }
+    }
+
+    /// Registers a channel request with the given priority hint
+    pub fn request(&self, priority: Priority) -> Request<'_> {
+        interrupt::free(|cs| {
+            let waiting = self.waiting.borrow(cs);
+            let mut counters = waiting.get();
+            counters[priority as usize] += 1;
+            waiting.set(counters);
+        });
+        Request {
+            arbiter: self,
+            priority,
+            pending: true,
+        }
+    }
+
+    /// Tries to grab a channel without queuing
+    pub fn try_acquire(&self, priority: Priority) -> Option<Grant<'_>> {
+        self.request(priority).poll().ok()
+    }
+
+    /// Waits for a free channel
+    pub fn acquire(&self, priority: Priority) -> Grant<'_> {
+        let mut request = self.request(priority);
+        loop {
+            match request.poll() {
+                Ok(grant) => return grant,
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(void)) => match void {},
+            }
+        }
+    }
+
+    /// Number of channels not granted to any driver
+    pub fn free_channels(&self) -> u8 {
+        let busy = interrupt::free(|cs| self.busy.borrow(cs).get());
+        CHANNELS - busy.count_ones() as u8
+    }
+
+    /// Releases channels back to the caller
+    ///
+    /// Grants borrow the arbiter, so none is outstanding here.
+    pub fn release(self) -> Channels {
+        self.channels
+    }
+}
+
+impl<'a> Request<'a> {
+    /// Polls for a free channel
+    ///
+    /// Returns `WouldBlock` if all channels are granted or a higher priority
+    /// request is waiting.
+    pub fn poll(&mut self) -> nb::Result<Grant<'a>, Void> {
+        assert!(self.pending, "request was already granted");
+        let arbiter = self.arbiter;
+        let priority = self.priority;
+        let granted = interrupt::free(|cs| {
+            let waiting = arbiter.waiting.borrow(cs);
+            let mut counters = waiting.get();
+            if counters[priority as usize + 1..].iter().any(|w| *w > 0) {
+                return None;
+            }
+            let busy = arbiter.busy.borrow(cs);
+            let index = (0..CHANNELS).find(|idx| busy.get() & (1 << *idx) == 0)?;
+            busy.set(busy.get() | 1 << index);
+            counters[priority as usize] -= 1;
+            waiting.set(counters);
+            Some(index)
+        });
+        match granted {
+            Some(index) => {
+                self.pending = false;
+                let mut grant = Grant { arbiter, index };
+                grant.set_priority(priority);
+                Ok(grant)
+            }
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl<'a> Drop for Request<'a> {
+    fn drop(&mut self) {
+        if !self.pending {
+            return;
+        }
+        interrupt::free(|cs| {
+            let waiting = self.arbiter.waiting.borrow(cs);
+            let mut counters = waiting.get();
+            counters[self.priority as usize] -= 1;
+            waiting.set(counters);
+        });
+    }
+}
+
+impl<'a> Grant<'a> {
+    /// Index of the granted channel (0 - Channel1)
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    fn with_channel<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut dyn DmaChannel) -> R,
+    {
+        match self.index {
+            0 => f(&mut Channel1),
+            1 => f(&mut Channel2),
+            2 => f(&mut Channel3),
+            3 => f(&mut Channel4),
+            4 => f(&mut Channel5),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            5 => f(&mut Channel6),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            6 => f(&mut Channel7),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<'a> Drop for Grant<'a> {
+    fn drop(&mut self) {
+        // Leaves no request line, interrupt or mode set for the next grant
+        self.with_channel(|ch| ch.stop());
+        crate::dma::reset(self.index);
+        let index = self.index;
+        interrupt::free(|cs| {
+            let busy = self.arbiter.busy.borrow(cs);
+            busy.set(busy.get() & !(1 << index));
+        });
+    }
+}
+
+impl<'a> DmaChannel for Grant<'a> {
+    fn set_peripheral_address(&mut self, address: u32, inc: bool) {
+        self.with_channel(|ch| ch.set_peripheral_address(address, inc))
+    }
+
+    fn set_memory_address(&mut self, address: u32, inc: bool) {
+        self.with_channel(|ch| ch.set_memory_address(address, inc))
+    }
+
+    fn set_transfer_length(&mut self, len: usize) {
+        self.with_channel(|ch| ch.set_transfer_length(len))
+    }
+
+    fn set_direction(&mut self, dir: TransferDirection) {
+        self.with_channel(|ch| ch.set_direction(dir))
+    }
+
+    fn set_priority(&mut self, priority: Priority) {
+        self.with_channel(|ch| ch.set_priority(priority))
+    }
+
+    fn set_word_size(&mut self, size: WordSize) {
+        self.with_channel(|ch| ch.set_word_size(size))
+    }
+
+    fn set_circular_mode(&mut self, circular: bool) {
+        self.with_channel(|ch| ch.set_circular_mode(circular))
+    }
+
+    fn select_peripheral(&mut self, index: DmaMuxIndex) {
+        self.with_channel(|ch| ch.select_peripheral(index))
+    }
+
+    fn get_transfer_remaining(&self) -> u16 {
+        match self.index {
+            0 => Channel1.get_transfer_remaining(),
+            1 => Channel2.get_transfer_remaining(),
+            2 => Channel3.get_transfer_remaining(),
+            3 => Channel4.get_transfer_remaining(),
+            4 => Channel5.get_transfer_remaining(),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            5 => Channel6.get_transfer_remaining(),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            6 => Channel7.get_transfer_remaining(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn clear_interrupts(&mut self) {
+        self.with_channel(|ch| ch.clear_interrupts())
+    }
+
+    fn start(&mut self) {
+        self.with_channel(|ch| ch.start())
+    }
+
+    fn stop(&mut self) {
+        self.with_channel(|ch| ch.stop())
+    }
+
+    fn listen(&mut self, event: Event) {
+        self.with_channel(|ch| ch.listen(event))
+    }
+
+    fn unlisten(&mut self, event: Event) {
+        self.with_channel(|ch| ch.unlisten(event))
+    }
+}
+
+impl<'a> DmaChannelExt for Grant<'a> {
+    fn is_pending(&self, event: ChannelEvent) -> bool {
+        Status::read().is_set(self.index, event)
+    }
+
+    fn clear_event(&mut self, event: ChannelEvent) {
+        crate::dma::clear(self.index, event)
+    }
+
+    fn interrupt(&self) -> Interrupt {
+        crate::dma::interrupt(self.index)
+    }
+}
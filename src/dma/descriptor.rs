@@ -0,0 +1,337 @@
+//! # Typed DMA transfer descriptors
+//!
+//! Descriptors encode direction and element size in their type. The buffer
+//! element type must match the data register word of the peripheral, so a
+//! `u8` buffer can't be bound to a 16 bit register:
+//!
+//! ```ignore
+//! let transfer = MemToPeriph::<u8>::new().start(dma.ch1, &tx, Pin::new(buffer));
+//! ```
+use core::marker::PhantomData;
+use core::ops;
+use core::pin::Pin;
+use core::sync::atomic::{self, Ordering};
+
+use as_slice::{AsMutSlice, AsSlice};
+
+use crate::dma::{DmaChannel, DmaMuxIndex, Priority, Transfer, TransferDirection, WordSize};
+use crate::serial::{Rx, Tx};
+use crate::stm32::*;
+
+/// DMA element type
+///
+/// # Safety
+///
+/// `SIZE` must match the size of the type.
+pub unsafe trait Word: Copy {
+    const SIZE: WordSize;
+}
+
+unsafe impl Word for u8 {
+    const SIZE: WordSize = WordSize::Bits8;
+}
+
+unsafe impl Word for u16 {
+    const SIZE: WordSize = WordSize::Bits16;
+}
+
+unsafe impl Word for u32 {
+    const SIZE: WordSize = WordSize::Bits32;
+}
+
+/// Peripheral register DMA reads `Word`s from
+///
+/// # Safety
+///
+/// `address` must be a register accepting `Word` sized reads, served by the
+/// `request` line, and stay valid while the value lives.
+pub unsafe trait DmaSource {
+    type Word: Word;
+
+    fn address(&self) -> u32;
+    fn request(&self) -> DmaMuxIndex;
+}
+
+/// Peripheral register DMA writes `Word`s to
+///
+/// # Safety
+///
+/// `address` must be a register accepting `Word` sized writes, served by the
+/// `request` line, and stay valid while the value lives.
+pub unsafe trait DmaSink {
+    type Word: Word;
+
+    fn address(&self) -> u32;
+    fn request(&self) -> DmaMuxIndex;
+}
+
+/// Raw peripheral register, for peripherals without a HAL binding
+pub struct Register<W> {
+    address: u32,
+    request: DmaMuxIndex,
+    _word: PhantomData<W>,
+}
+
+impl<W: Word> Register<W> {
+    /// Creates a register binding
+    ///
+    /// # Safety
+    ///
+    /// `address` must be a peripheral register accepting `W` sized accesses,
+    /// served by the `request` line.
+    pub unsafe fn new(address: u32, request: DmaMuxIndex) -> Self {
+        Register {
+            address,
+            request,
+            _word: PhantomData,
+        }
+    }
+}
+
+unsafe impl<W: Word> DmaSource for Register<W> {
+    type Word = W;
+
+    fn address(&self) -> u32 {
+        self.address
+    }
+
+    fn request(&self) -> DmaMuxIndex {
+        self.request
+    }
+}
+
+unsafe impl<W: Word> DmaSink for Register<W> {
+    type Word = W;
+
+    fn address(&self) -> u32 {
+        self.address
+    }
+
+    fn request(&self) -> DmaMuxIndex {
+        self.request
+    }
+}
+
+/// Memory to peripheral transfer of `W` elements
+pub struct MemToPeriph<W> {
+    circular: bool,
+    priority: Priority,
+    _word: PhantomData<W>,
+}
+
+/// Peripheral to memory transfer of `W` elements
+pub struct PeriphToMem<W> {
+    circular: bool,
+    priority: Priority,
+    _word: PhantomData<W>,
+}
+
+/// Memory to memory transfer of `W` elements
+pub struct MemToMem<W> {
+    priority: Priority,
+    _word: PhantomData<W>,
+}
+
+impl<W: Word> MemToPeriph<W> {
+    pub fn new() -> Self {
+        MemToPeriph {
+            circular: false,
+            priority: Priority::Low,
+            _word: PhantomData,
+        }
+    }
+
+    /// Restart from the beginning of the buffer once completed
+    pub fn circular(mut self, circular: bool) -> Self {
+        self.circular = circular;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Starts sending `buffer` to the peripheral
+    pub fn start<CHANNEL, P, B>(
+        self,
+        mut channel: CHANNEL,
+        periph: &P,
+        buffer: Pin<B>,
+    ) -> Transfer<CHANNEL, Pin<B>>
+    where
+        CHANNEL: DmaChannel,
+        P: DmaSink<Word = W>,
+        B: ops::Deref + 'static,
+        B::Target: AsSlice<Element = W>,
+    {
+        let slice = buffer.as_slice();
+        channel.set_direction(TransferDirection::MemoryToPeriph);
+        channel.set_circular_mode(self.circular);
+        channel.set_priority(self.priority);
+        channel.set_word_size(W::SIZE);
+        channel.set_peripheral_address(periph.address(), false);
+        channel.set_memory_address(slice.as_ptr() as u32, true);
+        channel.set_transfer_length(slice.len());
+        channel.select_peripheral(periph.request());
+
+        atomic::compiler_fence(Ordering::SeqCst);
+        channel.start();
+        Transfer { channel, buffer }
+    }
+}
+
+impl<W: Word> Default for MemToPeriph<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Word> PeriphToMem<W> {
+    pub fn new() -> Self {
+        PeriphToMem {
+            circular: false,
+            priority: Priority::Low,
+            _word: PhantomData,
+        }
+    }
+
+    /// Restart from the beginning of the buffer once completed
+    pub fn circular(mut self, circular: bool) -> Self {
+        self.circular = circular;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Starts receiving from the peripheral until `buffer` is filled
+    pub fn start<CHANNEL, P, B>(
+        self,
+        mut channel: CHANNEL,
+        periph: &P,
+        mut buffer: Pin<B>,
+    ) -> Transfer<CHANNEL, Pin<B>>
+    where
+        CHANNEL: DmaChannel,
+        P: DmaSource<Word = W>,
+        B: ops::DerefMut + 'static,
+        B::Target: AsMutSlice<Element = W> + Unpin,
+    {
+        let slice = buffer.as_mut_slice();
+        channel.set_direction(TransferDirection::PeriphToMemory);
+        channel.set_circular_mode(self.circular);
+        channel.set_priority(self.priority);
+        channel.set_word_size(W::SIZE);
+        channel.set_peripheral_address(periph.address(), false);
+        channel.set_memory_address(slice.as_ptr() as u32, true);
+        channel.set_transfer_length(slice.len());
+        channel.select_peripheral(periph.request());
+
+        atomic::compiler_fence(Ordering::SeqCst);
+        channel.start();
+        Transfer { channel, buffer }
+    }
+}
+
+impl<W: Word> Default for PeriphToMem<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Word> MemToMem<W> {
+    pub fn new() -> Self {
+        MemToMem {
+            priority: Priority::Low,
+            _word: PhantomData,
+        }
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Starts copying `from` into `to`, both must have the same length
+    pub fn start<CHANNEL, F, T>(
+        self,
+        mut channel: CHANNEL,
+        from: Pin<F>,
+        mut to: Pin<T>,
+    ) -> Transfer<CHANNEL, (Pin<F>, Pin<T>)>
+    where
+        CHANNEL: DmaChannel,
+        F: ops::Deref + 'static,
+        F::Target: AsSlice<Element = W>,
+        T: ops::DerefMut + 'static,
+        T::Target: AsMutSlice<Element = W> + Unpin,
+    {
+        let src = from.as_slice();
+        let dst = to.as_mut_slice();
+        assert!(src.len() == dst.len());
+        channel.set_direction(TransferDirection::MemoryToMemory);
+        channel.set_priority(self.priority);
+        channel.set_word_size(W::SIZE);
+        channel.set_memory_address(src.as_ptr() as u32, true);
+        channel.set_peripheral_address(dst.as_ptr() as u32, true);
+        channel.set_transfer_length(src.len());
+
+        atomic::compiler_fence(Ordering::SeqCst);
+        channel.start();
+        Transfer {
+            channel,
+            buffer: (from, to),
+        }
+    }
+}
+
+impl<W: Word> Default for MemToMem<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+macro_rules! serial_dma {
+    ($($USARTX:ident: ($rx_req:ident, $tx_req:ident),)+) => {
+        $(
+            unsafe impl DmaSource for Rx<$USARTX> {
+                type Word = u8;
+
+                fn address(&self) -> u32 {
+                    unsafe { &(*$USARTX::ptr()).rdr as *const _ as u32 }
+                }
+
+                fn request(&self) -> DmaMuxIndex {
+                    DmaMuxIndex::$rx_req
+                }
+            }
+
+            unsafe impl DmaSink for Tx<$USARTX> {
+                type Word = u8;
+
+                fn address(&self) -> u32 {
+                    unsafe { &(*$USARTX::ptr()).tdr as *const _ as u32 }
+                }
+
+                fn request(&self) -> DmaMuxIndex {
+                    DmaMuxIndex::$tx_req
+                }
+            }
+        )+
+    };
+}
+
+serial_dma! {
+    LPUART: (LPUART_RX, LPUART_TX),
+    USART1: (USART1_RX, USART1_TX),
+    USART2: (USART2_RX, USART2_TX),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+serial_dma! {
+    USART3: (USART3_RX, USART3_TX),
+    USART4: (USART4_RX, USART4_TX),
+}
@@ -0,0 +1,106 @@
+//! Ping-pong buffering for continuous DMA reception
+//!
+//! `DoubleBuffer` owns two buffers. While the DMA fills one, the application
+//! processes the other. On transfer complete the receiver is restarted into
+//! the spare buffer and the filled one is handed out, nothing is copied.
+//! Samples arriving between transfer complete and the restart are lost, so
+//! swap from the DMA interrupt:
+//!
+//! ```ignore
+//! // DMA transfer complete interrupt
+//! if let Ok(filled) = double_buffer.swap() {
+//!     process(filled);
+//!     double_buffer.recycle(filled);
+//! }
+//! ```
+use crate::dma::Error;
+
+/// DMA receiver restartable into a new buffer
+pub trait DmaRestart {
+    type Word: 'static;
+
+    /// Starts filling `buffer`, returns the previous buffer
+    fn restart(&mut self, buffer: &'static mut [Self::Word]) -> Option<&'static mut [Self::Word]>;
+
+    /// Stops the transfer and returns the buffer
+    fn stop(&mut self) -> Option<&'static mut [Self::Word]>;
+}
+
+/// Buffer handed back by a receiver, `None` if it was already taken
+pub type Buffer<W> = Option<&'static mut [W]>;
+
+/// Two buffers used in turns by a DMA receiver
+pub struct DoubleBuffer<RX: DmaRestart> {
+    rx: RX,
+    spare: Option<&'static mut [RX::Word]>,
+    running: bool,
+}
+
+impl<RX: DmaRestart> DoubleBuffer<RX> {
+    /// Starts receiving into `first`, `second` is filled next
+    pub fn new(
+        mut rx: RX,
+        first: &'static mut [RX::Word],
+        second: &'static mut [RX::Word],
+    ) -> Self {
+        rx.restart(first);
+        DoubleBuffer {
+            rx,
+            spare: Some(second),
+            running: true,
+        }
+    }
+
+    /// Restarts into the spare buffer and returns the filled one
+    ///
+    /// Call on transfer complete. Fails with `Error::Overrun` if the spare
+    /// buffer was not recycled in time, reception then stops until it is.
+    pub fn swap(&mut self) -> Result<&'static mut [RX::Word], Error> {
+        match self.spare.take() {
+            Some(spare) => self.rx.restart(spare).ok_or(Error::BufferError),
+            None => {
+                self.running = false;
+                let filled = self.rx.stop().ok_or(Error::BufferError)?;
+                self.spare = Some(filled);
+                Err(Error::Overrun)
+            }
+        }
+    }
+
+    /// Swaps the buffers and passes the filled one to `f`
+    pub fn process<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut [RX::Word]),
+    {
+        let filled = self.swap()?;
+        f(filled);
+        self.recycle(filled);
+        Ok(())
+    }
+
+    /// Returns a processed buffer, restarting reception after an overrun
+    pub fn recycle(&mut self, buffer: &'static mut [RX::Word]) {
+        if self.running {
+            self.spare = Some(buffer);
+        } else {
+            // The buffer filled during the overrun stays spare, its data is lost
+            self.rx.restart(buffer);
+            self.running = true;
+        }
+    }
+
+    /// Returns `true` if a spare buffer is ready for the next swap
+    pub fn has_spare(&self) -> bool {
+        self.spare.is_some()
+    }
+
+    pub fn rx(&mut self) -> &mut RX {
+        &mut self.rx
+    }
+
+    /// Stops reception and returns the receiver and both buffers
+    pub fn release(mut self) -> (RX, Buffer<RX::Word>, Buffer<RX::Word>) {
+        let active = self.rx.stop();
+        (self.rx, active, self.spare)
+    }
+}
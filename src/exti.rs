@@ -1,9 +1,19 @@
 //! External interrupt controller
+//!
+//! GPIO lines are routed with `listen()` on an input pin, e.g.
+//! `pc13.listen(SignalEdge::Falling, &mut exti)`, which selects the port
+//! in the EXTI mux, sets the trigger and unmasks the interrupt.
 use crate::gpio::SignalEdge;
 use crate::stm32::EXTI;
 
+/// IMR1 offset in words
+const IMR1: usize = 0x80 / 4;
+/// IMR2 offset in words
+const IMR2: usize = 0x90 / 4;
+
 /// EXTI trigger event
-#[derive(PartialEq, PartialOrd, Clone, Copy)]
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Event {
     GPIO0 = 0,
     GPIO1 = 1,
@@ -63,10 +73,11 @@ impl Event {
 }
 
 pub trait ExtiExt {
-    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    /// Unmasks the interrupt of `ev`
     fn wakeup(&self, ev: Event);
+    /// Sets the trigger edge of a configurable line and unmasks it
     fn listen(&self, ev: Event, edge: SignalEdge);
-    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    /// Masks `ev` and clears its triggers
     fn unlisten(&self, ev: Event);
     fn is_pending(&self, ev: Event, edge: SignalEdge) -> bool;
     fn unpend(&self, ev: Event);
@@ -77,52 +88,39 @@ impl ExtiExt for EXTI {
         let line = ev as u8;
         assert!(line <= 18);
         let mask = 1 << line;
-        match edge {
-            SignalEdge::Rising => {
-                self.rtsr1.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
-            }
-            SignalEdge::Falling => {
-                self.ftsr1.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
-            }
-            SignalEdge::All => {
-                self.rtsr1.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
-                self.ftsr1.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
-            }
-        }
-        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        let (rising, falling) = match edge {
+            SignalEdge::Rising => (mask, 0),
+            SignalEdge::Falling => (0, mask),
+            SignalEdge::All => (mask, mask),
+        };
+        self.rtsr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !mask | rising) });
+        self.ftsr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !mask | falling) });
         self.wakeup(ev);
     }
 
-    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
     fn wakeup(&self, ev: Event) {
-        match ev as u8 {
-            line if line < 32 => self
-                .imr1()
-                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << line) }),
-            line => self
-                .imr2
-                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << (line - 32)) }),
+        let (imr, bit) = interrupt_mask(ev);
+        unsafe {
+            let value = core::ptr::read_volatile(imr);
+            core::ptr::write_volatile(imr, value | bit);
         }
     }
 
-    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
     fn unlisten(&self, ev: Event) {
-        self.unpend(ev);
-        match ev as u8 {
-            line if line < 32 => {
-                let mask = !(1 << line);
-                self.imr1()
-                    .modify(|r, w| unsafe { w.bits(r.bits() & mask) });
-                if line <= 18 {
-                    self.rtsr1.modify(|r, w| unsafe { w.bits(r.bits() & mask) });
-                    self.ftsr1.modify(|r, w| unsafe { w.bits(r.bits() & mask) });
-                }
-            }
-            line => {
-                let mask = !(1 << (line - 32));
-                self.imr2.modify(|r, w| unsafe { w.bits(r.bits() & mask) })
-            }
+        let (imr, bit) = interrupt_mask(ev);
+        unsafe {
+            let value = core::ptr::read_volatile(imr);
+            core::ptr::write_volatile(imr, value & !bit);
+        }
+        let line = ev as u8;
+        if line <= 18 {
+            let mask = !(1 << line);
+            self.rtsr1.modify(|r, w| unsafe { w.bits(r.bits() & mask) });
+            self.ftsr1.modify(|r, w| unsafe { w.bits(r.bits() & mask) });
         }
+        self.unpend(ev);
     }
 
     fn is_pending(&self, ev: Event, edge: SignalEdge) -> bool {
@@ -135,7 +133,7 @@ impl ExtiExt for EXTI {
             SignalEdge::Rising => self.rpr1.read().bits() & mask != 0,
             SignalEdge::Falling => self.fpr1.read().bits() & mask != 0,
             SignalEdge::All => {
-                (self.rpr1.read().bits() & mask != 0) && (self.fpr1.read().bits() & mask != 0)
+                (self.rpr1.read().bits() & mask != 0) || (self.fpr1.read().bits() & mask != 0)
             }
         }
     }
@@ -148,3 +146,15 @@ impl ExtiExt for EXTI {
         }
     }
 }
+
+/// IMR register and bit of `ev`
+fn interrupt_mask(ev: Event) -> (*mut u32, u32) {
+    let line = ev as usize;
+    let (offset, bit) = if line < 32 {
+        (IMR1, line)
+    } else {
+        (IMR2, line - 32)
+    };
+    let imr = unsafe { (EXTI::ptr() as *mut u32).add(offset) };
+    (imr, 1 << bit)
+}
@@ -0,0 +1,197 @@
+//! # End-of-line self test
+//!
+//! Runs board checks and reports one line per test to any `fmt::Write`
+//! sink, e.g. a serial port:
+//!
+//! ```text
+//! TEST <name> <PASS|FAIL> <detail>
+//! DONE <passed> <failed>
+//! ```
+use core::fmt;
+
+use hal::adc::OneShot;
+use hal::blocking::i2c;
+use hal::blocking::spi;
+use hal::digital::v2::{InputPin, OutputPin};
+
+use crate::analog::adc::{Adc, Precision, SampleTime, VRef, VREFINT_CAL};
+use crate::rcc::{measure_ti1, Rcc, Ti1Source, CAPTURE_PRESCALER, LSE_FREQ};
+use crate::stm32::TIM16;
+
+/// LSE input captures per clock measurement
+const LSE_CAPTURES: u64 = 16;
+
+/// Test outcome
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+}
+
+/// Test summary
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub passed: u16,
+    pub failed: u16,
+}
+
+impl Summary {
+    /// Returns `true` if no test failed
+    pub fn is_ok(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+/// Self test runner
+pub struct FactoryTest<W> {
+    out: W,
+    summary: Summary,
+}
+
+impl<W: fmt::Write> FactoryTest<W> {
+    /// Creates test runner reporting to `out`
+    pub fn new(out: W) -> Self {
+        FactoryTest {
+            out,
+            summary: Summary {
+                passed: 0,
+                failed: 0,
+            },
+        }
+    }
+
+    /// Drives `output` high and low, `input` must follow
+    pub fn gpio_loopback<O, I>(&mut self, name: &str, output: &mut O, input: &I) -> Outcome
+    where
+        O: OutputPin,
+        I: InputPin,
+    {
+        let mut sampled = [false; 2];
+        let mut ok = true;
+        for (level, sample) in sampled.iter_mut().enumerate() {
+            let high = level == 1;
+            let driven = if high {
+                output.set_high().is_ok()
+            } else {
+                output.set_low().is_ok()
+            };
+            cortex_m::asm::delay(100);
+            *sample = match input.is_high() {
+                Ok(value) => value,
+                Err(_) => !high,
+            };
+            ok &= driven && *sample == high;
+        }
+        self.report(name, ok, format_args!("low={} high={}", sampled[0] as u8, sampled[1] as u8))
+    }
+
+    /// Checks that an I2C device acknowledges its address
+    pub fn i2c_probe<I2C>(&mut self, name: &str, i2c: &mut I2C, addr: u8) -> Outcome
+    where
+        I2C: i2c::Read,
+    {
+        let mut buf = [0];
+        let ok = i2c.read(addr, &mut buf).is_ok();
+        self.report(name, ok, format_args!("addr=0x{:02x}", addr))
+    }
+
+    /// Sends `command` to an SPI device and compares the response with `expected`
+    ///
+    /// Response bytes are matched after the command bytes, e.g. for a JEDEC ID read.
+    pub fn spi_probe<SPI, CS>(
+        &mut self,
+        name: &str,
+        spi: &mut SPI,
+        cs: &mut CS,
+        command: &[u8],
+        expected: &[u8],
+    ) -> Outcome
+    where
+        SPI: spi::Transfer<u8>,
+        CS: OutputPin,
+    {
+        let mut buf = [0u8; 16];
+        let len = command.len() + expected.len();
+        assert!(len <= buf.len());
+        buf[..command.len()].copy_from_slice(command);
+
+        let _ = cs.set_low();
+        let ok = match spi.transfer(&mut buf[..len]) {
+            Ok(response) => &response[command.len()..] == expected,
+            Err(_) => false,
+        };
+        let _ = cs.set_high();
+        let response = &buf[command.len()..len];
+        self.report(name, ok, format_args!("response={:02x?}", response))
+    }
+
+    /// Measures VDDA using the internal reference, expects `min_mv..=max_mv`
+    pub fn adc_reference(&mut self, name: &str, adc: &mut Adc, min_mv: u32, max_mv: u32) -> Outcome {
+        let mut vref = VRef::new();
+        vref.enable(adc);
+        adc.set_precision(Precision::B_12);
+        adc.set_sample_time(SampleTime::T_160);
+        cortex_m::asm::delay(1_000);
+        let raw: u32 = nb::block!(adc.read(&mut vref)).unwrap_or_default();
+        vref.disable(adc);
+
+        let cal = unsafe { core::ptr::read_volatile(VREFINT_CAL) } as u32;
+        let vdda = (3000 * cal).checked_div(raw).unwrap_or(0);
+        let ok = vdda >= min_mv && vdda <= max_mv;
+        self.report(name, ok, format_args!("vdda={}mV raw={}", vdda, raw))
+    }
+
+    /// Measures the timer clock against the LSE crystal and checks that it is
+    /// within `tolerance` percent of the configured timer clock
+    ///
+    /// LSE must be running, it is captured by TIM16 channel 1.
+    pub fn clock(&mut self, name: &str, tim: &mut TIM16, rcc: &mut Rcc, tolerance: u32) -> Outcome {
+        if rcc.rb.bdcr.read().lserdy().bit_is_clear() {
+            return self.report(name, false, format_args!("lse not ready"));
+        }
+        rcc.rb.apbenr2.modify(|_, w| w.tim16en().set_bit());
+        let ticks = measure_ti1(tim, Ti1Source::Lse, LSE_CAPTURES);
+        let actual = (ticks * LSE_FREQ / (CAPTURE_PRESCALER * LSE_CAPTURES)) as u32;
+        let expected = rcc.clocks.apb_tim_clk.0;
+        let margin = expected / 100 * tolerance;
+        let ok = actual + margin >= expected && actual <= expected + margin;
+        self.report(
+            name,
+            ok,
+            format_args!(
+                "tim={} expected={} sys={}",
+                actual, expected, rcc.clocks.sys_clk.0
+            ),
+        )
+    }
+
+    /// Records result of an application specific test
+    pub fn custom(&mut self, name: &str, ok: bool, detail: fmt::Arguments) -> Outcome {
+        self.report(name, ok, detail)
+    }
+
+    /// Returns test results so far
+    pub fn summary(&self) -> Summary {
+        self.summary
+    }
+
+    /// Reports summary line, returning results and the output sink
+    pub fn finish(mut self) -> (Summary, W) {
+        let summary = self.summary;
+        writeln!(self.out, "DONE {} {}\r", summary.passed, summary.failed).ok();
+        (summary, self.out)
+    }
+
+    fn report(&mut self, name: &str, ok: bool, detail: fmt::Arguments) -> Outcome {
+        let outcome = if ok {
+            self.summary.passed += 1;
+            Outcome::Pass
+        } else {
+            self.summary.failed += 1;
+            Outcome::Fail
+        };
+        let result = if ok { "PASS" } else { "FAIL" };
+        writeln!(self.out, "TEST {} {} {}\r", name, result, detail).ok();
+        outcome
+    }
+}
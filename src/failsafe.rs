@@ -0,0 +1,100 @@
+//! # Fail-safe shutdown
+//!
+//! Critical outputs such as PWM bridges or heater switches register a safe
+//! state action, `shutdown()` runs all of them with interrupts disabled.
+//! Call it first thing from the panic handler and the HardFault handler,
+//! before any logging that could fault or block again:
+//!
+//! ```ignore
+//! #[panic_handler]
+//! fn panic(_: &core::panic::PanicInfo) -> ! {
+//!     hal::failsafe::shutdown();
+//!     loop {}
+//! }
+//!
+//! #[exception]
+//! fn HardFault(_: &ExceptionFrame) -> ! {
+//!     hal::failsafe::shutdown();
+//!     loop {}
+//! }
+//! ```
+//!
+//! Actions are plain functions and must only touch registers, they run in
+//! whatever state the firmware faulted in.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::interrupt;
+
+use crate::stm32::*;
+
+/// Maximum number of registered actions
+pub const MAX_ACTIONS: usize = 8;
+
+static mut ACTIONS: [Option<fn()>; MAX_ACTIONS] = [None; MAX_ACTIONS];
+static SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Action list is full
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Full;
+
+/// Registers `action` to run on `shutdown()`
+///
+/// Actions run in registration order.
+pub fn register(action: fn()) -> Result<(), Full> {
+    interrupt::free(|_| {
+        let actions = unsafe { &mut *core::ptr::addr_of_mut!(ACTIONS) };
+        match actions.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(action);
+                Ok(())
+            }
+            None => Err(Full),
+        }
+    })
+}
+
+/// Disables interrupts and puts all registered outputs into their safe state
+///
+/// Runs the actions once, nested calls (e.g. a fault inside an action)
+/// return immediately. Interrupts stay disabled.
+pub fn shutdown() {
+    interrupt::disable();
+    if SHUT_DOWN.load(Ordering::Relaxed) {
+        return;
+    }
+    SHUT_DOWN.store(true, Ordering::Relaxed);
+    let actions = unsafe { &*core::ptr::addr_of!(ACTIONS) };
+    for action in actions.iter().flatten() {
+        action();
+    }
+}
+
+/// Returns `true` once `shutdown()` was called
+pub fn is_shut_down() -> bool {
+    SHUT_DOWN.load(Ordering::Relaxed)
+}
+
+macro_rules! timer_outputs_off {
+    ($($TIM:ident: $name:ident,)+) => {
+        $(
+            /// Clears the main output enable of the timer, its outputs go
+            /// to the configured idle state
+            pub fn $name() {
+                let tim = unsafe { &(*$TIM::ptr()) };
+                tim.bdtr.modify(|_, w| w.moe().clear_bit());
+            }
+        )+
+    };
+}
+
+timer_outputs_off! {
+    TIM1: tim1_outputs_off,
+    TIM16: tim16_outputs_off,
+    TIM17: tim17_outputs_off,
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+timer_outputs_off! {
+    TIM15: tim15_outputs_off,
+}
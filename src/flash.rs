@@ -0,0 +1,182 @@
+//! # Flash programming
+//!
+//! Erases 2KB pages and programs 64 bit double words of the main flash.
+//! Code keeps executing from flash, the CPU stalls on flash accesses while
+//! an operation is in progress.
+use crate::signature::FlashSize;
+use crate::stm32::FLASH;
+
+pub mod eeprom;
+
+/// Start of the main flash
+pub const FLASH_START: usize = 0x0800_0000;
+/// Erase granularity
+pub const PAGE_SIZE: usize = 2048;
+/// Program granularity
+pub const WORD_SIZE: usize = 8;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+// CR
+const PG: u32 = 1 << 0;
+const PER: u32 = 1 << 1;
+const STRT: u32 = 1 << 16;
+const LOCK: u32 = 1 << 31;
+
+// SR
+const OPERR: u32 = 1 << 1;
+const PROGERR: u32 = 1 << 3;
+const WRPERR: u32 = 1 << 4;
+const PGAERR: u32 = 1 << 5;
+const SIZERR: u32 = 1 << 6;
+const PGSERR: u32 = 1 << 7;
+const MISSERR: u32 = 1 << 8;
+const FASTERR: u32 = 1 << 9;
+const BSY1: u32 = 1 << 16;
+const ERRORS: u32 = OPERR | PROGERR | WRPERR | PGAERR | SIZERR | PGSERR | MISSERR | FASTERR;
+
+/// Flash errors
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Address or length is not double word aligned
+    Alignment,
+    /// Address is outside of the main flash
+    OutOfBounds,
+    /// Target double word was not erased
+    Programming,
+    /// Page is write protected
+    WriteProtected,
+    /// Wrong programming sequence
+    Sequence,
+    /// Operation failed
+    Operation,
+}
+
+/// Constrained FLASH peripheral
+pub struct Flash {
+    rb: FLASH,
+}
+
+pub trait FlashExt {
+    fn constrain(self) -> Flash;
+}
+
+impl FlashExt for FLASH {
+    fn constrain(self) -> Flash {
+        Flash { rb: self }
+    }
+}
+
+impl Flash {
+    /// Size of the main flash in bytes
+    pub fn size(&self) -> usize {
+        FlashSize::bytes()
+    }
+
+    /// Number of pages of the main flash
+    pub fn pages(&self) -> usize {
+        self.size() / PAGE_SIZE
+    }
+
+    /// Address of the first byte of `page`
+    pub fn page_address(&self, page: usize) -> usize {
+        FLASH_START + page * PAGE_SIZE
+    }
+
+    /// Reads flash at `offset` from the start of the main flash
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        self.check_bounds(offset, buf.len())?;
+        let src = (FLASH_START + offset) as *const u8;
+        let src = unsafe { core::slice::from_raw_parts(src, buf.len()) };
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    /// Erases `page`
+    pub fn erase_page(&mut self, page: usize) -> Result<(), Error> {
+        if page >= self.pages() {
+            return Err(Error::OutOfBounds);
+        }
+        self.unlocked(|rb| {
+            rb.cr.write(|w| unsafe { w.bits(PER | (page as u32) << 3) });
+            rb.cr.modify(|r, w| unsafe { w.bits(r.bits() | STRT) });
+            wait(rb)
+        })
+    }
+
+    /// Programs `data` at `offset` from the start of the main flash
+    ///
+    /// Offset and length must be multiples of 8, the target must be erased.
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        if (offset | data.len()) & (WORD_SIZE - 1) != 0 {
+            return Err(Error::Alignment);
+        }
+        self.check_bounds(offset, data.len())?;
+        self.unlocked(|rb| {
+            rb.cr.write(|w| unsafe { w.bits(PG) });
+            let mut res = Ok(());
+            for (idx, chunk) in data.chunks(WORD_SIZE).enumerate() {
+                let dst = (FLASH_START + offset + idx * WORD_SIZE) as *mut u32;
+                let low = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                let high = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+                unsafe {
+                    core::ptr::write_volatile(dst, low);
+                    core::ptr::write_volatile(dst.add(1), high);
+                }
+                res = wait(rb);
+                if res.is_err() {
+                    break;
+                }
+            }
+            rb.cr.write(|w| unsafe { w.bits(0) });
+            res
+        })
+    }
+
+    pub fn release(self) -> FLASH {
+        self.rb
+    }
+
+    fn check_bounds(&self, offset: usize, len: usize) -> Result<(), Error> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size() => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    fn unlocked<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&FLASH) -> Result<(), Error>,
+    {
+        while self.rb.sr.read().bits() & BSY1 != 0 {}
+        self.rb.sr.write(|w| unsafe { w.bits(ERRORS) });
+        if self.rb.cr.read().bits() & LOCK != 0 {
+            self.rb.keyr.write(|w| unsafe { w.bits(KEY1) });
+            self.rb.keyr.write(|w| unsafe { w.bits(KEY2) });
+        }
+        let res = f(&self.rb);
+        self.rb.cr.write(|w| unsafe { w.bits(LOCK) });
+        res
+    }
+}
+
+fn wait(rb: &FLASH) -> Result<(), Error> {
+    while rb.sr.read().bits() & BSY1 != 0 {}
+    let sr = rb.sr.read().bits();
+    rb.sr.write(|w| unsafe { w.bits(sr & ERRORS) });
+    if sr & WRPERR != 0 {
+        Err(Error::WriteProtected)
+    } else if sr & PROGERR != 0 {
+        Err(Error::Programming)
+    } else if sr & (PGAERR | SIZERR) != 0 {
+        Err(Error::Alignment)
+    } else if sr & (PGSERR | MISSERR | FASTERR) != 0 {
+        Err(Error::Sequence)
+    } else if sr & OPERR != 0 {
+        Err(Error::Operation)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+//! # EEPROM emulation
+//!
+//! Key-value store in two flash pages used in turns. Every write appends a
+//! record to the active page, a read returns the latest record of a key.
+//! When the active page is full the live records are copied to the other
+//! page, which then becomes active, and the old page is erased.
+//!
+//! Records are a double word header `[key, len, checksum, !key]` followed by
+//! the value padded to 8 bytes. A record torn by a reset fails its checksum
+//! and is ignored. The page header holds a magic word and a sequence
+//! number, so the newer page wins if a reset hits during the page swap.
+use super::{Error as FlashError, Flash, PAGE_SIZE, WORD_SIZE};
+
+/// Longest value
+pub const MAX_VALUE_LEN: usize = 256;
+
+const MAGIC: u32 = 0x4545_5052;
+const HEADER_LEN: usize = 8;
+const ERASED: u16 = 0xffff;
+/// Set in the length field of a removed key
+const REMOVED: u16 = 0x8000;
+
+/// EEPROM emulation errors
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Flash(FlashError),
+    /// Live values do not fit in a page
+    Full,
+    /// Value longer than `MAX_VALUE_LEN` or key 0xffff
+    Invalid,
+    /// Read buffer too small, contains the value length
+    BufferTooSmall(usize),
+}
+
+impl From<FlashError> for Error {
+    fn from(err: FlashError) -> Self {
+        Error::Flash(err)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Record {
+    key: u16,
+    len: u16,
+    offset: usize,
+}
+
+impl Record {
+    fn value_len(&self) -> usize {
+        (self.len & !REMOVED) as usize
+    }
+
+    fn is_removed(&self) -> bool {
+        self.len & REMOVED != 0
+    }
+
+    /// Record size including padding
+    fn size(&self) -> usize {
+        HEADER_LEN + padded(self.value_len())
+    }
+}
+
+/// Key-value store in two flash pages
+pub struct Eeprom {
+    flash: Flash,
+    pages: [usize; 2],
+    active: usize,
+    seq: u32,
+    // offset of the first free record in the active page
+    free: usize,
+}
+
+impl Eeprom {
+    /// Opens the store in pages `first_page` and `first_page + 1`
+    ///
+    /// Formats the pages if neither holds a valid store, and finishes an
+    /// interrupted page swap.
+    pub fn new(flash: Flash, first_page: usize) -> Result<Self, Error> {
+        assert!(first_page + 1 < flash.pages());
+        let mut eeprom = Eeprom {
+            flash,
+            pages: [first_page, first_page + 1],
+            active: 0,
+            seq: 0,
+            free: HEADER_LEN,
+        };
+
+        match (eeprom.page_seq(0), eeprom.page_seq(1)) {
+            (Some(a), Some(b)) => {
+                // Reset after the new page was marked, before the old was erased
+                let (newer, seq) = if b.wrapping_sub(a) as i32 > 0 {
+                    (1, b)
+                } else {
+                    (0, a)
+                };
+                eeprom.activate(newer, seq);
+                eeprom.erase(1 - newer)?;
+            }
+            (Some(seq), None) => {
+                eeprom.activate(0, seq);
+                eeprom.erase_if_dirty(1)?;
+            }
+            (None, Some(seq)) => {
+                eeprom.activate(1, seq);
+                eeprom.erase_if_dirty(0)?;
+            }
+            (None, None) => eeprom.format()?,
+        }
+        Ok(eeprom)
+    }
+
+    /// Reads the value of `key` into `buf`, returns its length
+    pub fn read(&self, key: u16, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        let record = match self.find(key) {
+            Some(record) if !record.is_removed() => record,
+            _ => return Ok(None),
+        };
+        let len = record.value_len();
+        if buf.len() < len {
+            return Err(Error::BufferTooSmall(len));
+        }
+        buf[..len].copy_from_slice(self.slice(self.active, record.offset + HEADER_LEN, len));
+        Ok(Some(len))
+    }
+
+    /// Returns `true` if `key` holds a value
+    pub fn contains(&self, key: u16) -> bool {
+        matches!(self.find(key), Some(record) if !record.is_removed())
+    }
+
+    /// Stores `value` for `key`
+    pub fn write(&mut self, key: u16, value: &[u8]) -> Result<(), Error> {
+        if key == ERASED || value.len() > MAX_VALUE_LEN {
+            return Err(Error::Invalid);
+        }
+        self.append(key, value.len() as u16, value)
+    }
+
+    /// Removes `key`
+    pub fn remove(&mut self, key: u16) -> Result<(), Error> {
+        if self.contains(key) {
+            self.append(key, REMOVED, &[])
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Erases all keys
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.erase(1 - self.active)?;
+        self.format()
+    }
+
+    /// Free bytes left in the active page
+    pub fn free_space(&self) -> usize {
+        PAGE_SIZE - self.free
+    }
+
+    pub fn release(self) -> Flash {
+        self.flash
+    }
+
+    fn append(&mut self, key: u16, len: u16, value: &[u8]) -> Result<(), Error> {
+        let record = Record {
+            key,
+            len,
+            offset: self.free,
+        };
+        if self.free + record.size() > PAGE_SIZE {
+            self.swap()?;
+            if self.free + record.size() > PAGE_SIZE {
+                return Err(Error::Full);
+            }
+        }
+        let offset = self.free;
+        self.program_record(self.active, offset, key, len, value)?;
+        self.free += record.size();
+        Ok(())
+    }
+
+    /// Copies live records to the other page and makes it active
+    fn swap(&mut self) -> Result<(), Error> {
+        let from = self.active;
+        let to = 1 - from;
+        self.erase_if_dirty(to)?;
+
+        let mut offset = HEADER_LEN;
+        let mut dst = HEADER_LEN;
+        let mut buf = [0; MAX_VALUE_LEN];
+        while let Some(record) = self.record_at(from, offset) {
+            offset += record.size();
+            let latest = self.find_in(from, record.key).map(|latest| latest.offset);
+            if record.is_removed() || latest != Some(record.offset) {
+                continue;
+            }
+            let len = record.value_len();
+            let src = self.page_offset(from) + record.offset + HEADER_LEN;
+            self.flash.read(src, &mut buf[..len])?;
+            self.program_record(to, dst, record.key, record.len, &buf[..len])?;
+            dst += record.size();
+        }
+
+        // Mark the new page valid, then drop the old one
+        let seq = self.seq.wrapping_add(1);
+        self.program_header(to, seq)?;
+        self.activate(to, seq);
+        self.erase(from)
+    }
+
+    fn format(&mut self) -> Result<(), Error> {
+        self.erase(0)?;
+        self.erase(1)?;
+        self.program_header(0, 0)?;
+        self.activate(0, 0);
+        Ok(())
+    }
+
+    fn activate(&mut self, page: usize, seq: u32) {
+        self.active = page;
+        self.seq = seq;
+        let mut offset = HEADER_LEN;
+        while let Some(record) = self.record_at(page, offset) {
+            offset += record.size();
+        }
+        self.free = offset;
+    }
+
+    fn find(&self, key: u16) -> Option<Record> {
+        self.find_in(self.active, key)
+    }
+
+    fn find_in(&self, page: usize, key: u16) -> Option<Record> {
+        let mut latest = None;
+        let mut offset = HEADER_LEN;
+        while let Some(record) = self.record_at(page, offset) {
+            if record.key == key && self.is_valid(page, &record) {
+                latest = Some(record);
+            }
+            offset += record.size();
+        }
+        latest
+    }
+
+    /// Record header at `offset`, `None` at the end of the records
+    fn record_at(&self, page: usize, offset: usize) -> Option<Record> {
+        if offset + HEADER_LEN > PAGE_SIZE {
+            return None;
+        }
+        let header = self.slice(page, offset, HEADER_LEN);
+        let key = u16::from_le_bytes([header[0], header[1]]);
+        let len = u16::from_le_bytes([header[2], header[3]]);
+        if key == ERASED && len == ERASED {
+            return None;
+        }
+        let record = Record { key, len, offset };
+        if record.value_len() > MAX_VALUE_LEN || offset + record.size() > PAGE_SIZE {
+            // Torn header, nothing after it can be trusted
+            return None;
+        }
+        Some(record)
+    }
+
+    fn is_valid(&self, page: usize, record: &Record) -> bool {
+        let header = self.slice(page, record.offset, HEADER_LEN);
+        let checksum = u16::from_le_bytes([header[4], header[5]]);
+        let inverted = u16::from_le_bytes([header[6], header[7]]);
+        let value = self.slice(page, record.offset + HEADER_LEN, record.value_len());
+        inverted == !record.key && checksum == fletcher16(record.key, record.len, value)
+    }
+
+    fn program_record(
+        &mut self,
+        page: usize,
+        offset: usize,
+        key: u16,
+        len: u16,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        let mut header = [0; HEADER_LEN];
+        header[0..2].copy_from_slice(&key.to_le_bytes());
+        header[2..4].copy_from_slice(&len.to_le_bytes());
+        header[4..6].copy_from_slice(&fletcher16(key, len, value).to_le_bytes());
+        header[6..8].copy_from_slice(&(!key).to_le_bytes());
+        let base = self.page_offset(page) + offset;
+        self.flash.write(base, &header)?;
+
+        let whole = value.len() - value.len() % WORD_SIZE;
+        self.flash.write(base + HEADER_LEN, &value[..whole])?;
+        if whole < value.len() {
+            let mut tail = [0xff; WORD_SIZE];
+            tail[..value.len() - whole].copy_from_slice(&value[whole..]);
+            self.flash.write(base + HEADER_LEN + whole, &tail)?;
+        }
+        Ok(())
+    }
+
+    fn program_header(&mut self, page: usize, seq: u32) -> Result<(), Error> {
+        let mut header = [0; HEADER_LEN];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&seq.to_le_bytes());
+        let offset = self.page_offset(page);
+        self.flash.write(offset, &header)?;
+        Ok(())
+    }
+
+    fn page_seq(&self, page: usize) -> Option<u32> {
+        let mut header = [0; HEADER_LEN];
+        self.flash.read(self.page_offset(page), &mut header).ok()?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        if magic == MAGIC {
+            Some(u32::from_le_bytes([header[4], header[5], header[6], header[7]]))
+        } else {
+            None
+        }
+    }
+
+    fn erase(&mut self, page: usize) -> Result<(), Error> {
+        self.flash.erase_page(self.pages[page])?;
+        Ok(())
+    }
+
+    fn erase_if_dirty(&mut self, page: usize) -> Result<(), Error> {
+        if self.slice(page, 0, PAGE_SIZE).iter().any(|byte| *byte != 0xff) {
+            self.erase(page)?;
+        }
+        Ok(())
+    }
+
+    /// Flash offset of `page`
+    fn page_offset(&self, page: usize) -> usize {
+        self.pages[page] * PAGE_SIZE
+    }
+
+    /// Flash contents at `offset` in `page`
+    fn slice(&self, page: usize, offset: usize, len: usize) -> &[u8] {
+        let addr = self.flash.page_address(self.pages[page]) + offset;
+        unsafe { core::slice::from_raw_parts(addr as *const u8, len) }
+    }
+}
+
+fn padded(len: usize) -> usize {
+    (len + WORD_SIZE - 1) & !(WORD_SIZE - 1)
+}
+
+fn fletcher16(key: u16, len: u16, value: &[u8]) -> u16 {
+    let mut sum1: u16 = 0;
+    let mut sum2: u16 = 0;
+    let header = [key.to_le_bytes(), len.to_le_bytes()];
+    for byte in header.iter().flatten().chain(value.iter()) {
+        sum1 = (sum1 + *byte as u16) % 255;
+        sum2 = (sum2 + sum1) % 255;
+    }
+    sum2 << 8 | sum1
+}
@@ -1,8 +1,16 @@
 //! General Purpose Input / Output
 use core::marker::PhantomData;
 
+use hal::digital::v2::{toggleable, InputPin, OutputPin, StatefulOutputPin};
+
 use crate::rcc::Rcc;
 
+pub mod erased;
+#[cfg(feature = "pin-registry")]
+pub mod registry;
+
+pub use self::erased::{DynamicMode, DynamicPin, Pin, PinModeError};
+
 /// Default pin mode
 pub type DefaultMode = Input<Floating>;
 
@@ -43,7 +51,46 @@ pub struct Output<MODE> {
 /// Push pull output (type state)
 pub struct PushPull;
 
+/// Alternate function mode (type state), `AF` is one of `AF0` to `AF7`
+///
+/// Peripherals accept a pin in this mode only with an alternate function the
+/// pin is mapped to for them, a wrong combination fails to compile.
+pub struct Alternate<AF> {
+    _af: PhantomData<AF>,
+}
+
+/// Alternate function number of the `AF0` to `AF7` markers
+pub trait AlternateFunction {
+    const NUMBER: u8;
+}
+
+macro_rules! alternate_functions {
+    ($($AF:ident: $n:expr,)+) => {
+        $(
+            /// Alternate function marker
+            pub struct $AF;
+
+            impl AlternateFunction for $AF {
+                const NUMBER: u8 = $n;
+            }
+        )+
+    };
+}
+
+alternate_functions! {
+    AF0: 0,
+    AF1: 1,
+    AF2: 2,
+    AF3: 3,
+    AF4: 4,
+    AF5: 5,
+    AF6: 6,
+    AF7: 7,
+}
+
 /// GPIO Pin speed selection
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Speed {
     Low = 0,
     Medium = 1,
@@ -51,7 +98,18 @@ pub enum Speed {
     VeryHigh = 3,
 }
 
-/// Trigger edgw
+/// Internal pull resistor
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pull {
+    None = 0,
+    Up = 1,
+    Down = 2,
+}
+
+/// Trigger edge
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SignalEdge {
     Rising,
     Falling,
@@ -70,6 +128,98 @@ pub(crate) enum AltFunction {
     AF7 = 7,
 }
 
+/// Pins of port `PORT`, built from output pins with `mask()` and `|`
+pub struct PinMask<PORT> {
+    bits: u16,
+    _port: PhantomData<PORT>,
+}
+
+impl<PORT> Clone for PinMask<PORT> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<PORT> Copy for PinMask<PORT> {}
+
+impl<PORT> PinMask<PORT> {
+    pub(crate) fn new(bits: u16) -> Self {
+        PinMask {
+            bits,
+            _port: PhantomData,
+        }
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.bits
+    }
+}
+
+impl<PORT> core::ops::BitOr for PinMask<PORT> {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        PinMask::new(self.bits | rhs.bits)
+    }
+}
+
+/// Pin with locked configuration
+///
+/// The mode of a locked pin can't change until the next reset, only the
+/// level can be read and set.
+pub struct LockedPin<PIN> {
+    pin: PIN,
+}
+
+impl<PIN> LockedPin<PIN> {
+    pub(crate) fn new(pin: PIN) -> Self {
+        LockedPin { pin }
+    }
+}
+
+impl<PIN: OutputPin> OutputPin for LockedPin<PIN> {
+    type Error = PIN::Error;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_high()
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_low()
+    }
+}
+
+impl<PIN: StatefulOutputPin> StatefulOutputPin for LockedPin<PIN> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        self.pin.is_set_high()
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.pin.is_set_low()
+    }
+}
+
+impl<PIN: StatefulOutputPin> toggleable::Default for LockedPin<PIN> {}
+
+impl<PIN: InputPin> InputPin for LockedPin<PIN> {
+    type Error = PIN::Error;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        self.pin.is_high()
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.pin.is_low()
+    }
+}
+
+macro_rules! record_pin {
+    ($port:expr, $i:expr, $function:ident $(($af:expr))*) => {
+        #[cfg(feature = "pin-registry")]
+        registry::record(registry::PinId::new($port, $i), registry::Function::$function $(($af))*);
+    };
+}
+
 macro_rules! gpio {
     ($GPIOX:ident, $gpiox:ident, $iopxenr:ident, $PXx:ident, $Pxn:expr, [
         $($PXi:ident: ($pxi:ident, $i:expr),)+
@@ -88,6 +238,82 @@ macro_rules! gpio {
                 $(
                     pub $pxi: $PXi<Input<Floating>>,
                 )+
+                pub port: Port,
+            }
+
+            /// Port wide access
+            ///
+            /// Writes update several output pins in one BSRR access, e.g. the
+            /// data lines of a parallel bus:
+            ///
+            /// ```ignore
+            /// let data = d0.mask() | d1.mask() | d2.mask() | d3.mask();
+            /// port.write(data, byte as u16);
+            /// ```
+            pub struct Port {
+                _0: (),
+            }
+
+            impl Port {
+                /// Reads the input levels of all pins
+                pub fn read(&self) -> u16 {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*$GPIOX::ptr()).idr.read().bits() as u16 }
+                }
+
+                /// Reads the output register
+                pub fn read_output(&self) -> u16 {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*$GPIOX::ptr()).odr.read().bits() as u16 }
+                }
+
+                /// Sets the pins in `mask` to the matching bits of `value`
+                pub fn write(&mut self, mask: PinMask<$GPIOX>, value: u16) {
+                    let set = value & mask.bits();
+                    let reset = !value & mask.bits();
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe {
+                        (*$GPIOX::ptr())
+                            .bsrr
+                            .write(|w| w.bits(set as u32 | (reset as u32) << 16))
+                    };
+                }
+
+                /// Drives the pins in `mask` high
+                pub fn set_high(&mut self, mask: PinMask<$GPIOX>) {
+                    self.write(mask, 0xffff);
+                }
+
+                /// Drives the pins in `mask` low
+                pub fn set_low(&mut self, mask: PinMask<$GPIOX>) {
+                    self.write(mask, 0);
+                }
+
+                /// Locks the configuration of the pins in `mask` until reset
+                ///
+                /// A port locks only once per reset, the lock key freezes LCKR
+                /// until then, so lock all pins of the port in one call.
+                /// Returns `false` if the pins in `mask` are not locked.
+                pub fn lock(&mut self, mask: PinMask<$GPIOX>) -> bool {
+                    lock(mask.bits())
+                }
+            }
+
+            /// Runs the LCKR lock sequence for `bits`
+            ///
+            /// Returns `true` if the key and all `bits` are set, once LCKK is
+            /// set LCKR stays frozen until reset.
+            fn lock(bits: u16) -> bool {
+                const LCKK: u32 = 1 << 16;
+                let bits = bits as u32;
+                cortex_m::interrupt::free(|_| unsafe {
+                    let gpio = &(*$GPIOX::ptr());
+                    gpio.lckr.write(|w| w.bits(LCKK | bits));
+                    gpio.lckr.write(|w| w.bits(bits));
+                    gpio.lckr.write(|w| w.bits(LCKK | bits));
+                    gpio.lckr.read();
+                    gpio.lckr.read().bits() & (LCKK | bits) == LCKK | bits
+                })
             }
 
             impl GpioExt for $GPIOX {
@@ -99,6 +325,7 @@ macro_rules! gpio {
                         $(
                             $pxi: $PXi { _mode: PhantomData },
                         )+
+                        port: Port { _0: () },
                     }
                 }
             }
@@ -109,6 +336,20 @@ macro_rules! gpio {
                 _mode: PhantomData<MODE>,
             }
 
+            impl<MODE> $PXx<MODE> {
+                /// Erases the port from the type
+                pub fn erase(self) -> Pin<MODE> {
+                    Pin::new($Pxn, self.i)
+                }
+            }
+
+            impl<MODE> $PXx<Output<MODE>> {
+                /// Mask for `Port` writes
+                pub fn mask(&self) -> PinMask<$GPIOX> {
+                    PinMask::new(1 << self.i)
+                }
+            }
+
             impl<MODE> OutputPin for $PXx<Output<MODE>> {
                 type Error = ();
 
@@ -171,6 +412,62 @@ macro_rules! gpio {
                 }
             }
 
+            #[cfg(feature = "embedded-hal-1")]
+            impl<MODE> embedded_hal_1::digital::ErrorType for $PXx<MODE> {
+                type Error = core::convert::Infallible;
+            }
+
+            #[cfg(feature = "embedded-hal-1")]
+            impl<MODE> embedded_hal_1::digital::OutputPin for $PXx<Output<MODE>> {
+                fn set_high(&mut self) -> Result<(), Self::Error> {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe { (*$GPIOX::ptr()).bsrr.write(|w| w.bits(1 << self.i)) };
+                    Ok(())
+                }
+
+                fn set_low(&mut self) -> Result<(), Self::Error> {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe { (*$GPIOX::ptr()).bsrr.write(|w| w.bits(1 << (self.i + 16))) };
+                    Ok(())
+                }
+            }
+
+            #[cfg(feature = "embedded-hal-1")]
+            impl<MODE> embedded_hal_1::digital::StatefulOutputPin for $PXx<Output<MODE>> {
+                fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+                    Ok(!embedded_hal_1::digital::StatefulOutputPin::is_set_low(self)?)
+                }
+
+                fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    Ok(unsafe { (*$GPIOX::ptr()).odr.read().bits() & (1 << self.i) == 0 })
+                }
+            }
+
+            #[cfg(feature = "embedded-hal-1")]
+            impl<MODE> embedded_hal_1::digital::InputPin for $PXx<Output<MODE>> {
+                fn is_high(&mut self) -> Result<bool, Self::Error> {
+                    Ok(!embedded_hal_1::digital::InputPin::is_low(self)?)
+                }
+
+                fn is_low(&mut self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    Ok(unsafe { (*$GPIOX::ptr()).idr.read().bits() & (1 << self.i) == 0 })
+                }
+            }
+
+            #[cfg(feature = "embedded-hal-1")]
+            impl<MODE> embedded_hal_1::digital::InputPin for $PXx<Input<MODE>> {
+                fn is_high(&mut self) -> Result<bool, Self::Error> {
+                    Ok(!embedded_hal_1::digital::InputPin::is_low(self)?)
+                }
+
+                fn is_low(&mut self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    Ok(unsafe { (*$GPIOX::ptr()).idr.read().bits() & (1 << self.i) == 0 })
+                }
+            }
+
             $(
                 pub struct $PXi<MODE> {
                     _mode: PhantomData<MODE>,
@@ -207,6 +504,22 @@ macro_rules! gpio {
                 }
 
                 impl<MODE> $PXi<MODE> {
+                    /// Locks the pin configuration until the next reset
+                    ///
+                    /// A port locks only once per reset, pins of a port locked
+                    /// together need `Port::lock`.
+                    pub fn lock(self) -> LockedPin<Self> {
+                        let locked = lock(1 << $i);
+                        debug_assert!(locked, "GPIO lock sequence failed");
+                        LockedPin::new(self)
+                    }
+
+                    /// Converts the pin into a `DynamicPin` as floating input
+                    pub fn into_dynamic(self) -> DynamicPin {
+                        record_pin!($Pxn, $i, Input);
+                        Pin::<MODE>::new($Pxn, $i).into_dynamic()
+                    }
+
                     /// Configures the pin to operate as a floating input pin
                     pub fn into_floating_input(self) -> $PXi<Input<Floating>> {
                         let offset = 2 * $i;
@@ -219,6 +532,7 @@ macro_rules! gpio {
                                 w.bits(r.bits() & !(0b11 << offset))
                             })
                         };
+                        record_pin!($Pxn, $i, Input);
                         $PXi { _mode: PhantomData }
                     }
 
@@ -234,6 +548,7 @@ macro_rules! gpio {
                                 w.bits(r.bits() & !(0b11 << offset))
                             })
                         };
+                        record_pin!($Pxn, $i, Input);
                         $PXi { _mode: PhantomData }
                     }
 
@@ -249,6 +564,7 @@ macro_rules! gpio {
                                 w.bits(r.bits() & !(0b11 << offset))
                             })
                         };
+                        record_pin!($Pxn, $i, Input);
                         $PXi { _mode: PhantomData }
                     }
 
@@ -264,6 +580,7 @@ macro_rules! gpio {
                                 w.bits((r.bits() & !(0b11 << offset)) | (0b11 << offset))
                             });
                         }
+                        record_pin!($Pxn, $i, Analog);
                         $PXi { _mode: PhantomData }
                     }
 
@@ -282,6 +599,30 @@ macro_rules! gpio {
                                 w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
                             })
                         };
+                        record_pin!($Pxn, $i, Output);
+                        $PXi { _mode: PhantomData }
+                    }
+
+                    /// Configures the pin to operate as an open drain output pin with the
+                    /// internal pull-up enabled
+                    ///
+                    /// The weak pull-up (about 40k) suffices for slow bit-banged buses and
+                    /// wired-OR signals on short traces.
+                    pub fn into_open_drain_output_with_pull_up(self) -> $PXi<Output<OpenDrain>> {
+                        let offset = 2 * $i;
+                        unsafe {
+                            let gpio = &(*$GPIOX::ptr());
+                            gpio.pupdr.modify(|r, w| {
+                                w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
+                            });
+                            gpio.otyper.modify(|r, w| {
+                                w.bits(r.bits() | (0b1 << $i))
+                            });
+                            gpio.moder.modify(|r, w| {
+                                w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
+                            })
+                        };
+                        record_pin!($Pxn, $i, Output);
                         $PXi { _mode: PhantomData }
                     }
 
@@ -300,16 +641,16 @@ macro_rules! gpio {
                                 w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
                             })
                         };
+                        record_pin!($Pxn, $i, Output);
                         $PXi { _mode: PhantomData }
                     }
 
                     /// Configures the pin as external trigger
+                    ///
+                    /// Switches the pin to input, the pull configuration is kept.
                     pub fn listen(self, edge: SignalEdge, exti: &mut EXTI) -> $PXi<Input<PushPull>> {
                         let offset = 2 * $i;
                         unsafe {
-                            &(*$GPIOX::ptr()).pupdr.modify(|r, w| {
-                                w.bits(r.bits() & !(0b11 << offset))
-                            });
                             &(*$GPIOX::ptr()).moder.modify(|r, w| {
                                 w.bits(r.bits() & !(0b11 << offset))
                             })
@@ -327,12 +668,13 @@ macro_rules! gpio {
                             8..=11 => exti.exticr3.modify(|r, w| unsafe {
                                 w.bits(r.bits() & reset | mask)
                             }),
-                            12..=16 => exti.exticr4.modify(|r, w| unsafe {
+                            12..=15 => exti.exticr4.modify(|r, w| unsafe {
                                 w.bits(r.bits() & reset | mask)
                             }),
                             _ => unreachable!(),
                         }
                         exti.listen(Event::from_code($i), edge);
+                        record_pin!($Pxn, $i, Input);
                         $PXi { _mode: PhantomData }
                     }
 
@@ -347,9 +689,68 @@ macro_rules! gpio {
                         self
                     }
 
+                    /// Set internal pull resistor, kept in output and alternate modes
+                    pub fn set_pull(mut self, pull: Pull) -> Self {
+                        self.pull(pull);
+                        self
+                    }
+
+                    /// Enables or disables the internal pull-up
+                    pub fn internal_pull_up(&mut self, on: bool) {
+                        self.pull(if on { Pull::Up } else { Pull::None });
+                    }
+
+                    /// Enables or disables the internal pull-down
+                    pub fn internal_pull_down(&mut self, on: bool) {
+                        self.pull(if on { Pull::Down } else { Pull::None });
+                    }
+
+                    /// Drives output and alternate function as open-drain
+                    ///
+                    /// Applies to alternate functions selected later, e.g. for I2C or
+                    /// one-wire on a pin handed to a peripheral.
+                    pub fn set_open_drain(self) -> Self {
+                        unsafe {
+                            (*$GPIOX::ptr()).otyper.modify(|r, w| {
+                                w.bits(r.bits() | (0b1 << $i))
+                            })
+                        };
+                        self
+                    }
+
+                    /// Drives output and alternate function as push-pull
+                    pub fn set_push_pull(self) -> Self {
+                        unsafe {
+                            (*$GPIOX::ptr()).otyper.modify(|r, w| {
+                                w.bits(r.bits() & !(0b1 << $i))
+                            })
+                        };
+                        self
+                    }
+
+                    fn pull(&mut self, pull: Pull) {
+                        let offset = 2 * $i;
+                        unsafe {
+                            (*$GPIOX::ptr()).pupdr.modify(|r, w| {
+                                w.bits((r.bits() & !(0b11 << offset)) | ((pull as u32) << offset))
+                            })
+                        };
+                    }
+
+                    /// Configures the pin for the alternate function `AF`
+                    ///
+                    /// The pin mode is set right away, the output type and pull are kept.
+                    pub fn into_alternate<AF: AlternateFunction>(self) -> $PXi<Alternate<AF>> {
+                        self.set_af(AF::NUMBER as u32);
+                        $PXi { _mode: PhantomData }
+                    }
+
                     #[allow(dead_code)]
                     pub(crate) fn set_alt_mode(&self, mode: AltFunction) {
-                        let mode = mode as u32;
+                        self.set_af(mode as u32);
+                    }
+
+                    fn set_af(&self, mode: u32) {
                         let offset = 2 * $i;
                         let offset2 = 4 * $i;
                         unsafe {
@@ -368,6 +769,7 @@ macro_rules! gpio {
                                 w.bits((r.bits() & !(0b11 << offset)) | (0b10 << offset))
                             });
                         }
+                        record_pin!($Pxn, $i, Alternate(mode as u8));
                     }
                 }
 
@@ -379,6 +781,16 @@ macro_rules! gpio {
                     pub fn downgrade(self) -> $PXx<Output<MODE>> {
                         $PXx { i: $i, _mode: self._mode }
                     }
+
+                    /// Erases port and pin number from the type
+                    pub fn erase(self) -> Pin<Output<MODE>> {
+                        Pin::new($Pxn, $i)
+                    }
+
+                    /// Mask for `Port` writes
+                    pub fn mask(&self) -> PinMask<$GPIOX> {
+                        PinMask::new(1 << $i)
+                    }
                 }
 
                 impl<MODE> OutputPin for $PXi<Output<MODE>> {
@@ -436,6 +848,31 @@ macro_rules! gpio {
                     pub fn downgrade(self) -> $PXx<Input<MODE>> {
                         $PXx { i: $i, _mode: self._mode }
                     }
+
+                    /// Erases port and pin number from the type
+                    pub fn erase(self) -> Pin<Input<MODE>> {
+                        Pin::new($Pxn, $i)
+                    }
+
+                    /// EXTI event of the pin line
+                    pub fn event(&self) -> Event {
+                        Event::from_code($i)
+                    }
+
+                    /// Masks the interrupt and clears the triggers of the pin line
+                    pub fn unlisten(&mut self, exti: &mut EXTI) {
+                        exti.unlisten(self.event());
+                    }
+
+                    /// Returns `true` if `edge` is pending on the pin line
+                    pub fn is_pending(&self, edge: SignalEdge, exti: &EXTI) -> bool {
+                        exti.is_pending(self.event(), edge)
+                    }
+
+                    /// Clears pending edges of the pin line
+                    pub fn unpend(&mut self, exti: &mut EXTI) {
+                        exti.unpend(self.event());
+                    }
                 }
 
                 impl<MODE> InputPin for $PXi<Input<MODE>> {
@@ -452,6 +889,62 @@ macro_rules! gpio {
                         Ok(is_low)
                     }
                 }
+
+                #[cfg(feature = "embedded-hal-1")]
+                impl<MODE> embedded_hal_1::digital::ErrorType for $PXi<MODE> {
+                    type Error = core::convert::Infallible;
+                }
+
+                #[cfg(feature = "embedded-hal-1")]
+                impl<MODE> embedded_hal_1::digital::OutputPin for $PXi<Output<MODE>> {
+                    fn set_high(&mut self) -> Result<(), Self::Error> {
+                        // NOTE(unsafe) atomic write to a stateless register
+                        unsafe { (*$GPIOX::ptr()).bsrr.write(|w| w.bits(1 << $i)) };
+                        Ok(())
+                    }
+
+                    fn set_low(&mut self) -> Result<(), Self::Error> {
+                        // NOTE(unsafe) atomic write to a stateless register
+                        unsafe { (*$GPIOX::ptr()).bsrr.write(|w| w.bits(1 << ($i + 16))) };
+                        Ok(())
+                    }
+                }
+
+                #[cfg(feature = "embedded-hal-1")]
+                impl<MODE> embedded_hal_1::digital::StatefulOutputPin for $PXi<Output<MODE>> {
+                    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+                        Ok(!embedded_hal_1::digital::StatefulOutputPin::is_set_low(self)?)
+                    }
+
+                    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+                        // NOTE(unsafe) atomic read with no side effects
+                        Ok(unsafe { (*$GPIOX::ptr()).odr.read().bits() & (1 << $i) == 0 })
+                    }
+                }
+
+                #[cfg(feature = "embedded-hal-1")]
+                impl<MODE> embedded_hal_1::digital::InputPin for $PXi<Output<MODE>> {
+                    fn is_high(&mut self) -> Result<bool, Self::Error> {
+                        Ok(!embedded_hal_1::digital::InputPin::is_low(self)?)
+                    }
+
+                    fn is_low(&mut self) -> Result<bool, Self::Error> {
+                        // NOTE(unsafe) atomic read with no side effects
+                        Ok(unsafe { (*$GPIOX::ptr()).idr.read().bits() & (1 << $i) == 0 })
+                    }
+                }
+
+                #[cfg(feature = "embedded-hal-1")]
+                impl<MODE> embedded_hal_1::digital::InputPin for $PXi<Input<MODE>> {
+                    fn is_high(&mut self) -> Result<bool, Self::Error> {
+                        Ok(!embedded_hal_1::digital::InputPin::is_low(self)?)
+                    }
+
+                    fn is_low(&mut self) -> Result<bool, Self::Error> {
+                        // NOTE(unsafe) atomic read with no side effects
+                        Ok(unsafe { (*$GPIOX::ptr()).idr.read().bits() & (1 << $i) == 0 })
+                    }
+                }
             )+
 
             impl<TYPE> $PXx<TYPE> {
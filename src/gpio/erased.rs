@@ -0,0 +1,333 @@
+//! Fully erased pins
+//!
+//! `Pin<MODE>` carries port and pin number at runtime, so pins of different
+//! ports can be stored in one array, e.g. keypad rows and columns:
+//!
+//! ```ignore
+//! let rows = [
+//!     gpioa.pa0.into_push_pull_output().erase(),
+//!     gpiob.pb3.into_push_pull_output().erase(),
+//! ];
+//! ```
+//!
+//! `DynamicPin` switches between input and output at runtime, for
+//! bit-banged buses like one-wire.
+use core::marker::PhantomData;
+
+use hal::digital::v2::{toggleable, InputPin, OutputPin, StatefulOutputPin};
+
+use super::{Floating, Input, OpenDrain, Output, PullDown, PullUp, PushPull};
+use crate::stm32::GPIOA;
+
+/// Distance between the GPIO ports
+const PORT_STRIDE: usize = 0x400;
+
+// Register offsets in words
+const MODER: usize = 0;
+const OTYPER: usize = 1;
+const PUPDR: usize = 3;
+const IDR: usize = 4;
+const ODR: usize = 5;
+const BSRR: usize = 6;
+
+/// Pin erased over port and number
+pub struct Pin<MODE> {
+    port: u8,
+    i: u8,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE> Pin<MODE> {
+    pub(crate) fn new(port: u8, i: u8) -> Self {
+        Pin {
+            port,
+            i,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Port index, 0 for GPIOA
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    pub fn pin_number(&self) -> u8 {
+        self.i
+    }
+
+    /// Converts the pin into a `DynamicPin` as floating input
+    pub fn into_dynamic(self) -> DynamicPin {
+        let mut pin = DynamicPin {
+            port: self.port,
+            i: self.i,
+            mode: DynamicMode::Floating,
+        };
+        pin.make_floating_input();
+        pin
+    }
+}
+
+impl<MODE> OutputPin for Pin<Output<MODE>> {
+    type Error = ();
+
+    fn set_high(&mut self) -> Result<(), ()> {
+        set(self.port, self.i, true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), ()> {
+        set(self.port, self.i, false);
+        Ok(())
+    }
+}
+
+impl<MODE> StatefulOutputPin for Pin<Output<MODE>> {
+    fn is_set_high(&self) -> Result<bool, ()> {
+        Ok(is_set(self.port, self.i))
+    }
+
+    fn is_set_low(&self) -> Result<bool, ()> {
+        Ok(!is_set(self.port, self.i))
+    }
+}
+
+impl<MODE> toggleable::Default for Pin<Output<MODE>> {}
+
+impl<MODE> InputPin for Pin<Output<MODE>> {
+    type Error = ();
+
+    fn is_high(&self) -> Result<bool, ()> {
+        Ok(is_high(self.port, self.i))
+    }
+
+    fn is_low(&self) -> Result<bool, ()> {
+        Ok(!is_high(self.port, self.i))
+    }
+}
+
+impl<MODE> InputPin for Pin<Input<MODE>> {
+    type Error = ();
+
+    fn is_high(&self) -> Result<bool, ()> {
+        Ok(is_high(self.port, self.i))
+    }
+
+    fn is_low(&self) -> Result<bool, ()> {
+        Ok(!is_high(self.port, self.i))
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<MODE> embedded_hal_1::digital::ErrorType for Pin<MODE> {
+    type Error = core::convert::Infallible;
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<MODE> embedded_hal_1::digital::OutputPin for Pin<Output<MODE>> {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        set(self.port, self.i, true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        set(self.port, self.i, false);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<MODE> embedded_hal_1::digital::StatefulOutputPin for Pin<Output<MODE>> {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(is_set(self.port, self.i))
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!is_set(self.port, self.i))
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<MODE> embedded_hal_1::digital::InputPin for Pin<Input<MODE>> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(is_high(self.port, self.i))
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!is_high(self.port, self.i))
+    }
+}
+
+/// Current mode of a `DynamicPin`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DynamicMode {
+    Floating,
+    PullUp,
+    PullDown,
+    PushPull,
+    OpenDrain,
+    OpenDrainPullUp,
+}
+
+impl DynamicMode {
+    fn is_output(self) -> bool {
+        matches!(
+            self,
+            DynamicMode::PushPull | DynamicMode::OpenDrain | DynamicMode::OpenDrainPullUp
+        )
+    }
+}
+
+/// Output access to a `DynamicPin` in an input mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PinModeError;
+
+/// Erased pin reconfigurable between input and output at runtime
+///
+/// The level can be read in every mode, setting it fails in input modes.
+pub struct DynamicPin {
+    port: u8,
+    i: u8,
+    mode: DynamicMode,
+}
+
+impl DynamicPin {
+    pub fn mode(&self) -> DynamicMode {
+        self.mode
+    }
+
+    pub fn make_floating_input(&mut self) {
+        self.configure(DynamicMode::Floating, 0b00, 0b00, false);
+    }
+
+    pub fn make_pull_up_input(&mut self) {
+        self.configure(DynamicMode::PullUp, 0b00, 0b01, false);
+    }
+
+    pub fn make_pull_down_input(&mut self) {
+        self.configure(DynamicMode::PullDown, 0b00, 0b10, false);
+    }
+
+    pub fn make_push_pull_output(&mut self) {
+        self.configure(DynamicMode::PushPull, 0b01, 0b00, false);
+    }
+
+    pub fn make_open_drain_output(&mut self) {
+        self.configure(DynamicMode::OpenDrain, 0b01, 0b00, true);
+    }
+
+    /// Open drain output with the internal pull-up, e.g. for one-wire
+    pub fn make_open_drain_output_with_pull_up(&mut self) {
+        self.configure(DynamicMode::OpenDrainPullUp, 0b01, 0b01, true);
+    }
+
+    /// Converts back to a typed erased pin
+    pub fn into_floating_input(mut self) -> Pin<Input<Floating>> {
+        self.make_floating_input();
+        Pin::new(self.port, self.i)
+    }
+
+    pub fn into_pull_up_input(mut self) -> Pin<Input<PullUp>> {
+        self.make_pull_up_input();
+        Pin::new(self.port, self.i)
+    }
+
+    pub fn into_pull_down_input(mut self) -> Pin<Input<PullDown>> {
+        self.make_pull_down_input();
+        Pin::new(self.port, self.i)
+    }
+
+    pub fn into_push_pull_output(mut self) -> Pin<Output<PushPull>> {
+        self.make_push_pull_output();
+        Pin::new(self.port, self.i)
+    }
+
+    pub fn into_open_drain_output(mut self) -> Pin<Output<OpenDrain>> {
+        self.make_open_drain_output();
+        Pin::new(self.port, self.i)
+    }
+
+    fn configure(&mut self, mode: DynamicMode, moder: u32, pupdr: u32, open_drain: bool) {
+        let offset = 2 * self.i as u32;
+        cortex_m::interrupt::free(|_| unsafe {
+            modify(self.port, PUPDR, 0b11 << offset, pupdr << offset);
+            modify(self.port, OTYPER, 1 << self.i, (open_drain as u32) << self.i);
+            modify(self.port, MODER, 0b11 << offset, moder << offset);
+        });
+        self.mode = mode;
+    }
+}
+
+impl OutputPin for DynamicPin {
+    type Error = PinModeError;
+
+    fn set_high(&mut self) -> Result<(), PinModeError> {
+        if !self.mode.is_output() {
+            return Err(PinModeError);
+        }
+        set(self.port, self.i, true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), PinModeError> {
+        if !self.mode.is_output() {
+            return Err(PinModeError);
+        }
+        set(self.port, self.i, false);
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for DynamicPin {
+    fn is_set_high(&self) -> Result<bool, PinModeError> {
+        if !self.mode.is_output() {
+            return Err(PinModeError);
+        }
+        Ok(is_set(self.port, self.i))
+    }
+
+    fn is_set_low(&self) -> Result<bool, PinModeError> {
+        self.is_set_high().map(|high| !high)
+    }
+}
+
+impl toggleable::Default for DynamicPin {}
+
+impl InputPin for DynamicPin {
+    type Error = PinModeError;
+
+    fn is_high(&self) -> Result<bool, PinModeError> {
+        Ok(is_high(self.port, self.i))
+    }
+
+    fn is_low(&self) -> Result<bool, PinModeError> {
+        Ok(!is_high(self.port, self.i))
+    }
+}
+
+fn register(port: u8, offset: usize) -> *mut u32 {
+    let base = GPIOA::ptr() as usize + port as usize * PORT_STRIDE;
+    (base as *mut u32).wrapping_add(offset)
+}
+
+unsafe fn modify(port: u8, offset: usize, mask: u32, value: u32) {
+    let reg = register(port, offset);
+    core::ptr::write_volatile(reg, core::ptr::read_volatile(reg) & !mask | value);
+}
+
+fn set(port: u8, i: u8, high: bool) {
+    let bit = if high { 1 << i } else { 1 << (i + 16) };
+    // NOTE(unsafe) atomic write to a stateless register
+    unsafe { core::ptr::write_volatile(register(port, BSRR), bit) };
+}
+
+fn is_set(port: u8, i: u8) -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { core::ptr::read_volatile(register(port, ODR)) & (1 << i) != 0 }
+}
+
+fn is_high(port: u8, i: u8) -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { core::ptr::read_volatile(register(port, IDR)) & (1 << i) != 0 }
+}
@@ -0,0 +1,235 @@
+//! Pin usage registry
+//!
+//! Every pin mode change made by the HAL is recorded here, so a board
+//! configuration can be validated at runtime and dumped as a pin map.
+//! Wrap peripheral constructors in `owned_by` to tag the pins they claim,
+//! and declare pins bonded together on small packages with `shared_pad`:
+//!
+//! ```ignore
+//! registry::shared_pad(PinId::new(0, 9), PinId::new(0, 11));
+//! let serial = registry::owned_by("USART1", || dp.USART1.usart(tx, rx, cfg, &mut rcc));
+//! registry::validate().unwrap_or_else(|_| registry::print_map(&mut debug).unwrap());
+//! ```
+use core::cell::RefCell;
+use core::fmt;
+
+use cortex_m::interrupt::{self, Mutex};
+
+const PORTS: usize = 6;
+const MAX_ALIASES: usize = 8;
+
+/// Pin identifier
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PinId {
+    pub port: u8,
+    pub index: u8,
+}
+
+impl PinId {
+    pub const fn new(port: u8, index: u8) -> Self {
+        PinId { port, index }
+    }
+}
+
+impl fmt::Display for PinId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "P{}{}", (b'A' + self.port) as char, self.index)
+    }
+}
+
+/// Pin function
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Function {
+    Input,
+    Output,
+    Analog,
+    Alternate(u8),
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Function::Input => f.write_str("INPUT"),
+            Function::Output => f.write_str("OUTPUT"),
+            Function::Analog => f.write_str("ANALOG"),
+            Function::Alternate(af) => write!(f, "AF{}", af),
+        }
+    }
+}
+
+/// Recorded pin usage
+#[derive(Clone, Copy, Debug)]
+pub struct Claim {
+    pub function: Function,
+    pub owner: Option<&'static str>,
+}
+
+/// Configuration conflict
+#[derive(Clone, Copy, Debug)]
+pub enum Conflict {
+    /// Pin was claimed by two owners
+    Owner {
+        pin: PinId,
+        first: &'static str,
+        second: &'static str,
+    },
+    /// Two pins bonded to the same package pad are both in use
+    SharedPad { pin: PinId, other: PinId },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Conflict::Owner { pin, first, second } => {
+                write!(f, "{} claimed by {} and {}", pin, first, second)
+            }
+            Conflict::SharedPad { pin, other } => {
+                write!(f, "{} and {} share a package pad", pin, other)
+            }
+        }
+    }
+}
+
+struct Registry {
+    pins: [[Option<Claim>; 16]; PORTS],
+    aliases: [Option<(PinId, PinId)>; MAX_ALIASES],
+    owner: Option<&'static str>,
+    conflict: Option<Conflict>,
+}
+
+static REGISTRY: Mutex<RefCell<Registry>> = Mutex::new(RefCell::new(Registry {
+    pins: [[None; 16]; PORTS],
+    aliases: [None; MAX_ALIASES],
+    owner: None,
+    conflict: None,
+}));
+
+pub(crate) fn record(pin: PinId, function: Function) {
+    interrupt::free(|cs| {
+        let mut reg = REGISTRY.borrow(cs).borrow_mut();
+        let owner = reg.owner;
+        let slot = &mut reg.pins[pin.port as usize][pin.index as usize];
+        let conflict = match (*slot, owner) {
+            (Some(Claim { owner: Some(first), .. }), Some(second)) if first != second => {
+                Some(Conflict::Owner { pin, first, second })
+            }
+            _ => None,
+        };
+        *slot = Some(Claim { function, owner });
+        if reg.conflict.is_none() {
+            reg.conflict = conflict;
+        }
+    });
+}
+
+/// Tags pins claimed inside the closure with the owner name
+pub fn owned_by<F, R>(owner: &'static str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let prev = interrupt::free(|cs| {
+        let mut reg = REGISTRY.borrow(cs).borrow_mut();
+        let prev = reg.owner;
+        reg.owner = Some(owner);
+        prev
+    });
+    let res = f();
+    interrupt::free(|cs| REGISTRY.borrow(cs).borrow_mut().owner = prev);
+    res
+}
+
+/// Forgets pin usage, e.g. after the peripheral owning it was released
+pub fn release(pin: PinId) {
+    interrupt::free(|cs| {
+        REGISTRY.borrow(cs).borrow_mut().pins[pin.port as usize][pin.index as usize] = None;
+    });
+}
+
+/// Declares two pins bonded to the same package pad
+pub fn shared_pad(a: PinId, b: PinId) {
+    interrupt::free(|cs| {
+        let mut reg = REGISTRY.borrow(cs).borrow_mut();
+        let slot = reg.aliases.iter_mut().find(|a| a.is_none());
+        *slot.expect("too many shared pads") = Some((a, b));
+    });
+}
+
+/// Returns recorded pin usage
+pub fn claim(pin: PinId) -> Option<Claim> {
+    interrupt::free(|cs| REGISTRY.borrow(cs).borrow().pins[pin.port as usize][pin.index as usize])
+}
+
+/// Checks recorded configuration for conflicts
+pub fn validate() -> Result<(), Conflict> {
+    interrupt::free(|cs| {
+        let reg = REGISTRY.borrow(cs).borrow();
+        if let Some(conflict) = reg.conflict {
+            return Err(conflict);
+        }
+        let in_use = |pin: &PinId| {
+            !matches!(
+                reg.pins[pin.port as usize][pin.index as usize],
+                Some(Claim { function: Function::Input, owner: None }) | None
+            )
+        };
+        for (a, b) in reg.aliases.iter().flatten() {
+            if in_use(a) && in_use(b) {
+                return Err(Conflict::SharedPad { pin: *a, other: *b });
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Writes human-readable pin map
+pub fn print_map<W: fmt::Write>(out: &mut W) -> fmt::Result {
+    writeln!(out, "PIN   FUNCTION  OWNER\r")?;
+    for port in 0..PORTS as u8 {
+        for index in 0..16 {
+            let pin = PinId::new(port, index);
+            if let Some(claim) = claim(pin) {
+                let function = ArgWidth(claim.function);
+                writeln!(
+                    out,
+                    "{:<5} {:<9} {}\r",
+                    ArgWidth(pin),
+                    function,
+                    claim.owner.unwrap_or("-")
+                )?;
+            }
+        }
+    }
+    match validate() {
+        Ok(()) => writeln!(out, "OK\r"),
+        Err(conflict) => writeln!(out, "CONFLICT: {}\r", conflict),
+    }
+}
+
+/// Formats value into a buffer so the padding flags are honored
+struct ArgWidth<T>(T);
+
+impl<T: fmt::Display> fmt::Display for ArgWidth<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut buf = Buf { data: [0; 16], len: 0 };
+        fmt::write(&mut buf, format_args!("{}", self.0))?;
+        f.pad(core::str::from_utf8(&buf.data[..buf.len]).map_err(|_| fmt::Error)?)
+    }
+}
+
+struct Buf {
+    data: [u8; 16],
+    len: usize,
+}
+
+impl fmt::Write for Buf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let bytes = s.as_bytes();
+        let end = self.len + bytes.len();
+        if end > self.data.len() {
+            return Err(fmt::Error);
+        }
+        self.data[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! # Hardware-in-the-loop tests
+//!
+//! Test firmware entry points exercising the drivers on a real board with
+//! loopback wiring (TX to RX, MOSI to MISO) and an I2C EEPROM. Results are
+//! reported with the `factory_test` line protocol, a host runner waits for
+//! the `DONE <passed> <failed>` line and fails the CI job if `failed != 0`.
+use core::fmt;
+
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+use hal::blocking::{i2c, spi};
+use hal::serial;
+use hal::timer::CountDown;
+
+use crate::factory_test::{FactoryTest, Outcome};
+use crate::time::{Hertz, MicroSecond};
+
+/// Polling iterations before a loopback byte is considered lost
+const LOOPBACK_TIMEOUT: u32 = 100_000;
+
+/// HIL test failure
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HilError {
+    /// Driver returned an error
+    Bus,
+    /// Loopback byte was not received
+    Timeout,
+    /// Data read back differs, carries index of the first mismatch
+    Mismatch(usize),
+    /// Timer period deviates, carries error in ppm
+    Inaccurate(i32),
+}
+
+impl fmt::Display for HilError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HilError::Bus => f.write_str("error=bus"),
+            HilError::Timeout => f.write_str("error=timeout"),
+            HilError::Mismatch(idx) => write!(f, "error=mismatch index={}", idx),
+            HilError::Inaccurate(ppm) => write!(f, "error=inaccurate ppm={}", ppm),
+        }
+    }
+}
+
+/// Sends all byte values and expects them back on a TX-RX loopback
+pub fn uart_loopback<S>(serial: &mut S) -> Result<(), HilError>
+where
+    S: serial::Read<u8> + serial::Write<u8>,
+{
+    // drain stale data
+    while serial.read().is_ok() {}
+    for byte in 0..=255u8 {
+        nb::block!(serial.write(byte)).map_err(|_| HilError::Bus)?;
+        let mut timeout = LOOPBACK_TIMEOUT;
+        let echo = loop {
+            match serial.read() {
+                Ok(echo) => break echo,
+                Err(nb::Error::WouldBlock) if timeout > 0 => timeout -= 1,
+                Err(nb::Error::WouldBlock) => return Err(HilError::Timeout),
+                Err(nb::Error::Other(_)) => return Err(HilError::Bus),
+            }
+        };
+        if echo != byte {
+            return Err(HilError::Mismatch(byte as usize));
+        }
+    }
+    Ok(())
+}
+
+/// Transfers a test pattern on a MOSI-MISO loopback
+pub fn spi_loopback<SPI>(spi: &mut SPI) -> Result<(), HilError>
+where
+    SPI: spi::Transfer<u8>,
+{
+    let mut buf = [0u8; 64];
+    for (idx, byte) in buf.iter_mut().enumerate() {
+        *byte = (idx as u8).wrapping_mul(37) ^ 0xa5;
+    }
+    let expected = buf;
+    let received = spi.transfer(&mut buf).map_err(|_| HilError::Bus)?;
+    match received.iter().zip(expected.iter()).position(|(a, b)| a != b) {
+        Some(idx) => Err(HilError::Mismatch(idx)),
+        None => Ok(()),
+    }
+}
+
+/// Writes a page to a 24Cxx EEPROM with 8-bit memory address and reads it back
+///
+/// `page` must not be longer than the EEPROM page size (at most 16 bytes).
+pub fn i2c_eeprom<I2C>(i2c: &mut I2C, addr: u8, mem_addr: u8, page: &[u8]) -> Result<(), HilError>
+where
+    I2C: i2c::Write + i2c::WriteRead,
+{
+    assert!(page.len() <= 16);
+    let mut frame = [0u8; 17];
+    frame[0] = mem_addr;
+    frame[1..=page.len()].copy_from_slice(page);
+    i2c.write(addr, &frame[..=page.len()])
+        .map_err(|_| HilError::Bus)?;
+
+    // acknowledge polling while the write cycle is in progress
+    let mut readback = [0u8; 16];
+    let mut timeout = LOOPBACK_TIMEOUT;
+    while i2c
+        .write_read(addr, &[mem_addr], &mut readback[..page.len()])
+        .is_err()
+    {
+        if timeout == 0 {
+            return Err(HilError::Timeout);
+        }
+        timeout -= 1;
+    }
+    match readback.iter().zip(page.iter()).position(|(a, b)| a != b) {
+        Some(idx) => Err(HilError::Mismatch(idx)),
+        None => Ok(()),
+    }
+}
+
+/// Measures `timer` period against SysTick running at `core_clk` (HCLK / 8)
+///
+/// Fails if the error exceeds `tolerance_ppm`. The period must be shorter
+/// than the SysTick range (2^24 core clock cycles).
+pub fn timer_accuracy<T>(
+    timer: &mut T,
+    syst: &mut SYST,
+    core_clk: Hertz,
+    period: MicroSecond,
+    tolerance_ppm: u32,
+) -> Result<i32, HilError>
+where
+    T: CountDown<Time = MicroSecond>,
+{
+    let expected = period.cycles(core_clk);
+    assert!(expected < 0x00ff_ffff);
+    syst.set_clock_source(SystClkSource::External);
+    syst.set_reload(0x00ff_ffff);
+
+    timer.start(period);
+    // synchronize with the first timeout
+    nb::block!(timer.wait()).ok();
+    syst.clear_current();
+    syst.enable_counter();
+    nb::block!(timer.wait()).ok();
+    let measured = 0x00ff_ffff - SYST::get_current();
+    syst.disable_counter();
+
+    let ppm = (measured as i64 - expected as i64) * 1_000_000 / expected as i64;
+    if ppm.unsigned_abs() > tolerance_ppm as u64 {
+        Err(HilError::Inaccurate(ppm as i32))
+    } else {
+        Ok(ppm as i32)
+    }
+}
+
+/// Reports HIL test result
+pub fn report<W, T>(runner: &mut FactoryTest<W>, name: &str, result: Result<T, HilError>) -> Outcome
+where
+    W: fmt::Write,
+    T: fmt::Debug,
+{
+    match result {
+        Ok(value) => runner.custom(name, true, format_args!("result={:?}", value)),
+        Err(err) => runner.custom(name, false, format_args!("{}", err)),
+    }
+}
@@ -1,12 +1,20 @@
 //! I2C
 use hal::blocking::i2c::{Read, Write, WriteRead};
 
+#[cfg(feature = "async")]
+use crate::asynch::{AsyncInterrupt, AsyncWaker};
 use crate::gpio::{gpioa::*, gpiob::*};
-use crate::gpio::{AltFunction, OpenDrain, Output};
-use crate::rcc::Rcc;
-use crate::stm32::{I2C1, I2C2};
+use crate::gpio::{AltFunction, Alternate, OpenDrain, Output, AF6};
+use crate::rcc::{Enable, Rcc};
+use crate::stm32::{Interrupt, I2C1, I2C2};
 use crate::time::Hertz;
 use core::cmp;
+#[cfg(feature = "async")]
+use core::future::poll_fn;
+#[cfg(feature = "async")]
+use core::task::Poll;
+#[cfg(feature = "embedded-hal-1")]
+use embedded_hal_1::i2c::Operation;
 
 pub struct Config {
     speed: Option<Hertz>,
@@ -79,18 +87,42 @@ pub struct I2c<I2C, SDA, SCL> {
     scl: SCL,
 }
 
-// I2C SDA pin
+// I2C SDA pin, an open drain output or an open drain `Alternate<AF6>` pin
 pub trait SDAPin<I2C> {
     fn setup(&self);
 }
 
-// I2C SCL pin
+// I2C SCL pin, an open drain output or an open drain `Alternate<AF6>` pin
 pub trait SCLPin<I2C> {
     fn setup(&self);
 }
 
+// ICR
+const ERROR_FLAGS: u32 = 0b11_1111 << 8;
+
+/// I2C interrupt event
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// Transmit data register empty
+    Txis = 1 << 1,
+    /// Receive data register not empty
+    Rxne = 1 << 2,
+    /// Own address matched in slave mode
+    AddressMatch = 1 << 3,
+    /// NACK received
+    Nack = 1 << 4,
+    /// STOP condition detected
+    Stop = 1 << 5,
+    /// Transfer complete
+    TransferComplete = 1 << 6,
+    /// Bus error, arbitration loss, overrun, PEC error, timeout or SMBus alert
+    Error = 1 << 7,
+}
+
 // I2C error
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     Overrun,
     Nack,
@@ -99,6 +131,69 @@ pub enum Error {
     ArbitrationLost,
 }
 
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::i2c::Error for Error {
+    fn kind(&self) -> embedded_hal_1::i2c::ErrorKind {
+        use embedded_hal_1::i2c::{ErrorKind, NoAcknowledgeSource};
+        match self {
+            Error::Overrun => ErrorKind::Overrun,
+            Error::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            Error::PECError => ErrorKind::Other,
+            Error::BusError => ErrorKind::Bus,
+            Error::ArbitrationLost => ErrorKind::ArbitrationLoss,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<I2C, SDA, SCL> embedded_hal_1::i2c::ErrorType for I2c<I2C, SDA, SCL> {
+    type Error = Error;
+}
+
+/// Part of a transaction sent as one transfer
+#[cfg(feature = "embedded-hal-1")]
+struct Segment {
+    read: bool,
+    len: usize,
+    /// Next operation continues this transfer
+    reload: bool,
+    last: bool,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl Segment {
+    /// Adjacent operations of the same kind are merged into one transfer
+    fn new(operations: &[Operation<'_>], idx: usize) -> Self {
+        let (read, len) = match &operations[idx] {
+            Operation::Read(buf) => (true, buf.len()),
+            Operation::Write(bytes) => (false, bytes.len()),
+        };
+        assert!(len < 256);
+        let reload = match operations.get(idx + 1) {
+            Some(Operation::Read(_)) => read,
+            Some(Operation::Write(_)) => !read,
+            None => false,
+        };
+        Segment {
+            read,
+            len,
+            reload,
+            last: idx + 1 == operations.len(),
+        }
+    }
+}
+
+/// Transfer state awaited by async transactions
+#[cfg(feature = "async")]
+#[derive(Clone, Copy)]
+enum Flag {
+    Txis,
+    Rxne,
+    TransferComplete,
+    TransferReload,
+    Stop,
+}
+
 pub trait I2cExt<I2C> {
     fn i2c<SDA, SCL>(self, sda: SDA, scl: SCL, config: Config, rcc: &mut Rcc) -> I2c<I2C, SDA, SCL>
     where
@@ -144,24 +239,32 @@ macro_rules! busy_wait {
 }
 
 macro_rules! i2c {
-    ($I2CX:ident, $i2cx:ident, $i2cxen:ident, $i2crst:ident,
-        sda: [ $($PSDA:ty,)+ ],
-        scl: [ $($PSCL:ty,)+ ],
+    ($I2CX:ident, $i2cx:ident, $i2cxen:ident, $i2crst:ident, $irq:ident,
+        sda: [ $($PSDA:ident,)+ ],
+        scl: [ $($PSCL:ident,)+ ],
     ) => {
         $(
-            impl SDAPin<$I2CX> for $PSDA {
+            impl SDAPin<$I2CX> for $PSDA<Output<OpenDrain>> {
                 fn setup(&self) {
                     self.set_alt_mode(AltFunction::AF6)
                 }
             }
+
+            impl SDAPin<$I2CX> for $PSDA<Alternate<AF6>> {
+                fn setup(&self) {}
+            }
         )+
 
         $(
-            impl SCLPin<$I2CX> for $PSCL {
+            impl SCLPin<$I2CX> for $PSCL<Output<OpenDrain>> {
                 fn setup(&self) {
                     self.set_alt_mode(AltFunction::AF6)
                 }
             }
+
+            impl SCLPin<$I2CX> for $PSCL<Alternate<AF6>> {
+                fn setup(&self) {}
+            }
         )+
 
         impl I2cExt<$I2CX> for $I2CX {
@@ -220,6 +323,43 @@ macro_rules! i2c {
             pub fn release(self) -> ($I2CX, SDA, SCL) {
                 (self.i2c, self.sda, self.scl)
             }
+
+            /// Disables the I2C and its clock, returns the peripheral and pins
+            pub fn release_and_disable(self, rcc: &mut Rcc) -> ($I2CX, SDA, SCL) {
+                self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                $I2CX::disable(rcc);
+                (self.i2c, self.sda, self.scl)
+            }
+
+            /// NVIC interrupt line of the I2C
+            pub const INTERRUPT: Interrupt = Interrupt::$irq;
+
+            pub fn interrupt(&self) -> Interrupt {
+                Self::INTERRUPT
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self, event: Event) {
+                self.i2c.cr1.modify(|r, w| unsafe { w.bits(r.bits() | event as u32) });
+            }
+
+            /// Stop listening for an interrupt event
+            pub fn unlisten(&mut self, event: Event) {
+                self.i2c.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !(event as u32)) });
+            }
+
+            /// Clears the flag of a pending `event`
+            ///
+            /// `Txis`, `Rxne` and `TransferComplete` are only cleared by the
+            /// data transfer itself.
+            pub fn clear_interrupt(&mut self, event: Event) {
+                let bits = match event {
+                    Event::AddressMatch | Event::Nack | Event::Stop => event as u32,
+                    Event::Error => ERROR_FLAGS,
+                    _ => return,
+                };
+                self.i2c.icr.write(|w| unsafe { w.bits(bits) });
+            }
         }
 
         impl<SDA, SCL> WriteRead for I2c<$I2CX, SDA, SCL> {
@@ -302,6 +442,204 @@ macro_rules! i2c {
                 Ok(())
             }
         }
+
+        #[cfg(feature = "embedded-hal-1")]
+        impl<SDA, SCL> I2c<$I2CX, SDA, SCL> {
+            /// Starts a new transfer with (repeated) START or continues a reloaded one
+            fn start_segment(&mut self, addr: u8, segment: &Segment, restart: bool) {
+                if restart {
+                    self.i2c.cr2.write(|w| unsafe {
+                        w.sadd()
+                            .bits((addr << 1) as u16)
+                            .rd_wrn()
+                            .bit(segment.read)
+                            .nbytes()
+                            .bits(segment.len as u8)
+                            .reload()
+                            .bit(segment.reload)
+                            .autoend()
+                            .bit(segment.last)
+                            .start()
+                            .set_bit()
+                    });
+                } else {
+                    self.i2c.cr2.modify(|_, w| unsafe {
+                        w.nbytes()
+                            .bits(segment.len as u8)
+                            .reload()
+                            .bit(segment.reload)
+                            .autoend()
+                            .bit(segment.last)
+                    });
+                }
+            }
+
+            /// Generates STOP if the bus is still owned after a failed transaction
+            fn abort(&mut self) {
+                if self.i2c.isr.read().busy().bit_is_set() {
+                    self.i2c.cr2.modify(|_, w| w.stop().set_bit());
+                }
+                self.i2c.icr.write(|w| w.stopcf().set_bit());
+            }
+
+            fn transfer_blocking(&mut self, addr: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+                // No START is generated, so there is no STOP to wait for
+                if operations.is_empty() {
+                    return Ok(());
+                }
+                let mut prev_read = None;
+                for idx in 0..operations.len() {
+                    let segment = Segment::new(operations, idx);
+                    let restart = prev_read != Some(segment.read);
+                    if !restart {
+                        busy_wait!(self.i2c, tcr);
+                    }
+                    self.start_segment(addr, &segment, restart);
+
+                    match &mut operations[idx] {
+                        Operation::Read(buf) => {
+                            for byte in buf.iter_mut() {
+                                busy_wait!(self.i2c, rxne);
+                                *byte = self.i2c.rxdr.read().rxdata().bits();
+                            }
+                        }
+                        Operation::Write(bytes) => {
+                            for byte in bytes.iter() {
+                                busy_wait!(self.i2c, txis);
+                                self.i2c.txdr.write(|w| unsafe { w.txdata().bits(*byte) });
+                            }
+                        }
+                    }
+
+                    if !segment.last && !segment.reload {
+                        busy_wait!(self.i2c, tc);
+                    }
+                    prev_read = Some(segment.read);
+                }
+                busy_wait!(self.i2c, stopf);
+                self.i2c.icr.write(|w| w.stopcf().set_bit());
+                Ok(())
+            }
+        }
+
+        #[cfg(feature = "embedded-hal-1")]
+        impl<SDA, SCL> embedded_hal_1::i2c::I2c for I2c<$I2CX, SDA, SCL> {
+            fn transaction(
+                &mut self,
+                addr: u8,
+                operations: &mut [Operation<'_>],
+            ) -> Result<(), Error> {
+                let res = self.transfer_blocking(addr, operations);
+                if res.is_err() {
+                    self.abort();
+                }
+                res
+            }
+        }
+
+        #[cfg(feature = "async")]
+        async_waker! {
+            $I2CX,
+        }
+
+        #[cfg(feature = "async")]
+        impl AsyncInterrupt for $I2CX {
+            fn on_interrupt() {
+                let i2c = unsafe { &(*$I2CX::ptr()) };
+                // TXIE, RXIE, ADDRIE, NACKIE, STOPIE, TCIE, ERRIE
+                i2c.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !0xfe) });
+                <$I2CX as AsyncWaker>::waker().wake();
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<SDA, SCL> I2c<$I2CX, SDA, SCL> {
+            async fn wait_flag(&mut self, flag: Flag) -> Result<(), Error> {
+                let i2c = &self.i2c;
+                poll_fn(|cx| {
+                    let isr = i2c.isr.read();
+                    if isr.berr().bit_is_set() {
+                        i2c.icr.write(|w| w.berrcf().set_bit());
+                        return Poll::Ready(Err(Error::BusError));
+                    } else if isr.arlo().bit_is_set() {
+                        i2c.icr.write(|w| w.arlocf().set_bit());
+                        return Poll::Ready(Err(Error::ArbitrationLost));
+                    } else if isr.nackf().bit_is_set() {
+                        i2c.icr.write(|w| w.nackcf().set_bit());
+                        return Poll::Ready(Err(Error::Nack));
+                    }
+                    let (ready, ie) = match flag {
+                        Flag::Txis => (isr.txis().bit_is_set(), 1 << 1),
+                        Flag::Rxne => (isr.rxne().bit_is_set(), 1 << 2),
+                        Flag::TransferComplete => (isr.tc().bit_is_set(), 1 << 6),
+                        Flag::TransferReload => (isr.tcr().bit_is_set(), 1 << 6),
+                        Flag::Stop => (isr.stopf().bit_is_set(), 1 << 5),
+                    };
+                    if ready {
+                        return Poll::Ready(Ok(()));
+                    }
+                    <$I2CX as AsyncWaker>::waker().register(cx.waker());
+                    // NACKIE and ERRIE are always enabled
+                    i2c.cr1.modify(|r, w| unsafe { w.bits(r.bits() | ie | 1 << 4 | 1 << 7) });
+                    Poll::Pending
+                })
+                .await
+            }
+
+            async fn transfer(&mut self, addr: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+                // No START is generated, so there is no STOP to wait for
+                if operations.is_empty() {
+                    return Ok(());
+                }
+                let mut prev_read = None;
+                for idx in 0..operations.len() {
+                    let segment = Segment::new(operations, idx);
+                    let restart = prev_read != Some(segment.read);
+                    if !restart {
+                        self.wait_flag(Flag::TransferReload).await?;
+                    }
+                    self.start_segment(addr, &segment, restart);
+
+                    match &mut operations[idx] {
+                        Operation::Read(buf) => {
+                            for byte in buf.iter_mut() {
+                                self.wait_flag(Flag::Rxne).await?;
+                                *byte = self.i2c.rxdr.read().rxdata().bits();
+                            }
+                        }
+                        Operation::Write(bytes) => {
+                            for byte in bytes.iter() {
+                                self.wait_flag(Flag::Txis).await?;
+                                self.i2c.txdr.write(|w| unsafe { w.txdata().bits(*byte) });
+                            }
+                        }
+                    }
+
+                    if !segment.last && !segment.reload {
+                        self.wait_flag(Flag::TransferComplete).await?;
+                    }
+                    prev_read = Some(segment.read);
+                }
+                self.wait_flag(Flag::Stop).await?;
+                self.i2c.icr.write(|w| w.stopcf().set_bit());
+                Ok(())
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<SDA, SCL> embedded_hal_async::i2c::I2c for I2c<$I2CX, SDA, SCL> {
+            async fn transaction(
+                &mut self,
+                addr: u8,
+                operations: &mut [Operation<'_>],
+            ) -> Result<(), Error> {
+                let res = self.transfer(addr, operations).await;
+                if res.is_err() {
+                    self.abort();
+                }
+                res
+            }
+        }
     };
 }
 
@@ -310,15 +648,16 @@ i2c!(
     i2c1,
     i2c1en,
     i2c1rst,
+    I2C1,
     sda: [
-        PA10<Output<OpenDrain>>,
-        PB7<Output<OpenDrain>>,
-        PB9<Output<OpenDrain>>,
+        PA10,
+        PB7,
+        PB9,
     ],
     scl: [
-        PA9<Output<OpenDrain>>,
-        PB6<Output<OpenDrain>>,
-        PB8<Output<OpenDrain>>,
+        PA9,
+        PB6,
+        PB8,
     ],
 );
 
@@ -327,14 +666,15 @@ i2c!(
     i2c2,
     i2c2en,
     i2c2rst,
+    I2C2,
     sda: [
-        PA12<Output<OpenDrain>>,
-        PB11<Output<OpenDrain>>,
-        PB14<Output<OpenDrain>>,
+        PA12,
+        PB11,
+        PB14,
     ],
     scl: [
-        PA11<Output<OpenDrain>>,
-        PB10<Output<OpenDrain>>,
-        PB13<Output<OpenDrain>>,
+        PA11,
+        PB10,
+        PB13,
     ],
 );
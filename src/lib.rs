@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(non_camel_case_types)]
 
 #[cfg(not(any(
@@ -40,21 +40,58 @@ pub use stm32g0::stm32g081 as stm32;
 #[cfg(feature = "rt")]
 pub use crate::stm32::interrupt;
 
+#[cfg(any(feature = "stm32g041", feature = "stm32g081"))]
+pub mod aes;
 pub mod analog;
+#[cfg(feature = "async")]
+#[macro_use]
+pub mod asynch;
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pub mod cec;
+pub mod compat;
 #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 pub mod comparator;
 pub mod crc;
+pub mod dbg;
 pub mod delay;
 pub mod dma;
 pub mod exti;
+pub mod failsafe;
+pub mod flash;
+#[cfg(feature = "factory-test")]
+pub mod factory_test;
 pub mod gpio;
+#[cfg(feature = "hil-test")]
+pub mod hil;
 pub mod i2c;
+#[cfg(any(
+    feature = "stm32g031",
+    feature = "stm32g041",
+    feature = "stm32g07x",
+    feature = "stm32g081"
+))]
+pub mod lptim;
+pub mod mailbox;
+#[cfg(feature = "modbus")]
+pub mod modbus;
+#[cfg(feature = "debug-monitor")]
+pub mod monitor;
+pub mod nvic;
+pub mod onewire;
+pub mod power;
 pub mod prelude;
+pub mod probe;
 pub mod rcc;
 pub mod rng;
 pub mod rtc;
 pub mod serial;
+pub mod signature;
 pub mod spi;
+pub mod syscfg;
+pub mod tamp;
+pub mod thermal;
 pub mod time;
 pub mod timer;
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pub mod ucpd;
 pub mod watchdog;
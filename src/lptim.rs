@@ -0,0 +1,464 @@
+//! # Low power timer
+//!
+//! LPTIM1 and LPTIM2 are 16 bit timers that keep counting in Stop mode
+//! when clocked from LSI, LSE or HSI16. They provide periodic and one-shot
+//! ticks, a PWM output, quadrature encoder decoding and pulse counting on
+//! an input pin. The match and auto-reload events are wired to EXTI, so a
+//! running LPTIM can wake the MCU from Stop mode.
+use crate::gpio::gpioa::PA4;
+use crate::gpio::gpiob::{PB1, PB2, PB5, PB7};
+use crate::gpio::{AltFunction, Alternate, DefaultMode, SignalEdge, AF5};
+use crate::hal::{self, Direction};
+use crate::rcc::Rcc;
+use crate::stm32::{LPTIM1, LPTIM2};
+use crate::time::{Hertz, MicroSecond, U32Ext};
+use core::cell::Cell;
+
+// ISR / ICR / IER
+const ARROK: u32 = 1 << 4;
+const CMPOK: u32 = 1 << 3;
+
+// CFGR
+const CKPOL_MASK: u32 = 0b11 << 1;
+const PRESC_MASK: u32 = 0b111 << 9;
+const WAVPOL: u32 = 1 << 21;
+const PRELOAD: u32 = 1 << 22;
+const COUNTMODE: u32 = 1 << 23;
+const ENC: u32 = 1 << 24;
+
+// CR
+const ENABLE: u32 = 1 << 0;
+const SNGSTRT: u32 = 1 << 1;
+const CNTSTRT: u32 = 1 << 2;
+
+/// Kernel clock (LPTIMxSEL)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockSource {
+    /// APB clock, stops in Stop mode
+    Pclk = 0,
+    Lsi = 1,
+    Hsi16 = 2,
+    Lse = 3,
+}
+
+/// Kernel clock prescaler
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Prescaler {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+    Div8 = 3,
+    Div16 = 4,
+    Div32 = 5,
+    Div64 = 6,
+    Div128 = 7,
+}
+
+/// Counting mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CountMode {
+    /// Counts up to the auto-reload value and restarts
+    Continuous,
+    /// Counts up to the auto-reload value once
+    OneShot,
+}
+
+/// LPTIM interrupt events
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// Counter matched the compare value
+    CompareMatch = 1 << 0,
+    /// Counter matched the auto-reload value
+    AutoReloadMatch = 1 << 1,
+    /// Valid edge on the external trigger
+    ExternalTrigger = 1 << 2,
+    /// Encoder counting direction changed to up
+    Up = 1 << 5,
+    /// Encoder counting direction changed to down
+    Down = 1 << 6,
+}
+
+/// LPTIM output pin
+pub trait OutPin<LPTIM> {
+    fn setup(&self);
+}
+
+/// LPTIM input 1 pin, clock or encoder channel A
+pub trait In1Pin<LPTIM> {
+    fn setup(&self);
+}
+
+/// LPTIM input 2 pin, encoder channel B
+pub trait In2Pin<LPTIM> {
+    fn setup(&self);
+}
+
+/// Low power timer
+pub struct LowPowerTimer<LPTIM> {
+    rb: LPTIM,
+    clk: Hertz,
+}
+
+/// PWM output of a low power timer
+pub struct LptimPwm<LPTIM, PIN> {
+    rb: LPTIM,
+    pin: PIN,
+    clk: Hertz,
+}
+
+/// Quadrature encoder on a low power timer
+pub struct LptimQei<LPTIM, IN1, IN2> {
+    rb: LPTIM,
+    in1: IN1,
+    in2: IN2,
+    clk: Hertz,
+    direction: Cell<Direction>,
+}
+
+pub trait LptimExt: Sized {
+    fn lptim(self, clk: ClockSource, prescaler: Prescaler, rcc: &mut Rcc) -> LowPowerTimer<Self>;
+}
+
+macro_rules! lptim {
+    ($($LPTIMX:ident: ($lptimXen:ident, $lptimXrst:ident, $shift:expr, $exti:ident),)+) => {
+        $(
+            impl LptimExt for $LPTIMX {
+                fn lptim(
+                    self,
+                    clk: ClockSource,
+                    prescaler: Prescaler,
+                    rcc: &mut Rcc,
+                ) -> LowPowerTimer<$LPTIMX> {
+                    let src_freq = match clk {
+                        ClockSource::Pclk => rcc.clocks.apb_clk,
+                        ClockSource::Lsi => {
+                            rcc.enable_lsi();
+                            32_000.hz()
+                        }
+                        ClockSource::Hsi16 => {
+                            rcc.rb.cr.modify(|_, w| w.hsion().set_bit());
+                            while rcc.rb.cr.read().hsirdy().bit_is_clear() {}
+                            16.mhz()
+                        }
+                        ClockSource::Lse => {
+                            if rcc.rb.bdcr.read().lserdy().bit_is_clear() {
+                                rcc.enable_lse(false);
+                            }
+                            32_768.hz()
+                        }
+                    };
+                    rcc.rb.ccipr.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0b11 << $shift) | (clk as u32) << $shift)
+                    });
+                    rcc.rb.apbenr1.modify(|_, w| w.$lptimXen().set_bit());
+                    rcc.rb.apbrstr1.modify(|_, w| w.$lptimXrst().set_bit());
+                    rcc.rb.apbrstr1.modify(|_, w| w.$lptimXrst().clear_bit());
+
+                    self.cfgr.write(|w| unsafe { w.bits((prescaler as u32) << 9) });
+                    LowPowerTimer {
+                        rb: self,
+                        clk: (src_freq.0 >> prescaler as u32).hz(),
+                    }
+                }
+            }
+
+            impl LowPowerTimer<$LPTIMX> {
+                /// Counter frequency
+                pub fn clk(&self) -> Hertz {
+                    self.clk
+                }
+
+                /// Starts counting with `period`
+                pub fn start<T: Into<MicroSecond>>(&mut self, period: T, mode: CountMode) {
+                    let ticks = period.into().cycles(self.clk);
+                    assert!(ticks > 0 && ticks <= 0x1_0000);
+                    self.start_ticks((ticks - 1) as u16, mode);
+                }
+
+                /// Starts counting from zero to `arr`
+                pub fn start_ticks(&mut self, arr: u16, mode: CountMode) {
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb.cr.write(|w| unsafe { w.bits(ENABLE) });
+                    set_arr(&self.rb, arr);
+                    let start = match mode {
+                        CountMode::Continuous => CNTSTRT,
+                        CountMode::OneShot => SNGSTRT,
+                    };
+                    self.rb.cr.write(|w| unsafe { w.bits(ENABLE | start) });
+                }
+
+                /// Counts edges on the input 1 pin instead of kernel clock
+                /// cycles
+                ///
+                /// The kernel clock still samples the input, so it must run
+                /// faster than the counted signal.
+                pub fn count_input<PIN: In1Pin<$LPTIMX>>(&mut self, pin: &PIN, edge: SignalEdge) {
+                    pin.setup();
+                    let ckpol = match edge {
+                        SignalEdge::Rising => 0b00,
+                        SignalEdge::Falling => 0b01,
+                        SignalEdge::All => 0b10,
+                    };
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb.cfgr.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !CKPOL_MASK | ckpol << 1 | COUNTMODE)
+                    });
+                }
+
+                /// Sets the compare value, the timer must be started
+                pub fn set_compare(&mut self, cmp: u16) {
+                    set_cmp(&self.rb, cmp);
+                }
+
+                /// Stops counting
+                pub fn stop(&mut self) {
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                }
+
+                /// Current counter value
+                pub fn counter(&self) -> u16 {
+                    read_cnt(&self.rb)
+                }
+
+                /// Starts listening for an interrupt event
+                ///
+                /// Stops the timer, the interrupt enables can only be changed
+                /// while it is disabled. Events also wake the MCU from Stop
+                /// mode, see `wakeup()`.
+                pub fn listen(&mut self, event: Event) {
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb
+                        .ier
+                        .modify(|r, w| unsafe { w.bits(r.bits() | event as u32) });
+                }
+
+                /// Stops listening for an interrupt event, stops the timer
+                pub fn unlisten(&mut self, event: Event) {
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb
+                        .ier
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !(event as u32)) });
+                }
+
+                /// Returns `true` if `event` is pending
+                pub fn is_pending(&self, event: Event) -> bool {
+                    self.rb.isr.read().bits() & event as u32 != 0
+                }
+
+                /// Clears a pending event
+                pub fn unpend(&mut self, event: Event) {
+                    self.rb.icr.write(|w| unsafe { w.bits(event as u32) });
+                }
+
+                /// Routes listened events to EXTI so they wake the MCU from Stop
+                /// mode
+                #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+                pub fn wakeup(&self, exti: &crate::stm32::EXTI) {
+                    use crate::exti::ExtiExt;
+                    exti.wakeup(crate::exti::Event::$exti);
+                }
+
+                /// Configures PWM on the output pin with `freq`
+                ///
+                /// The output is high while the counter is below the duty
+                /// cycle.
+                pub fn pwm<PIN: OutPin<$LPTIMX>>(
+                    self,
+                    pin: PIN,
+                    freq: Hertz,
+                ) -> LptimPwm<$LPTIMX, PIN> {
+                    pin.setup();
+                    let ticks = self.clk.0 / freq.0;
+                    assert!(ticks > 1 && ticks <= 0x1_0000);
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb
+                        .cfgr
+                        .modify(|r, w| unsafe { w.bits(r.bits() | WAVPOL | PRELOAD) });
+                    self.rb.cr.write(|w| unsafe { w.bits(ENABLE) });
+                    set_arr(&self.rb, (ticks - 1) as u16);
+                    set_cmp(&self.rb, 0);
+                    LptimPwm {
+                        rb: self.rb,
+                        pin,
+                        clk: self.clk,
+                    }
+                }
+
+                /// Decodes a quadrature encoder on input 1 and 2
+                ///
+                /// Counts on both edges of both inputs, the kernel clock
+                /// prescaler must be `Div1`.
+                pub fn qei<IN1, IN2>(self, in1: IN1, in2: IN2) -> LptimQei<$LPTIMX, IN1, IN2>
+                where
+                    IN1: In1Pin<$LPTIMX>,
+                    IN2: In2Pin<$LPTIMX>,
+                {
+                    assert!(self.rb.cfgr.read().bits() & PRESC_MASK == 0);
+                    in1.setup();
+                    in2.setup();
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb.cfgr.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(CKPOL_MASK | COUNTMODE) | 0b10 << 1 | ENC)
+                    });
+                    self.rb.cr.write(|w| unsafe { w.bits(ENABLE) });
+                    set_arr(&self.rb, 0xffff);
+                    self.rb.cr.write(|w| unsafe { w.bits(ENABLE | CNTSTRT) });
+                    LptimQei {
+                        rb: self.rb,
+                        in1,
+                        in2,
+                        clk: self.clk,
+                        direction: Cell::new(Direction::Upcounting),
+                    }
+                }
+
+                /// Disables the timer and returns the peripheral
+                pub fn release(self) -> $LPTIMX {
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb
+                }
+            }
+
+            impl<PIN> LptimPwm<$LPTIMX, PIN> {
+                /// Stops PWM, returning the timer and pin
+                pub fn release(self) -> (LowPowerTimer<$LPTIMX>, PIN) {
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb
+                        .cfgr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !(WAVPOL | PRELOAD)) });
+                    let timer = LowPowerTimer {
+                        rb: self.rb,
+                        clk: self.clk,
+                    };
+                    (timer, self.pin)
+                }
+            }
+
+            impl<PIN> hal::PwmPin for LptimPwm<$LPTIMX, PIN> {
+                type Duty = u16;
+
+                fn disable(&mut self) {
+                    // Registers keep their values, only the counter is reset
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb.cr.write(|w| unsafe { w.bits(ENABLE) });
+                }
+
+                fn enable(&mut self) {
+                    self.rb.cr.write(|w| unsafe { w.bits(ENABLE | CNTSTRT) });
+                }
+
+                fn get_duty(&self) -> u16 {
+                    self.rb.cmp.read().bits() as u16
+                }
+
+                fn get_max_duty(&self) -> u16 {
+                    self.rb.arr.read().bits() as u16
+                }
+
+                fn set_duty(&mut self, duty: u16) {
+                    set_cmp(&self.rb, duty);
+                }
+            }
+
+            impl<IN1, IN2> LptimQei<$LPTIMX, IN1, IN2> {
+                /// Stops decoding, returning the timer and pins
+                pub fn release(self) -> (LowPowerTimer<$LPTIMX>, IN1, IN2) {
+                    self.rb.cr.write(|w| unsafe { w.bits(0) });
+                    self.rb
+                        .cfgr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !(ENC | CKPOL_MASK)) });
+                    let timer = LowPowerTimer {
+                        rb: self.rb,
+                        clk: self.clk,
+                    };
+                    (timer, self.in1, self.in2)
+                }
+            }
+
+            impl<IN1, IN2> hal::Qei for LptimQei<$LPTIMX, IN1, IN2> {
+                type Count = u16;
+
+                fn count(&self) -> u16 {
+                    read_cnt(&self.rb)
+                }
+
+                fn direction(&self) -> Direction {
+                    // UP and DOWN flag direction changes since the last call,
+                    // both set means it changed back and forth
+                    let (up, down) = (Event::Up as u32, Event::Down as u32);
+                    let flags = self.rb.isr.read().bits() & (up | down);
+                    self.rb.icr.write(|w| unsafe { w.bits(flags) });
+                    if flags == up {
+                        self.direction.set(Direction::Upcounting);
+                    } else if flags == down {
+                        self.direction.set(Direction::Downcounting);
+                    }
+                    self.direction.get()
+                }
+            }
+        )+
+    };
+}
+
+/// Writes ARR, the timer must be enabled
+fn set_arr(rb: &crate::stm32::lptim1::RegisterBlock, arr: u16) {
+    rb.icr.write(|w| unsafe { w.bits(ARROK) });
+    rb.arr.write(|w| unsafe { w.bits(arr as u32) });
+    while rb.isr.read().bits() & ARROK == 0 {}
+}
+
+/// Writes CMP, the timer must be enabled
+fn set_cmp(rb: &crate::stm32::lptim1::RegisterBlock, cmp: u16) {
+    rb.icr.write(|w| unsafe { w.bits(CMPOK) });
+    rb.cmp.write(|w| unsafe { w.bits(cmp as u32) });
+    while rb.isr.read().bits() & CMPOK == 0 {}
+}
+
+/// The counter is clocked asynchronously, two equal reads are needed
+fn read_cnt(rb: &crate::stm32::lptim1::RegisterBlock) -> u16 {
+    loop {
+        let cnt = rb.cnt.read().bits();
+        if cnt == rb.cnt.read().bits() {
+            return cnt as u16;
+        }
+    }
+}
+
+lptim! {
+    LPTIM1: (lptim1en, lptim1rst, 18, LPTIM1),
+    LPTIM2: (lptim2en, lptim2rst, 20, LPTIM2),
+}
+
+macro_rules! lptim_pins {
+    ($($LPTIMX:ident: { $($PIN:ident: $Trait:ident,)+ })+) => {
+        $(
+            $(
+                impl $Trait<$LPTIMX> for $PIN<DefaultMode> {
+                    fn setup(&self) {
+                        self.set_alt_mode(AltFunction::AF5);
+                    }
+                }
+
+                impl $Trait<$LPTIMX> for $PIN<Alternate<AF5>> {
+                    fn setup(&self) {}
+                }
+            )+
+        )+
+    };
+}
+
+lptim_pins! {
+    LPTIM1: {
+        PB2: OutPin,
+        PB5: In1Pin,
+        PB7: In2Pin,
+    }
+    LPTIM2: {
+        PA4: OutPin,
+        PB1: In1Pin,
+    }
+}
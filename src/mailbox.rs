@@ -0,0 +1,408 @@
+//! # Inter-processor mailbox
+//!
+//! Reliable message channel between the MCU and a host processor. Messages
+//! are sent in frames protected by CRC-16 and carrying sequence numbers.
+//! One data frame is in flight at a time (stop-and-wait); it is retransmitted
+//! until acknowledged, acks are piggybacked on outgoing frames. Received
+//! messages are queued until the application takes them; while the queue is
+//! full incoming frames are not acknowledged, so the sender backs off.
+//!
+//! Frame layout: `SEQ ACK FLAGS LEN PAYLOAD CRC16(LE)`, bytes after the CRC
+//! are ignored (e.g. SPI padding).
+//!
+//! Transports:
+//! - `SerialLink`: SLIP framed frames over any serial port
+//! - `spi::slave::FrameSlave`: one frame per NSS assertion, call
+//!   `Mailbox::poll` from the NSS rising edge handler
+use core::marker::PhantomData;
+
+use hal::serial;
+
+/// Maximum message payload size
+pub const MAX_PAYLOAD: usize = 64;
+/// Number of received messages buffered
+pub const QUEUE_DEPTH: usize = 4;
+
+const HEADER: usize = 4;
+const FRAME_MAX: usize = HEADER + MAX_PAYLOAD + 2;
+
+const FLAG_DATA: u8 = 1 << 0;
+const FLAG_ACK: u8 = 1 << 1;
+
+/// Mailbox error
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// Transport error
+    Link(E),
+    /// Previous message is not acknowledged yet
+    Busy,
+    /// Message was not acknowledged after all retries and was dropped
+    Timeout,
+}
+
+/// Message carried by the mailbox
+pub trait Message: Sized {
+    /// Serializes message into `buf` (`MAX_PAYLOAD` bytes), returns length
+    fn encode(&self, buf: &mut [u8]) -> usize;
+
+    /// Deserializes message, returns `None` if the payload is malformed
+    fn decode(buf: &[u8]) -> Option<Self>;
+}
+
+/// Frame transport
+pub trait Transport {
+    type Error;
+
+    /// Sends one frame
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Receives one frame into `buf`, returns frame length
+    fn recv_frame(&mut self, buf: &mut [u8]) -> nb::Result<usize, Self::Error>;
+}
+
+/// Link statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    /// Frames dropped due to CRC or format errors
+    pub bad_frames: u32,
+    /// Data frames sent again
+    pub retransmits: u32,
+    /// Data frames received again
+    pub duplicates: u32,
+    /// Data frames dropped while the queue was full
+    pub overflows: u32,
+}
+
+struct Pending {
+    frame: [u8; FRAME_MAX],
+    len: usize,
+    sent_at: Option<u32>,
+    retries: u8,
+}
+
+struct Queue {
+    slots: [[u8; MAX_PAYLOAD]; QUEUE_DEPTH],
+    lens: [usize; QUEUE_DEPTH],
+    head: usize,
+    count: usize,
+}
+
+impl Queue {
+    fn push(&mut self, payload: &[u8]) -> bool {
+        if self.count == QUEUE_DEPTH {
+            return false;
+        }
+        let idx = (self.head + self.count) % QUEUE_DEPTH;
+        self.slots[idx][..payload.len()].copy_from_slice(payload);
+        self.lens[idx] = payload.len();
+        self.count += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<&[u8]> {
+        if self.count == 0 {
+            return None;
+        }
+        let idx = self.head;
+        self.head = (self.head + 1) % QUEUE_DEPTH;
+        self.count -= 1;
+        Some(&self.slots[idx][..self.lens[idx]])
+    }
+}
+
+/// Reliable typed message channel
+pub struct Mailbox<T, M> {
+    link: T,
+    tx_seq: u8,
+    rx_seq: Option<u8>,
+    ack: Option<u8>,
+    pending: Option<Pending>,
+    queue: Queue,
+    timeout_ms: u32,
+    max_retries: u8,
+    stats: Stats,
+    _msg: PhantomData<M>,
+}
+
+impl<T, M> Mailbox<T, M>
+where
+    T: Transport,
+    M: Message,
+{
+    /// Creates mailbox retransmitting after `timeout_ms` up to `max_retries` times
+    pub fn new(link: T, timeout_ms: u32, max_retries: u8) -> Self {
+        Mailbox {
+            link,
+            tx_seq: 0,
+            rx_seq: None,
+            ack: None,
+            pending: None,
+            queue: Queue {
+                slots: [[0; MAX_PAYLOAD]; QUEUE_DEPTH],
+                lens: [0; QUEUE_DEPTH],
+                head: 0,
+                count: 0,
+            },
+            timeout_ms,
+            max_retries,
+            stats: Stats::default(),
+            _msg: PhantomData,
+        }
+    }
+
+    /// Queues message for transmission on the next `poll`
+    pub fn send(&mut self, msg: &M) -> Result<(), Error<T::Error>> {
+        if self.pending.is_some() {
+            return Err(Error::Busy);
+        }
+        let mut frame = [0; FRAME_MAX];
+        let len = msg.encode(&mut frame[HEADER..HEADER + MAX_PAYLOAD]);
+        assert!(len <= MAX_PAYLOAD);
+        frame[0] = self.tx_seq;
+        frame[2] = FLAG_DATA;
+        frame[3] = len as u8;
+        self.pending = Some(Pending {
+            frame,
+            len: HEADER + len + 2,
+            sent_at: None,
+            retries: 0,
+        });
+        Ok(())
+    }
+
+    /// Returns `true` if the last message was acknowledged
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_none()
+    }
+
+    /// Takes the next received message
+    ///
+    /// Messages failing to decode are dropped.
+    pub fn recv(&mut self) -> Option<M> {
+        while let Some(payload) = self.queue.pop() {
+            if let Some(msg) = M::decode(payload) {
+                return Some(msg);
+            }
+        }
+        None
+    }
+
+    /// Processes one received frame and (re)transmits pending frames
+    ///
+    /// `now_ms` is a free running millisecond counter.
+    pub fn poll(&mut self, now_ms: u32) -> Result<(), Error<T::Error>> {
+        let mut buf = [0; FRAME_MAX];
+        match self.link.recv_frame(&mut buf) {
+            Ok(len) => self.handle_frame(&buf[..len]),
+            Err(nb::Error::WouldBlock) => {}
+            Err(nb::Error::Other(err)) => return Err(Error::Link(err)),
+        }
+        self.transmit(now_ms)
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Releases the transport
+    pub fn release(self) -> T {
+        self.link
+    }
+
+    fn handle_frame(&mut self, frame: &[u8]) {
+        if frame.len() < HEADER + 2 {
+            self.stats.bad_frames += 1;
+            return;
+        }
+        let (seq, ack, flags, len) = (frame[0], frame[1], frame[2], frame[3] as usize);
+        if flags & (FLAG_DATA | FLAG_ACK) == 0 {
+            // idle SPI frame
+            return;
+        }
+        let end = HEADER + len;
+        if len > MAX_PAYLOAD || frame.len() < end + 2 {
+            self.stats.bad_frames += 1;
+            return;
+        }
+        let crc = u16::from_le_bytes([frame[end], frame[end + 1]]);
+        if crc16(&frame[..end]) != crc {
+            self.stats.bad_frames += 1;
+            return;
+        }
+
+        if flags & FLAG_ACK != 0 && self.pending.is_some() && ack == self.tx_seq {
+            self.pending = None;
+            self.tx_seq = self.tx_seq.wrapping_add(1);
+        }
+        if flags & FLAG_DATA != 0 {
+            if self.rx_seq == Some(seq) {
+                self.stats.duplicates += 1;
+                self.ack = Some(seq);
+            } else if self.queue.push(&frame[HEADER..end]) {
+                self.rx_seq = Some(seq);
+                self.ack = Some(seq);
+            } else {
+                self.stats.overflows += 1;
+            }
+        }
+    }
+
+    fn transmit(&mut self, now_ms: u32) -> Result<(), Error<T::Error>> {
+        let ack = self.ack.take();
+        let (timeout_ms, max_retries) = (self.timeout_ms, self.max_retries);
+        if let Some(pending) = self.pending.as_mut() {
+            let due = match pending.sent_at {
+                None => true,
+                Some(sent_at) => now_ms.wrapping_sub(sent_at) >= timeout_ms,
+            };
+            if due {
+                if pending.sent_at.is_some() {
+                    if pending.retries >= max_retries {
+                        self.pending = None;
+                        self.tx_seq = self.tx_seq.wrapping_add(1);
+                        self.ack = ack;
+                        return Err(Error::Timeout);
+                    }
+                    pending.retries += 1;
+                    self.stats.retransmits += 1;
+                }
+                pending.sent_at = Some(now_ms);
+                pending.frame[1] = ack.unwrap_or(0);
+                pending.frame[2] = FLAG_DATA | if ack.is_some() { FLAG_ACK } else { 0 };
+                seal(&mut pending.frame[..pending.len]);
+                return self
+                    .link
+                    .send_frame(&pending.frame[..pending.len])
+                    .map_err(Error::Link);
+            }
+        }
+        if let Some(ack) = ack {
+            let mut frame = [0, ack, FLAG_ACK, 0, 0, 0];
+            seal(&mut frame);
+            self.link.send_frame(&frame).map_err(Error::Link)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes CRC into the last two bytes of the frame
+fn seal(frame: &mut [u8]) {
+    let end = frame.len() - 2;
+    let crc = crc16(&frame[..end]).to_le_bytes();
+    frame[end..].copy_from_slice(&crc);
+}
+
+/// CRC-16/CCITT-FALSE
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+/// SLIP framed transport over a serial port
+pub struct SerialLink<S> {
+    serial: S,
+    rx: [u8; FRAME_MAX],
+    rx_len: usize,
+    escape: bool,
+    overflow: bool,
+}
+
+impl<S> SerialLink<S> {
+    pub fn new(serial: S) -> Self {
+        SerialLink {
+            serial,
+            rx: [0; FRAME_MAX],
+            rx_len: 0,
+            escape: false,
+            overflow: false,
+        }
+    }
+
+    pub fn release(self) -> S {
+        self.serial
+    }
+}
+
+impl<S, E> Transport for SerialLink<S>
+where
+    S: serial::Read<u8, Error = E> + serial::Write<u8, Error = E>,
+{
+    type Error = E;
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), E> {
+        nb::block!(self.serial.write(SLIP_END))?;
+        for byte in frame {
+            match *byte {
+                SLIP_END => {
+                    nb::block!(self.serial.write(SLIP_ESC))?;
+                    nb::block!(self.serial.write(SLIP_ESC_END))?;
+                }
+                SLIP_ESC => {
+                    nb::block!(self.serial.write(SLIP_ESC))?;
+                    nb::block!(self.serial.write(SLIP_ESC_ESC))?;
+                }
+                byte => nb::block!(self.serial.write(byte))?,
+            }
+        }
+        nb::block!(self.serial.write(SLIP_END))
+    }
+
+    fn recv_frame(&mut self, buf: &mut [u8]) -> nb::Result<usize, E> {
+        loop {
+            let byte = match self.serial.read() {
+                Ok(byte) => byte,
+                Err(err) => {
+                    if let nb::Error::Other(_) = err {
+                        // frame is corrupted, wait for the next one
+                        self.overflow = true;
+                    }
+                    return Err(err);
+                }
+            };
+            let byte = match (self.escape, byte) {
+                (false, SLIP_END) => {
+                    let len = self.rx_len;
+                    let valid = !self.overflow && len > 0 && len <= buf.len();
+                    self.rx_len = 0;
+                    self.overflow = false;
+                    if valid {
+                        buf[..len].copy_from_slice(&self.rx[..len]);
+                        return Ok(len);
+                    }
+                    continue;
+                }
+                (false, SLIP_ESC) => {
+                    self.escape = true;
+                    continue;
+                }
+                (true, SLIP_ESC_END) => SLIP_END,
+                (true, SLIP_ESC_ESC) => SLIP_ESC,
+                (_, byte) => byte,
+            };
+            self.escape = false;
+            if self.rx_len < self.rx.len() {
+                self.rx[self.rx_len] = byte;
+                self.rx_len += 1;
+            } else {
+                self.overflow = true;
+            }
+        }
+    }
+}
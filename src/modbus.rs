@@ -0,0 +1,204 @@
+//! # Modbus RTU framing on a USART
+//!
+//! A frame ends after 3.5 characters of silence on the line, detected by the
+//! USART receiver timeout. Only USART1, and USART2 on STM32G07x/G081, have a
+//! receiver timeout. The CRC16 of received frames is checked by the CRC unit
+//! and stripped, `write_frame` appends it and keeps the line silent for 3.5
+//! characters before sending. Above 19200 baud the silent interval is fixed to
+//! 1.75 ms.
+//!
+//! ```ignore
+//! let serial = dp.USART1.usart(tx, rx, Config::default(), &mut rcc)?;
+//! let crc = dp.CRC.constrain(&mut rcc);
+//! let mut modbus = Modbus::new(serial, dp.TIM16.timer(&mut rcc), crc);
+//! loop {
+//!     let request = block!(modbus.read_frame())?;
+//!     // ...
+//!     modbus.write_frame(&response)?;
+//! }
+//! ```
+use crate::crc::{self, Crc};
+use crate::serial::{self, Serial};
+use crate::stm32::*;
+use crate::time::MicroSecond;
+use hal::serial::{Read, Write};
+use hal::timer::CountDown;
+use nb::block;
+
+// CR2
+const RTOEN: u32 = 1 << 23;
+// ISR and ICR
+const RTOF: u32 = 1 << 11;
+
+/// Largest RTU frame, address, PDU and CRC
+pub const MAX_FRAME: usize = 256;
+
+/// Bit times of 3.5 characters with 11 bits each, rounded up
+const SILENCE_BITS: u64 = 39;
+/// Fixed silent interval above 19200 baud, in microseconds
+const FAST_SILENCE_US: u64 = 1_750;
+
+/// Modbus error
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A character of the frame was received with an error
+    Serial(serial::Error),
+    /// Frame shorter than four bytes or with a wrong CRC
+    Crc,
+    /// Frame longer than `MAX_FRAME`
+    Overflow,
+}
+
+impl From<serial::Error> for Error {
+    fn from(err: serial::Error) -> Self {
+        Error::Serial(err)
+    }
+}
+
+/// Modbus RTU port
+pub struct Modbus<USART, PINS, TIMER> {
+    serial: Serial<USART, PINS>,
+    timer: TIMER,
+    crc: Crc,
+    silence: MicroSecond,
+    buffer: [u8; MAX_FRAME],
+    len: usize,
+    error: Option<Error>,
+    complete: bool,
+}
+
+macro_rules! modbus {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl<PINS, TIMER> Modbus<$USARTX, PINS, TIMER>
+            where
+                TIMER: CountDown<Time = MicroSecond>,
+            {
+                /// Enables the receiver timeout for the baud rate of `serial`
+                ///
+                /// `timer` times the silent interval before transmitting, `crc`
+                /// is configured for CRC-16/MODBUS.
+                pub fn new(serial: Serial<$USARTX, PINS>, timer: TIMER, mut crc: Crc) -> Self {
+                    let baud = serial.baudrate().0 as u64;
+                    let bits = if baud > 19_200 {
+                        (FAST_SILENCE_US * baud).div_ceil(1_000_000)
+                    } else {
+                        SILENCE_BITS
+                    };
+                    let usart = unsafe { &(*$USARTX::ptr()) };
+                    usart.rtor.write(|w| unsafe { w.bits(bits as u32) });
+                    usart.icr.write(|w| unsafe { w.bits(RTOF) });
+                    usart.cr2.modify(|r, w| unsafe { w.bits(r.bits() | RTOEN) });
+                    crc.configure(&crc::Config::crc16_modbus());
+                    Modbus {
+                        serial,
+                        timer,
+                        crc,
+                        silence: MicroSecond((bits * 1_000_000).div_ceil(baud) as u32),
+                        buffer: [0; MAX_FRAME],
+                        len: 0,
+                        error: None,
+                        complete: false,
+                    }
+                }
+
+                /// Returns the address and PDU of the next complete frame
+                ///
+                /// Call it at least once per character time, or listen for
+                /// `Rxne` and call it from the interrupt, since characters
+                /// are only stored here.
+                pub fn read_frame(&mut self) -> nb::Result<&[u8], Error> {
+                    if self.complete {
+                        self.complete = false;
+                        self.len = 0;
+                    }
+                    loop {
+                        match self.serial.read() {
+                            Ok(byte) => {
+                                if self.len < MAX_FRAME {
+                                    self.buffer[self.len] = byte;
+                                    self.len += 1;
+                                } else if self.error.is_none() {
+                                    self.error = Some(Error::Overflow);
+                                }
+                            }
+                            Err(nb::Error::Other(err)) => {
+                                if self.error.is_none() {
+                                    self.error = Some(Error::Serial(err));
+                                }
+                            }
+                            Err(nb::Error::WouldBlock) => break,
+                        }
+                    }
+
+                    // Checked after draining RDR, a character after the
+                    // timeout belongs to the next frame
+                    let usart = unsafe { &(*$USARTX::ptr()) };
+                    if usart.isr.read().bits() & RTOF == 0 {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    usart.icr.write(|w| unsafe { w.bits(RTOF) });
+                    if self.len == 0 && self.error.is_none() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    self.complete = true;
+                    if let Some(err) = self.error.take() {
+                        return Err(nb::Error::Other(err));
+                    }
+                    // The CRC over a frame including its CRC is zero
+                    if self.len < 4 || self.crc.checksum(&self.buffer[..self.len]) != 0 {
+                        return Err(nb::Error::Other(Error::Crc));
+                    }
+                    Ok(&self.buffer[..self.len - 2])
+                }
+
+                /// Sends `frame`, the address and PDU, followed by its CRC
+                ///
+                /// Blocks for the silent interval and until the last character
+                /// was sent. The receiver is disabled meanwhile, so the echo on
+                /// a two-wire RS-485 bus is not read back.
+                pub fn write_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+                    if frame.len() > MAX_FRAME - 2 {
+                        return Err(Error::Overflow);
+                    }
+                    block!(self.serial.flush())?;
+                    self.timer.start(self.silence);
+                    let _ = block!(self.timer.wait());
+
+                    self.serial.enable_receiver(false);
+                    // Sent low byte first
+                    let crc = (self.crc.checksum(frame) as u16).to_le_bytes();
+                    for byte in frame.iter().chain(crc.iter()) {
+                        block!(self.serial.write(*byte))?;
+                    }
+                    let sent = block!(self.serial.flush());
+                    self.serial.enable_receiver(true);
+                    sent.map_err(Error::Serial)
+                }
+
+                /// Silent interval marking the end of a frame
+                pub fn silence(&self) -> MicroSecond {
+                    self.silence
+                }
+
+                /// Disables the receiver timeout, returns the serial port, timer and CRC unit
+                pub fn release(self) -> (Serial<$USARTX, PINS>, TIMER, Crc) {
+                    let usart = unsafe { &(*$USARTX::ptr()) };
+                    usart.cr2.modify(|r, w| unsafe { w.bits(r.bits() & !RTOEN) });
+                    (self.serial, self.timer, self.crc)
+                }
+            }
+        )+
+    }
+}
+
+modbus! {
+    USART1,
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+modbus! {
+    USART2,
+}
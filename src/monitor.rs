@@ -0,0 +1,306 @@
+//! # Debug monitor
+//!
+//! Tiny framed protocol over a serial port for manufacturing test and field
+//! diagnostics. The monitor can peek/poke memory, access peripheral registers
+//! and reset the MCU once unlocked with the key given at construction.
+//!
+//! Request frame: `0xA5 CMD LEN PAYLOAD.. SUM`, response frame:
+//! `0x5A STATUS LEN PAYLOAD.. SUM`, where `SUM` makes the byte sum of
+//! everything after the sync byte zero. Multi-byte values are little endian.
+use core::ptr;
+
+use cortex_m::peripheral::SCB;
+use hal::serial;
+use nb::block;
+
+const REQUEST_SYNC: u8 = 0xa5;
+const RESPONSE_SYNC: u8 = 0x5a;
+const MAX_PAYLOAD: usize = 64;
+const MAX_UNLOCK_ATTEMPTS: u8 = 3;
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+const SRAM_END: u32 = 0x2000_9000;
+#[cfg(not(any(feature = "stm32g07x", feature = "stm32g081")))]
+const SRAM_END: u32 = 0x2000_2000;
+
+/// Monitor command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Unlock with `KEY..`
+    Unlock = 0x01,
+    /// Lock monitor
+    Lock = 0x02,
+    /// Read `LEN:u8` bytes at `ADDR:u32`
+    Peek = 0x03,
+    /// Write `DATA..` at `ADDR:u32`
+    Poke = 0x04,
+    /// Read 32-bit register at `ADDR:u32`
+    ReadRegister = 0x05,
+    /// Write `VALUE:u32` to 32-bit register at `ADDR:u32`
+    WriteRegister = 0x06,
+    /// System reset
+    Reset = 0x07,
+}
+
+/// Response status
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Ok = 0x00,
+    Locked = 0x01,
+    BadFrame = 0x02,
+    BadAddress = 0x03,
+    UnknownCommand = 0x04,
+    BadKey = 0x05,
+}
+
+enum State {
+    Sync,
+    Command,
+    Length,
+    Payload,
+    Checksum,
+}
+
+/// Debug monitor bound to a serial port
+pub struct Monitor<SERIAL> {
+    serial: SERIAL,
+    key: &'static [u8],
+    unlocked: bool,
+    attempts: u8,
+    state: State,
+    command: u8,
+    len: usize,
+    pos: usize,
+    sum: u8,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl<SERIAL, E> Monitor<SERIAL>
+where
+    SERIAL: serial::Read<u8, Error = E> + serial::Write<u8, Error = E>,
+{
+    /// Creates locked monitor, `key` must be at most 64 bytes long
+    pub fn new(serial: SERIAL, key: &'static [u8]) -> Self {
+        assert!(!key.is_empty() && key.len() <= MAX_PAYLOAD);
+        Monitor {
+            serial,
+            key,
+            unlocked: false,
+            attempts: 0,
+            state: State::Sync,
+            command: 0,
+            len: 0,
+            pos: 0,
+            sum: 0,
+            payload: [0; MAX_PAYLOAD],
+        }
+    }
+
+    /// Returns `true` if the monitor accepts privileged commands
+    pub fn is_unlocked(&self) -> bool {
+        self.unlocked
+    }
+
+    /// Processes received bytes, returns the command once a frame was served
+    pub fn poll(&mut self) -> nb::Result<Command, E> {
+        loop {
+            let byte = match self.serial.read() {
+                Ok(byte) => byte,
+                Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                Err(err) => {
+                    self.state = State::Sync;
+                    return Err(err);
+                }
+            };
+            if let Some(command) = self.feed(byte)? {
+                return Ok(command);
+            }
+        }
+    }
+
+    /// Releases the serial port
+    pub fn release(self) -> SERIAL {
+        self.serial
+    }
+
+    fn feed(&mut self, byte: u8) -> Result<Option<Command>, E> {
+        match self.state {
+            State::Sync => {
+                if byte == REQUEST_SYNC {
+                    self.sum = 0;
+                    self.state = State::Command;
+                }
+                return Ok(None);
+            }
+            State::Command => {
+                self.command = byte;
+                self.state = State::Length;
+            }
+            State::Length => {
+                self.len = byte as usize;
+                self.pos = 0;
+                self.state = if self.len > MAX_PAYLOAD {
+                    State::Sync
+                } else if self.len == 0 {
+                    State::Checksum
+                } else {
+                    State::Payload
+                };
+            }
+            State::Payload => {
+                self.payload[self.pos] = byte;
+                self.pos += 1;
+                if self.pos == self.len {
+                    self.state = State::Checksum;
+                }
+            }
+            State::Checksum => {
+                self.state = State::Sync;
+                if self.sum.wrapping_add(byte) != 0 {
+                    self.respond(Status::BadFrame, &[])?;
+                    return Ok(None);
+                }
+                return self.execute();
+            }
+        }
+        self.sum = self.sum.wrapping_add(byte);
+        Ok(None)
+    }
+
+    fn execute(&mut self) -> Result<Option<Command>, E> {
+        let command = match self.command {
+            0x01 => Command::Unlock,
+            0x02 => Command::Lock,
+            0x03 => Command::Peek,
+            0x04 => Command::Poke,
+            0x05 => Command::ReadRegister,
+            0x06 => Command::WriteRegister,
+            0x07 => Command::Reset,
+            _ => {
+                self.respond(Status::UnknownCommand, &[])?;
+                return Ok(None);
+            }
+        };
+        match command {
+            Command::Unlock => {
+                let status = self.unlock();
+                self.respond(status, &[])?;
+                return Ok(Some(command));
+            }
+            Command::Lock => {
+                self.unlocked = false;
+                self.respond(Status::Ok, &[])?;
+                return Ok(Some(command));
+            }
+            _ if !self.unlocked => {
+                self.respond(Status::Locked, &[])?;
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        let payload = self.payload;
+        let args = &payload[..self.len];
+        if args.len() < 4 {
+            self.respond(Status::BadFrame, &[])?;
+            return Ok(None);
+        }
+        let addr = u32::from_le_bytes([args[0], args[1], args[2], args[3]]);
+        match command {
+            Command::Peek => {
+                let len = if args.len() > 4 { args[4] as usize } else { 4 };
+                if len > MAX_PAYLOAD || !readable(addr, len) {
+                    return self.reject();
+                }
+                let mut data = [0; MAX_PAYLOAD];
+                for (idx, byte) in data[..len].iter_mut().enumerate() {
+                    *byte = unsafe { ptr::read_volatile((addr as usize + idx) as *const u8) };
+                }
+                self.respond(Status::Ok, &data[..len])?;
+            }
+            Command::Poke => {
+                let data = &args[4..];
+                if !writable(addr, data.len()) {
+                    return self.reject();
+                }
+                for (idx, byte) in data.iter().enumerate() {
+                    unsafe { ptr::write_volatile((addr as usize + idx) as *mut u8, *byte) };
+                }
+                self.respond(Status::Ok, &[])?;
+            }
+            Command::ReadRegister => {
+                if addr & 0b11 != 0 || !readable(addr, 4) {
+                    return self.reject();
+                }
+                let value = unsafe { ptr::read_volatile(addr as *const u32) };
+                self.respond(Status::Ok, &value.to_le_bytes())?;
+            }
+            Command::WriteRegister => {
+                if args.len() != 8 || addr & 0b11 != 0 || !writable(addr, 4) {
+                    return self.reject();
+                }
+                let value = u32::from_le_bytes([args[4], args[5], args[6], args[7]]);
+                unsafe { ptr::write_volatile(addr as *mut u32, value) };
+                self.respond(Status::Ok, &[])?;
+            }
+            Command::Reset => {
+                self.respond(Status::Ok, &[])?;
+                block!(self.serial.flush())?;
+                SCB::sys_reset();
+            }
+            Command::Unlock | Command::Lock => unreachable!(),
+        }
+        Ok(Some(command))
+    }
+
+    fn unlock(&mut self) -> Status {
+        if self.attempts >= MAX_UNLOCK_ATTEMPTS {
+            return Status::Locked;
+        }
+        let key = &self.payload[..self.len];
+        let mut diff = (key.len() != self.key.len()) as u8;
+        for (a, b) in key.iter().zip(self.key.iter()) {
+            diff |= a ^ b;
+        }
+        if diff == 0 {
+            self.attempts = 0;
+            self.unlocked = true;
+            Status::Ok
+        } else {
+            self.attempts += 1;
+            Status::BadKey
+        }
+    }
+
+    fn reject(&mut self) -> Result<Option<Command>, E> {
+        self.respond(Status::BadAddress, &[])?;
+        Ok(None)
+    }
+
+    fn respond(&mut self, status: Status, data: &[u8]) -> Result<(), E> {
+        let header = [status as u8, data.len() as u8];
+        let mut sum = 0u8;
+        block!(self.serial.write(RESPONSE_SYNC))?;
+        for byte in header.iter().chain(data.iter()) {
+            sum = sum.wrapping_add(*byte);
+            block!(self.serial.write(*byte))?;
+        }
+        block!(self.serial.write(sum.wrapping_neg()))
+    }
+}
+
+fn in_range(addr: u32, len: usize, start: u32, end: u32) -> bool {
+    addr >= start && (addr as u64 + len as u64) <= end as u64
+}
+
+fn readable(addr: u32, len: usize) -> bool {
+    in_range(addr, len, 0x0800_0000, 0x0802_0000)
+        || in_range(addr, len, 0x1fff_0000, 0x1fff_7880)
+        || writable(addr, len)
+}
+
+fn writable(addr: u32, len: usize) -> bool {
+    in_range(addr, len, 0x2000_0000, SRAM_END)
+        || in_range(addr, len, 0x4000_0000, 0x4002_6400)
+        || in_range(addr, len, 0x5000_0000, 0x5000_1800)
+}
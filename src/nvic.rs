@@ -0,0 +1,192 @@
+//! Selective interrupt masking
+//!
+//! Cortex-M0+ has no BASEPRI register, so the only way to protect shared state
+//! without disabling every interrupt is to temporarily clear the NVIC enable bits
+//! of the interrupts that touch that state. Interrupts outside of the mask (e.g.
+//! motor control loops) keep running while the masked section executes.
+use core::cell::UnsafeCell;
+use core::sync::atomic::{self, AtomicBool, Ordering};
+
+use bare_metal::Nr;
+use cortex_m::interrupt;
+use cortex_m::peripheral::NVIC;
+
+/// Set of NVIC interrupt lines
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IrqMask(u32);
+
+impl IrqMask {
+    /// Empty mask
+    pub const fn new() -> Self {
+        IrqMask(0)
+    }
+
+    /// Creates mask from raw NVIC line bits
+    pub const fn from_bits(bits: u32) -> Self {
+        IrqMask(bits)
+    }
+
+    /// Adds interrupt line to the mask
+    pub fn with<I: Nr>(self, irq: I) -> Self {
+        IrqMask(self.0 | 1 << irq.nr())
+    }
+
+    /// Returns raw NVIC line bits
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if the interrupt line is part of the mask
+    pub fn contains<I: Nr>(&self, irq: I) -> bool {
+        self.0 & (1 << irq.nr()) != 0
+    }
+
+    /// Returns `true` if all lines of `other` are part of the mask
+    pub fn covers(&self, other: &IrqMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for IrqMask {
+    fn default() -> Self {
+        IrqMask::new()
+    }
+}
+
+/// Token proving that the interrupts of a mask are disabled
+pub struct MaskedSection<'a> {
+    mask: &'a IrqMask,
+}
+
+impl<'a> MaskedSection<'a> {
+    /// Interrupt lines disabled by this section
+    pub fn mask(&self) -> &IrqMask {
+        self.mask
+    }
+}
+
+/// Executes closure with interrupts from the mask disabled
+///
+/// Only lines that were enabled on entry are re-enabled on exit, so nested
+/// sections and interrupts that were disabled by the application stay intact.
+pub fn masked<F, R>(mask: &IrqMask, f: F) -> R
+where
+    F: FnOnce(&MaskedSection) -> R,
+{
+    // NOTE(unsafe) M0+ NVIC supports up to 32 lines, only first ISER/ICER word is used
+    let nvic = unsafe { &*NVIC::ptr() };
+    // A handler running between the read and the write could enable a line
+    // that is then left disabled, or disabled for good on exit
+    let disabled = interrupt::free(|_| {
+        let disabled = nvic.iser[0].read() & mask.0;
+        unsafe { nvic.icer[0].write(disabled) };
+        disabled
+    });
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    atomic::compiler_fence(Ordering::SeqCst);
+
+    let res = f(&MaskedSection { mask });
+
+    atomic::compiler_fence(Ordering::SeqCst);
+    unsafe { nvic.iser[0].write(disabled) };
+    res
+}
+
+/// Data shared between thread mode and a set of interrupt handlers
+///
+/// The mask lists the handlers touching the data, access is granted only
+/// inside a section masking all of them. The borrow flag is checked with all
+/// interrupts disabled for a few instructions, so sharing data with a handler
+/// outside of the mask panics instead of racing.
+pub struct Shared<T> {
+    mask: IrqMask,
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    /// Creates a value shared with the interrupts of `mask`
+    pub const fn new(mask: IrqMask, value: T) -> Self {
+        Shared {
+            mask,
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Interrupt lines that access the data
+    pub fn mask(&self) -> &IrqMask {
+        &self.mask
+    }
+
+    /// Masks the interrupts of the data and grants mutable access to it
+    pub fn lock<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        masked(&self.mask, |section| self.borrow_mut(section, f))
+    }
+
+    /// Grants mutable access from an already masked section
+    ///
+    /// Panics if the section leaves an interrupt of the data enabled.
+    pub fn borrow_mut<F, R>(&self, section: &MaskedSection, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        assert!(
+            section.mask().covers(&self.mask),
+            "section does not mask the shared data"
+        );
+        interrupt::free(|_| {
+            assert!(!self.locked.load(Ordering::Relaxed), "shared data is locked");
+            self.locked.store(true, Ordering::Relaxed);
+        });
+        let res = f(unsafe { &mut *self.data.get() });
+        self.locked.store(false, Ordering::Release);
+        res
+    }
+
+    /// Consumes the wrapper, returning the data
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Line(u8);
+
+    unsafe impl Nr for Line {
+        fn nr(&self) -> u8 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn mask_lines() {
+        let mask = IrqMask::new().with(Line(0)).with(Line(5)).with(Line(31));
+        assert_eq!(mask.bits(), 1 | 1 << 5 | 1 << 31);
+        assert!(mask.contains(Line(5)));
+        assert!(!mask.contains(Line(6)));
+        assert_eq!(IrqMask::from_bits(mask.bits()), mask);
+        assert_eq!(IrqMask::default().bits(), 0);
+        assert!(mask.covers(&IrqMask::new().with(Line(5))));
+        assert!(!mask.covers(&IrqMask::new().with(Line(6))));
+    }
+
+    #[test]
+    #[should_panic(expected = "section does not mask the shared data")]
+    fn borrow_in_foreign_section() {
+        let shared = Shared::new(IrqMask::new().with(Line(3)), 1);
+        let mask = IrqMask::new().with(Line(4));
+        let section = MaskedSection { mask: &mask };
+        shared.borrow_mut(&section, |value| *value += 1);
+    }
+}
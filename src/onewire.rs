@@ -0,0 +1,291 @@
+//! # 1-Wire bus master on a half-duplex USART
+//!
+//! The USART TX pin, set to open-drain and pulled up, drives the bus. A reset
+//! pulse is a `0xf0` character at 9600 baud, the presence pulse of a device
+//! shows up in the echo. Time slots are characters at 115200 baud: `0xff`
+//! writes or reads a one, `0x00` writes a zero.
+//!
+//! ```ignore
+//! let pin = gpioa.pa9.set_open_drain();
+//! let mut bus = OneWire::usart1(dp.USART1, pin, &mut rcc);
+//! let mut search = Search::new();
+//! while let Some(rom) = bus.search(&mut search)? {
+//!     bus.select(&rom)?;
+//! }
+//! ```
+use crate::rcc::{Enable, KernelClockUser, Rcc};
+use crate::serial::TxPin;
+use crate::stm32::*;
+
+// CR1
+const UE: u32 = 1;
+const RE: u32 = 1 << 2;
+const TE: u32 = 1 << 3;
+// CR3
+const HDSEL: u32 = 1 << 3;
+// ISR and ICR
+const FE: u32 = 1 << 1;
+const ORE: u32 = 1 << 3;
+const RXNE: u32 = 1 << 5;
+const TXE: u32 = 1 << 7;
+
+const RESET_BAUD: u32 = 9_600;
+const SLOT_BAUD: u32 = 115_200;
+const RESET_PULSE: u8 = 0xf0;
+
+// ROM commands
+const SEARCH_ROM: u8 = 0xf0;
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xcc;
+
+/// 1-Wire error
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Bus held low, e.g. shorted or missing pull-up
+    BusFault,
+    /// Received character overrun
+    Overrun,
+    /// ROM code with invalid CRC
+    Crc,
+}
+
+/// ROM search progress, shared by successive `search` calls
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Search {
+    rom: [u8; 8],
+    last_discrepancy: u8,
+    done: bool,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Dallas/Maxim CRC8 of `data`, zero over a ROM code including its CRC
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0;
+    for byte in data {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8c;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+/// 1-Wire bus master
+pub struct OneWire<USART, PIN> {
+    usart: USART,
+    pin: PIN,
+    clk: u32,
+}
+
+macro_rules! onewire {
+    ($($USARTX:ident: ($usartX:ident, $kernel:expr),)+) => {
+        $(
+            impl<PIN: TxPin<$USARTX>> OneWire<$USARTX, PIN> {
+                /// Configures the USART in half-duplex mode on its TX pin
+                ///
+                /// The pin must be open-drain with a pull-up, usually an external
+                /// 4.7k resistor.
+                pub fn $usartX(usart: $USARTX, pin: PIN, rcc: &mut Rcc) -> Self {
+                    pin.setup();
+                    let kernel: Option<KernelClockUser> = $kernel;
+                    let clk = match kernel {
+                        Some(user) => rcc.kernel_clock(user).0,
+                        None => rcc.clocks.apb_clk.0,
+                    };
+                    $USARTX::enable(rcc);
+                    usart.cr1.reset();
+                    usart.cr2.reset();
+                    usart.cr3.write(|w| unsafe { w.bits(HDSEL) });
+                    let mut bus = OneWire { usart, pin, clk };
+                    bus.set_baudrate(SLOT_BAUD);
+                    bus
+                }
+
+                /// Sends a reset pulse, returns `true` if a device answered
+                pub fn reset(&mut self) -> Result<bool, Error> {
+                    self.set_baudrate(RESET_BAUD);
+                    let echo = self.exchange(RESET_PULSE);
+                    self.set_baudrate(SLOT_BAUD);
+                    Ok(echo? != RESET_PULSE)
+                }
+
+                pub fn write_bit(&mut self, bit: bool) -> Result<(), Error> {
+                    let slot = if bit { 0xff } else { 0x00 };
+                    if self.exchange(slot)? != slot {
+                        return Err(Error::BusFault);
+                    }
+                    Ok(())
+                }
+
+                /// Reads a bit, devices pull the bus low in the slot to send a zero
+                pub fn read_bit(&mut self) -> Result<bool, Error> {
+                    Ok(self.exchange(0xff)? == 0xff)
+                }
+
+                /// Writes a byte, LSB first
+                pub fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+                    for bit in 0..8 {
+                        self.write_bit(byte & (1 << bit) != 0)?;
+                    }
+                    Ok(())
+                }
+
+                pub fn read_byte(&mut self) -> Result<u8, Error> {
+                    let mut byte = 0;
+                    for bit in 0..8 {
+                        if self.read_bit()? {
+                            byte |= 1 << bit;
+                        }
+                    }
+                    Ok(byte)
+                }
+
+                pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+                    for byte in bytes {
+                        self.write_byte(*byte)?;
+                    }
+                    Ok(())
+                }
+
+                pub fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
+                    for byte in bytes.iter_mut() {
+                        *byte = self.read_byte()?;
+                    }
+                    Ok(())
+                }
+
+                /// Resets the bus and addresses the device with `rom`
+                pub fn select(&mut self, rom: &[u8; 8]) -> Result<bool, Error> {
+                    if !self.reset()? {
+                        return Ok(false);
+                    }
+                    self.write_byte(MATCH_ROM)?;
+                    self.write_bytes(rom)?;
+                    Ok(true)
+                }
+
+                /// Resets the bus and addresses all devices, or the only one
+                pub fn skip_rom(&mut self) -> Result<bool, Error> {
+                    if !self.reset()? {
+                        return Ok(false);
+                    }
+                    self.write_byte(SKIP_ROM)?;
+                    Ok(true)
+                }
+
+                /// Returns the next ROM code on the bus, `None` once all were found
+                pub fn search(&mut self, search: &mut Search) -> Result<Option<[u8; 8]>, Error> {
+                    if search.done || !self.reset()? {
+                        return Ok(None);
+                    }
+                    self.write_byte(SEARCH_ROM)?;
+
+                    let mut last_zero = 0;
+                    for id_bit in 1..=64u8 {
+                        let (byte, mask) = (((id_bit - 1) / 8) as usize, 1 << ((id_bit - 1) % 8));
+                        let bit = self.read_bit()?;
+                        let complement = self.read_bit()?;
+                        let direction = match (bit, complement) {
+                            (true, true) => {
+                                *search = Search::new();
+                                return Ok(None);
+                            }
+                            (true, false) => true,
+                            (false, true) => false,
+                            // Discrepancy, devices with both values answered
+                            (false, false) => {
+                                let direction = if id_bit < search.last_discrepancy {
+                                    search.rom[byte] & mask != 0
+                                } else {
+                                    id_bit == search.last_discrepancy
+                                };
+                                if !direction {
+                                    last_zero = id_bit;
+                                }
+                                direction
+                            }
+                        };
+                        if direction {
+                            search.rom[byte] |= mask;
+                        } else {
+                            search.rom[byte] &= !mask;
+                        }
+                        self.write_bit(direction)?;
+                    }
+
+                    search.last_discrepancy = last_zero;
+                    search.done = last_zero == 0;
+                    if crc8(&search.rom) != 0 {
+                        return Err(Error::Crc);
+                    }
+                    Ok(Some(search.rom))
+                }
+
+                /// Disables the USART and its clock, returns the peripheral and pin
+                pub fn release(self, rcc: &mut Rcc) -> ($USARTX, PIN) {
+                    self.usart.cr1.reset();
+                    self.usart.cr3.reset();
+                    $USARTX::disable(rcc);
+                    (self.usart, self.pin)
+                }
+
+                fn set_baudrate(&mut self, baud: u32) {
+                    let usart = &self.usart;
+                    usart.cr1.write(|w| unsafe { w.bits(0) });
+                    usart.brr.write(|w| unsafe { w.bits((self.clk + baud / 2) / baud) });
+                    usart.cr1.write(|w| unsafe { w.bits(UE | TE | RE) });
+                }
+
+                /// Sends `byte` and returns the character read back from the bus
+                fn exchange(&mut self, byte: u8) -> Result<u8, Error> {
+                    let usart = &self.usart;
+                    // Drop a stale character
+                    usart.icr.write(|w| unsafe { w.bits(FE | ORE) });
+                    if usart.isr.read().bits() & RXNE != 0 {
+                        let _ = usart.rdr.read();
+                    }
+                    while usart.isr.read().bits() & TXE == 0 {}
+                    usart.tdr.write(|w| unsafe { w.bits(byte as u32) });
+                    let isr = loop {
+                        let isr = usart.isr.read().bits();
+                        if isr & RXNE != 0 {
+                            break isr;
+                        }
+                    };
+                    let echo = usart.rdr.read().bits() as u8;
+                    if isr & ORE != 0 {
+                        usart.icr.write(|w| unsafe { w.bits(ORE) });
+                        return Err(Error::Overrun);
+                    }
+                    if isr & FE != 0 {
+                        usart.icr.write(|w| unsafe { w.bits(FE) });
+                        return Err(Error::BusFault);
+                    }
+                    Ok(echo)
+                }
+            }
+        )+
+    }
+}
+
+onewire! {
+    USART1: (usart1, Some(KernelClockUser::Usart1)),
+    USART2: (usart2, crate::serial::USART2_KERNEL),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+onewire! {
+    USART3: (usart3, None),
+    USART4: (usart4, None),
+}
@@ -0,0 +1,590 @@
+//! # Power management
+//!
+//! `Power` selects the voltage range and low power run mode, enters Stop,
+//! Standby and Shutdown modes and configures the wakeup pins that bring the
+//! MCU out of Standby and Shutdown. The programmable voltage detector
+//! (PVD) warns about a falling supply through EXTI line 16, the brown-out
+//! reset (BOR) thresholds are programmed in the option bytes.
+//!
+//! Time spent in Run, Sleep and Stop modes is accumulated from a low power
+//! tick source (e.g. LPTIM or RTC counter) that keeps running in Stop mode.
+//! The tick source must be sampled at least once per counter wrap period.
+use core::cell::Cell;
+
+use cortex_m::interrupt::{self, Mutex};
+use cortex_m::peripheral::SCB;
+
+use crate::rcc::Rcc;
+use crate::stm32::{FLASH, PWR};
+use crate::time::Hertz;
+
+// CR1
+const LPMS_MASK: u32 = 0b111;
+const VOS_MASK: u32 = 0b11 << 9;
+const LPR: u32 = 1 << 14;
+// CR3
+const APC: u32 = 1 << 10;
+// SR1
+const SBF: u32 = 1 << 8;
+const WUFI: u32 = 1 << 15;
+// CR2
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+const PVDE: u32 = 1 << 0;
+// SR2
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+const PVDO: u32 = 1 << 11;
+const REGLPF: u32 = 1 << 9;
+const VOSF: u32 = 1 << 10;
+// SCR
+const CWUF_MASK: u32 = 0b11_1111;
+const CSBF: u32 = 1 << 8;
+// FLASH_OPTR
+const BOR_EN: u32 = 1 << 8;
+const BORF_LEV_SHIFT: u32 = 9;
+const BORR_LEV_SHIFT: u32 = 11;
+const BOR_MASK: u32 = BOR_EN | 0b11 << BORF_LEV_SHIFT | 0b11 << BORR_LEV_SHIFT;
+// FLASH_SR
+const FLASH_BSY1: u32 = 1 << 16;
+
+/// Regulator voltage range
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VoltageScale {
+    /// High performance, up to 64MHz
+    Range1 = 1,
+    /// Low power, up to 16MHz
+    Range2 = 2,
+}
+
+/// Stop mode flavour
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StopMode {
+    /// Main regulator on, fastest wakeup
+    Stop0 = 0,
+    /// Low power regulator
+    Stop1 = 1,
+}
+
+/// Standby and Shutdown wakeup pin
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeupPin {
+    /// PA0
+    Wkup1 = 0,
+    /// PC13
+    Wkup2 = 1,
+    /// PA2
+    Wkup4 = 3,
+    /// PC5
+    Wkup5 = 4,
+    /// PB5
+    Wkup6 = 5,
+}
+
+impl WakeupPin {
+    /// GPIO port index and pin number
+    fn gpio(self) -> (usize, u32) {
+        match self {
+            WakeupPin::Wkup1 => (0, 0),
+            WakeupPin::Wkup2 => (2, 13),
+            WakeupPin::Wkup4 => (0, 2),
+            WakeupPin::Wkup5 => (2, 5),
+            WakeupPin::Wkup6 => (1, 5),
+        }
+    }
+}
+
+/// Wakeup pin active edge
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeupEdge {
+    Rising,
+    Falling,
+}
+
+/// Pull applied to a wakeup pin in Standby and Shutdown
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeupPull {
+    None,
+    Up,
+    Down,
+}
+
+/// What brought the MCU out of its last low power mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeupReason {
+    /// Woke up from Standby by a wakeup pin
+    Pin(WakeupPin),
+    /// Woke up from Standby by the RTC or TAMP
+    Internal,
+    /// Woke up from Standby by a reset
+    Standby,
+    /// No Standby wakeup: power on, reset or wakeup from Shutdown
+    Reset,
+}
+
+/// PVD threshold, rising / falling voltage
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PvdThreshold {
+    /// 2.15V / 2.05V
+    Level0 = 0,
+    /// 2.30V / 2.20V
+    Level1 = 1,
+    /// 2.46V / 2.36V
+    Level2 = 2,
+    /// 2.62V / 2.52V
+    Level3 = 3,
+    /// 2.76V / 2.67V
+    Level4 = 4,
+    /// 2.90V / 2.80V
+    Level5 = 5,
+    /// 3.05V / 2.95V
+    Level6 = 6,
+    /// PVD_IN pin (PB7) against the internal reference
+    External = 7,
+}
+
+/// Brown-out reset threshold, rising / falling voltage
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BorThreshold {
+    /// 2.10V / 2.00V
+    Level1 = 0,
+    /// 2.30V / 2.20V
+    Level2 = 1,
+    /// 2.60V / 2.50V
+    Level3 = 2,
+    /// 2.90V / 2.80V
+    Level4 = 3,
+}
+
+impl BorThreshold {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0 => BorThreshold::Level1,
+            1 => BorThreshold::Level2,
+            2 => BorThreshold::Level3,
+            _ => BorThreshold::Level4,
+        }
+    }
+}
+
+/// Constrained PWR peripheral
+pub struct Power {
+    rb: PWR,
+}
+
+pub trait PowerExt {
+    fn constrain(self, rcc: &mut Rcc) -> Power;
+}
+
+impl PowerExt for PWR {
+    fn constrain(self, rcc: &mut Rcc) -> Power {
+        rcc.rb.apbenr1.modify(|_, w| w.pwren().set_bit());
+        Power { rb: self }
+    }
+}
+
+impl Power {
+    /// Selects the regulator voltage range
+    ///
+    /// Range 2 limits the system clock to 16MHz and needs one flash wait
+    /// state above 8MHz, which is set here.
+    pub fn set_voltage_scale(&mut self, scale: VoltageScale, rcc: &Rcc) {
+        if scale == VoltageScale::Range2 {
+            let sys_clk = rcc.clocks.sys_clk.0;
+            assert!(sys_clk <= 16_000_000);
+            if sys_clk > 8_000_000 {
+                let flash = unsafe { &(*FLASH::ptr()) };
+                flash.acr.modify(|r, w| unsafe { w.bits(r.bits() & !0b111 | 1) });
+            }
+        }
+        self.rb.cr1.modify(|r, w| unsafe {
+            w.bits(r.bits() & !VOS_MASK | (scale as u32) << 9)
+        });
+        while self.rb.sr2.read().bits() & VOSF != 0 {}
+    }
+
+    /// Switches the regulator to low power run mode
+    ///
+    /// The system clock must not exceed 2MHz.
+    pub fn enter_low_power_run(&mut self, rcc: &Rcc) {
+        assert!(rcc.clocks.sys_clk.0 <= 2_000_000);
+        self.rb.cr1.modify(|r, w| unsafe { w.bits(r.bits() | LPR) });
+    }
+
+    /// Returns to the main regulator
+    pub fn exit_low_power_run(&mut self) {
+        self.rb.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !LPR) });
+        while self.rb.sr2.read().bits() & REGLPF != 0 {}
+    }
+
+    /// Enables wakeup from Standby and Shutdown on `pin`
+    pub fn enable_wakeup_pin(&mut self, pin: WakeupPin, edge: WakeupEdge, pull: WakeupPull) {
+        let bit = 1 << pin as u32;
+        self.rb.cr4.modify(|r, w| unsafe {
+            w.bits(match edge {
+                WakeupEdge::Rising => r.bits() & !bit,
+                WakeupEdge::Falling => r.bits() | bit,
+            })
+        });
+
+        // PUCRx and PDCRx pairs start at 0x20
+        let (port, n) = pin.gpio();
+        let pucr = unsafe { (PWR::ptr() as *mut u32).add(8 + 2 * port) };
+        let pdcr = unsafe { pucr.add(1) };
+        let (up, down) = match pull {
+            WakeupPull::None => (false, false),
+            WakeupPull::Up => (true, false),
+            WakeupPull::Down => (false, true),
+        };
+        unsafe {
+            set_bit(pucr, n, up);
+            set_bit(pdcr, n, down);
+        }
+
+        self.rb.cr3.modify(|r, w| unsafe { w.bits(r.bits() | bit | APC) });
+    }
+
+    /// Disables wakeup on `pin`
+    pub fn disable_wakeup_pin(&mut self, pin: WakeupPin) {
+        self.rb
+            .cr3
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << pin as u32)) });
+    }
+
+    /// Enables the voltage detector
+    ///
+    /// VDD dropping below `falling` or rising above `rising` sets EXTI
+    /// line 16, listen to `exti::Event::PVD` with the matching edge (falling
+    /// edge for a dropping supply).
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    pub fn enable_pvd(&mut self, rising: PvdThreshold, falling: PvdThreshold) {
+        self.rb.cr2.write(|w| unsafe {
+            w.bits((rising as u32) << 4 | (falling as u32) << 1 | PVDE)
+        });
+    }
+
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    pub fn disable_pvd(&mut self) {
+        self.rb.cr2.write(|w| unsafe { w.bits(0) });
+    }
+
+    /// Returns `true` while VDD is below the PVD threshold
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    pub fn is_below_pvd_threshold(&self) -> bool {
+        self.rb.sr2.read().bits() & PVDO != 0
+    }
+
+    /// Brown-out reset thresholds programmed in the option bytes
+    ///
+    /// Returns the rising and falling threshold, `None` if only the fixed
+    /// power-on / power-down reset is active.
+    pub fn brown_out_reset(&self) -> Option<(BorThreshold, BorThreshold)> {
+        let flash = unsafe { &(*FLASH::ptr()) };
+        let optr = flash.optr.read().bits();
+        if optr & BOR_EN == 0 {
+            return None;
+        }
+        Some((
+            BorThreshold::from_bits(optr >> BORR_LEV_SHIFT),
+            BorThreshold::from_bits(optr >> BORF_LEV_SHIFT),
+        ))
+    }
+
+    /// Programs the brown-out reset thresholds into the option bytes
+    ///
+    /// `None` disables the brown-out reset. Does nothing if the thresholds
+    /// are already programmed. Otherwise the option bytes are reloaded after
+    /// programming, which resets the device, so this is safe to call early on
+    /// every start-up.
+    pub fn set_brown_out_reset(&mut self, thresholds: Option<(BorThreshold, BorThreshold)>) {
+        if self.brown_out_reset() == thresholds {
+            return;
+        }
+        let bits = match thresholds {
+            Some((rising, falling)) => {
+                BOR_EN | (rising as u32) << BORR_LEV_SHIFT | (falling as u32) << BORF_LEV_SHIFT
+            }
+            None => 0,
+        };
+        unsafe {
+            let flash = &(*FLASH::ptr());
+
+            // Unlock flash
+            flash.keyr.write(|w| w.keyr().bits(0x4567_0123));
+            flash.keyr.write(|w| w.keyr().bits(0xcdef_89ab));
+
+            // Unlock option bytes
+            flash.optkeyr.write(|w| w.optkeyr().bits(0x0819_2a3b));
+            flash.optkeyr.write(|w| w.optkeyr().bits(0x4c5d_6e7f));
+            flash.cr.modify(|_, w| w.optlock().clear_bit());
+
+            while flash.sr.read().bits() & FLASH_BSY1 != 0 {}
+            flash
+                .optr
+                .modify(|r, w| w.bits(r.bits() & !BOR_MASK | bits));
+            flash.cr.modify(|_, w| w.optstrt().set_bit());
+            while flash.sr.read().bits() & FLASH_BSY1 != 0 {}
+            // Reloads the option bytes and resets the device
+            flash.cr.modify(|_, w| w.obl_launch().set_bit());
+        }
+    }
+
+    /// Reason for the last wakeup, read it before `clear_wakeup_flags()`
+    pub fn wakeup_reason(&self) -> WakeupReason {
+        let sr1 = self.rb.sr1.read().bits();
+        if sr1 & SBF == 0 {
+            return WakeupReason::Reset;
+        }
+        let pins = [
+            WakeupPin::Wkup1,
+            WakeupPin::Wkup2,
+            WakeupPin::Wkup4,
+            WakeupPin::Wkup5,
+            WakeupPin::Wkup6,
+        ];
+        match pins.iter().find(|pin| sr1 & (1 << **pin as u32) != 0) {
+            Some(pin) => WakeupReason::Pin(*pin),
+            None if sr1 & WUFI != 0 => WakeupReason::Internal,
+            None => WakeupReason::Standby,
+        }
+    }
+
+    /// Clears the Standby and wakeup pin flags
+    pub fn clear_wakeup_flags(&mut self) {
+        self.rb.scr.write(|w| unsafe { w.bits(CSBF | CWUF_MASK) });
+    }
+
+    /// Enters Stop 0 or Stop 1 until the next wakeup event
+    pub fn stop(&mut self, scb: &mut SCB, mode: StopMode) {
+        self.set_low_power_mode(mode as u32);
+        stop(scb);
+    }
+
+    /// Enters Standby mode, the MCU resets on wakeup
+    ///
+    /// SRAM and registers are lost except for the backup domain and the
+    /// pulls configured for the wakeup pins.
+    pub fn standby(&mut self, scb: &mut SCB) -> ! {
+        self.set_low_power_mode(0b011);
+        self.enter(scb)
+    }
+
+    /// Enters Shutdown mode, the MCU resets on wakeup
+    ///
+    /// Lowest consumption, only the backup domain is kept. The brownout
+    /// reset is off, so the wakeup looks like a power on reset.
+    pub fn shutdown(&mut self, scb: &mut SCB) -> ! {
+        self.set_low_power_mode(0b100);
+        self.enter(scb)
+    }
+
+    pub fn release(self) -> PWR {
+        self.rb
+    }
+
+    fn set_low_power_mode(&mut self, lpms: u32) {
+        self.rb
+            .cr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !LPMS_MASK | lpms) });
+    }
+
+    fn enter(&mut self, scb: &mut SCB) -> ! {
+        // A pending wakeup flag prevents entering the mode
+        self.clear_wakeup_flags();
+        scb.set_sleepdeep();
+        loop {
+            cortex_m::asm::dsb();
+            cortex_m::asm::wfi();
+        }
+    }
+}
+
+unsafe fn set_bit(reg: *mut u32, n: u32, set: bool) {
+    let bits = core::ptr::read_volatile(reg) & !(1 << n);
+    core::ptr::write_volatile(reg, bits | (set as u32) << n);
+}
+
+/// Power state
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerState {
+    Run,
+    Sleep,
+    Stop,
+}
+
+/// Free running tick counter used for energy accounting
+#[derive(Clone, Copy)]
+pub struct TickSource {
+    /// Returns current counter value
+    pub now: fn() -> u32,
+    /// Counter width mask, e.g. `0xffff` for LPTIM
+    pub mask: u32,
+    /// Counter frequency
+    pub freq: Hertz,
+}
+
+/// Power state statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    /// Ticks spent in Run mode
+    pub run_ticks: u64,
+    /// Ticks spent in Sleep mode
+    pub sleep_ticks: u64,
+    /// Ticks spent in Stop mode
+    pub stop_ticks: u64,
+    /// Number of wakeups from Sleep or Stop mode
+    pub wakeups: u32,
+    /// Tick frequency
+    pub tick_freq: u32,
+}
+
+impl Stats {
+    /// Total accounted ticks
+    pub fn total_ticks(&self) -> u64 {
+        self.run_ticks + self.sleep_ticks + self.stop_ticks
+    }
+
+    /// Time spent in Run mode in permille
+    pub fn duty_cycle(&self) -> u16 {
+        match self.total_ticks() {
+            0 => 1000,
+            total => (self.run_ticks * 1000 / total) as u16,
+        }
+    }
+
+    /// Converts ticks into milliseconds
+    pub fn ticks_to_ms(&self, ticks: u64) -> u64 {
+        match self.tick_freq {
+            0 => 0,
+            freq => ticks * 1000 / freq as u64,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Accounting {
+    source: Option<TickSource>,
+    last: u32,
+    stats: Stats,
+}
+
+static ACCOUNTING: Mutex<Cell<Accounting>> = Mutex::new(Cell::new(Accounting {
+    source: None,
+    last: 0,
+    stats: Stats {
+        run_ticks: 0,
+        sleep_ticks: 0,
+        stop_ticks: 0,
+        wakeups: 0,
+        tick_freq: 0,
+    },
+}));
+
+/// Starts energy accounting, resetting statistics
+pub fn enable_stats(source: TickSource) {
+    interrupt::free(|cs| {
+        ACCOUNTING.borrow(cs).set(Accounting {
+            source: Some(source),
+            last: (source.now)(),
+            stats: Stats {
+                tick_freq: source.freq.0,
+                ..Stats::default()
+            },
+        })
+    });
+}
+
+/// Stops energy accounting
+pub fn disable_stats() {
+    interrupt::free(|cs| {
+        let acc = ACCOUNTING.borrow(cs);
+        let mut state = acc.get();
+        state.source = None;
+        acc.set(state);
+    });
+}
+
+/// Returns statistics accumulated since accounting was enabled
+pub fn stats() -> Stats {
+    sample(PowerState::Run);
+    interrupt::free(|cs| ACCOUNTING.borrow(cs).get().stats)
+}
+
+/// Resets statistics
+pub fn reset_stats() {
+    interrupt::free(|cs| {
+        let acc = ACCOUNTING.borrow(cs);
+        let mut state = acc.get();
+        if let Some(source) = state.source {
+            state.last = (source.now)();
+        }
+        state.stats = Stats {
+            tick_freq: state.stats.tick_freq,
+            ..Stats::default()
+        };
+        acc.set(state);
+    });
+}
+
+/// Accounts time spent in the closure to the given power state
+pub fn account<F, R>(state: PowerState, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    sample(PowerState::Run);
+    let res = f();
+    sample(state);
+    if state != PowerState::Run {
+        interrupt::free(|cs| {
+            let acc = ACCOUNTING.borrow(cs);
+            let mut accounting = acc.get();
+            accounting.stats.wakeups = accounting.stats.wakeups.wrapping_add(1);
+            acc.set(accounting);
+        });
+    }
+    res
+}
+
+/// Enters Sleep mode until the next interrupt
+pub fn sleep(scb: &mut SCB) {
+    scb.clear_sleepdeep();
+    account(PowerState::Sleep, cortex_m::asm::wfi);
+}
+
+/// Enters Stop mode until the next wakeup event
+///
+/// Low power mode selection in PWR is left untouched (Stop 0 after reset).
+/// System clock is switched to HSI16 on wakeup.
+pub fn stop(scb: &mut SCB) {
+    scb.set_sleepdeep();
+    account(PowerState::Stop, cortex_m::asm::wfi);
+    scb.clear_sleepdeep();
+}
+
+fn sample(state: PowerState) {
+    interrupt::free(|cs| {
+        let acc = ACCOUNTING.borrow(cs);
+        let mut accounting = acc.get();
+        if let Some(source) = accounting.source {
+            let now = (source.now)();
+            let ticks = now.wrapping_sub(accounting.last) & source.mask;
+            accounting.last = now;
+            let stats = &mut accounting.stats;
+            match state {
+                PowerState::Run => stats.run_ticks += ticks as u64,
+                PowerState::Sleep => stats.sleep_ticks += ticks as u64,
+                PowerState::Stop => stats.stop_ticks += ticks as u64,
+            }
+            acc.set(accounting);
+        }
+    });
+}
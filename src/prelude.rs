@@ -1,9 +1,12 @@
 pub use hal::digital::v2::*;
 pub use hal::prelude::*;
 pub use hal::adc::OneShot as _;
+pub use hal::timer::Cancel as _;
 pub use hal::watchdog::Watchdog as _;
 pub use hal::watchdog::WatchdogEnable as _;
 
+#[cfg(any(feature = "stm32g041", feature = "stm32g081"))]
+pub use crate::aes::AesExt as _;
 pub use crate::analog::adc::AdcExt as _;
 #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 pub use crate::analog::dac::DacExt as _;
@@ -12,16 +15,32 @@ pub use crate::analog::dac::DacOut as _;
 #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 pub use crate::analog::dac::DacPin as _;
 #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pub use crate::analog::vrefbuf::VrefBufExt as _;
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pub use crate::cec::CecExt as _;
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 pub use crate::comparator::ComparatorExt as _;
 pub use crate::crc::CrcExt as _;
+pub use crate::dbg::DbgExt as _;
 pub use crate::delay::DelayExt as _;
+pub use crate::dma::DmaChannelExt as _;
 pub use crate::dma::DmaExt as _;
 pub use crate::dma::CopyDma as _;
 pub use crate::dma::ReadDma as _;
 pub use crate::dma::WriteDma as _;
 pub use crate::exti::ExtiExt as _;
+pub use crate::flash::FlashExt as _;
 pub use crate::gpio::GpioExt as _;
 pub use crate::i2c::I2cExt as _;
+#[cfg(any(
+    feature = "stm32g031",
+    feature = "stm32g041",
+    feature = "stm32g07x",
+    feature = "stm32g081"
+))]
+pub use crate::lptim::LptimExt as _;
+pub use crate::power::PowerExt as _;
+pub use crate::probe::Probe as _;
 pub use crate::rcc::LSCOExt as _;
 pub use crate::rcc::MCOExt as _;
 pub use crate::rcc::RccExt as _;
@@ -29,12 +48,23 @@ pub use crate::rng::RngCore as _;
 pub use crate::rng::RngExt as _;
 pub use crate::rtc::RtcExt as _;
 pub use crate::serial::SerialExt as _;
+pub use crate::spi::slave::SpiSlaveExt as _;
 pub use crate::spi::SpiExt as _;
+pub use crate::syscfg::SysCfgExt as _;
+pub use crate::tamp::TampExt as _;
 pub use crate::time::U32Ext as _;
+pub use crate::timer::capture::CaptureExt as _;
+pub use crate::timer::capture::PwmInputExt as _;
+pub use crate::timer::monotonic::MonoTimerExt as _;
 pub use crate::timer::opm::OpmExt as _;
 pub use crate::timer::pwm::PwmExt as _;
 pub use crate::timer::qei::QeiExt as _;
+pub use crate::timer::recorder::EdgeRecorderExt as _;
 pub use crate::timer::stopwatch::StopwatchExt as _;
+pub use crate::timer::sync::MasterTimer as _;
+pub use crate::timer::sync::SlaveTimer as _;
 pub use crate::timer::TimerExt as _;
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pub use crate::ucpd::UcpdExt as _;
 pub use crate::watchdog::IWDGExt as _;
 pub use crate::watchdog::WWDGExt as _;
@@ -0,0 +1,69 @@
+//! # Peripheral probing
+//!
+//! Devices sharing a PAC feature (e.g. STM32G070/G071, STM32G031/G041) do
+//! not implement the same peripheral set. Probing enables the peripheral
+//! clock, checks that a scratch register holds a test pattern and restores
+//! the reset state, so one binary can adapt to the device it runs on.
+use crate::rcc::Rcc;
+use crate::signature::FlashSize;
+use crate::stm32::*;
+
+/// Runtime peripheral presence check
+pub trait Probe: Sized {
+    /// Returns `true` if the peripheral is implemented on this device
+    fn is_present(&self, rcc: &mut Rcc) -> bool;
+
+    /// Returns the peripheral if it is implemented on this device
+    fn probe(self, rcc: &mut Rcc) -> Option<Self> {
+        if self.is_present(rcc) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+/// Flash memory size in kilobytes
+pub fn flash_size_kb() -> u16 {
+    FlashSize::kb()
+}
+
+macro_rules! probe {
+    ($($PER:ident: ($enr:ident, $en:ident, $reg:ident, $pattern:expr),)+) => {
+        $(
+            impl Probe for $PER {
+                fn is_present(&self, rcc: &mut Rcc) -> bool {
+                    let enabled = rcc.rb.$enr.read().$en().bit_is_set();
+                    rcc.rb.$enr.modify(|_, w| w.$en().set_bit());
+                    if rcc.rb.$enr.read().$en().bit_is_clear() {
+                        return false;
+                    }
+                    let reset = self.$reg.read().bits();
+                    self.$reg.write(|w| unsafe { w.bits($pattern) });
+                    let present = self.$reg.read().bits() == $pattern;
+                    self.$reg.write(|w| unsafe { w.bits(reset) });
+                    rcc.rb.$enr.modify(|_, w| w.$en().bit(enabled));
+                    present
+                }
+            }
+        )+
+    }
+}
+
+probe! {
+    LPUART: (apbenr1, lpuart1en, brr, 0x5a5),
+    RNG: (ahbenr, rngen, cr, 1 << 3),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+probe! {
+    DAC: (apbenr1, dac1en, dac_dhr12r1, 0x5a5),
+    LPTIM1: (apbenr1, lptim1en, cfgr, 0b101 << 9),
+    LPTIM2: (apbenr1, lptim2en, cfgr, 0b101 << 9),
+    TIM15: (apbenr2, tim15en, arr, 0x5a5),
+}
+
+#[cfg(any(feature = "stm32g041", feature = "stm32g081"))]
+probe! {
+    AES: (ahbenr, aesen, cr, 0b10 << 1),
+}
@@ -4,11 +4,11 @@ use crate::stm32::RCC;
 
 pub type LscoPin = gpioa::PA2<DefaultMode>;
 
-pub struct Lsco {
-    pin: LscoPin,
+pub struct Lsco<PIN = LscoPin> {
+    pin: PIN,
 }
 
-impl Lsco {
+impl<PIN> Lsco<PIN> {
     pub fn enable(&self) {
         let rcc = unsafe { &(*RCC::ptr()) };
         rcc.bdcr.modify(|_, w| w.lscoen().set_bit());
@@ -19,34 +19,42 @@ impl Lsco {
         rcc.bdcr.modify(|_, w| w.lscoen().clear_bit());
     }
 
-    pub fn release(self) -> LscoPin {
+    pub fn release(self) -> PIN {
         self.pin
     }
 }
 
-pub trait LSCOExt {
-    fn lsco(self, src: LSCOSrc, rcc: &mut Rcc) -> Lsco;
+pub trait LSCOExt: Sized {
+    fn lsco(self, src: LSCOSrc, rcc: &mut Rcc) -> Lsco<Self>;
 }
 
-impl LSCOExt for LscoPin {
-    fn lsco(self, src: LSCOSrc, rcc: &mut Rcc) -> Lsco {
-        self.set_alt_mode(AltFunction::AF0);
-        let src_select_bit = match src {
-            LSCOSrc::LSE => {
-                rcc.enable_lse(false);
-                true
-            }
-            LSCOSrc::LSI => {
-                rcc.enable_lsi();
-                false
+macro_rules! lsco {
+    ($($PIN:ty),+) => {
+        $(
+            impl LSCOExt for $PIN {
+                fn lsco(self, src: LSCOSrc, rcc: &mut Rcc) -> Lsco<$PIN> {
+                    self.set_alt_mode(AltFunction::AF0);
+                    let src_select_bit = match src {
+                        LSCOSrc::LSE => {
+                            rcc.enable_lse(false);
+                            true
+                        }
+                        LSCOSrc::LSI => {
+                            rcc.enable_lsi();
+                            false
+                        }
+                    };
+                    rcc.unlock_rtc();
+                    rcc.rb.bdcr.modify(|_, w| w.lscosel().bit(src_select_bit));
+                    Lsco { pin: self }
+                }
             }
-        };
-        rcc.unlock_rtc();
-        rcc.rb.bdcr.modify(|_, w| w.lscosel().bit(src_select_bit));
-        Lsco { pin: self }
-    }
+        )+
+    };
 }
 
+lsco!(LscoPin, gpioa::PA2<Alternate<AF0>>);
+
 pub struct Mco<PIN> {
     pin: PIN,
     src_bits: u8,
@@ -123,6 +131,9 @@ macro_rules! mco {
 
 mco!(
     gpioa::PA8<DefaultMode>,
+    gpioa::PA8<Alternate<AF0>>,
     gpioa::PA9<DefaultMode>,
-    gpiof::PF2<DefaultMode>
+    gpioa::PA9<Alternate<AF0>>,
+    gpiof::PF2<DefaultMode>,
+    gpiof::PF2<Alternate<AF0>>
 );
@@ -1,7 +1,10 @@
+use crate::rcc::{PLLClocks, HSI_FREQ};
 use crate::time::Hertz;
+use core::ops::RangeInclusive;
 
 /// Prescaler
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Prescaler {
     NotDivided,
     Div2,
@@ -16,6 +19,7 @@ pub enum Prescaler {
 }
 
 /// System clock mux source
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SysClockSrc {
     LSI,
     PLL,
@@ -27,6 +31,7 @@ pub enum SysClockSrc {
 }
 
 /// Microcontroller clock output source
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum MCOSrc {
     LSI,
     PLL,
@@ -37,6 +42,7 @@ pub enum MCOSrc {
 }
 
 /// Low-speed clocks output source
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum LSCOSrc {
     LSI,
     LSE,
@@ -44,6 +50,7 @@ pub enum LSCOSrc {
 
 /// PLL clock input source
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum PLLSrc {
     HSI,
     HSE(Hertz),
@@ -52,6 +59,7 @@ pub enum PLLSrc {
 
 /// RTC clock input source
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RTCSrc {
     LSE = 0b01,
     LSI = 0b10,
@@ -64,8 +72,23 @@ pub type PLLDiv = u8;
 /// PLL multiplier
 pub type PLLMul = u8;
 
+/// PLL configuration outside of the datasheet range
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PllError {
+    /// PLLM, PLLN, PLLR, PLLP or PLLQ divider out of range
+    Divider,
+    /// PLL input divided by PLLM outside of 2.66-16MHz
+    InputFrequency,
+    /// VCO outside of 64-344MHz
+    VcoFrequency,
+    /// R output above 64MHz, P above 122MHz or Q above 128MHz
+    OutputFrequency,
+}
+
 /// PLL config
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct PllConfig {
     pub mux: PLLSrc,
     pub m: PLLDiv,
@@ -99,9 +122,92 @@ impl PllConfig {
             p: None,
         }
     }
+
+    pub fn with_hse(freq: Hertz, m: PLLDiv, n: PLLMul, r: PLLDiv) -> PllConfig {
+        PllConfig {
+            mux: PLLSrc::HSE(freq),
+            ..PllConfig::with_hsi(m, n, r)
+        }
+    }
+
+    pub fn with_hse_bypass(freq: Hertz, m: PLLDiv, n: PLLMul, r: PLLDiv) -> PllConfig {
+        PllConfig {
+            mux: PLLSrc::HSE_BYPASS(freq),
+            ..PllConfig::with_hsi(m, n, r)
+        }
+    }
+
+    /// Enables the P output divided by `div`, 2 to 32
+    pub fn p(mut self, div: PLLDiv) -> Self {
+        self.p = Some(div);
+        self
+    }
+
+    /// Enables the Q output divided by `div`, 2 to 8
+    pub fn q(mut self, div: PLLDiv) -> Self {
+        self.q = Some(div);
+        self
+    }
+
+    /// Input frequency
+    pub fn input_freq(&self) -> Hertz {
+        match self.mux {
+            PLLSrc::HSI => Hertz(HSI_FREQ),
+            PLLSrc::HSE(freq) | PLLSrc::HSE_BYPASS(freq) => freq,
+        }
+    }
+
+    /// VCO frequency
+    pub fn vco_freq(&self) -> Hertz {
+        Hertz(self.input_freq().0 / self.m as u32 * self.n as u32)
+    }
+
+    /// Output frequencies
+    ///
+    /// Fails if a divider or frequency is out of the datasheet range.
+    pub fn clocks(&self) -> Result<PLLClocks, PllError> {
+        if !(1..=8).contains(&self.m) || !(8..=86).contains(&self.n) {
+            return Err(PllError::Divider);
+        }
+        let input = self.input_freq().0 / self.m as u32;
+        if !(2_660_000..=16_000_000).contains(&input) {
+            return Err(PllError::InputFrequency);
+        }
+        let vco = self.vco_freq().0;
+        if !(64_000_000..=344_000_000).contains(&vco) {
+            return Err(PllError::VcoFrequency);
+        }
+
+        let r = output(vco, self.r, 2..=8, 64_000_000)?;
+        let p = self.p.map(|div| output(vco, div, 2..=32, 122_000_000));
+        let q = self.q.map(|div| output(vco, div, 2..=8, 128_000_000));
+        Ok(PLLClocks {
+            r,
+            q: q.transpose()?,
+            p: p.transpose()?,
+        })
+    }
+}
+
+/// PLL output frequency with divider `div`
+fn output(
+    vco: u32,
+    div: PLLDiv,
+    range: RangeInclusive<PLLDiv>,
+    max: u32,
+) -> Result<Hertz, PllError> {
+    if !range.contains(&div) {
+        return Err(PllError::Divider);
+    }
+    let freq = vco / div as u32;
+    if freq > max {
+        return Err(PllError::OutputFrequency);
+    }
+    Ok(Hertz(freq))
 }
 
 /// Clocks configutation
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Config {
     pub(crate) sys_mux: SysClockSrc,
     pub(crate) pll_cfg: PllConfig,
@@ -157,3 +263,83 @@ impl Default for Config {
         }
     }
 }
+
+/// Peripheral kernel clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KernelClock {
+    /// APB clock, APB timer clock for timers
+    Pclk,
+    SysClk,
+    Hsi16,
+    Lse,
+    Lsi,
+    PllP,
+    PllQ,
+}
+
+/// Peripheral with a kernel clock mux in CCIPR
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KernelClockUser {
+    Usart1,
+    Usart2,
+    Lpuart1,
+    I2c1,
+    I2s1,
+    Lptim1,
+    Lptim2,
+    Tim1,
+    Tim15,
+    /// HSI16 means HSI16 / 488
+    Cec,
+    Adc,
+}
+
+/// Clock source not available for the peripheral, or PLL output disabled
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClockSelectError;
+
+impl KernelClockUser {
+    /// CCIPR field offset and width
+    pub(crate) fn field(self) -> (u32, u32) {
+        match self {
+            KernelClockUser::Usart1 => (0, 2),
+            KernelClockUser::Usart2 => (2, 2),
+            KernelClockUser::Cec => (6, 1),
+            KernelClockUser::Lpuart1 => (10, 2),
+            KernelClockUser::I2c1 => (12, 2),
+            KernelClockUser::I2s1 => (14, 2),
+            KernelClockUser::Lptim1 => (18, 2),
+            KernelClockUser::Lptim2 => (20, 2),
+            KernelClockUser::Tim1 => (22, 1),
+            KernelClockUser::Tim15 => (24, 1),
+            KernelClockUser::Adc => (30, 2),
+        }
+    }
+
+    /// Field value selecting `clock`
+    pub(crate) fn select(self, clock: KernelClock) -> Option<u32> {
+        use KernelClock::*;
+        use KernelClockUser::*;
+        match (self, clock) {
+            (Usart1, Pclk) | (Usart2, Pclk) | (Lpuart1, Pclk) | (I2c1, Pclk) => Some(0b00),
+            (Usart1, SysClk) | (Usart2, SysClk) | (Lpuart1, SysClk) | (I2c1, SysClk) => {
+                Some(0b01)
+            }
+            (Usart1, Hsi16) | (Usart2, Hsi16) | (Lpuart1, Hsi16) | (I2c1, Hsi16) => Some(0b10),
+            (Usart1, Lse) | (Usart2, Lse) | (Lpuart1, Lse) => Some(0b11),
+            (I2s1, SysClk) | (Adc, SysClk) => Some(0b00),
+            (I2s1, PllP) | (Adc, PllP) => Some(0b01),
+            (I2s1, Hsi16) | (Adc, Hsi16) => Some(0b10),
+            (Lptim1, Pclk) | (Lptim2, Pclk) => Some(0b00),
+            (Lptim1, Lsi) | (Lptim2, Lsi) => Some(0b01),
+            (Lptim1, Hsi16) | (Lptim2, Hsi16) => Some(0b10),
+            (Lptim1, Lse) | (Lptim2, Lse) => Some(0b11),
+            (Tim1, Pclk) | (Tim15, Pclk) | (Cec, Hsi16) => Some(0),
+            (Tim1, PllQ) | (Tim15, PllQ) | (Cec, Lse) => Some(1),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Peripheral clock gating and reset
+//!
+//! Drivers, including ones outside this crate, enable and reset their
+//! peripheral through these traits instead of poking `RCC` bits:
+//!
+//! ```ignore
+//! USART1::enable(&mut rcc);
+//! USART1::reset(&mut rcc);
+//! ```
+use super::Rcc;
+use crate::stm32::*;
+
+/// Peripheral clock gating
+pub trait Enable {
+    /// Enables the peripheral clock
+    fn enable(rcc: &mut Rcc);
+
+    /// Disables the peripheral clock
+    fn disable(rcc: &mut Rcc);
+
+    /// Returns `true` if the peripheral clock is enabled
+    fn is_enabled(rcc: &Rcc) -> bool;
+}
+
+/// Peripheral reset
+pub trait Reset {
+    /// Resets the peripheral registers to their reset values
+    fn reset(rcc: &mut Rcc);
+}
+
+macro_rules! bus {
+    ($($PER:ident: ($enr:ident, $en:ident, $rstr:ident, $rst:ident),)+) => {
+        $(
+            impl Enable for $PER {
+                fn enable(rcc: &mut Rcc) {
+                    rcc.rb.$enr.modify(|_, w| w.$en().set_bit());
+                }
+
+                fn disable(rcc: &mut Rcc) {
+                    rcc.rb.$enr.modify(|_, w| w.$en().clear_bit());
+                }
+
+                fn is_enabled(rcc: &Rcc) -> bool {
+                    rcc.rb.$enr.read().$en().bit_is_set()
+                }
+            }
+
+            impl Reset for $PER {
+                fn reset(rcc: &mut Rcc) {
+                    rcc.rb.$rstr.modify(|_, w| w.$rst().set_bit());
+                    rcc.rb.$rstr.modify(|_, w| w.$rst().clear_bit());
+                }
+            }
+        )+
+    }
+}
+
+bus! {
+    GPIOA: (iopenr, iopaen, ioprstr, ioparst),
+    GPIOB: (iopenr, iopben, ioprstr, iopbrst),
+    GPIOC: (iopenr, iopcen, ioprstr, iopcrst),
+    GPIOD: (iopenr, iopden, ioprstr, iopdrst),
+    GPIOF: (iopenr, iopfen, ioprstr, iopfrst),
+    DMA: (ahbenr, dmaen, ahbrstr, dmarst),
+    FLASH: (ahbenr, flashen, ahbrstr, flashrst),
+    CRC: (ahbenr, crcen, ahbrstr, crcrst),
+    RNG: (ahbenr, rngen, ahbrstr, rngrst),
+    TIM2: (apbenr1, tim2en, apbrstr1, tim2rst),
+    TIM3: (apbenr1, tim3en, apbrstr1, tim3rst),
+    SPI2: (apbenr1, spi2en, apbrstr1, spi2rst),
+    USART2: (apbenr1, usart2en, apbrstr1, usart2rst),
+    LPUART: (apbenr1, lpuart1en, apbrstr1, lpuart1rst),
+    I2C1: (apbenr1, i2c1en, apbrstr1, i2c1rst),
+    I2C2: (apbenr1, i2c2en, apbrstr1, i2c2rst),
+    DBG: (apbenr1, dbgen, apbrstr1, dbgrst),
+    PWR: (apbenr1, pwren, apbrstr1, pwrrst),
+    TIM1: (apbenr2, tim1en, apbrstr2, tim1rst),
+    SPI1: (apbenr2, spi1en, apbrstr2, spi1rst),
+    USART1: (apbenr2, usart1en, apbrstr2, usart1rst),
+    TIM14: (apbenr2, tim14en, apbrstr2, tim14rst),
+    TIM16: (apbenr2, tim16en, apbrstr2, tim16rst),
+    TIM17: (apbenr2, tim17en, apbrstr2, tim17rst),
+    ADC: (apbenr2, adcen, apbrstr2, adcrst),
+}
+
+#[cfg(any(
+    feature = "stm32g031",
+    feature = "stm32g041",
+    feature = "stm32g07x",
+    feature = "stm32g081"
+))]
+bus! {
+    LPTIM1: (apbenr1, lptim1en, apbrstr1, lptim1rst),
+    LPTIM2: (apbenr1, lptim2en, apbrstr1, lptim2rst),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+bus! {
+    HDMI_CEC: (apbenr1, cecen, apbrstr1, cecrst),
+    SYSCFG_VREFBUF: (apbenr2, syscfgen, apbrstr2, syscfgrst),
+    TIM6: (apbenr1, tim6en, apbrstr1, tim6rst),
+    TIM7: (apbenr1, tim7en, apbrstr1, tim7rst),
+    TIM15: (apbenr2, tim15en, apbrstr2, tim15rst),
+    USART3: (apbenr1, usart3en, apbrstr1, usart3rst),
+    USART4: (apbenr1, usart4en, apbrstr1, usart4rst),
+    UCPD1: (apbenr1, ucpd1en, apbrstr1, ucpd1rst),
+    UCPD2: (apbenr1, ucpd2en, apbrstr1, ucpd2rst),
+    DAC: (apbenr1, dac1en, apbrstr1, dac1rst),
+}
+
+#[cfg(any(
+    feature = "stm32g030",
+    feature = "stm32g031",
+    feature = "stm32g041"
+))]
+bus! {
+    SYSCFG: (apbenr2, syscfgen, apbrstr2, syscfgrst),
+}
+
+#[cfg(any(feature = "stm32g041", feature = "stm32g081"))]
+bus! {
+    AES: (ahbenr, aesen, ahbrstr, aesrst),
+}
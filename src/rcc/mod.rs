@@ -3,15 +3,68 @@ use crate::time::{Hertz, U32Ext};
 
 mod clockout;
 mod config;
+mod enable;
+mod trim;
 
 pub use clockout::*;
 pub use config::*;
+pub use enable::{Enable, Reset};
+pub(crate) use trim::{measure_ti1, Ti1Source, CAPTURE_PRESCALER};
+#[cfg(feature = "factory-test")]
+pub(crate) use trim::LSE_FREQ;
+pub use trim::{CalibrationError, HsiCalibration};
 
 /// HSI speed
 pub const HSI_FREQ: u32 = 16_000_000;
+/// LSI speed
+pub const LSI_FREQ: u32 = 32_000;
+
+// CR
+const HSIKERON: u32 = 1 << 9;
+const CSSON: u32 = 1 << 19;
+
+// BDCR
+const LSEDRV_SHIFT: u32 = 3;
+const LSECSSON: u32 = 1 << 5;
+const LSECSSD: u32 = 1 << 6;
+
+// CIFR, CICR
+const CSSF: u32 = 1 << 8;
+const LSECSSF: u32 = 1 << 9;
+
+// FLASH SR
+const FLASH_BSY1: u32 = 1 << 16;
+
+static mut CSS_HOOK: Option<fn(ClockFailure)> = None;
+
+/// LSE oscillator drive capability
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LseDrive {
+    Low = 0b00,
+    MediumLow = 0b01,
+    MediumHigh = 0b10,
+    High = 0b11,
+}
+
+/// Oscillator detected as failed by the clock security system
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockFailure {
+    /// HSE failed, the system clock switched to HSI
+    Hse,
+    /// LSE failed, RTC and LPTIM stopped
+    Lse,
+}
+
+// PLLSYSCFGR
+const PLLPEN: u32 = 1 << 16;
+const PLLQEN: u32 = 1 << 24;
+const PLLREN: u32 = 1 << 28;
 
 /// Clock frequencies
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Clocks {
     /// System frequency
     pub sys_clk: Hertz,
@@ -29,6 +82,7 @@ pub struct Clocks {
 
 /// PLL Clock frequencies
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct PLLClocks {
     /// R frequency
     pub r: Hertz,
@@ -56,6 +110,11 @@ impl Default for Clocks {
 }
 
 /// Reset pin mode and remap
+///
+/// On the G031 and G041 the NRST pin is shared with PF2, which only works as
+/// GPIO in `ResetMode::GPIO`. 8 pin packages need this to get a sixth I/O.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ResetMode {
     /// Reset Input only: a low level on the NRST pin generates system reset, internal RESET not propagated to the NSRT pin
     ResetInput = 0b01,
@@ -74,8 +133,33 @@ pub struct Rcc {
 
 impl Rcc {
     /// Apply clock configuration
+    ///
+    /// Panics on an invalid PLL configuration, see `try_freeze`.
     pub fn freeze(self, rcc_cfg: Config) -> Self {
-        let pll_clk = self.config_pll(rcc_cfg.pll_cfg);
+        self.try_freeze(rcc_cfg).expect("invalid PLL configuration")
+    }
+
+    /// Apply clock configuration, fails on an invalid PLL configuration
+    pub fn try_freeze(mut self, rcc_cfg: Config) -> Result<Self, PllError> {
+        self.set_clocks(rcc_cfg)?;
+        Ok(self)
+    }
+
+    /// Switches the clock configuration at runtime
+    ///
+    /// Peripherals keep the timing derived from the old `clocks`, e.g. baud
+    /// rates, and must be reconfigured. The clocks are left untouched if the
+    /// PLL configuration is invalid.
+    pub fn set_clocks(&mut self, rcc_cfg: Config) -> Result<(), PllError> {
+        let pll_clk = rcc_cfg.pll_cfg.clocks()?;
+
+        // Run from HSI while the PLL is reprogrammed
+        if self.rb.cfgr.read().sws().bits() == 0b010 {
+            self.enable_hsi();
+            self.rb.cfgr.modify(|_, w| unsafe { w.sw().bits(0b000) });
+            while self.rb.cfgr.read().sws().bits() != 0b000 {}
+        }
+        self.config_pll(rcc_cfg.pll_cfg);
 
         let (sys_clk, sw_bits) = match rcc_cfg.sys_mux {
             SysClockSrc::HSE(freq) => {
@@ -97,7 +181,7 @@ impl Rcc {
             }
             SysClockSrc::LSI => {
                 self.enable_lsi();
-                (32_768.hz(), 0b011)
+                (LSI_FREQ.hz(), 0b011)
             }
             SysClockSrc::HSI(prs) => {
                 self.enable_hsi();
@@ -111,12 +195,13 @@ impl Rcc {
                     Prescaler::Div128 => (HSI_FREQ / 128, 0b111),
                     _ => (HSI_FREQ, 0b000),
                 };
-                self.rb.cr.write(|w| unsafe { w.hsidiv().bits(div_bits) });
+                self.rb.cr.modify(|_, w| unsafe { w.hsidiv().bits(div_bits) });
                 (freq.hz(), 0b000)
             }
         };
 
         let sys_freq = sys_clk.0;
+        // HPRE has no divide by 32
         let (ahb_freq, ahb_psc_bits) = match rcc_cfg.ahb_psc {
             Prescaler::Div2 => (sys_freq / 2, 0b1000),
             Prescaler::Div4 => (sys_freq / 4, 0b1001),
@@ -129,25 +214,27 @@ impl Rcc {
             _ => (sys_clk.0, 0b0000),
         };
         let (apb_freq, apb_tim_freq, apb_psc_bits) = match rcc_cfg.apb_psc {
-            Prescaler::Div2 => (sys_freq / 2, sys_freq, 0b100),
-            Prescaler::Div4 => (sys_freq / 4, sys_freq / 2, 0b101),
-            Prescaler::Div8 => (sys_freq / 8, sys_freq / 4, 0b110),
-            Prescaler::Div16 => (sys_freq / 16, sys_freq / 8, 0b111),
-            _ => (sys_clk.0, sys_clk.0, 0b000),
+            Prescaler::Div2 => (ahb_freq / 2, ahb_freq, 0b100),
+            Prescaler::Div4 => (ahb_freq / 4, ahb_freq / 2, 0b101),
+            Prescaler::Div8 => (ahb_freq / 8, ahb_freq / 4, 0b110),
+            Prescaler::Div16 => (ahb_freq / 16, ahb_freq / 8, 0b111),
+            _ => (ahb_freq, ahb_freq, 0b000),
         };
-
-        unsafe {
-            // Adjust flash wait states
-            let flash = &(*FLASH::ptr());
-            flash.acr.modify(|_, w| {
-                w.latency().bits(if sys_clk.0 <= 24_000_000 {
-                    0b000
-                } else if sys_clk.0 <= 48_000_000 {
-                    0b001
-                } else {
-                    0b010
-                })
-            })
+        assert!(ahb_freq <= 64_000_000, "HCLK above 64MHz");
+
+        // Extra flash wait states are harmless, missing ones are not: raise
+        // them before the clock goes up and lower them after it went down
+        let latency = if ahb_freq <= 24_000_000 {
+            0b000
+        } else if ahb_freq <= 48_000_000 {
+            0b001
+        } else {
+            0b010
+        };
+        let flash = unsafe { &(*FLASH::ptr()) };
+        let current = flash.acr.read().latency().bits();
+        if latency > current {
+            set_flash_latency(flash, latency);
         }
 
         self.rb.cfgr.modify(|_, w| unsafe {
@@ -160,21 +247,112 @@ impl Rcc {
         });
 
         while self.rb.cfgr.read().sws().bits() != sw_bits {}
+        if latency < current {
+            set_flash_latency(flash, latency);
+        }
 
-        Rcc {
-            rb: self.rb,
-            clocks: Clocks {
-                pll_clk,
-                sys_clk,
-                core_clk: (ahb_freq / 8).hz(),
-                ahb_clk: ahb_freq.hz(),
-                apb_clk: apb_freq.hz(),
-                apb_tim_clk: apb_tim_freq.hz(),
+        self.clocks = Clocks {
+            pll_clk,
+            sys_clk,
+            core_clk: (ahb_freq / 8).hz(),
+            ahb_clk: ahb_freq.hz(),
+            apb_clk: apb_freq.hz(),
+            apb_tim_clk: apb_tim_freq.hz(),
+        };
+        Ok(())
+    }
+
+    /// Selects the kernel clock of `periph` and returns its frequency
+    ///
+    /// Drivers created afterwards pick up the kernel clock, e.g. an HSI16
+    /// clocked USART keeps receiving in Stop mode.
+    pub fn set_kernel_clock(
+        &mut self,
+        periph: KernelClockUser,
+        clock: KernelClock,
+    ) -> Result<Hertz, ClockSelectError> {
+        let (shift, width) = periph.field();
+        let bits = periph.select(clock).ok_or(ClockSelectError)?;
+        match clock {
+            KernelClock::Hsi16 => self.enable_hsi(),
+            KernelClock::Lse if self.rb.bdcr.read().lserdy().bit_is_clear() => {
+                self.enable_lse(false)
+            }
+            KernelClock::Lsi => self.enable_lsi(),
+            _ => {}
+        }
+        let freq = self.kernel_clock_freq(periph, clock)?;
+        let mask = ((1 << width) - 1) << shift;
+        self.rb
+            .ccipr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !mask | bits << shift) });
+        Ok(freq)
+    }
+
+    /// Returns the frequency of the kernel clock currently selected for `periph`
+    pub fn kernel_clock(&self, periph: KernelClockUser) -> Hertz {
+        use KernelClock::*;
+        let (shift, width) = periph.field();
+        let bits = (self.rb.ccipr.read().bits() >> shift) & ((1 << width) - 1);
+        [Pclk, SysClk, Hsi16, Lse, Lsi, PllP, PllQ]
+            .iter()
+            .find(|&&clock| periph.select(clock) == Some(bits))
+            .and_then(|&clock| self.kernel_clock_freq(periph, clock).ok())
+            .unwrap_or(self.clocks.apb_clk)
+    }
+
+    fn kernel_clock_freq(
+        &self,
+        periph: KernelClockUser,
+        clock: KernelClock,
+    ) -> Result<Hertz, ClockSelectError> {
+        Ok(match clock {
+            KernelClock::Pclk => match periph {
+                KernelClockUser::Tim1 | KernelClockUser::Tim15 => self.clocks.apb_tim_clk,
+                _ => self.clocks.apb_clk,
+            },
+            KernelClock::SysClk => self.clocks.sys_clk,
+            KernelClock::Hsi16 => match periph {
+                KernelClockUser::Cec => (HSI_FREQ / 488).hz(),
+                _ => HSI_FREQ.hz(),
             },
+            KernelClock::Lse => 32_768.hz(),
+            KernelClock::Lsi => LSI_FREQ.hz(),
+            KernelClock::PllP => self.clocks.pll_clk.p.ok_or(ClockSelectError)?,
+            KernelClock::PllQ => self.clocks.pll_clk.q.ok_or(ClockSelectError)?,
+        })
+    }
+
+    /// Keeps HSI16 running in Stop mode for peripheral kernel clocks
+    pub fn set_hsi_kernel_on(&mut self, on: bool) {
+        self.rb.cr.modify(|r, w| unsafe {
+            w.bits(if on {
+                r.bits() | HSIKERON
+            } else {
+                r.bits() & !HSIKERON
+            })
+        });
+    }
+
+    /// Reset pin mode programmed in the option bytes
+    pub fn reset_mode(&self) -> ResetMode {
+        let flash = unsafe { &(*FLASH::ptr()) };
+        match flash.optr.read().nrst_mode().bits() {
+            0b01 => ResetMode::ResetInput,
+            0b10 => ResetMode::GPIO,
+            _ => ResetMode::Bidirectional,
         }
     }
 
+    /// Programs the reset pin mode into the option bytes
+    ///
+    /// Does nothing if `mode` is already programmed. Otherwise the option
+    /// bytes are reloaded after programming, which resets the device, so this
+    /// is safe to call early on every start-up.
     pub fn set_reset_mode(&mut self, mode: ResetMode) {
+        if self.reset_mode() == mode {
+            return;
+        }
         unsafe {
             let flash = &(*FLASH::ptr());
 
@@ -187,99 +365,142 @@ impl Rcc {
             flash.optkeyr.write(|w| w.optkeyr().bits(0x4c5d_6e7f));
             flash.cr.modify(|_, w| w.optlock().clear_bit());
 
+            while flash.sr.read().bits() & FLASH_BSY1 != 0 {}
             flash.optr.modify(|_, w| w.nrst_mode().bits(mode as u8));
             flash.cr.modify(|_, w| w.optstrt().set_bit());
+            while flash.sr.read().bits() & FLASH_BSY1 != 0 {}
+            // Reloads the option bytes and resets the device
+            flash.cr.modify(|_, w| w.obl_launch().set_bit());
         }
     }
 
-    fn config_pll(&self, pll_cfg: PllConfig) -> PLLClocks {
-        assert!(pll_cfg.m > 0 && pll_cfg.m <= 8);
-        assert!(pll_cfg.r > 1 && pll_cfg.r <= 8);
-
+    fn config_pll(&self, pll_cfg: PllConfig) {
         // Disable PLL
-        self.rb.cr.write(|w| w.pllon().clear_bit());
+        self.rb.cr.modify(|_, w| w.pllon().clear_bit());
         while self.rb.cr.read().pllrdy().bit_is_set() {}
 
-        let (freq, pll_sw_bits) = match pll_cfg.mux {
+        let pll_sw_bits = match pll_cfg.mux {
             PLLSrc::HSI => {
                 self.enable_hsi();
-                (HSI_FREQ, 0b10)
+                0b10
             }
-            PLLSrc::HSE(freq) => {
+            PLLSrc::HSE(_) => {
                 self.enable_hse(false);
-                (freq.0, 0b11)
+                0b11
             }
-            PLLSrc::HSE_BYPASS(freq) => {
+            PLLSrc::HSE_BYPASS(_) => {
                 self.enable_hse(true);
-                (freq.0, 0b11)
-            }
-        };
-
-        let pll_freq = freq / (pll_cfg.m as u32) * (pll_cfg.n as u32);
-        let r = (pll_freq / (pll_cfg.r as u32)).hz();
-        let q = match pll_cfg.q {
-            Some(div) if div > 1 && div <= 8 => {
-                self.rb
-                    .pllsyscfgr
-                    .write(move |w| unsafe { w.pllq().bits(div - 1) });
-                let req = freq / div as u32;
-                Some(req.hz())
+                0b11
             }
-            _ => None,
         };
+        let mut bits = pll_sw_bits
+            | ((pll_cfg.m as u32 - 1) << 4)
+            | ((pll_cfg.n as u32) << 8)
+            | ((pll_cfg.r as u32 - 1) << 29)
+            | PLLREN;
+        if let Some(p) = pll_cfg.p {
+            bits |= ((p as u32 - 1) << 17) | PLLPEN;
+        }
+        if let Some(q) = pll_cfg.q {
+            bits |= ((q as u32 - 1) << 25) | PLLQEN;
+        }
+        self.rb.pllsyscfgr.write(|w| unsafe { w.bits(bits) });
 
-        let p = match pll_cfg.p {
-            Some(div) if div > 1 && div <= 8 => {
-                self.rb
-                    .pllsyscfgr
-                    .write(move |w| unsafe { w.pllp().bits(div - 1) });
-                let req = freq / div as u32;
-                Some(req.hz())
-            }
-            _ => None,
-        };
+        // Enable PLL
+        self.rb.cr.modify(|_, w| w.pllon().set_bit());
+        while self.rb.cr.read().pllrdy().bit_is_clear() {}
+    }
 
-        self.rb.pllsyscfgr.write(move |w| unsafe {
-            w.pllsrc()
-                .bits(pll_sw_bits)
-                .pllm()
-                .bits(pll_cfg.m - 1)
-                .plln()
-                .bits(pll_cfg.n)
-                .pllr()
-                .bits(pll_cfg.r - 1)
-                .pllren()
-                .set_bit()
+    /// Starts the LSE oscillator
+    ///
+    /// `drive` trades start-up reliability for current, higher drive for
+    /// crystals with high ESR. With `bypass` an external clock is applied
+    /// to OSC32_IN and `drive` is ignored.
+    pub fn start_lse(&mut self, drive: LseDrive, bypass: bool) {
+        self.unlock_rtc();
+        if self.rb.bdcr.read().lserdy().bit_is_set() {
+            return;
+        }
+        // LSEDRV is written before the oscillator is enabled
+        self.rb.bdcr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b11 << LSEDRV_SHIFT) | (drive as u32) << LSEDRV_SHIFT)
         });
+        self.enable_lse(bypass);
+    }
 
-        // Enable PLL
-        self.rb.cr.write(|w| w.pllon().set_bit());
-        while self.rb.cr.read().pllrdy().bit_is_clear() {}
+    /// Starts the LSI oscillator
+    pub fn start_lsi(&mut self) {
+        self.enable_lsi();
+    }
+
+    /// Stops the LSI oscillator, unless the IWDG is running
+    pub fn stop_lsi(&mut self) {
+        self.rb.csr.modify(|_, w| w.lsion().clear_bit());
+    }
+
+    /// Selects the RTC clock and enables the RTC domain
+    ///
+    /// Resets the backup domain only if the source changes.
+    pub fn set_rtc_clock(&mut self, src: RTCSrc) {
+        self.enable_rtc(src);
+    }
+
+    /// Enables the clock security system on HSE
+    ///
+    /// On an HSE failure the system clock switches to HSI and an NMI is
+    /// raised, call `handle_clock_failure()` from the NMI handler. `clocks`
+    /// are stale afterwards.
+    pub fn enable_css(&mut self) {
+        self.rb
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | CSSON) });
+    }
+
+    /// Enables the clock security system on LSE
+    ///
+    /// Needs a running LSE selected as RTC clock. A failure is signaled on
+    /// EXTI line 31, `ExtiExt::wakeup(Event::LSE_CSS)` routes it to the RCC
+    /// interrupt.
+    pub fn enable_lse_css(&mut self) {
+        assert!(self.rb.bdcr.read().lserdy().bit_is_set(), "LSE not running");
+        self.unlock_rtc();
+        self.rb
+            .bdcr
+            .modify(|r, w| unsafe { w.bits(r.bits() | LSECSSON) });
+    }
+
+    /// Returns `true` if the LSE clock security system detected a failure
+    pub fn is_lse_failed(&self) -> bool {
+        self.rb.bdcr.read().bits() & LSECSSD != 0
+    }
 
-        PLLClocks { r, q, p }
+    /// Sets the function called by `handle_clock_failure()`
+    pub fn on_clock_failure(&mut self, hook: fn(ClockFailure)) {
+        cortex_m::interrupt::free(|_| unsafe { CSS_HOOK = Some(hook) });
     }
 
     pub(crate) fn enable_hsi(&self) {
-        self.rb.cr.write(|w| w.hsion().set_bit());
+        self.rb.cr.modify(|_, w| w.hsion().set_bit());
         while self.rb.cr.read().hsirdy().bit_is_clear() {}
     }
 
     pub(crate) fn enable_hse(&self, bypass: bool) {
         self.rb
             .cr
-            .write(|w| w.hseon().set_bit().hsebyp().bit(bypass));
+            .modify(|_, w| w.hseon().set_bit().hsebyp().bit(bypass));
         while self.rb.cr.read().hserdy().bit_is_clear() {}
     }
 
     pub(crate) fn enable_lse(&self, bypass: bool) {
+        self.unlock_rtc();
         self.rb
             .bdcr
-            .write(|w| w.lseon().set_bit().lsebyp().bit(bypass));
+            .modify(|_, w| w.lseon().set_bit().lsebyp().bit(bypass));
         while self.rb.bdcr.read().lserdy().bit_is_clear() {}
     }
 
     pub(crate) fn enable_lsi(&self) {
-        self.rb.csr.write(|w| w.lsion().set_bit());
+        self.rb.csr.modify(|_, w| w.lsion().set_bit());
         while self.rb.csr.read().lsirdy().bit_is_clear() {}
     }
 
@@ -301,6 +522,11 @@ impl Rcc {
             .modify(|_, w| w.rtcapben().set_bit().pwren().set_bit());
         self.rb.apbsmenr1.modify(|_, w| w.rtcapbsmen().set_bit());
         self.unlock_rtc();
+        // Keep the calendar and backup registers across resets
+        let bdcr = self.rb.bdcr.read();
+        if bdcr.rtcen().bit_is_set() && bdcr.rtcsel().bits() == src as u8 {
+            return;
+        }
         self.rb.bdcr.modify(|_, w| w.bdrst().set_bit());
         self.rb.bdcr.modify(|_, w| unsafe {
             w.rtcsel()
@@ -313,6 +539,32 @@ impl Rcc {
     }
 }
 
+/// Clears clock security system failures and reports them to the hook
+///
+/// Call from the NMI handler for HSE failures and from the RCC interrupt
+/// for LSE failures.
+pub fn handle_clock_failure() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    let flags = rcc.cifr.read().bits() & (CSSF | LSECSSF);
+    rcc.cicr.write(|w| unsafe { w.bits(flags) });
+    let hook = unsafe { CSS_HOOK };
+    if let Some(hook) = hook {
+        if flags & CSSF != 0 {
+            hook(ClockFailure::Hse);
+        }
+        if flags & LSECSSF != 0 {
+            hook(ClockFailure::Lse);
+        }
+    }
+}
+
+fn set_flash_latency(flash: &crate::stm32::flash::RegisterBlock, latency: u8) {
+    flash
+        .acr
+        .modify(|_, w| unsafe { w.latency().bits(latency) });
+    while flash.acr.read().latency().bits() != latency {}
+}
+
 /// Extension trait that constrains the `RCC` peripheral
 pub trait RccExt {
     /// Constrains the `RCC` peripheral so it plays nicely with the other abstractions
@@ -0,0 +1,137 @@
+//! HSI trimming against LSE
+use crate::rcc::Rcc;
+use crate::stm32::TIM16;
+
+/// LSE frequency assumed by `calibrate_hsi()`
+pub(crate) const LSE_FREQ: u64 = 32_768;
+/// Input captures per HSI measurement
+const LSE_CAPTURES: u64 = 16;
+/// Reference clock periods per input capture
+pub(crate) const CAPTURE_PRESCALER: u64 = 8;
+
+/// TIM16 TI1 input selection
+#[derive(Clone, Copy)]
+pub(crate) enum Ti1Source {
+    Lsi = 0b0001,
+    Lse = 0b0010,
+}
+
+/// Result of `Rcc::calibrate_hsi()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HsiCalibration {
+    /// Selected HSITRIM value
+    pub trim: u8,
+    /// Remaining HSI error in ppm
+    pub error_ppm: i32,
+}
+
+/// HSI calibration errors
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CalibrationError {
+    /// LSE is not running, see `Rcc::start_lse()`
+    LseNotReady,
+}
+
+impl Rcc {
+    /// Factory HSI calibration value
+    pub fn hsi_calibration(&self) -> u8 {
+        self.rb.icscr.read().bits() as u8
+    }
+
+    /// HSI trimming, 0 to 127, 64 by default
+    pub fn hsi_trim(&self) -> u8 {
+        ((self.rb.icscr.read().bits() >> 8) & 0x7f) as u8
+    }
+
+    /// Sets HSI trimming, one step is about 0.3%
+    pub fn set_hsi_trim(&mut self, trim: u8) {
+        let trim = trim.min(0x7f) as u32;
+        self.rb
+            .icscr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0x7f << 8) | trim << 8) });
+    }
+
+    /// Trims HSI against the LSE crystal
+    ///
+    /// LSE is captured by TIM16 channel 1, the timer clock must be derived
+    /// from HSI. Takes a few ms, call periodically to follow temperature.
+    pub fn calibrate_hsi(
+        &mut self,
+        tim: &mut TIM16,
+    ) -> Result<HsiCalibration, CalibrationError> {
+        if self.rb.bdcr.read().lserdy().bit_is_clear() {
+            return Err(CalibrationError::LseNotReady);
+        }
+        self.rb.apbenr2.modify(|_, w| w.tim16en().set_bit());
+        let periods = CAPTURE_PRESCALER * LSE_CAPTURES;
+        let expected = self.clocks.apb_tim_clk.0 as u64 * periods / LSE_FREQ;
+        let mut error = |rcc: &mut Rcc, trim: u8| {
+            rcc.set_hsi_trim(trim);
+            let ticks = measure_ti1(tim, Ti1Source::Lse, LSE_CAPTURES);
+            (ticks as i64 - expected as i64) * 1_000_000 / expected.max(1) as i64
+        };
+
+        // Step towards the target until the error changes sign
+        let mut trim = self.hsi_trim();
+        let mut best = (trim, error(self, trim));
+        let step_down = best.1 > 0;
+        loop {
+            let next = match (step_down, trim) {
+                (true, 0) | (false, 0x7f) => break,
+                (true, _) => trim - 1,
+                (false, _) => trim + 1,
+            };
+            let ppm = error(self, next);
+            if ppm.abs() < best.1.abs() {
+                best = (next, ppm);
+            }
+            if (ppm > 0) != step_down {
+                break;
+            }
+            trim = next;
+        }
+
+        self.set_hsi_trim(best.0);
+        Ok(HsiCalibration {
+            trim: best.0,
+            error_ppm: best.1 as i32,
+        })
+    }
+}
+
+/// Measures `captures` times `CAPTURE_PRESCALER` periods of `source` in
+/// timer clock cycles
+pub(crate) fn measure_ti1(tim: &mut TIM16, source: Ti1Source, captures: u64) -> u64 {
+    tim.cr1.modify(|_, w| w.cen().clear_bit());
+    let tisel = unsafe { (TIM16::ptr() as *mut u8).add(0x68) as *mut u32 };
+    unsafe { core::ptr::write_volatile(tisel, source as u32) };
+    tim.ccer.modify(|_, w| w.cc1e().clear_bit());
+    tim.ccmr1_input()
+        .write(|w| unsafe { w.cc1s().bits(0b01).ic1psc().bits(0b11).ic1f().bits(0) });
+    tim.psc.write(|w| unsafe { w.psc().bits(0) });
+    tim.arr.write(|w| unsafe { w.bits(0xffff) });
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim.sr.write(|w| unsafe { w.bits(0) });
+    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+    let capture = || {
+        while tim.sr.read().cc1if().bit_is_clear() {}
+        tim.ccr1.read().bits() as u16
+    };
+    // 8 periods of a 32kHz clock take at most 0xffff timer cycles up to 64MHz
+    let mut last = capture();
+    let mut ticks = 0u64;
+    for _ in 0..captures {
+        let now = capture();
+        ticks += now.wrapping_sub(last) as u64;
+        last = now;
+    }
+
+    tim.cr1.modify(|_, w| w.cen().clear_bit());
+    tim.ccer.modify(|_, w| w.cc1e().clear_bit());
+    unsafe { core::ptr::write_volatile(tisel, 0) };
+    ticks
+}
@@ -1,3 +1,9 @@
+//! # True random number generator
+//!
+//! Available on parts with the RNG peripheral (STM32G041, STM32G081). The
+//! analog noise source needs a 48MHz or slower kernel clock, see `Config`.
+//! With the `rand_core` feature `Rng` implements `rand_core::RngCore` and
+//! `rand_core::CryptoRng`.
 use core::cmp;
 use core::mem;
 
@@ -6,6 +12,7 @@ use crate::rcc::Rcc;
 use crate::stm32::RNG;
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RngClkSource {
     HSI = 1,
     SysClock = 2,
@@ -13,6 +20,7 @@ pub enum RngClkSource {
 }
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RngClkDivider {
     NotDivided = 0,
     Div2 = 1,
@@ -50,12 +58,21 @@ impl Default for Config {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ErrorKind {
+    /// Kernel clock too slow compared to the AHB clock
     ClockError,
+    /// Noise source failure, see `Rng::recover()`
     SeedError,
 }
 
+// SR
+const CECS: u32 = 1 << 1;
+const SECS: u32 = 1 << 2;
+const CEIS: u32 = 1 << 5;
+const SEIS: u32 = 1 << 6;
+
 pub trait RngExt {
     fn constrain(self, cfg: Config, rcc: &mut Rcc) -> Rng;
 }
@@ -102,6 +119,44 @@ impl Rng {
         }
     }
 
+    /// Blocks for a random word, recovering from seed errors
+    ///
+    /// Fails only on a clock error or if the seed error persists.
+    pub fn read_u32(&mut self) -> Result<u32, ErrorKind> {
+        match self.gen() {
+            Err(ErrorKind::SeedError) => {
+                self.recover()?;
+                self.gen()
+            }
+            res => res,
+        }
+    }
+
+    /// Recovers from a seed or clock error
+    ///
+    /// Follows the reference manual: clears the error flags, discards 12
+    /// words of possibly bad entropy and restarts the generator if the
+    /// seed error is still present.
+    pub fn recover(&mut self) -> Result<(), ErrorKind> {
+        self.rb
+            .sr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(CEIS | SEIS)) });
+        if self.rb.sr.read().bits() & CECS != 0 {
+            return Err(ErrorKind::ClockError);
+        }
+        for _ in 0..12 {
+            self.rb.dr.read();
+        }
+        if self.rb.sr.read().bits() & SECS != 0 {
+            self.rb.cr.modify(|_, w| w.rngen().clear_bit());
+            self.rb.cr.modify(|_, w| w.rngen().set_bit());
+            if self.rb.sr.read().bits() & SECS != 0 {
+                return Err(ErrorKind::SeedError);
+            }
+        }
+        Ok(())
+    }
+
     pub fn release(self) -> RNG {
         self.rb
     }
@@ -151,6 +206,41 @@ impl rng::Read for Rng {
     }
 }
 
+#[cfg(feature = "rand_core")]
+impl From<ErrorKind> for rand_core::Error {
+    fn from(err: ErrorKind) -> Self {
+        let code = rand_core::Error::CUSTOM_START + err as u32;
+        core::num::NonZeroU32::new(code).unwrap().into()
+    }
+}
+
+#[cfg(feature = "rand_core")]
+impl rand_core::RngCore for Rng {
+    /// Panics if the generator fails
+    fn next_u32(&mut self) -> u32 {
+        self.read_u32().expect("RNG failure")
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_u32(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest).expect("RNG failure")
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        for chunk in dest.chunks_mut(4) {
+            let word = self.read_u32()?.to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rand_core")]
+impl rand_core::CryptoRng for Rng {}
+
 macro_rules! rng_core {
     ($($type:ty),+) => {
         $(
@@ -1,8 +1,89 @@
 //! Real Time Clock
-use crate::rcc::{RTCSrc, Rcc};
-use crate::stm32::RTC;
+//!
+//! The calendar runs in the backup domain, it keeps counting across resets
+//! and in Standby as long as the RTC clock source stays the same. Alarms and
+//! the periodic wakeup timer are routed to EXTI line 19 and can wake the MCU
+//! from Stop and Standby modes.
+use crate::rcc::{measure_ti1, RTCSrc, Rcc, Ti1Source, CAPTURE_PRESCALER};
+use crate::stm32::{RTC, TIM16};
 use crate::time::*;
 
+/// Input captures per LSI measurement
+const LSI_CAPTURES: u64 = 16;
+/// Asynchronous prescaler used with LSI, keeps the synchronous prescaler
+/// resolution within the smooth calibration range
+const LSI_PREDIV_A: u32 = 8;
+/// Smooth calibration cycle length in RTCCLK periods
+const CALIBRATION_CYCLE: i64 = 1 << 20;
+
+// CR
+const WUTE: u32 = 1 << 10;
+const WUCKSEL_MASK: u32 = 0b111;
+// ICSR
+const WUTWF: u32 = 1 << 2;
+/// PWR_CR3 EIWUL, internal wakeup line for Standby
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+const EIWUL: u32 = 1 << 15;
+
+/// RTC alarm
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Alarm {
+    A,
+    B,
+}
+
+/// Day an alarm matches on
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AlarmDay {
+    /// Every day
+    Daily,
+    /// Day of the month, 1-31
+    Date(u8),
+    /// Day of the week, 1 (Monday) - 7
+    WeekDay(u8),
+}
+
+/// RTC interrupt events
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    AlarmA,
+    AlarmB,
+    WakeupTimer,
+}
+
+impl Event {
+    /// SR / SCR flag
+    fn flag(self) -> u32 {
+        match self {
+            Event::AlarmA => 1 << 0,
+            Event::AlarmB => 1 << 1,
+            Event::WakeupTimer => 1 << 2,
+        }
+    }
+
+    /// CR interrupt enable
+    fn enable(self) -> u32 {
+        self.flag() << 12
+    }
+}
+
+/// Result of an LSI calibration
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LsiCalibration {
+    /// Measured LSI frequency in millihertz
+    pub lsi_mhz: u32,
+    /// Synchronous prescaler value
+    pub prediv_s: u16,
+    /// Smooth calibration in RTCCLK pulses per 2^20, negative values slow down the clock
+    pub smooth: i16,
+    /// `true` when the prescaler was reloaded, the calendar lost its sub-second count
+    pub prescaler_reloaded: bool,
+}
+
 pub struct Rtc {
     rb: RTC,
 }
@@ -11,9 +92,12 @@ impl Rtc {
     pub fn new(rtc: RTC, src: RTCSrc, rcc: &mut Rcc) -> Self {
         let mut rtc = Rtc { rb: rtc };
         rcc.enable_rtc(src);
-        rtc.modify(|rb| {
-            rb.cr.modify(|_, w| w.fmt().clear_bit());
-        });
+        // Init mode stops the calendar, skip it when it is already running
+        if rtc.rb.cr.read().fmt().bit_is_set() {
+            rtc.modify(|rb| {
+                rb.cr.modify(|_, w| w.fmt().clear_bit());
+            });
+        }
         rtc
     }
 
@@ -111,6 +195,216 @@ impl Rtc {
     pub fn get_week_day(&self) -> u8 {
         self.rb.dr.read().wdu().bits()
     }
+
+    /// Measures LSI against the timer clock and trims the RTC to 1Hz
+    ///
+    /// Intended for RTCs running from LSI without an LSE crystal. LSI is
+    /// captured by TIM16 channel 1, so the accuracy is bounded by the timer
+    /// clock source (HSE or the factory trimmed HSI). Call periodically, e.g.
+    /// every few minutes, to follow LSI drift with temperature and supply.
+    ///
+    /// The first call sets up the prescalers. Later calls only update the
+    /// smooth calibration and keep the calendar running, unless LSI drifted
+    /// out of the smooth calibration range of about ±480ppm.
+    pub fn calibrate_lsi(&mut self, tim: &mut TIM16, rcc: &mut Rcc) -> LsiCalibration {
+        rcc.rb.apbenr2.modify(|_, w| w.tim16en().set_bit());
+        let lsi_mhz = measure_lsi(tim, rcc.clocks.apb_tim_clk);
+
+        let prer = self.rb.prer.read().bits();
+        let mut prediv_s = (prer & 0x7fff) + 1;
+        let mut smooth = smooth_calibration(lsi_mhz, prediv_s);
+        let prescaler_reloaded = (prer >> 16) & 0x7f != LSI_PREDIV_A - 1 || smooth.is_none();
+        if prescaler_reloaded {
+            // Round down to run fast, then slow down with smooth calibration
+            prediv_s = (lsi_mhz / LSI_PREDIV_A as u64 / 1_000) as u32;
+            smooth = smooth_calibration(lsi_mhz, prediv_s);
+            self.modify(|rb| {
+                rb.prer.write(|w| unsafe {
+                    w.bits((LSI_PREDIV_A - 1) << 16 | (prediv_s - 1))
+                });
+            });
+        }
+
+        let smooth = smooth.unwrap_or(0);
+        self.set_smooth_calibration(smooth);
+        LsiCalibration {
+            lsi_mhz: lsi_mhz as u32,
+            prediv_s: (prediv_s - 1) as u16,
+            smooth,
+            prescaler_reloaded,
+        }
+    }
+
+    /// Sets smooth calibration in RTCCLK pulses per 2^20, -511..=512
+    pub fn set_smooth_calibration(&mut self, pulses: i16) {
+        assert!((-511..=512).contains(&pulses));
+        // CALP inserts 512 pulses, CALM masks up to 511
+        let (calp, calm) = if pulses > 0 {
+            (1 << 15, (512 - pulses) as u32)
+        } else {
+            (0, -pulses as u32)
+        };
+        // Wait for a pending recalibration (RECALPF)
+        while self.rb.icsr.read().bits() & (1 << 16) != 0 {}
+        self.unprotected(|rb| {
+            rb.calr.write(|w| unsafe { w.bits(calp | calm) });
+        });
+    }
+
+    /// Sets `alarm` to fire at `time` on `day`
+    ///
+    /// The alarm interrupt is enabled with `listen()`.
+    pub fn set_alarm(&mut self, alarm: Alarm, time: &Time, day: AlarmDay) {
+        let (ht, hu) = bcd2_encode(time.hours);
+        let (mnt, mnu) = bcd2_encode(time.minutes);
+        let (st, su) = bcd2_encode(time.seconds);
+        let day_bits = match day {
+            // MSK4
+            AlarmDay::Daily => 1 << 31,
+            AlarmDay::Date(date) => {
+                assert!((1..=31).contains(&date));
+                let (dt, du) = bcd2_encode(date as u32);
+                (dt as u32) << 28 | (du as u32 & 0xf) << 24
+            }
+            AlarmDay::WeekDay(wd) => {
+                assert!((1..=7).contains(&wd));
+                // WDSEL
+                1 << 30 | (wd as u32) << 24
+            }
+        };
+        let bits = day_bits
+            | (ht as u32) << 20
+            | (hu as u32 & 0xf) << 16
+            | (mnt as u32) << 12
+            | (mnu as u32 & 0xf) << 8
+            | (st as u32) << 4
+            | su as u32 & 0xf;
+
+        let enable = alarm_enable(alarm);
+        self.unprotected(|rb| {
+            rb.cr.modify(|r, w| unsafe { w.bits(r.bits() & !enable) });
+            match alarm {
+                Alarm::A => rb.alrmar.write(|w| unsafe { w.bits(bits) }),
+                Alarm::B => rb.alrmbr.write(|w| unsafe { w.bits(bits) }),
+            }
+            rb.cr.modify(|r, w| unsafe { w.bits(r.bits() | enable) });
+        });
+    }
+
+    /// Disables `alarm`
+    pub fn disable_alarm(&mut self, alarm: Alarm) {
+        let enable = alarm_enable(alarm);
+        self.unprotected(|rb| {
+            rb.cr.modify(|r, w| unsafe { w.bits(r.bits() & !enable) });
+        });
+    }
+
+    /// Starts the periodic wakeup timer
+    ///
+    /// Periods up to 32s use RTCCLK/16 with sub-millisecond resolution,
+    /// longer periods are rounded down to whole seconds.
+    pub fn enable_wakeup_timer<T: Into<MicroSecond>>(&mut self, period: T) {
+        let period = period.into().0 as u64;
+        let prer = self.rb.prer.read().bits();
+        // The prescalers divide RTCCLK down to 1Hz
+        let rtc_clk = (((prer >> 16) & 0x7f) + 1) as u64 * ((prer & 0x7fff) + 1) as u64;
+        let ticks = period * rtc_clk / 16 / 1_000_000;
+        let (wucksel, wut) = if ticks <= 0x1_0000 {
+            (0b000, ticks.max(1) - 1)
+        } else {
+            let secs = period / 1_000_000;
+            if secs <= 0x1_0000 {
+                (0b100, secs - 1)
+            } else {
+                // WUT is extended by 2^16
+                (0b110, secs - 1 - 0x1_0000)
+            }
+        };
+
+        self.disable_wakeup_timer();
+        while self.rb.icsr.read().bits() & WUTWF == 0 {}
+        self.unprotected(|rb| {
+            rb.wutr.write(|w| unsafe { w.bits(wut as u32) });
+            rb.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !WUCKSEL_MASK | wucksel | WUTE) });
+        });
+    }
+
+    /// Stops the periodic wakeup timer
+    pub fn disable_wakeup_timer(&mut self) {
+        self.unprotected(|rb| {
+            rb.cr.modify(|r, w| unsafe { w.bits(r.bits() & !WUTE) });
+        });
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        self.unprotected(|rb| {
+            rb.cr.modify(|r, w| unsafe { w.bits(r.bits() | event.enable()) });
+        });
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        self.unprotected(|rb| {
+            rb.cr.modify(|r, w| unsafe { w.bits(r.bits() & !event.enable()) });
+        });
+    }
+
+    /// Returns `true` if `event` is pending
+    pub fn is_pending(&self, event: Event) -> bool {
+        self.rb.sr.read().bits() & event.flag() != 0
+    }
+
+    /// Clears a pending event
+    pub fn unpend(&mut self, event: Event) {
+        self.rb.scr.write(|w| unsafe { w.bits(event.flag()) });
+    }
+
+    /// Routes listened events to EXTI line 19 so they wake the MCU from Stop
+    /// and Standby modes
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    pub fn wakeup(&self, exti: &crate::stm32::EXTI) {
+        use crate::exti::ExtiExt;
+        exti.wakeup(crate::exti::Event::RTC);
+        let pwr = unsafe { &(*crate::stm32::PWR::ptr()) };
+        pwr.cr3.modify(|r, w| unsafe { w.bits(r.bits() | EIWUL) });
+    }
+
+    fn unprotected<F>(&mut self, mut closure: F)
+    where
+        F: FnMut(&mut RTC),
+    {
+        self.rb.wpr.write(|w| unsafe { w.bits(0xCA) });
+        self.rb.wpr.write(|w| unsafe { w.bits(0x53) });
+        closure(&mut self.rb);
+        self.rb.wpr.write(|w| unsafe { w.bits(0xFF) });
+    }
+}
+
+/// ALRAE / ALRBE
+fn alarm_enable(alarm: Alarm) -> u32 {
+    match alarm {
+        Alarm::A => 1 << 8,
+        Alarm::B => 1 << 9,
+    }
+}
+
+/// Measures LSI frequency in millihertz with TIM16 input capture
+fn measure_lsi(tim: &mut TIM16, clk: Hertz) -> u64 {
+    let ticks = measure_ti1(tim, Ti1Source::Lsi, LSI_CAPTURES);
+    clk.0 as u64 * CAPTURE_PRESCALER * LSI_CAPTURES * 1_000 / ticks.max(1)
+}
+
+/// Smooth calibration pulses to trim `lsi_mhz / LSI_PREDIV_A / prediv_s` to 1Hz
+fn smooth_calibration(lsi_mhz: u64, prediv_s: u32) -> Option<i16> {
+    let target = LSI_PREDIV_A as i64 * prediv_s as i64 * 1_000;
+    let pulses = (target - lsi_mhz as i64) * CALIBRATION_CYCLE / lsi_mhz.max(1) as i64;
+    if (-511..=512).contains(&pulses) {
+        Some(pulses as i16)
+    } else {
+        None
+    }
 }
 
 pub trait RtcExt {
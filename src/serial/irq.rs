@@ -0,0 +1,176 @@
+//! # Shared USART interrupt vectors
+//!
+//! USART3, USART4 and LPUART share one interrupt vector on the G07x and G081.
+//! `which_pending` lists the instances with an enabled event pending, so a
+//! handler on the shared vector only services those.
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn USART3_USART4_LPUART1() {
+//!     for uart in irq::which_pending() {
+//!         match uart {
+//!             UartId::Usart3 => { /* ... */ }
+//!             UartId::Lpuart => { /* ... */ }
+//!             _ => {}
+//!         }
+//!     }
+//! }
+//! ```
+use super::{Rx, Serial, Tx, LPUART_INTERRUPT};
+use crate::stm32::*;
+use bare_metal::Nr;
+
+/// CR1 interrupt enable bits and the ISR flags they enable
+const CR1_EVENTS: [(u32, u32); 7] = [
+    // PEIE: PE
+    (1 << 8, 1),
+    // TXEIE: TXE
+    (1 << 7, 1 << 7),
+    // TCIE: TC
+    (1 << 6, 1 << 6),
+    // RXNEIE: RXNE, ORE
+    (1 << 5, 1 << 5 | 1 << 3),
+    // IDLEIE: IDLE
+    (1 << 4, 1 << 4),
+    // CMIE: CMF
+    (1 << 14, 1 << 17),
+    // RTOIE: RTOF
+    (1 << 26, 1 << 11),
+];
+
+/// CR3 interrupt enable bits and the ISR flags they enable
+const CR3_EVENTS: [(u32, u32); 3] = [
+    // EIE: FE, NF, ORE
+    (1, 1 << 1 | 1 << 2 | 1 << 3),
+    // CTSIE: CTSIF
+    (1 << 10, 1 << 9),
+    // WUFIE: WUF
+    (1 << 22, 1 << 20),
+];
+
+/// USART instance
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UartId {
+    Usart1,
+    Usart2,
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    Usart3,
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    Usart4,
+    Lpuart,
+}
+
+impl UartId {
+    /// All instances of the device
+    pub const ALL: &'static [UartId] = &[
+        UartId::Usart1,
+        UartId::Usart2,
+        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        UartId::Usart3,
+        #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+        UartId::Usart4,
+        UartId::Lpuart,
+    ];
+
+    /// NVIC interrupt line of the instance
+    pub fn interrupt(self) -> Interrupt {
+        match self {
+            UartId::Usart1 => Interrupt::USART1,
+            UartId::Usart2 => Interrupt::USART2,
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            UartId::Usart3 | UartId::Usart4 => Interrupt::USART3_USART4_LPUART1,
+            UartId::Lpuart => LPUART_INTERRUPT,
+        }
+    }
+
+    /// Returns `true` if an enabled interrupt event of the instance is pending
+    pub fn is_pending(self) -> bool {
+        let (isr, cr1, cr3) = self.registers();
+        let enabled = |events: &[(u32, u32)], cr: u32| {
+            events
+                .iter()
+                .filter(|(enable, _)| cr & enable != 0)
+                .fold(0, |flags, (_, flag)| flags | flag)
+        };
+        isr & (enabled(&CR1_EVENTS, cr1) | enabled(&CR3_EVENTS, cr3)) != 0
+    }
+
+    /// ISR, CR1 and CR3
+    fn registers(self) -> (u32, u32, u32) {
+        macro_rules! read {
+            ($USARTX:ident) => {{
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                (
+                    usart.isr.read().bits(),
+                    usart.cr1.read().bits(),
+                    usart.cr3.read().bits(),
+                )
+            }};
+        }
+        match self {
+            UartId::Usart1 => read!(USART1),
+            UartId::Usart2 => read!(USART2),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            UartId::Usart3 => read!(USART3),
+            #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+            UartId::Usart4 => read!(USART4),
+            UartId::Lpuart => read!(LPUART),
+        }
+    }
+}
+
+/// Instances with an enabled interrupt event pending
+pub fn which_pending() -> impl Iterator<Item = UartId> {
+    UartId::ALL.iter().copied().filter(|uart| uart.is_pending())
+}
+
+/// Instances served by the interrupt vector `irq`
+pub fn sharing(irq: Interrupt) -> impl Iterator<Item = UartId> {
+    UartId::ALL
+        .iter()
+        .copied()
+        .filter(move |uart| uart.interrupt().nr() == irq.nr())
+}
+
+macro_rules! uart_id {
+    ($($USARTX:ident: $id:ident,)+) => {
+        $(
+            impl<PINS> Serial<$USARTX, PINS> {
+                /// Instance of the USART, e.g. to match `which_pending`
+                pub const ID: UartId = UartId::$id;
+
+                /// Returns `true` if an enabled interrupt event is pending
+                pub fn is_interrupt_pending(&self) -> bool {
+                    Self::ID.is_pending()
+                }
+            }
+
+            impl Tx<$USARTX> {
+                /// Returns `true` if an enabled interrupt event of the USART is pending
+                pub fn is_interrupt_pending(&self) -> bool {
+                    UartId::$id.is_pending()
+                }
+            }
+
+            impl Rx<$USARTX> {
+                /// Returns `true` if an enabled interrupt event of the USART is pending
+                pub fn is_interrupt_pending(&self) -> bool {
+                    UartId::$id.is_pending()
+                }
+            }
+        )+
+    }
+}
+
+uart_id! {
+    USART1: Usart1,
+    USART2: Usart2,
+    LPUART: Lpuart,
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+uart_id! {
+    USART3: Usart3,
+    USART4: Usart4,
+}
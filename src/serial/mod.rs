@@ -0,0 +1,1506 @@
+use core::fmt;
+#[cfg(feature = "async")]
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::ops;
+use core::pin::Pin;
+use core::sync::atomic::{self, Ordering};
+#[cfg(feature = "async")]
+use core::task::Poll;
+
+#[cfg(feature = "async")]
+use crate::asynch::{AsyncInterrupt, AsyncWaker};
+use crate::dma::descriptor::{DmaSink, DmaSource};
+use crate::dma::double_buffer::DmaRestart;
+use crate::dma::{DmaChannel, ReadDma, Transfer, TransferDirection, WriteDma};
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+use crate::gpio::AF4;
+use crate::gpio::{gpioa::*, gpiob::*, gpioc::*, gpiod::*};
+use crate::gpio::{AltFunction, Alternate, DefaultMode, AF0, AF1, AF6};
+use crate::prelude::*;
+use crate::rcc::{Enable, KernelClock, KernelClockUser, Rcc};
+use crate::stm32::*;
+use crate::time::{Bps, Hertz};
+use as_slice::{AsMutSlice, AsSlice};
+use cortex_m::interrupt;
+use hal;
+use hal::timer::CountDown;
+use nb::block;
+
+pub mod irq;
+
+// CR1
+const UE: u32 = 1;
+const UESM: u32 = 1 << 1;
+const RE: u32 = 1 << 2;
+const TE: u32 = 1 << 3;
+const OVER8: u32 = 1 << 15;
+const M0: u32 = 1 << 12;
+const WAKE: u32 = 1 << 11;
+const MME: u32 = 1 << 13;
+// CR2
+const ADDM7: u32 = 1 << 4;
+const ADD_SHIFT: u32 = 24;
+const ADD_MASK: u32 = 0xff << ADD_SHIFT;
+/// Largest accepted deviation of the actual baud rate, in permille
+const MAX_BAUD_ERROR: u64 = 20;
+
+// RQR
+const MMRQ: u32 = 1 << 2;
+const RXFRQ: u32 = 1 << 3;
+// ISR and ICR
+const IDLE: u32 = 1 << 4;
+const TC: u32 = 1 << 6;
+const TXE: u32 = 1 << 7;
+const BUSY: u32 = 1 << 16;
+const CMF: u32 = 1 << 17;
+const RWU: u32 = 1 << 19;
+const TEACK: u32 = 1 << 21;
+const REACK: u32 = 1 << 22;
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pub(crate) const USART2_KERNEL: Option<KernelClockUser> = Some(KernelClockUser::Usart2);
+#[cfg(not(any(feature = "stm32g07x", feature = "stm32g081")))]
+pub(crate) const USART2_KERNEL: Option<KernelClockUser> = None;
+
+const LPUART_INTERRUPT: Interrupt = Interrupt::USART3_USART4_LPUART1;
+
+/// Serial error
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Framing error
+    Framing,
+    /// Noise error
+    Noise,
+    /// RX buffer overrun
+    Overrun,
+    /// Parity check error
+    Parity,
+    /// No data received in time
+    Timeout,
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::Overrun => embedded_io::ErrorKind::Other,
+            Error::Timeout => embedded_io::ErrorKind::TimedOut,
+            _ => embedded_io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WordLength {
+    DataBits7,
+    DataBits8,
+    DataBits9,
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Parity {
+    ParityNone,
+    ParityEven,
+    ParityOdd,
+}
+
+/// Interrupt event
+pub enum Event {
+    /// New data has been received
+    Rxne,
+    /// New data can be sent
+    Txe,
+    /// Last character shifted out, the line is idle
+    TransmissionComplete,
+    /// Idle line state detected
+    Idle,
+    /// Own address received, the receiver left mute mode
+    WakeFromMute,
+}
+
+/// Condition that takes the receiver out of mute mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MuteWakeup {
+    /// Idle frame on the line
+    IdleLine,
+    /// Character with the address mark (MSB) set and this 7-bit address
+    AddressMark(u8),
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StopBits {
+    #[doc = "1 stop bit"]
+    STOP1,
+    #[doc = "0.5 stop bits"]
+    STOP0P5,
+    #[doc = "2 stop bits"]
+    STOP2,
+    #[doc = "1.5 stop bits"]
+    STOP1P5,
+}
+
+pub struct Config {
+    baudrate: Bps,
+    wordlength: WordLength,
+    parity: Parity,
+    stopbits: StopBits,
+    clock: Option<KernelClock>,
+    over8: bool,
+    transmitter: bool,
+    receiver: bool,
+}
+
+impl Config {
+    pub fn baudrate(mut self, baudrate: Bps) -> Self {
+        self.baudrate = baudrate;
+        self
+    }
+
+    pub fn parity_none(mut self) -> Self {
+        self.parity = Parity::ParityNone;
+        self
+    }
+
+    pub fn parity_even(mut self) -> Self {
+        self.parity = Parity::ParityEven;
+        self
+    }
+
+    pub fn parity_odd(mut self) -> Self {
+        self.parity = Parity::ParityOdd;
+        self
+    }
+
+    pub fn wordlength_8(mut self) -> Self {
+        self.wordlength = WordLength::DataBits8;
+        self
+    }
+
+    pub fn wordlength_9(mut self) -> Self {
+        self.wordlength = WordLength::DataBits9;
+        self
+    }
+
+    pub fn stopbits(mut self, stopbits: StopBits) -> Self {
+        self.stopbits = stopbits;
+        self
+    }
+
+    /// Oversamples by 8, for baud rates up to fck / 8 at a lower noise tolerance
+    ///
+    /// Not available on LPUART.
+    pub fn oversampling_8(mut self) -> Self {
+        self.over8 = true;
+        self
+    }
+
+    pub fn oversampling_16(mut self) -> Self {
+        self.over8 = false;
+        self
+    }
+
+    /// Selects the kernel clock in CCIPR, the current selection is kept otherwise
+    ///
+    /// LPUART clocked from LSE runs in Stop mode at up to 9600 baud.
+    pub fn kernel_clock(mut self, clock: KernelClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Enables TE after construction, see `Serial::enable_transmitter`
+    ///
+    /// Always disabled with `NoTx`.
+    pub fn transmitter(mut self, enable: bool) -> Self {
+        self.transmitter = enable;
+        self
+    }
+
+    /// Enables RE after construction, see `Serial::enable_receiver`
+    ///
+    /// Always disabled with `NoRx`.
+    pub fn receiver(mut self, enable: bool) -> Self {
+        self.receiver = enable;
+        self
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InvalidConfig;
+
+impl Default for Config {
+    fn default() -> Config {
+        let baudrate = 19_200.bps();
+        Config {
+            baudrate,
+            wordlength: WordLength::DataBits8,
+            parity: Parity::ParityNone,
+            stopbits: StopBits::STOP1,
+            clock: None,
+            over8: false,
+            transmitter: true,
+            receiver: true,
+        }
+    }
+}
+
+/// Computes BRR for `baud` from the kernel clock `clk`
+///
+/// Fails if BRR is out of range, LPUART needs 3 x baud <= fck <= 4096 x baud,
+/// or if the actual baud rate deviates by more than `MAX_BAUD_ERROR`.
+fn brr_bits(clk_mul: u64, clk: u64, baud: u64, over8: bool) -> Result<u32, InvalidConfig> {
+    let lpuart = clk_mul == 256;
+    if baud == 0 || lpuart && over8 {
+        return Err(InvalidConfig);
+    }
+    let mul = if over8 { 2 } else { clk_mul };
+    let div = (mul * clk + baud / 2) / baud;
+    let range = if lpuart {
+        0x300..=0xf_ffff
+    } else {
+        16..=0xffff
+    };
+    if !range.contains(&div) {
+        return Err(InvalidConfig);
+    }
+    let actual = mul * clk / div;
+    if actual.abs_diff(baud) * 1000 > baud * MAX_BAUD_ERROR {
+        return Err(InvalidConfig);
+    }
+    Ok(if over8 {
+        // BRR[3] stays clear, BRR[2:0] holds USARTDIV[3:0] >> 1
+        (div & !0xf | (div & 0xf) >> 1) as u32
+    } else {
+        div as u32
+    })
+}
+
+/// Baud rate resulting from `brr`
+fn brr_baudrate(clk_mul: u64, clk: u64, brr: u32, over8: bool) -> Bps {
+    let brr = brr as u64;
+    let (mul, div) = if over8 {
+        (2, brr & !0xf | (brr & 0x7) << 1)
+    } else {
+        (clk_mul, brr)
+    };
+    Bps((mul * clk / div.max(1)) as u32)
+}
+
+/// Applies the kernel clock selection of `config` and returns the kernel clock
+fn kernel_clock(
+    user: Option<KernelClockUser>,
+    config: &Config,
+    rcc: &mut Rcc,
+) -> Result<Hertz, InvalidConfig> {
+    match (user, config.clock) {
+        (Some(user), Some(clock)) => rcc.set_kernel_clock(user, clock).map_err(|_| InvalidConfig),
+        (Some(user), None) => Ok(rcc.kernel_clock(user)),
+        (None, None) | (None, Some(KernelClock::Pclk)) => Ok(rcc.clocks.apb_clk),
+        (None, Some(_)) => Err(InvalidConfig),
+    }
+}
+
+/// Serial receiver
+///
+/// Reads RDR and ISR, clears error flags in ICR and changes only RXNEIE and
+/// IDLEIE in CR1 and DMAR in CR3, within critical sections. `Rx` and `Tx` of the same
+/// USART can be used from different interrupt handlers.
+pub struct Rx<USART> {
+    _usart: PhantomData<USART>,
+}
+
+/// Serial transmitter
+///
+/// Writes TDR, reads ISR and changes only TXEIE and TCIE in CR1 and DMAT in
+/// CR3, within critical sections.
+pub struct Tx<USART> {
+    _usart: PhantomData<USART>,
+}
+
+/// Serial DMA receiver
+pub struct DmaRx<USART, CHANNEL> {
+    _usart: PhantomData<USART>,
+    channel: CHANNEL,
+    buffer: Option<&'static mut [u8]>,
+}
+
+/// Serial DMA transmitter
+pub struct DmaTx<USART, CHANNEL> {
+    _usart: PhantomData<USART>,
+    channel: CHANNEL,
+    buffer: Option<&'static [u8]>,
+}
+
+/// Serial abstraction
+///
+/// `PINS` defaults to `()` for a port whose pins were erased with `erase_pins`.
+pub struct Serial<USART, PINS = ()> {
+    tx: Tx<USART>,
+    rx: Rx<USART>,
+    usart: USART,
+    pins: PINS,
+    clk: Hertz,
+}
+
+pub trait SerialExt<USART> {
+    fn usart<TX, RX>(
+        self,
+        tx: TX,
+        rx: RX,
+        config: Config,
+        rcc: &mut Rcc,
+    ) -> Result<Serial<USART, (TX, RX)>, InvalidConfig>
+    where
+        TX: TxPin<USART>,
+        RX: RxPin<USART>;
+}
+
+// Serial TX pin
+pub trait TxPin<USART> {
+    /// `false` for `NoTx`, which leaves the transmitter disabled
+    const CONNECTED: bool = true;
+
+    fn setup(&self);
+}
+
+// Serial RX pin
+pub trait RxPin<USART> {
+    /// `false` for `NoRx`, which leaves the receiver disabled
+    const CONNECTED: bool = true;
+
+    fn setup(&self);
+}
+
+/// A filler type for a receive-only serial port
+pub struct NoTx;
+/// A filler type for a transmit-only serial port
+pub struct NoRx;
+
+impl<USART> TxPin<USART> for NoTx {
+    const CONNECTED: bool = false;
+
+    fn setup(&self) {}
+}
+
+impl<USART> RxPin<USART> for NoRx {
+    const CONNECTED: bool = false;
+
+    fn setup(&self) {}
+}
+
+#[cfg(feature = "embedded-io")]
+impl<USART, PINS> embedded_io::ErrorType for Serial<USART, PINS> {
+    type Error = Error;
+}
+
+#[cfg(feature = "embedded-io")]
+impl<USART> embedded_io::ErrorType for Tx<USART> {
+    type Error = Error;
+}
+
+#[cfg(feature = "embedded-io")]
+impl<USART> embedded_io::ErrorType for Rx<USART> {
+    type Error = Error;
+}
+
+impl<USART, PINS> fmt::Write for Serial<USART, PINS>
+where
+    Serial<USART, PINS>: hal::serial::Write<u8>,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = s.as_bytes().iter().map(|c| block!(self.write(*c))).last();
+        Ok(())
+    }
+}
+
+impl<USART> fmt::Write for Tx<USART>
+where
+    Tx<USART>: hal::serial::Write<u8>,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = s.as_bytes().iter().map(|c| block!(self.write(*c))).last();
+        Ok(())
+    }
+}
+
+macro_rules! uart {
+    ($USARTX:ident,
+        $usartX:ident, $clk_mul:expr, $irq:expr, $kernel:expr,
+        tx: [ $(($PTX:ident, $TAF:ident),)+ ],
+        rx: [ $(($PRX:ident, $RAF:ident),)+ ],
+    ) => {
+        $(
+            impl TxPin<$USARTX> for $PTX<DefaultMode> {
+                fn setup(&self) {
+                    self.set_alt_mode(AltFunction::$TAF)
+                }
+            }
+
+            impl TxPin<$USARTX> for $PTX<Alternate<$TAF>> {
+                fn setup(&self) {}
+            }
+        )+
+
+        $(
+            impl RxPin<$USARTX> for $PRX<DefaultMode> {
+                fn setup(&self) {
+                    self.set_alt_mode(AltFunction::$RAF)
+                }
+            }
+
+            impl RxPin<$USARTX> for $PRX<Alternate<$RAF>> {
+                fn setup(&self) {}
+            }
+        )+
+
+        impl SerialExt<$USARTX> for $USARTX {
+            fn usart<TX, RX>(
+                self,
+                tx: TX,
+                rx: RX,
+                config: Config,
+                rcc: &mut Rcc) -> Result<Serial<$USARTX, (TX, RX)>, InvalidConfig>
+            where
+                TX: TxPin<$USARTX>,
+                RX: RxPin<$USARTX>,
+            {
+                Serial::$usartX(self, tx, rx, config, rcc)
+            }
+        }
+
+        impl<TX, RX> Serial<$USARTX, (TX, RX)> {
+            pub fn $usartX(
+                usart: $USARTX,
+                tx: TX,
+                rx: RX,
+                config: Config,
+                rcc: &mut Rcc,
+            ) -> Result<Self, InvalidConfig>
+            where
+                TX: TxPin<$USARTX>,
+                RX: RxPin<$USARTX>,
+            {
+                tx.setup();
+                rx.setup();
+
+                let clk = kernel_clock($kernel, &config, rcc)?;
+                let brr = brr_bits($clk_mul, clk.0 as u64, config.baudrate.0 as u64, config.over8)?;
+
+                // Enable clock for USART
+                $USARTX::enable(rcc);
+                // Reset other registers to disable advanced USART features
+                usart.cr1.reset();
+                usart.cr2.reset();
+                usart.cr3.reset();
+                usart.brr.write(|w| unsafe { w.bits(brr) });
+
+                // Frame format is written while the USART is disabled
+                usart.cr1.write(|w| {
+                    w.te()
+                        .bit(TX::CONNECTED && config.transmitter)
+                        .re()
+                        .bit(RX::CONNECTED && config.receiver)
+                        .m0()
+                        .bit(config.wordlength == WordLength::DataBits9)
+                        .m1()
+                        .bit(config.wordlength == WordLength::DataBits7)
+                        .pce()
+                        .bit(config.parity != Parity::ParityNone)
+                        .ps()
+                        .bit(config.parity == Parity::ParityOdd)
+                });
+                usart.cr2.write(|w| unsafe {
+                    w.stop().bits(match config.stopbits {
+                        StopBits::STOP1 => 0b00,
+                        StopBits::STOP0P5 => 0b01,
+                        StopBits::STOP2 => 0b10,
+                        StopBits::STOP1P5 => 0b11,
+                    })
+                });
+                // Enable transmission and receiving
+                let over8 = if config.over8 { OVER8 } else { 0 };
+                usart.cr1.modify(|r, w| unsafe { w.bits(r.bits() | over8 | UE) });
+                Ok(Serial {
+                    tx: Tx { _usart: PhantomData },
+                    rx: Rx { _usart: PhantomData },
+                    usart,
+                    pins: (tx, rx),
+                    clk,
+                })
+            }
+
+            /// Drops the pin types, so the port can be named `Serial<USART>`
+            ///
+            /// The pins stay configured, `release` no longer returns them.
+            pub fn erase_pins(self) -> Serial<$USARTX> {
+                Serial {
+                    tx: self.tx,
+                    rx: self.rx,
+                    usart: self.usart,
+                    pins: (),
+                    clk: self.clk,
+                }
+            }
+        }
+
+        impl<PINS> Serial<$USARTX, PINS> {
+            /// Disables the USART and its clock, returns the peripheral and pins
+            pub fn release(self, rcc: &mut Rcc) -> ($USARTX, PINS) {
+                self.usart.cr1.reset();
+                $USARTX::disable(rcc);
+                (self.usart, self.pins)
+            }
+
+            /// NVIC interrupt line of the USART
+            pub const INTERRUPT: Interrupt = $irq;
+
+            /// Returns the NVIC interrupt line, shared with other USARTs on some instances
+            pub fn interrupt(&self) -> Interrupt {
+                Self::INTERRUPT
+            }
+
+            /// Clears the flag of a pending `event`
+            ///
+            /// `Rxne` discards the received data, `Txe` is only cleared by
+            /// writing data. `TransmissionComplete` is also cleared by writing
+            /// data.
+            pub fn clear_interrupt(&mut self, event: Event) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                match event {
+                    Event::Rxne => usart.rqr.write(|w| unsafe { w.bits(RXFRQ) }),
+                    Event::Txe => {}
+                    Event::TransmissionComplete => usart.icr.write(|w| unsafe { w.bits(TC) }),
+                    Event::Idle => usart.icr.write(|w| unsafe { w.bits(IDLE) }),
+                    Event::WakeFromMute => usart.icr.write(|w| unsafe { w.bits(CMF) }),
+                }
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self, event: Event) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+
+                interrupt::free(|_| match event {
+                    Event::Rxne => usart.cr1.modify(|_, w| w.rxneie().set_bit()),
+                    Event::Txe => usart.cr1.modify(|_, w| w.txeie().set_bit()),
+                    Event::TransmissionComplete => {
+                        usart.cr1.modify(|_, w| w.tcie().set_bit())
+                    }
+                    Event::Idle => usart.cr1.modify(|_, w| w.idleie().set_bit()),
+                    Event::WakeFromMute => usart.cr1.modify(|_, w| w.cmie().set_bit()),
+                });
+            }
+
+            /// Stop listening for an interrupt event
+            pub fn unlisten(&mut self, event: Event) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+
+                interrupt::free(|_| match event {
+                    Event::Rxne => usart.cr1.modify(|_, w| w.rxneie().clear_bit()),
+                    Event::Txe => usart.cr1.modify(|_, w| w.txeie().clear_bit()),
+                    Event::TransmissionComplete => {
+                        usart.cr1.modify(|_, w| w.tcie().clear_bit())
+                    }
+                    Event::Idle => usart.cr1.modify(|_, w| w.idleie().clear_bit()),
+                    Event::WakeFromMute => usart.cr1.modify(|_, w| w.cmie().clear_bit()),
+                });
+            }
+
+            /// Enables multiprocessor mute mode
+            ///
+            /// The receiver ignores all characters while muted and leaves mute
+            /// mode on `wakeup`. Address marks need 8 or 9-bit words, the master
+            /// sends the address with `Tx::write_address` and data with the MSB
+            /// cleared.
+            pub fn enable_mute_mode(&mut self, wakeup: MuteWakeup) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| {
+                    let cr1 = usart.cr1.read().bits();
+                    usart.cr1.write(|w| unsafe { w.bits(cr1 & !UE) });
+                    match wakeup {
+                        MuteWakeup::IdleLine => {
+                            usart.cr1.write(|w| unsafe { w.bits(cr1 & !(UE | WAKE) | MME) });
+                        }
+                        MuteWakeup::AddressMark(address) => {
+                            // The character match compares the full character, in
+                            // 8-bit mode it includes the address mark
+                            let mark = if cr1 & M0 == 0 { 0x80 } else { 0 };
+                            let add = (mark | address as u32 & 0x7f) << ADD_SHIFT;
+                            usart.cr2.modify(|r, w| unsafe {
+                                w.bits(r.bits() & !ADD_MASK | add | ADDM7)
+                            });
+                            usart.cr1.write(|w| unsafe { w.bits(cr1 & !UE | WAKE | MME) });
+                        }
+                    }
+                    usart.cr1.write(|w| unsafe { w.bits(usart.cr1.read().bits() | UE) });
+                });
+            }
+
+            pub fn disable_mute_mode(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| {
+                    let cr1 = usart.cr1.read().bits();
+                    usart.cr1.write(|w| unsafe { w.bits(cr1 & !UE) });
+                    usart.cr1.write(|w| unsafe { w.bits(cr1 & !(WAKE | MME) | UE) });
+                });
+            }
+
+            /// Returns the actual baud rate, which may deviate from the configured one
+            pub fn baudrate(&self) -> Bps {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let over8 = usart.cr1.read().bits() & OVER8 != 0;
+                brr_baudrate($clk_mul, self.clk.0 as u64, usart.brr.read().bits(), over8)
+            }
+
+            /// Keeps the USART able to wake the MCU from Stop mode (UESM)
+            ///
+            /// Needs a kernel clock running in Stop mode, HSI16 or LSE, and the
+            /// USART EXTI line unmasked with `ExtiExt::wakeup`.
+            pub fn set_stop_mode_wakeup(&mut self, enable: bool) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| {
+                    usart.cr1.modify(|r, w| unsafe {
+                        w.bits(if enable { r.bits() | UESM } else { r.bits() & !UESM })
+                    })
+                });
+            }
+
+            /// Enters mute mode until the next wakeup condition
+            pub fn mute(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.rqr.write(|w| unsafe { w.bits(MMRQ) });
+            }
+
+            /// Returns `true` while the receiver is in mute mode
+            pub fn is_muted(&self) -> bool {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.isr.read().bits() & RWU != 0
+            }
+
+            /// Returns `true` if the own address was received
+            pub fn is_woken_from_mute(&self) -> bool {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.isr.read().bits() & CMF != 0
+            }
+
+            /// Clears the `WakeFromMute` flag
+            pub fn clear_wake_from_mute(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.icr.write(|w| unsafe { w.bits(CMF) });
+            }
+
+            /// Enables or disables the transmitter (TE)
+            ///
+            /// A disabled transmitter draws less current and releases TX to
+            /// idle. Wait for `is_tx_complete` first, the data register is
+            /// dropped otherwise. Enabling sends an idle frame.
+            pub fn enable_transmitter(&mut self, enable: bool) {
+                self.set_direction(TE, TEACK, enable)
+            }
+
+            /// Enables or disables the receiver (RE)
+            ///
+            /// A disabled receiver ignores RX, e.g. our own echo on a shared
+            /// line. A character being received is lost.
+            pub fn enable_receiver(&mut self, enable: bool) {
+                self.set_direction(RE, REACK, enable)
+            }
+
+            /// Returns `true` if the transmitter is enabled
+            pub fn is_transmitter_enabled(&self) -> bool {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.cr1.read().bits() & TE != 0
+            }
+
+            /// Returns `true` if the receiver is enabled
+            pub fn is_receiver_enabled(&self) -> bool {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.cr1.read().bits() & RE != 0
+            }
+
+            /// Sets or clears TE/RE in CR1 and waits for the acknowledge flag
+            fn set_direction(&mut self, bit: u32, ack: u32, enable: bool) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| {
+                    usart.cr1.modify(|r, w| unsafe {
+                        w.bits(if enable { r.bits() | bit } else { r.bits() & !bit })
+                    })
+                });
+                while (usart.isr.read().bits() & ack != 0) != enable {}
+            }
+
+            /// Returns `true` once the last character was shifted out
+            pub fn is_tx_complete(&self) -> bool {
+                self.tx.is_tx_complete()
+            }
+
+            /// Clears the `TransmissionComplete` flag
+            pub fn clear_tc(&mut self) {
+                self.tx.clear_tc()
+            }
+
+            /// Blocks until `buf` is filled, or fails once `timer` expires
+            pub fn read_exact<T: CountDown>(
+                &mut self,
+                buf: &mut [u8],
+                timer: &mut T,
+                timeout: T::Time,
+            ) -> Result<(), Error> {
+                self.rx.read_exact(buf, timer, timeout)
+            }
+
+            /// Separates the serial struct into separate channel objects for sending (Tx) and
+            /// receiving (Rx)
+            pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
+                (self.tx, self.rx)
+            }
+
+            /// Borrows the halves, the `Serial` keeps owning the USART and pins
+            pub fn split_ref(&mut self) -> (&mut Tx<$USARTX>, &mut Rx<$USARTX>) {
+                (&mut self.tx, &mut self.rx)
+            }
+        }
+
+        impl Tx<$USARTX> {
+            pub fn with_dma<CHANNEL>(self, channel: CHANNEL) -> DmaTx<$USARTX, CHANNEL>
+                where CHANNEL: DmaChannel
+            {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let usart_ptr = &usart.tdr as *const _ as _;
+
+                let mut channel = channel;
+                channel.set_direction(TransferDirection::MemoryToPeriph);
+                channel.set_peripheral_address(usart_ptr, false);
+                DmaTx {
+                    channel,
+                    _usart: PhantomData,
+                    buffer: None,
+                }
+            }
+
+            /// Creates a transmitter of an already configured USART
+            ///
+            /// # Safety
+            ///
+            /// No other `Tx` of this USART may be in use, e.g. the one split off
+            /// the `Serial`.
+            pub unsafe fn steal() -> Self {
+                Tx { _usart: PhantomData }
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| usart.cr1.modify(|_, w| w.txeie().set_bit()));
+            }
+
+            /// Sends a 7-bit address with the address mark (MSB) set
+            pub fn write_address(&mut self, address: u8) -> nb::Result<(), Error> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                if usart.isr.read().txe().bit_is_clear() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                let mark = if usart.cr1.read().bits() & M0 == 0 { 0x80 } else { 0x100 };
+                usart
+                    .tdr
+                    .write(|w| unsafe { w.bits(mark | address as u32 & 0x7f) });
+                Ok(())
+            }
+
+            /// Stop listening for an interrupt event
+            pub fn unlisten(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| usart.cr1.modify(|_, w| w.txeie().clear_bit()));
+            }
+
+            /// Starts listening for the `TransmissionComplete` event
+            ///
+            /// Unlike TXE, TC is only set once the stop bit of the last
+            /// character left the pin, e.g. to release an RS-485 driver.
+            pub fn listen_tc(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| usart.cr1.modify(|_, w| w.tcie().set_bit()));
+            }
+
+            /// Stop listening for the `TransmissionComplete` event
+            pub fn unlisten_tc(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| usart.cr1.modify(|_, w| w.tcie().clear_bit()));
+            }
+
+            /// Returns `true` once the last character was shifted out
+            pub fn is_tx_complete(&self) -> bool {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.isr.read().bits() & TC != 0
+            }
+
+            /// Clears the `TransmissionComplete` flag, it is set again once the
+            /// next write completes
+            pub fn clear_tc(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.icr.write(|w| unsafe { w.bits(TC) });
+            }
+
+            /// Returns `true` while data is pending or being shifted out
+            ///
+            /// Based on TXE and TC, so it stays `true` after `clear_tc` until
+            /// the next write completes.
+            pub fn is_busy(&self) -> bool {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.isr.read().bits() & (TXE | TC) != TXE | TC
+            }
+        }
+
+        impl Rx<$USARTX> {
+            pub fn with_dma<CHANNEL>(self, channel: CHANNEL) -> DmaRx<$USARTX, CHANNEL>
+                where CHANNEL: DmaChannel
+            {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let usart_ptr = &usart.rdr as *const _ as _;
+
+                let mut channel = channel;
+                channel.set_direction(TransferDirection::PeriphToMemory);
+                channel.set_peripheral_address(usart_ptr, false);
+                DmaRx {
+                    channel,
+                    _usart: PhantomData,
+                    buffer: None,
+                }
+            }
+
+            /// Creates a receiver of an already configured USART
+            ///
+            /// # Safety
+            ///
+            /// No other `Rx` of this USART may be in use, e.g. the one split off
+            /// the `Serial`.
+            pub unsafe fn steal() -> Self {
+                Rx { _usart: PhantomData }
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| usart.cr1.modify(|_, w| w.rxneie().set_bit()));
+            }
+
+            /// Stop listening for an interrupt event
+            pub fn unlisten(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                interrupt::free(|_| usart.cr1.modify(|_, w| w.rxneie().clear_bit()));
+            }
+
+            /// Returns `true` while a character is being received
+            pub fn is_busy(&self) -> bool {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.isr.read().bits() & BUSY != 0
+            }
+
+            /// Blocks until `buf` is filled, or fails once `timer` expires
+            ///
+            /// The timeout covers the whole buffer. Shadows `embedded_io::Read::read_exact`,
+            /// call that one with the trait path.
+            pub fn read_exact<T: CountDown>(
+                &mut self,
+                buf: &mut [u8],
+                timer: &mut T,
+                timeout: T::Time,
+            ) -> Result<(), Error> {
+                timer.start(timeout);
+                for byte in buf.iter_mut() {
+                    *byte = loop {
+                        match hal::serial::Read::read(self) {
+                            Ok(byte) => break byte,
+                            Err(nb::Error::Other(err)) => return Err(err),
+                            Err(nb::Error::WouldBlock) => {
+                                if timer.wait().is_ok() {
+                                    return Err(Error::Timeout);
+                                }
+                            }
+                        }
+                    };
+                }
+                Ok(())
+            }
+        }
+
+        impl<CHANNEL: DmaChannel> DmaRx<$USARTX, CHANNEL> {
+            /// Starts receiving into `buffer`, the adapter is kept for restarts
+            ///
+            /// Returns the buffer of a running transfer.
+            pub fn start(&mut self, buffer: &'static mut [u8]) -> Option<&'static mut [u8]> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let rx = Rx::<$USARTX> { _usart: PhantomData };
+                self.channel.select_peripheral(rx.request());
+                // CR3 is shared with the other half, maybe used from an interrupt
+                interrupt::free(|_| usart.cr3.modify(|_, w| w.dmar().set_bit()));
+                self.restart(buffer)
+            }
+
+            /// Swaps in `buffer` and restarts the transfer, returns the previous buffer
+            ///
+            /// Only the memory address and transfer length are rewritten, so a
+            /// ping-pong scheme can restart from the DMA interrupt with minimal
+            /// latency.
+            pub fn restart(&mut self, buffer: &'static mut [u8]) -> Option<&'static mut [u8]> {
+                self.channel.stop();
+                atomic::compiler_fence(Ordering::SeqCst);
+                self.channel.set_memory_address(buffer.as_ptr() as u32, true);
+                self.channel.set_transfer_length(buffer.len());
+                let previous = self.buffer.replace(buffer);
+                atomic::compiler_fence(Ordering::SeqCst);
+                self.channel.start();
+                previous
+            }
+
+            /// Stops the transfer and returns the buffer
+            pub fn stop(&mut self) -> Option<&'static mut [u8]> {
+                self.channel.stop();
+                atomic::compiler_fence(Ordering::SeqCst);
+                self.buffer.take()
+            }
+
+            /// Bytes received into the current buffer
+            pub fn received(&self) -> usize {
+                match &self.buffer {
+                    Some(buffer) => buffer.len() - self.channel.get_transfer_remaining() as usize,
+                    None => 0,
+                }
+            }
+
+            /// Starts receiving a frame of unknown length into `buffer`
+            ///
+            /// Enables the IDLE interrupt, the frame ends with the first idle
+            /// line after a received character. Call `poll_idle` from the
+            /// USART interrupt or in a loop. Returns the buffer of a running
+            /// transfer.
+            pub fn read_until_idle(
+                &mut self,
+                buffer: &'static mut [u8],
+            ) -> Option<&'static mut [u8]> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.icr.write(|w| unsafe { w.bits(IDLE) });
+                interrupt::free(|_| usart.cr1.modify(|_, w| w.idleie().set_bit()));
+                self.start(buffer)
+            }
+
+            /// Stops the transfer once the line went idle
+            ///
+            /// Returns the buffer and the number of bytes received, or `None`
+            /// while the frame is still coming in. Longer frames are cut at the
+            /// buffer length.
+            pub fn poll_idle(&mut self) -> Option<(&'static mut [u8], usize)> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                if usart.isr.read().bits() & IDLE == 0 {
+                    return None;
+                }
+                usart.icr.write(|w| unsafe { w.bits(IDLE) });
+                let received = self.received();
+                self.stop().map(|buffer| (buffer, received))
+            }
+
+            pub fn channel(&mut self) -> &mut CHANNEL {
+                &mut self.channel
+            }
+        }
+
+        impl<CHANNEL: DmaChannel> DmaRestart for DmaRx<$USARTX, CHANNEL> {
+            type Word = u8;
+
+            fn restart(&mut self, buffer: &'static mut [u8]) -> Option<&'static mut [u8]> {
+                DmaRx::<$USARTX, CHANNEL>::restart(self, buffer)
+            }
+
+            fn stop(&mut self) -> Option<&'static mut [u8]> {
+                DmaRx::<$USARTX, CHANNEL>::stop(self)
+            }
+        }
+
+        impl<CHANNEL: DmaChannel> DmaTx<$USARTX, CHANNEL> {
+            /// Starts sending `buffer`, the adapter is kept for restarts
+            ///
+            /// Returns the buffer of a running transfer.
+            pub fn start(&mut self, buffer: &'static [u8]) -> Option<&'static [u8]> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let tx = Tx::<$USARTX> { _usart: PhantomData };
+                self.channel.select_peripheral(tx.request());
+                // CR3 is shared with the other half, maybe used from an interrupt
+                interrupt::free(|_| usart.cr3.modify(|_, w| w.dmat().set_bit()));
+                self.restart(buffer)
+            }
+
+            /// Swaps in `buffer` and restarts the transfer, returns the previous buffer
+            pub fn restart(&mut self, buffer: &'static [u8]) -> Option<&'static [u8]> {
+                self.channel.stop();
+                atomic::compiler_fence(Ordering::SeqCst);
+                self.channel.set_memory_address(buffer.as_ptr() as u32, true);
+                self.channel.set_transfer_length(buffer.len());
+                let previous = self.buffer.replace(buffer);
+                atomic::compiler_fence(Ordering::SeqCst);
+                self.channel.start();
+                previous
+            }
+
+            /// Stops the transfer and returns the buffer
+            pub fn stop(&mut self) -> Option<&'static [u8]> {
+                self.channel.stop();
+                atomic::compiler_fence(Ordering::SeqCst);
+                self.buffer.take()
+            }
+
+            /// Bytes taken from the current buffer
+            pub fn sent(&self) -> usize {
+                match &self.buffer {
+                    Some(buffer) => buffer.len() - self.channel.get_transfer_remaining() as usize,
+                    None => 0,
+                }
+            }
+
+            pub fn channel(&mut self) -> &mut CHANNEL {
+                &mut self.channel
+            }
+        }
+
+        impl<CHANNEL, B> ReadDma<B> for DmaRx<$USARTX, CHANNEL>
+        where
+            CHANNEL: DmaChannel,
+            B: ops::DerefMut + 'static,
+            B::Target: AsMutSlice<Element = u8> + Unpin,
+            Self: core::marker::Sized,
+        {
+            fn read(mut self, buffer: Pin<B>) -> Transfer<Self, Pin<B>> {
+                let mut buffer = buffer;
+                let slice = buffer.as_mut_slice();
+                let (ptr, len) = (slice.as_ptr(), slice.len());
+
+                let dma_channel = &mut self.channel;
+                dma_channel.set_memory_address(ptr as u32, true);
+                dma_channel.set_transfer_length(len);
+
+                atomic::compiler_fence(Ordering::SeqCst);
+                dma_channel.start();
+
+                Transfer {
+                    buffer,
+                    channel: self,
+                }
+            }
+        }
+
+        impl<CHANNEL, B> WriteDma<B> for DmaTx<$USARTX, CHANNEL>
+        where
+            CHANNEL: DmaChannel,
+            B: ops::Deref + 'static,
+            B::Target: AsSlice<Element = u8> + Unpin,
+            Self: core::marker::Sized,
+        {
+            fn write(mut self, buffer: Pin<B>) -> Transfer<Self, Pin<B>> {
+                let slice = buffer.as_slice();
+                let (ptr, len) = (slice.as_ptr(), slice.len());
+
+                let dma_channel = &mut self.channel;
+                dma_channel.set_memory_address(ptr as u32, true);
+                dma_channel.set_transfer_length(len);
+
+                atomic::compiler_fence(Ordering::SeqCst);
+                dma_channel.start();
+
+                Transfer {
+                    buffer,
+                    channel: self,
+                }
+            }
+        }
+
+        impl hal::serial::Read<u8> for Rx<$USARTX> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u8, Error> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let isr = usart.isr.read();
+                Err(
+                    if isr.pe().bit_is_set() {
+                        usart.icr.write(|w| w.pecf().set_bit());
+                        nb::Error::Other(Error::Parity)
+                    } else if isr.fe().bit_is_set() {
+                        usart.icr.write(|w| w.fecf().set_bit());
+                        nb::Error::Other(Error::Framing)
+                    } else if isr.nf().bit_is_set() {
+                        usart.icr.write(|w| w.ncf().set_bit());
+                        nb::Error::Other(Error::Noise)
+                    } else if isr.ore().bit_is_set() {
+                        usart.icr.write(|w| w.orecf().set_bit());
+                        nb::Error::Other(Error::Overrun)
+                    } else if isr.rxne().bit_is_set() {
+                        return Ok(usart.rdr.read().bits() as u8)
+                    } else {
+                        nb::Error::WouldBlock
+                    }
+                )
+            }
+        }
+
+        impl<PINS> hal::serial::Read<u8> for Serial<$USARTX, PINS> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u8, Error> {
+                self.rx.read()
+            }
+        }
+
+        impl hal::serial::Write<u8> for Tx<$USARTX> {
+            type Error = Error;
+
+            fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                if usart.isr.read().tc().bit_is_set() {
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+
+            fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                if usart.isr.read().txe().bit_is_set() {
+                    usart.tdr.write(|w| unsafe { w.bits(byte as u32) });
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+        }
+
+        impl<PINS> hal::serial::Write<u8> for Serial<$USARTX, PINS> {
+            type Error = Error;
+
+            fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                self.tx.flush()
+            }
+
+            fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                self.tx.write(byte)
+            }
+        }
+
+        impl hal::blocking::serial::write::Default<u8> for Tx<$USARTX> {}
+
+        impl<PINS> hal::blocking::serial::write::Default<u8> for Serial<$USARTX, PINS> {}
+
+        #[cfg(feature = "embedded-io")]
+        impl embedded_io::Read for Rx<$USARTX> {
+            /// Blocks until the first byte, then returns all bytes received so far
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = block!(hal::serial::Read::read(self))?;
+                let mut len = 1;
+                while len < buf.len() {
+                    match hal::serial::Read::read(self) {
+                        Ok(byte) => buf[len] = byte,
+                        Err(_) => break,
+                    }
+                    len += 1;
+                }
+                Ok(len)
+            }
+        }
+
+        #[cfg(feature = "embedded-io")]
+        impl embedded_io::ReadReady for Rx<$USARTX> {
+            /// Returns `true` if a byte or a receive error is pending
+            fn read_ready(&mut self) -> Result<bool, Error> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let isr = usart.isr.read();
+                Ok(isr.rxne().bit_is_set()
+                    || isr.pe().bit_is_set()
+                    || isr.fe().bit_is_set()
+                    || isr.nf().bit_is_set()
+                    || isr.ore().bit_is_set())
+            }
+        }
+
+        #[cfg(feature = "embedded-io")]
+        impl embedded_io::Write for Tx<$USARTX> {
+            fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                for byte in buf {
+                    block!(hal::serial::Write::write(self, *byte))?;
+                }
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> Result<(), Error> {
+                block!(hal::serial::Write::flush(self))
+            }
+        }
+
+        #[cfg(feature = "embedded-io")]
+        impl embedded_io::WriteReady for Tx<$USARTX> {
+            fn write_ready(&mut self) -> Result<bool, Error> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                Ok(usart.isr.read().txe().bit_is_set())
+            }
+        }
+
+        #[cfg(feature = "embedded-io")]
+        impl<PINS> embedded_io::Read for Serial<$USARTX, PINS> {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                embedded_io::Read::read(&mut self.rx, buf)
+            }
+        }
+
+        #[cfg(feature = "embedded-io")]
+        impl<PINS> embedded_io::Write for Serial<$USARTX, PINS> {
+            fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                embedded_io::Write::write(&mut self.tx, buf)
+            }
+
+            fn flush(&mut self) -> Result<(), Error> {
+                embedded_io::Write::flush(&mut self.tx)
+            }
+        }
+
+        #[cfg(feature = "embedded-io")]
+        impl<PINS> embedded_io::ReadReady for Serial<$USARTX, PINS> {
+            fn read_ready(&mut self) -> Result<bool, Error> {
+                embedded_io::ReadReady::read_ready(&mut self.rx)
+            }
+        }
+
+        #[cfg(feature = "embedded-io")]
+        impl<PINS> embedded_io::WriteReady for Serial<$USARTX, PINS> {
+            fn write_ready(&mut self) -> Result<bool, Error> {
+                embedded_io::WriteReady::write_ready(&mut self.tx)
+            }
+        }
+
+        #[cfg(feature = "async")]
+        async_waker! {
+            Rx<$USARTX>,
+            Tx<$USARTX>,
+        }
+
+        #[cfg(feature = "async")]
+        impl AsyncInterrupt for $USARTX {
+            fn on_interrupt() {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let cr1 = usart.cr1.read();
+                let isr = usart.isr.read();
+                let rx_error = isr.pe().bit_is_set()
+                    || isr.fe().bit_is_set()
+                    || isr.nf().bit_is_set()
+                    || isr.ore().bit_is_set();
+                if cr1.rxneie().bit_is_set() && (isr.rxne().bit_is_set() || rx_error) {
+                    interrupt::free(|_| usart.cr1.modify(|_, w| w.rxneie().clear_bit()));
+                    Rx::<$USARTX>::waker().wake();
+                }
+                if (cr1.txeie().bit_is_set() && isr.txe().bit_is_set())
+                    || (cr1.tcie().bit_is_set() && isr.tc().bit_is_set())
+                {
+                    interrupt::free(|_| {
+                        usart.cr1.modify(|_, w| w.txeie().clear_bit().tcie().clear_bit())
+                    });
+                    Tx::<$USARTX>::waker().wake();
+                }
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl embedded_io_async::Read for Rx<$USARTX> {
+            /// Waits for the first byte, then returns all bytes received so far
+            async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = poll_fn(|cx| match hal::serial::Read::read(self) {
+                    Ok(byte) => Poll::Ready(Ok(byte)),
+                    Err(nb::Error::Other(err)) => Poll::Ready(Err(err)),
+                    Err(nb::Error::WouldBlock) => {
+                        Self::waker().register(cx.waker());
+                        let usart = unsafe { &(*$USARTX::ptr()) };
+                        interrupt::free(|_| usart.cr1.modify(|_, w| w.rxneie().set_bit()));
+                        Poll::Pending
+                    }
+                })
+                .await?;
+                let mut len = 1;
+                while len < buf.len() {
+                    match hal::serial::Read::read(self) {
+                        Ok(byte) => buf[len] = byte,
+                        Err(_) => break,
+                    }
+                    len += 1;
+                }
+                Ok(len)
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl embedded_io_async::Write for Tx<$USARTX> {
+            async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                for byte in buf {
+                    poll_fn(|cx| match hal::serial::Write::write(self, *byte) {
+                        Ok(()) => Poll::Ready(Ok(())),
+                        Err(nb::Error::Other(err)) => Poll::Ready(Err(err)),
+                        Err(nb::Error::WouldBlock) => {
+                            Self::waker().register(cx.waker());
+                            let usart = unsafe { &(*$USARTX::ptr()) };
+                            interrupt::free(|_| usart.cr1.modify(|_, w| w.txeie().set_bit()));
+                            Poll::Pending
+                        }
+                    })
+                    .await?;
+                }
+                Ok(buf.len())
+            }
+
+            async fn flush(&mut self) -> Result<(), Error> {
+                poll_fn(|cx| match hal::serial::Write::flush(self) {
+                    Ok(()) => Poll::Ready(Ok(())),
+                    Err(nb::Error::Other(err)) => Poll::Ready(Err(err)),
+                    Err(nb::Error::WouldBlock) => {
+                        Self::waker().register(cx.waker());
+                        let usart = unsafe { &(*$USARTX::ptr()) };
+                        interrupt::free(|_| usart.cr1.modify(|_, w| w.tcie().set_bit()));
+                        Poll::Pending
+                    }
+                })
+                .await
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<PINS> embedded_io_async::Read for Serial<$USARTX, PINS> {
+            async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                embedded_io_async::Read::read(&mut self.rx, buf).await
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<PINS> embedded_io_async::Write for Serial<$USARTX, PINS> {
+            async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                embedded_io_async::Write::write(&mut self.tx, buf).await
+            }
+
+            async fn flush(&mut self) -> Result<(), Error> {
+                embedded_io_async::Write::flush(&mut self.tx).await
+            }
+        }
+    }
+}
+
+uart!(
+    LPUART, lpuart, 256, LPUART_INTERRUPT, Some(KernelClockUser::Lpuart1),
+    tx: [
+        (PA2, AF6),
+        (PB11, AF1),
+        (PC1, AF1),
+    ],
+    rx: [
+        (PA3, AF6),
+        (PB10, AF1),
+        (PC0, AF1),
+    ],
+);
+
+uart!(
+    USART1, usart1, 1, Interrupt::USART1, Some(KernelClockUser::Usart1),
+    tx: [
+        (PA9, AF1),
+        (PB6, AF0),
+        (PC4, AF1),
+    ],
+    rx: [
+        (PA10, AF1),
+        (PB7, AF0),
+        (PC5, AF1),
+    ],
+);
+
+uart!(
+    USART2, usart2, 1, Interrupt::USART2, USART2_KERNEL,
+    tx: [
+        (PA2, AF1),
+        (PA14, AF1),
+        (PD5, AF0),
+    ],
+    rx: [
+        (PA3, AF1),
+        (PA15, AF1),
+        (PD6, AF0),
+    ],
+);
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+uart!(
+    USART3, usart3, 1, Interrupt::USART3_USART4_LPUART1, None,
+    tx: [
+        (PA5, AF4),
+        (PB2, AF4),
+        (PB8, AF4),
+        (PB10, AF4),
+        (PC4, AF1),
+        (PC10, AF1),
+        (PD8, AF1),
+    ],
+    rx: [
+        (PB0, AF4),
+        (PB9, AF4),
+        (PB11, AF4),
+        (PC5, AF1),
+        (PC11, AF1),
+        (PD9, AF1),
+    ],
+);
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+uart!(
+    USART4, usart4, 1, Interrupt::USART3_USART4_LPUART1, None,
+    tx: [
+        (PA0, AF4),
+        (PC10, AF1),
+    ],
+    rx: [
+        (PC11, AF1),
+        (PA1, AF4),
+    ],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brr_oversampling_16() {
+        assert_eq!(brr_bits(1, 16_000_000, 115_200, false).unwrap(), 139);
+        assert_eq!(brr_baudrate(1, 16_000_000, 139, false), Bps(115_107));
+    }
+
+    #[test]
+    fn brr_oversampling_8() {
+        // USARTDIV 278 = 0x116, BRR[2:0] holds 0x6 >> 1
+        assert_eq!(brr_bits(1, 16_000_000, 115_200, true).unwrap(), 0x113);
+        assert_eq!(brr_baudrate(1, 16_000_000, 0x113, true), Bps(115_107));
+    }
+
+    #[test]
+    fn brr_lpuart() {
+        assert_eq!(brr_bits(256, 16_000_000, 9_600, false).unwrap(), 426_667);
+        assert_eq!(brr_baudrate(256, 16_000_000, 426_667, false), Bps(9_599));
+        assert!(brr_bits(256, 16_000_000, 9_600, true).is_err());
+        // fck above 4096 x baud
+        assert!(brr_bits(256, 64_000_000, 9_600, false).is_err());
+    }
+
+    #[test]
+    fn brr_out_of_range() {
+        assert!(brr_bits(1, 16_000_000, 0, false).is_err());
+        assert!(brr_bits(1, 64_000_000, 300, false).is_err());
+        assert!(brr_bits(1, 1_000_000, 115_200, false).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! # Device electronic signature
+//!
+//! Factory programmed, read-only identification data: a 96 bit unique ID,
+//! the flash memory size and the package type.
+//!
+//! ```ignore
+//! let serial_number = Uid::read();
+//! let flash_kb = FlashSize::kb();
+//! ```
+use core::ptr;
+
+const UID_BASE: *const u8 = 0x1fff_7590 as *const u8;
+const FLASH_SIZE: *const u16 = 0x1fff_75e0 as *const u16;
+const PACKAGE: *const u16 = 0x1fff_7500 as *const u16;
+
+/// 96 bit unique device ID
+pub struct Uid;
+
+impl Uid {
+    /// Returns the unique ID, least significant byte first
+    pub fn read() -> [u8; 12] {
+        let mut uid = [0; 12];
+        for (i, byte) in uid.iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile(UID_BASE.add(i)) };
+        }
+        uid
+    }
+
+    /// X and Y coordinates of the die on the wafer, BCD coded
+    pub fn coordinates() -> u32 {
+        let uid = Self::read();
+        u32::from_le_bytes([uid[0], uid[1], uid[2], uid[3]])
+    }
+
+    pub fn wafer_number() -> u8 {
+        Self::read()[4]
+    }
+
+    /// Lot number, ASCII coded
+    pub fn lot_number() -> [u8; 7] {
+        let uid = Self::read();
+        let mut lot = [0; 7];
+        lot.copy_from_slice(&uid[5..]);
+        lot
+    }
+}
+
+/// Size of the main flash memory
+pub struct FlashSize;
+
+impl FlashSize {
+    pub fn kb() -> u16 {
+        unsafe { ptr::read_volatile(FLASH_SIZE) }
+    }
+
+    pub fn bytes() -> usize {
+        Self::kb() as usize * 1024
+    }
+}
+
+/// Package type
+pub struct Package;
+
+impl Package {
+    /// Package code (PKG), see the device reference manual for the encoding
+    pub fn code() -> u8 {
+        unsafe { (ptr::read_volatile(PACKAGE) & 0xf) as u8 }
+    }
+}
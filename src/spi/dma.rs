@@ -0,0 +1,192 @@
+//! # Full duplex SPI over DMA
+//!
+//! `transfer_dma` clocks the transmit buffer out and the receive buffer in on
+//! two DMA channels. Either buffer can be left out: a read only transfer
+//! clocks out `0xff` dummy words, a write only transfer discards the received
+//! words. The buffer element type selects 8 or 16 bit frames.
+//!
+//! ```ignore
+//! let transfer = spi.transfer_dma(dma.ch1, dma.ch2, Some(cmd), Some(response));
+//! let (spi, tx_ch, rx_ch, cmd, response) = transfer.wait();
+//! ```
+use crate::dma::descriptor::Word;
+use crate::dma::{DmaChannel, DmaMuxIndex, TransferDirection};
+use crate::spi::Spi;
+use crate::stm32::{SPI1, SPI2};
+use core::ptr;
+use core::sync::atomic::{self, Ordering};
+
+// CR2
+const FRXTH: u32 = 1 << 12;
+const DS_SHIFT: u32 = 8;
+const DS_MASK: u32 = 0b1111 << DS_SHIFT;
+
+/// Clocked out by read only transfers
+static DUMMY_TX: u16 = 0xffff;
+/// Collects the words of write only transfers
+static mut DUMMY_RX: u16 = 0;
+
+/// SPI frame, `u8` or `u16`
+pub trait Frame: Word {
+    /// CR2 DS and FRXTH bits
+    const CR2: u32;
+}
+
+impl Frame for u8 {
+    const CR2: u32 = 0b0111 << DS_SHIFT | FRXTH;
+}
+
+impl Frame for u16 {
+    const CR2: u32 = 0b1111 << DS_SHIFT;
+}
+
+/// Transfer in progress, holding the SPI, both channels and buffers
+pub struct DmaTransfer<SPI, PINS, TXCH, RXCH, W: 'static> {
+    spi: Spi<SPI, PINS>,
+    tx_channel: TXCH,
+    rx_channel: RXCH,
+    tx: Option<&'static [W]>,
+    rx: Option<&'static mut [W]>,
+}
+
+/// SPI, channels and buffers of a finished transfer
+pub type Released<SPI, PINS, TXCH, RXCH, W> = (
+    Spi<SPI, PINS>,
+    TXCH,
+    RXCH,
+    Option<&'static [W]>,
+    Option<&'static mut [W]>,
+);
+
+macro_rules! spi_dma {
+    ($($SPIX:ident: ($rx_req:ident, $tx_req:ident),)+) => {
+        $(
+            impl<PINS> Spi<$SPIX, PINS> {
+                /// Starts a full duplex transfer of `tx` and `rx`
+                ///
+                /// At least one buffer must be given, both must have the same length.
+                pub fn transfer_dma<TXCH, RXCH, W>(
+                    self,
+                    mut tx_channel: TXCH,
+                    mut rx_channel: RXCH,
+                    tx: Option<&'static [W]>,
+                    rx: Option<&'static mut [W]>,
+                ) -> DmaTransfer<$SPIX, PINS, TXCH, RXCH, W>
+                where
+                    TXCH: DmaChannel,
+                    RXCH: DmaChannel,
+                    W: Frame,
+                {
+                    let len = match (&tx, &rx) {
+                        (Some(tx), Some(rx)) => {
+                            assert!(tx.len() == rx.len());
+                            tx.len()
+                        }
+                        (Some(tx), None) => tx.len(),
+                        (None, Some(rx)) => rx.len(),
+                        (None, None) => panic!("no buffer"),
+                    };
+                    let spi = &self.spi;
+                    let dr = &spi.dr as *const _ as u32;
+                    spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    spi.cr2.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(DS_MASK | FRXTH) | W::CR2)
+                    });
+
+                    rx_channel.set_direction(TransferDirection::PeriphToMemory);
+                    rx_channel.set_word_size(W::SIZE);
+                    rx_channel.set_peripheral_address(dr, false);
+                    match &rx {
+                        Some(rx) => rx_channel.set_memory_address(rx.as_ptr() as u32, true),
+                        None => {
+                            let sink = ptr::addr_of_mut!(DUMMY_RX);
+                            rx_channel.set_memory_address(sink as u32, false);
+                        }
+                    }
+                    rx_channel.set_transfer_length(len);
+                    rx_channel.select_peripheral(DmaMuxIndex::$rx_req);
+
+                    tx_channel.set_direction(TransferDirection::MemoryToPeriph);
+                    tx_channel.set_word_size(W::SIZE);
+                    tx_channel.set_peripheral_address(dr, false);
+                    match &tx {
+                        Some(tx) => tx_channel.set_memory_address(tx.as_ptr() as u32, true),
+                        None => tx_channel.set_memory_address(&DUMMY_TX as *const _ as u32, false),
+                    }
+                    tx_channel.set_transfer_length(len);
+                    tx_channel.select_peripheral(DmaMuxIndex::$tx_req);
+
+                    // RXDMAEN first, TXDMAEN once the channels run, then SPE
+                    spi.cr2.modify(|_, w| w.rxdmaen().set_bit());
+                    atomic::compiler_fence(Ordering::SeqCst);
+                    rx_channel.start();
+                    tx_channel.start();
+                    spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+                    spi.cr1.modify(|_, w| w.spe().set_bit());
+
+                    DmaTransfer {
+                        spi: self,
+                        tx_channel,
+                        rx_channel,
+                        tx,
+                        rx,
+                    }
+                }
+            }
+
+            impl<PINS, TXCH, RXCH, W> DmaTransfer<$SPIX, PINS, TXCH, RXCH, W>
+            where
+                TXCH: DmaChannel,
+                RXCH: DmaChannel,
+                W: Frame,
+            {
+                /// Returns `true` once the last word was received
+                pub fn is_done(&self) -> bool {
+                    self.rx_channel.get_transfer_remaining() == 0
+                }
+
+                /// Number of words left to receive
+                pub fn remaining(&self) -> usize {
+                    self.rx_channel.get_transfer_remaining() as usize
+                }
+
+                /// Blocks until the transfer is complete
+                pub fn wait(self) -> Released<$SPIX, PINS, TXCH, RXCH, W> {
+                    while !self.is_done() {}
+                    self.finish()
+                }
+
+                /// Stops the transfer, the received words are only partly valid
+                pub fn abort(self) -> Released<$SPIX, PINS, TXCH, RXCH, W> {
+                    self.finish()
+                }
+
+                fn finish(mut self) -> Released<$SPIX, PINS, TXCH, RXCH, W> {
+                    let spi = &self.spi.spi;
+                    self.tx_channel.stop();
+                    // Let the words in the TX FIFO and shift register complete
+                    while spi.sr.read().ftlvl().bits() != 0 {}
+                    while spi.sr.read().bsy().bit_is_set() {}
+                    self.rx_channel.stop();
+                    atomic::compiler_fence(Ordering::SeqCst);
+                    spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    spi.cr2.modify(|_, w| w.rxdmaen().clear_bit().txdmaen().clear_bit());
+                    while spi.sr.read().frlvl().bits() != 0 {
+                        let _ = spi.dr.read();
+                    }
+                    // Back to the 8 bit frames of the blocking API
+                    spi.cr2.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(DS_MASK | FRXTH) | u8::CR2)
+                    });
+                    spi.cr1.modify(|_, w| w.spe().set_bit());
+                    (self.spi, self.tx_channel, self.rx_channel, self.tx, self.rx)
+                }
+            }
+        )+
+    }
+}
+
+spi_dma! {
+    SPI1: (SPI1_RX, SPI1_TX),
+    SPI2: (SPI2_RX, SPI2_TX),
+}
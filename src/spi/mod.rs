@@ -0,0 +1,471 @@
+#[cfg(feature = "async")]
+use crate::asynch::{AsyncInterrupt, AsyncWaker};
+use crate::gpio::{gpioa::*, gpiob::*, gpioc::*, gpiod::*, AltFunction, DefaultMode};
+use crate::gpio::{Alternate, AF0, AF1, AF2, AF4, AF5};
+use crate::rcc::{Enable, Rcc, Reset};
+use crate::stm32::{SPI1, SPI2};
+use crate::time::Hertz;
+#[cfg(feature = "async")]
+use core::future::poll_fn;
+use core::ptr;
+#[cfg(feature = "async")]
+use core::task::Poll;
+use hal;
+use nb;
+
+pub mod dma;
+pub mod pdm;
+pub mod slave;
+
+pub use hal::spi::{Mode, Phase, Polarity, MODE_0, MODE_1, MODE_2, MODE_3};
+
+/// SPI error
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Overrun occurred
+    Overrun,
+    /// Mode fault occurred
+    ModeFault,
+    /// CRC error
+    Crc,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::spi::Error for Error {
+    fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
+        match self {
+            Error::Overrun => embedded_hal_1::spi::ErrorKind::Overrun,
+            Error::ModeFault => embedded_hal_1::spi::ErrorKind::ModeFault,
+            Error::Crc => embedded_hal_1::spi::ErrorKind::Other,
+        }
+    }
+}
+
+/// A filler type for when the SCK pin is unnecessary
+pub struct NoSck;
+/// A filler type for when the Miso pin is unnecessary
+pub struct NoMiso;
+/// A filler type for when the Mosi pin is unnecessary
+pub struct NoMosi;
+
+pub trait Pins<SPI> {
+    fn setup(&self);
+}
+
+pub trait PinSck<SPI> {
+    fn setup(&self);
+}
+
+pub trait PinMiso<SPI> {
+    fn setup(&self);
+}
+
+pub trait PinMosi<SPI> {
+    fn setup(&self);
+}
+
+impl<SPI, SCK, MISO, MOSI> Pins<SPI> for (SCK, MISO, MOSI)
+where
+    SCK: PinSck<SPI>,
+    MISO: PinMiso<SPI>,
+    MOSI: PinMosi<SPI>,
+{
+    fn setup(&self) {
+        self.0.setup();
+        self.1.setup();
+        self.2.setup();
+    }
+}
+
+#[derive(Debug)]
+pub struct Spi<SPI, PINS> {
+    spi: SPI,
+    pins: PINS,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<SPI, PINS> embedded_hal_1::spi::ErrorType for Spi<SPI, PINS> {
+    type Error = Error;
+}
+
+pub trait SpiExt<SPI>: Sized {
+    fn spi<PINS, T>(self, pins: PINS, mode: Mode, freq: T, rcc: &mut Rcc) -> Spi<SPI, PINS>
+    where
+        PINS: Pins<SPI>,
+        T: Into<Hertz>;
+}
+
+macro_rules! spi {
+    ($SPIX:ident, $spiX:ident,
+        sck: [ $(($SCK:ident, $SCK_AF:ident),)+ ],
+        miso: [ $(($MISO:ident, $MISO_AF:ident),)+ ],
+        mosi: [ $(($MOSI:ident, $MOSI_AF:ident),)+ ],
+    ) => {
+        impl PinSck<$SPIX> for NoSck {
+            fn setup(&self) {}
+        }
+
+        impl PinMiso<$SPIX> for NoMiso {
+            fn setup(&self) {}
+        }
+
+        impl PinMosi<$SPIX> for NoMosi {
+            fn setup(&self) {}
+        }
+
+        $(
+            impl PinSck<$SPIX> for $SCK<DefaultMode> {
+                fn setup(&self) {
+                    self.set_alt_mode(AltFunction::$SCK_AF);
+                }
+            }
+
+            impl PinSck<$SPIX> for $SCK<Alternate<$SCK_AF>> {
+                fn setup(&self) {}
+            }
+        )*
+        $(
+            impl PinMiso<$SPIX> for $MISO<DefaultMode> {
+                fn setup(&self) {
+                    self.set_alt_mode(AltFunction::$MISO_AF);
+                }
+            }
+
+            impl PinMiso<$SPIX> for $MISO<Alternate<$MISO_AF>> {
+                fn setup(&self) {}
+            }
+        )*
+        $(
+            impl PinMosi<$SPIX> for $MOSI<DefaultMode> {
+                fn setup(&self) {
+                    self.set_alt_mode(AltFunction::$MOSI_AF);
+                }
+            }
+
+            impl PinMosi<$SPIX> for $MOSI<Alternate<$MOSI_AF>> {
+                fn setup(&self) {}
+            }
+        )*
+
+        impl<PINS> Spi<$SPIX, PINS> {
+            pub fn $spiX<T>(
+                spi: $SPIX,
+                pins: PINS,
+                mode: Mode,
+                speed: T,
+                rcc: &mut Rcc
+            ) -> Self
+            where
+            PINS: Pins<$SPIX>,
+            T: Into<Hertz>
+            {
+                pins.setup();
+
+                // Enable clock for SPI
+                $SPIX::enable(rcc);
+                $SPIX::reset(rcc);
+
+                // disable SS output
+                spi.cr2.write(|w| w.ssoe().clear_bit());
+
+                let spi_freq = speed.into().0;
+                let apb_freq = rcc.clocks.apb_clk.0;
+                let br = match apb_freq / spi_freq {
+                    0 => unreachable!(),
+                    1..=2 => 0b000,
+                    3..=5 => 0b001,
+                    6..=11 => 0b010,
+                    12..=23 => 0b011,
+                    24..=47 => 0b100,
+                    48..=95 => 0b101,
+                    96..=191 => 0b110,
+                    _ => 0b111,
+                };
+
+                spi.cr2.write(|w| unsafe {
+                    w.frxth().set_bit().ds().bits(0b111).ssoe().clear_bit()
+                });
+
+                spi.cr1.write(|w| unsafe {
+                    w.cpha()
+                        .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                        .cpol()
+                        .bit(mode.polarity == Polarity::IdleHigh)
+                        .mstr()
+                        .set_bit()
+                        .br()
+                        .bits(br)
+                        .lsbfirst()
+                        .clear_bit()
+                        .ssm()
+                        .set_bit()
+                        .ssi()
+                        .set_bit()
+                        .rxonly()
+                        .clear_bit()
+                        .dff()
+                        .clear_bit()
+                        .bidimode()
+                        .clear_bit()
+                        .ssi()
+                        .set_bit()
+                        .spe()
+                        .set_bit()
+                });
+
+
+                Spi { spi, pins }
+            }
+
+            pub fn release(self) -> ($SPIX, PINS) {
+                (self.spi, self.pins)
+            }
+
+            /// Disables the SPI and its clock, returns the peripheral and pins
+            pub fn release_and_disable(self, rcc: &mut Rcc) -> ($SPIX, PINS) {
+                self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                $SPIX::disable(rcc);
+                (self.spi, self.pins)
+            }
+        }
+
+        impl SpiExt<$SPIX> for $SPIX {
+            fn spi<PINS, T>(self, pins: PINS, mode: Mode, freq: T, rcc: &mut Rcc) -> Spi<$SPIX, PINS>
+            where
+                PINS: Pins<$SPIX>,
+                T: Into<Hertz>
+                {
+                    Spi::$spiX(self, pins, mode, freq, rcc)
+                }
+        }
+
+        impl<PINS> hal::spi::FullDuplex<u8> for Spi<$SPIX, PINS> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u8, Error> {
+                let sr = self.spi.sr.read();
+
+                Err(if sr.ovr().bit_is_set() {
+                    nb::Error::Other(Error::Overrun)
+                } else if sr.modf().bit_is_set() {
+                    nb::Error::Other(Error::ModeFault)
+                } else if sr.crcerr().bit_is_set() {
+                    nb::Error::Other(Error::Crc)
+                } else if sr.rxne().bit_is_set() {
+                    // NOTE(read_volatile) read only 1 byte (the svd2rust API only allows
+                    // reading a half-word)
+                    return Ok(unsafe {
+                        ptr::read_volatile(&self.spi.dr as *const _ as *const u8)
+                    });
+                } else {
+                    nb::Error::WouldBlock
+                })
+            }
+
+            fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                let sr = self.spi.sr.read();
+
+                Err(if sr.ovr().bit_is_set() {
+                    nb::Error::Other(Error::Overrun)
+                } else if sr.modf().bit_is_set() {
+                    nb::Error::Other(Error::ModeFault)
+                } else if sr.crcerr().bit_is_set() {
+                    nb::Error::Other(Error::Crc)
+                } else if sr.txe().bit_is_set() {
+                    // NOTE(write_volatile) see note above
+                    unsafe { ptr::write_volatile(ptr::addr_of!(self.spi.dr) as *mut u8, byte) }
+                    return Ok(());
+                } else {
+                    nb::Error::WouldBlock
+                })
+            }
+        }
+
+        impl<PINS> ::hal::blocking::spi::transfer::Default<u8> for Spi<$SPIX, PINS> {}
+
+        impl<PINS> ::hal::blocking::spi::write::Default<u8> for Spi<$SPIX, PINS> {}
+
+        #[cfg(feature = "embedded-hal-1")]
+        impl<PINS> Spi<$SPIX, PINS> {
+            fn exchange_blocking(&mut self, byte: u8) -> Result<u8, Error> {
+                nb::block!(hal::spi::FullDuplex::send(self, byte))?;
+                nb::block!(hal::spi::FullDuplex::read(self))
+            }
+        }
+
+        #[cfg(feature = "embedded-hal-1")]
+        impl<PINS> embedded_hal_1::spi::SpiBus<u8> for Spi<$SPIX, PINS> {
+            fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                for word in words.iter_mut() {
+                    *word = self.exchange_blocking(0)?;
+                }
+                Ok(())
+            }
+
+            fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+                for word in words {
+                    self.exchange_blocking(*word)?;
+                }
+                Ok(())
+            }
+
+            /// Clocks out zeros past the end of `write`, discards bytes past the end of `read`
+            fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+                for idx in 0..read.len().max(write.len()) {
+                    let byte = self.exchange_blocking(write.get(idx).cloned().unwrap_or(0))?;
+                    if let Some(word) = read.get_mut(idx) {
+                        *word = byte;
+                    }
+                }
+                Ok(())
+            }
+
+            fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                for word in words.iter_mut() {
+                    *word = self.exchange_blocking(*word)?;
+                }
+                Ok(())
+            }
+
+            fn flush(&mut self) -> Result<(), Error> {
+                while self.spi.sr.read().bsy().bit_is_set() {}
+                Ok(())
+            }
+        }
+
+        #[cfg(feature = "async")]
+        async_waker! {
+            $SPIX,
+        }
+
+        #[cfg(feature = "async")]
+        impl AsyncInterrupt for $SPIX {
+            fn on_interrupt() {
+                let spi = unsafe { &(*$SPIX::ptr()) };
+                spi.cr2.modify(|_, w| w.txeie().clear_bit().rxneie().clear_bit());
+                <$SPIX as AsyncWaker>::waker().wake();
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<PINS> Spi<$SPIX, PINS> {
+            async fn exchange(&mut self, byte: u8) -> Result<u8, Error> {
+                poll_fn(|cx| match hal::spi::FullDuplex::send(self, byte) {
+                    Ok(()) => Poll::Ready(Ok(())),
+                    Err(nb::Error::Other(err)) => Poll::Ready(Err(err)),
+                    Err(nb::Error::WouldBlock) => {
+                        <$SPIX as AsyncWaker>::waker().register(cx.waker());
+                        self.spi.cr2.modify(|_, w| w.txeie().set_bit());
+                        Poll::Pending
+                    }
+                })
+                .await?;
+                poll_fn(|cx| match hal::spi::FullDuplex::read(self) {
+                    Ok(byte) => Poll::Ready(Ok(byte)),
+                    Err(nb::Error::Other(err)) => Poll::Ready(Err(err)),
+                    Err(nb::Error::WouldBlock) => {
+                        <$SPIX as AsyncWaker>::waker().register(cx.waker());
+                        self.spi.cr2.modify(|_, w| w.rxneie().set_bit());
+                        Poll::Pending
+                    }
+                })
+                .await
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<PINS> embedded_hal_async::spi::SpiBus<u8> for Spi<$SPIX, PINS> {
+            async fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                for word in words.iter_mut() {
+                    *word = self.exchange(0).await?;
+                }
+                Ok(())
+            }
+
+            async fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+                for word in words {
+                    self.exchange(*word).await?;
+                }
+                Ok(())
+            }
+
+            /// Clocks out zeros past the end of `write`, discards bytes past the end of `read`
+            async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+                for idx in 0..read.len().max(write.len()) {
+                    let byte = self.exchange(write.get(idx).cloned().unwrap_or(0)).await?;
+                    if let Some(word) = read.get_mut(idx) {
+                        *word = byte;
+                    }
+                }
+                Ok(())
+            }
+
+            async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                for word in words.iter_mut() {
+                    *word = self.exchange(*word).await?;
+                }
+                Ok(())
+            }
+
+            async fn flush(&mut self) -> Result<(), Error> {
+                while self.spi.sr.read().bsy().bit_is_set() {}
+                Ok(())
+            }
+        }
+    }
+}
+
+spi!(
+    SPI1,
+    spi1,
+    sck: [
+        (PA1, AF0),
+        (PA5, AF0),
+        (PB3, AF0),
+        (PD8, AF1),
+    ],
+    miso: [
+        (PA6, AF0),
+        (PA11, AF0),
+        (PB4, AF0),
+        (PD5, AF1),
+    ],
+    mosi: [
+        (PA2, AF0),
+        (PA7, AF0),
+        (PA12, AF0),
+        (PB5, AF0),
+        (PD6, AF1),
+    ],
+);
+
+spi!(
+    SPI2,
+    spi2,
+    sck: [
+        (PA0, AF0),
+        (PB8, AF1),
+        (PB10, AF5),
+        (PB13, AF0),
+        (PD1, AF1),
+    ],
+    miso: [
+        (PA3, AF0),
+        (PA9, AF4),
+        (PB2, AF2),
+        (PB6, AF4),
+        (PB14, AF0),
+        (PC2, AF1),
+        (PD3, AF1),
+    ],
+    mosi: [
+        (PA4, AF1),
+        (PA10, AF0),
+        (PB7, AF1),
+        (PB11, AF0),
+        (PB15, AF0),
+        (PC3, AF1),
+        (PD4, AF1),
+    ],
+);
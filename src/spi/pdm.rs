@@ -0,0 +1,220 @@
+//! # PDM microphone capture
+//!
+//! The SPI master runs receive only and clocks the microphone on SCK, the
+//! bit stream is sampled on MISO. A circular DMA transfer fills a byte
+//! buffer, the DMA half and full transfer interrupts hand the completed half
+//! to a fourth order CIC decimator producing 16 bit PCM. Without the DMA
+//! interrupt, `poll()` from the main loop picks up completed halves.
+//!
+//! The PCM sample rate is the SPI bit clock divided by the decimation ratio.
+//! 16kHz audio with 64x decimation needs a 1.024MHz bit clock, e.g. APB
+//! at 32.768MHz divided by 32.
+use crate::dma::{DmaChannel, DmaMuxIndex, Event, TransferDirection, WordSize};
+use crate::spi::Spi;
+use crate::stm32::{SPI1, SPI2};
+use core::slice;
+use core::sync::atomic::{self, Ordering};
+use void::Void;
+
+const ORDER: usize = 4;
+
+/// Fixed-point CIC decimation filter
+///
+/// Integrators run at the PDM bit rate with wrapping 32 bit arithmetic, the
+/// combs at the output rate. Output is scaled to full 16 bit range.
+pub struct Cic {
+    integrators: [i32; ORDER],
+    combs: [i32; ORDER],
+    decimation: u32,
+    count: u32,
+    shift: u32,
+}
+
+impl Cic {
+    /// Creates a filter, `decimation` must be a power of two from 16 to 128
+    pub fn new(decimation: u32) -> Self {
+        assert!(decimation.is_power_of_two() && (16..=128).contains(&decimation));
+        // Gain is decimation^ORDER, keep 15 bits plus sign
+        let shift = ORDER as u32 * decimation.trailing_zeros() - 15;
+        Cic {
+            integrators: [0; ORDER],
+            combs: [0; ORDER],
+            decimation,
+            count: 0,
+            shift,
+        }
+    }
+
+    /// Clears filter state
+    pub fn reset(&mut self) {
+        self.integrators = [0; ORDER];
+        self.combs = [0; ORDER];
+        self.count = 0;
+    }
+
+    /// Decimates PDM bytes, MSB first, into PCM samples
+    ///
+    /// Returns number of samples written. Samples that don't fit into `pcm`
+    /// are dropped, it should hold `pdm.len() * 8 / decimation` samples.
+    pub fn process(&mut self, pdm: &[u8], pcm: &mut [i16]) -> usize {
+        let mut written = 0;
+        for &byte in pdm {
+            for bit in (0..8).rev() {
+                let x = if byte & (1 << bit) != 0 { 1 } else { -1 };
+                self.integrators[0] = self.integrators[0].wrapping_add(x);
+                self.integrators[1] = self.integrators[1].wrapping_add(self.integrators[0]);
+                self.integrators[2] = self.integrators[2].wrapping_add(self.integrators[1]);
+                self.integrators[3] = self.integrators[3].wrapping_add(self.integrators[2]);
+
+                self.count += 1;
+                if self.count < self.decimation {
+                    continue;
+                }
+                self.count = 0;
+
+                let mut y = self.integrators[ORDER - 1];
+                for comb in self.combs.iter_mut() {
+                    let delayed = *comb;
+                    *comb = y;
+                    y = y.wrapping_sub(delayed);
+                }
+
+                if let Some(sample) = pcm.get_mut(written) {
+                    let y = y >> self.shift;
+                    *sample = if y > i16::MAX as i32 { i16::MAX } else { y as i16 };
+                    written += 1;
+                }
+            }
+        }
+        written
+    }
+}
+
+/// PDM microphone capture in progress
+pub struct PdmMic<SPI, PINS, CHANNEL> {
+    spi: Spi<SPI, PINS>,
+    channel: CHANNEL,
+    buffer: &'static mut [u8],
+    cic: Cic,
+    // offset of the next half to decimate
+    next: usize,
+}
+
+impl<SPI, PINS, CHANNEL: DmaChannel> PdmMic<SPI, PINS, CHANNEL> {
+    /// Decimates the half of the buffer that was just captured
+    ///
+    /// Call from the DMA channel interrupt. Returns number of PCM samples
+    /// written to `pcm`, a quarter of the buffer length in bits divided by
+    /// the decimation ratio.
+    pub fn on_interrupt(&mut self, pcm: &mut [i16]) -> usize {
+        self.channel.clear_interrupts();
+        let half = self.buffer.len() / 2;
+        let remaining = self.channel.get_transfer_remaining() as usize;
+        // DMA fills the first half while more than half of the bytes remain
+        let offset = if remaining > half { half } else { 0 };
+        self.decimate(offset, pcm)
+    }
+
+    /// Decimates a captured half of the buffer, without the DMA interrupt
+    ///
+    /// Must be called at least once per half buffer period.
+    pub fn poll(&mut self, pcm: &mut [i16]) -> nb::Result<usize, Void> {
+        let half = self.buffer.len() / 2;
+        let remaining = self.channel.get_transfer_remaining() as usize;
+        let active = if remaining > half { 0 } else { half };
+        if active == self.next {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(self.decimate(self.next, pcm))
+    }
+
+    /// Number of PCM samples delivered per interrupt
+    pub fn frame_len(&self) -> usize {
+        self.buffer.len() / 2 * 8 / self.cic.decimation as usize
+    }
+
+    fn decimate(&mut self, offset: usize, pcm: &mut [i16]) -> usize {
+        let half = self.buffer.len() / 2;
+        self.next = half - offset;
+        atomic::compiler_fence(Ordering::SeqCst);
+        let pdm = unsafe { slice::from_raw_parts(self.buffer.as_ptr().add(offset), half) };
+        self.cic.process(pdm, pcm)
+    }
+}
+
+macro_rules! pdm {
+    ($($SPIX:ident: $rx_req:ident,)+) => {
+        $(
+            impl<PINS> Spi<$SPIX, PINS> {
+                /// Starts clocking a PDM microphone
+                ///
+                /// SPI should be configured with 8 bit frames and no MOSI pin, its
+                /// frequency sets the PDM bit clock. The buffer length must be even.
+                pub fn pdm<CHANNEL>(
+                    self,
+                    mut channel: CHANNEL,
+                    buffer: &'static mut [u8],
+                    decimation: u32,
+                ) -> PdmMic<$SPIX, PINS, CHANNEL>
+                where
+                    CHANNEL: DmaChannel,
+                {
+                    assert!(buffer.len() >= 2 && buffer.len() % 2 == 0);
+                    let spi = &self.spi;
+                    spi.cr1.modify(|_, w| w.spe().clear_bit());
+
+                    channel.set_direction(TransferDirection::PeriphToMemory);
+                    channel.set_word_size(WordSize::Bits8);
+                    channel.set_peripheral_address(&spi.dr as *const _ as u32, false);
+                    channel.set_memory_address(buffer.as_ptr() as u32, true);
+                    channel.set_transfer_length(buffer.len());
+                    channel.set_circular_mode(true);
+                    channel.select_peripheral(DmaMuxIndex::$rx_req);
+                    channel.clear_interrupts();
+                    channel.listen(Event::HalfTransfer);
+                    channel.listen(Event::TransferComplete);
+                    atomic::compiler_fence(Ordering::SeqCst);
+                    channel.start();
+
+                    // Receive only master clocks continuously once enabled
+                    spi.cr2.modify(|_, w| w.rxdmaen().set_bit());
+                    spi.cr1.modify(|_, w| w.rxonly().set_bit());
+                    spi.cr1.modify(|_, w| w.spe().set_bit());
+
+                    PdmMic {
+                        spi: self,
+                        channel,
+                        buffer,
+                        cic: Cic::new(decimation),
+                        next: 0,
+                    }
+                }
+            }
+
+            impl<PINS, CHANNEL: DmaChannel> PdmMic<$SPIX, PINS, CHANNEL> {
+                /// Stops capture, returning SPI, DMA channel and buffer
+                pub fn release(mut self) -> (Spi<$SPIX, PINS>, CHANNEL, &'static mut [u8]) {
+                    let spi = &self.spi.spi;
+                    // Receive only mode stops the clock when SPE is cleared
+                    spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.channel.stop();
+                    self.channel.unlisten(Event::HalfTransfer);
+                    self.channel.unlisten(Event::TransferComplete);
+                    self.channel.set_circular_mode(false);
+                    spi.cr2.modify(|_, w| w.rxdmaen().clear_bit());
+                    spi.cr1.modify(|_, w| w.rxonly().clear_bit());
+                    while spi.sr.read().frlvl().bits() != 0 {
+                        let _ = spi.dr.read();
+                    }
+                    spi.cr1.modify(|_, w| w.spe().set_bit());
+                    (self.spi, self.channel, self.buffer)
+                }
+            }
+        )+
+    }
+}
+
+pdm! {
+    SPI1: SPI1_RX,
+    SPI2: SPI2_RX,
+}
@@ -0,0 +1,294 @@
+//! # SPI slave with NSS framing
+//!
+//! The slave is selected by the hardware NSS pin and every NSS assertion
+//! carries one frame. DMA receives the frame into one of two buffers and
+//! clocks out the reply buffer. The application listens for the NSS rising
+//! edge on EXTI and calls `end_of_frame()` from the handler: the completed
+//! frame is handed over and DMA is re-armed with the other receive buffer.
+//! Reply buffers are swapped the same way, so a new reply never corrupts the
+//! frame in flight.
+//!
+//! Frames longer than the receive buffer are truncated.
+use crate::dma::{DmaChannel, DmaMuxIndex, TransferDirection, WordSize};
+use crate::gpio::{gpioa::*, gpiob::*, gpiod::*, AltFunction, Alternate, Input};
+use crate::gpio::{AF0, AF1, AF5};
+use crate::mailbox::Transport;
+use crate::rcc::Rcc;
+use crate::spi::{Mode, Phase, PinMiso, PinMosi, PinSck, Polarity};
+use crate::stm32::{RCC, SPI1, SPI2};
+use core::sync::atomic::{self, Ordering};
+use void::Void;
+
+pub trait PinNss<SPI> {
+    fn setup(&self);
+}
+
+pub trait SlavePins<SPI> {
+    fn setup(&self);
+}
+
+impl<SPI, SCK, MISO, MOSI, NSS> SlavePins<SPI> for (SCK, MISO, MOSI, NSS)
+where
+    SCK: PinSck<SPI>,
+    MISO: PinMiso<SPI>,
+    MOSI: PinMosi<SPI>,
+    NSS: PinNss<SPI>,
+{
+    fn setup(&self) {
+        self.0.setup();
+        self.1.setup();
+        self.2.setup();
+        self.3.setup();
+    }
+}
+
+/// SPI slave without DMA
+pub struct SpiSlave<SPI, PINS> {
+    spi: SPI,
+    pins: PINS,
+}
+
+/// SPI slave exchanging NSS delimited frames over DMA
+pub struct FrameSlave<SPI, PINS, RXCH, TXCH> {
+    spi: SPI,
+    pins: PINS,
+    rx_channel: RXCH,
+    tx_channel: TXCH,
+    rx: [&'static mut [u8]; 2],
+    active: usize,
+    tx: [&'static mut [u8]; 2],
+    tx_active: usize,
+    reply_pending: bool,
+}
+
+pub trait SpiSlaveExt<SPI>: Sized {
+    fn spi_slave<PINS>(self, pins: PINS, mode: Mode, rcc: &mut Rcc) -> SpiSlave<SPI, PINS>
+    where
+        PINS: SlavePins<SPI>;
+}
+
+macro_rules! spi_slave {
+    ($SPIX:ident, $spiX_slave:ident, $apbXenr:ident, $apbXrst:ident, $spiXen:ident, $spiXrst:ident,
+        $rx_req:ident, $tx_req:ident,
+        nss: [ $(($NSS:ident, $NSS_AF:ident),)+ ],
+    ) => {
+        $(
+            impl<MODE> PinNss<$SPIX> for $NSS<Input<MODE>> {
+                fn setup(&self) {
+                    self.set_alt_mode(AltFunction::$NSS_AF);
+                }
+            }
+
+            impl PinNss<$SPIX> for $NSS<Alternate<$NSS_AF>> {
+                fn setup(&self) {}
+            }
+        )+
+
+        impl SpiSlaveExt<$SPIX> for $SPIX {
+            fn spi_slave<PINS>(self, pins: PINS, mode: Mode, rcc: &mut Rcc) -> SpiSlave<$SPIX, PINS>
+            where
+                PINS: SlavePins<$SPIX>,
+            {
+                SpiSlave::$spiX_slave(self, pins, mode, rcc)
+            }
+        }
+
+        impl<PINS> SpiSlave<$SPIX, PINS> {
+            pub fn $spiX_slave(spi: $SPIX, pins: PINS, mode: Mode, rcc: &mut Rcc) -> Self
+            where
+                PINS: SlavePins<$SPIX>,
+            {
+                pins.setup();
+
+                rcc.rb.$apbXenr.modify(|_, w| w.$spiXen().set_bit());
+                rcc.rb.$apbXrst.modify(|_, w| w.$spiXrst().set_bit());
+                rcc.rb.$apbXrst.modify(|_, w| w.$spiXrst().clear_bit());
+
+                spi.cr2.write(|w| unsafe { w.frxth().set_bit().ds().bits(0b111) });
+                // hardware NSS, slave mode
+                spi.cr1.write(|w| {
+                    w.cpha()
+                        .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                        .cpol()
+                        .bit(mode.polarity == Polarity::IdleHigh)
+                        .mstr()
+                        .clear_bit()
+                        .ssm()
+                        .clear_bit()
+                });
+
+                SpiSlave { spi, pins }
+            }
+
+            /// Starts frame exchange
+            ///
+            /// `rx` buffers are filled alternately, the active `tx` buffer is clocked
+            /// out in every frame.
+            pub fn frames<RXCH, TXCH>(
+                self,
+                mut rx_channel: RXCH,
+                mut tx_channel: TXCH,
+                rx: [&'static mut [u8]; 2],
+                tx: [&'static mut [u8]; 2],
+            ) -> FrameSlave<$SPIX, PINS, RXCH, TXCH>
+            where
+                RXCH: DmaChannel,
+                TXCH: DmaChannel,
+            {
+                assert!(rx[0].len() == rx[1].len() && !rx[0].is_empty());
+                assert!(tx[0].len() == tx[1].len() && !tx[0].is_empty());
+                let dr = &self.spi.dr as *const _ as u32;
+
+                rx_channel.set_direction(TransferDirection::PeriphToMemory);
+                rx_channel.set_word_size(WordSize::Bits8);
+                rx_channel.set_peripheral_address(dr, false);
+                rx_channel.select_peripheral(DmaMuxIndex::$rx_req);
+
+                tx_channel.set_direction(TransferDirection::MemoryToPeriph);
+                tx_channel.set_word_size(WordSize::Bits8);
+                tx_channel.set_peripheral_address(dr, false);
+                tx_channel.select_peripheral(DmaMuxIndex::$tx_req);
+
+                let mut slave = FrameSlave {
+                    spi: self.spi,
+                    pins: self.pins,
+                    rx_channel,
+                    tx_channel,
+                    rx,
+                    active: 0,
+                    tx,
+                    tx_active: 0,
+                    reply_pending: false,
+                };
+                slave.arm();
+                slave
+            }
+
+            pub fn release(self) -> ($SPIX, PINS) {
+                (self.spi, self.pins)
+            }
+        }
+
+        impl<PINS, RXCH, TXCH> FrameSlave<$SPIX, PINS, RXCH, TXCH>
+        where
+            RXCH: DmaChannel,
+            TXCH: DmaChannel,
+        {
+            /// Completes current frame, call on NSS rising edge
+            ///
+            /// Returns received frame, valid until the next call.
+            pub fn end_of_frame(&mut self) -> &[u8] {
+                let remaining = self.rx_channel.get_transfer_remaining() as usize;
+                let received = self.rx[self.active].len() - remaining;
+                self.rx_channel.stop();
+                self.tx_channel.stop();
+                atomic::compiler_fence(Ordering::SeqCst);
+
+                // FIFOs are flushed by the peripheral reset only
+                let cr1 = self.spi.cr1.read().bits() & !(1 << 6);
+                let cr2 = self.spi.cr2.read().bits() & !0b11;
+                let rcc = unsafe { &(*RCC::ptr()) };
+                rcc.$apbXrst.modify(|_, w| w.$spiXrst().set_bit());
+                rcc.$apbXrst.modify(|_, w| w.$spiXrst().clear_bit());
+                self.spi.cr2.write(|w| unsafe { w.bits(cr2) });
+                self.spi.cr1.write(|w| unsafe { w.bits(cr1) });
+
+                let done = self.active;
+                self.active ^= 1;
+                if self.reply_pending {
+                    self.tx_active ^= 1;
+                    self.reply_pending = false;
+                }
+                self.arm();
+                &self.rx[done][..received]
+            }
+
+            /// Sets reply for the next frames, padded with zeros
+            ///
+            /// Takes effect on the next `end_of_frame()`.
+            pub fn set_reply(&mut self, reply: &[u8]) {
+                let tx = &mut self.tx[self.tx_active ^ 1];
+                let len = reply.len().min(tx.len());
+                tx[..len].copy_from_slice(&reply[..len]);
+                for byte in tx[len..].iter_mut() {
+                    *byte = 0;
+                }
+                self.reply_pending = true;
+            }
+
+            /// Returns `true` while NSS is asserted and data is transferred
+            pub fn is_busy(&self) -> bool {
+                self.spi.sr.read().bsy().bit_is_set()
+            }
+
+            /// Stops frame exchange and releases resources
+            pub fn release(
+                mut self,
+            ) -> ($SPIX, PINS, RXCH, TXCH, [&'static mut [u8]; 2], [&'static mut [u8]; 2]) {
+                self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                self.rx_channel.stop();
+                self.tx_channel.stop();
+                self.spi.cr2.modify(|_, w| w.rxdmaen().clear_bit().txdmaen().clear_bit());
+                (self.spi, self.pins, self.rx_channel, self.tx_channel, self.rx, self.tx)
+            }
+
+            fn arm(&mut self) {
+                let rx = &self.rx[self.active];
+                let tx = &self.tx[self.tx_active];
+                self.spi.cr2.modify(|_, w| w.rxdmaen().set_bit());
+                self.rx_channel.set_memory_address(rx.as_ptr() as u32, true);
+                self.rx_channel.set_transfer_length(rx.len());
+                self.tx_channel.set_memory_address(tx.as_ptr() as u32, true);
+                self.tx_channel.set_transfer_length(tx.len());
+                atomic::compiler_fence(Ordering::SeqCst);
+                self.rx_channel.start();
+                self.tx_channel.start();
+                self.spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+                self.spi.cr1.modify(|_, w| w.spe().set_bit());
+            }
+        }
+
+        impl<PINS, RXCH, TXCH> Transport for FrameSlave<$SPIX, PINS, RXCH, TXCH>
+        where
+            RXCH: DmaChannel,
+            TXCH: DmaChannel,
+        {
+            type Error = Void;
+
+            /// Reply is clocked out after the next `recv_frame()`
+            fn send_frame(&mut self, frame: &[u8]) -> Result<(), Void> {
+                self.set_reply(frame);
+                Ok(())
+            }
+
+            /// Completes current frame, call on NSS rising edge only
+            fn recv_frame(&mut self, buf: &mut [u8]) -> nb::Result<usize, Void> {
+                let frame = self.end_of_frame();
+                let len = frame.len().min(buf.len());
+                buf[..len].copy_from_slice(&frame[..len]);
+                Ok(len)
+            }
+        }
+    };
+}
+
+spi_slave!(
+    SPI1, spi1_slave, apbenr2, apbrstr2, spi1en, spi1rst,
+    SPI1_RX, SPI1_TX,
+    nss: [
+        (PA4, AF0),
+        (PA15, AF0),
+        (PB0, AF0),
+        (PD9, AF1),
+    ],
+);
+
+spi_slave!(
+    SPI2, spi2_slave, apbenr1, apbrstr1, spi2en, spi2rst,
+    SPI2_RX, SPI2_TX,
+    nss: [
+        (PB9, AF5),
+        (PB12, AF0),
+        (PD0, AF1),
+    ],
+);
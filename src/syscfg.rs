@@ -0,0 +1,110 @@
+//! # System configuration controller
+//!
+//! On small packages (e.g. TSSOP20, SO8) PA11 and PA12 share a pad with PA9
+//! and PA10. The remap connects PA11/PA12 to these pads, the PA9/PA10 GPIO
+//! functions are then lost.
+use crate::rcc::Rcc;
+#[cfg(any(
+    feature = "stm32g030",
+    feature = "stm32g031",
+    feature = "stm32g041"
+))]
+use crate::stm32::SYSCFG;
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+use crate::stm32::SYSCFG_VREFBUF as SYSCFG;
+
+// CFGR1
+const PA11_RMP: u32 = 1 << 3;
+const PA12_RMP: u32 = 1 << 4;
+const IR_POL: u32 = 1 << 5;
+const IR_MOD_SHIFT: u32 = 6;
+const IR_MOD_MASK: u32 = 0b11 << IR_MOD_SHIFT;
+const BOOSTEN: u32 = 1 << 8;
+
+/// IR modulation envelope source (IR_MOD)
+///
+/// The envelope is ANDed with the TIM17 carrier on the IR_OUT pin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IrEnvelope {
+    Tim16 = 0b00,
+    Usart1 = 0b01,
+    #[cfg(any(
+        feature = "stm32g030",
+        feature = "stm32g031",
+        feature = "stm32g041"
+    ))]
+    Usart2 = 0b10,
+    #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+    Usart4 = 0b10,
+}
+
+/// System configuration controller
+pub struct SysCfg {
+    rb: SYSCFG,
+}
+
+impl SysCfg {
+    pub fn new(syscfg: SYSCFG, rcc: &mut Rcc) -> Self {
+        rcc.rb.apbenr2.modify(|_, w| w.syscfgen().set_bit());
+        SysCfg { rb: syscfg }
+    }
+
+    /// Connects PA11 to the PA9 pad
+    pub fn remap_pa11(&mut self, remap: bool) {
+        self.set(PA11_RMP, remap);
+    }
+
+    /// Connects PA12 to the PA10 pad
+    pub fn remap_pa12(&mut self, remap: bool) {
+        self.set(PA12_RMP, remap);
+    }
+
+    pub fn is_pa11_remapped(&self) -> bool {
+        self.rb.cfgr1.read().bits() & PA11_RMP != 0
+    }
+
+    pub fn is_pa12_remapped(&self) -> bool {
+        self.rb.cfgr1.read().bits() & PA12_RMP != 0
+    }
+
+    /// Selects the IR envelope source and the IR_OUT polarity
+    pub fn set_ir_modulation(&mut self, envelope: IrEnvelope, inverted: bool) {
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            let bits = r.bits() & !(IR_MOD_MASK | IR_POL);
+            w.bits(bits | (envelope as u32) << IR_MOD_SHIFT | if inverted { IR_POL } else { 0 })
+        });
+    }
+
+    /// Enables the I/O analog switch voltage booster
+    ///
+    /// Keeps the analog switch performance of ADC and comparator inputs when
+    /// VDDA is below 2.4V, at the cost of extra supply current.
+    pub fn set_analog_booster(&mut self, enable: bool) {
+        self.set(BOOSTEN, enable);
+    }
+
+    pub fn release(self) -> SYSCFG {
+        self.rb
+    }
+
+    fn set(&mut self, mask: u32, enable: bool) {
+        self.rb.cfgr1.modify(|r, w| unsafe {
+            w.bits(if enable {
+                r.bits() | mask
+            } else {
+                r.bits() & !mask
+            })
+        });
+    }
+}
+
+pub trait SysCfgExt {
+    fn constrain(self, rcc: &mut Rcc) -> SysCfg;
+}
+
+impl SysCfgExt for SYSCFG {
+    fn constrain(self, rcc: &mut Rcc) -> SysCfg {
+        SysCfg::new(self, rcc)
+    }
+}
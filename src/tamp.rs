@@ -0,0 +1,189 @@
+//! # Tamper and backup registers
+//!
+//! TAMP holds five 32 bit backup registers in the backup domain, they keep
+//! their contents in Standby and on VBAT, e.g. to pass a reset reason to a
+//! bootloader. A tamper event on TAMP_IN1 (PC13) or TAMP_IN2 (PA0) erases
+//! them unless erasing is disabled for that input.
+use crate::rcc::Rcc;
+use crate::stm32::TAMP;
+
+/// Number of backup registers
+pub const BACKUP_REGISTERS: usize = 5;
+
+/// BKP0R offset in words
+const BKP0R: usize = 0x100 / 4;
+
+/// Tamper input
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TamperPin {
+    /// TAMP_IN1 on PC13
+    Tamp1 = 0,
+    /// TAMP_IN2 on PA0
+    Tamp2 = 1,
+}
+
+/// Tamper detection
+///
+/// Level detection uses the filter configured with `set_filter()`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    RisingEdge,
+    FallingEdge,
+    LowLevel,
+    HighLevel,
+}
+
+/// Consecutive samples at the active level for level detection
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Filter {
+    /// Edge detection
+    Edge = 0,
+    Samples2 = 1,
+    Samples4 = 2,
+    Samples8 = 3,
+}
+
+/// Input sampling frequency for level detection
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SampleFrequency {
+    RtcClkDiv32768 = 0,
+    RtcClkDiv16384 = 1,
+    RtcClkDiv8192 = 2,
+    RtcClkDiv4096 = 3,
+    RtcClkDiv2048 = 4,
+    RtcClkDiv1024 = 5,
+    RtcClkDiv512 = 6,
+    RtcClkDiv256 = 7,
+}
+
+/// Tamper interrupt events
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    Tamper1 = 1 << 0,
+    Tamper2 = 1 << 1,
+}
+
+/// Tamper and backup registers
+pub struct Tamp {
+    rb: TAMP,
+}
+
+impl Tamp {
+    /// Enables access to the backup domain
+    pub fn new(tamp: TAMP, rcc: &mut Rcc) -> Self {
+        rcc.rb.apbenr1.modify(|_, w| w.rtcapben().set_bit());
+        rcc.unlock_rtc();
+        Tamp { rb: tamp }
+    }
+
+    /// Reads backup register `idx`
+    pub fn read_backup(&self, idx: usize) -> u32 {
+        assert!(idx < BACKUP_REGISTERS);
+        unsafe { core::ptr::read_volatile(backup_register(idx)) }
+    }
+
+    /// Writes backup register `idx`
+    pub fn write_backup(&mut self, idx: usize, value: u32) {
+        assert!(idx < BACKUP_REGISTERS);
+        unsafe { core::ptr::write_volatile(backup_register(idx), value) }
+    }
+
+    /// Enables tamper detection on `pin`
+    ///
+    /// With `erase` cleared the backup registers survive tamper events on
+    /// this input, which are only flagged.
+    pub fn enable_tamper(&mut self, pin: TamperPin, trigger: Trigger, erase: bool) {
+        let bit = 1 << pin as u32;
+        self.disable_tamper(pin);
+        // TAMPxTRG selects falling edge or high level
+        let trg = match trigger {
+            Trigger::RisingEdge | Trigger::LowLevel => 0,
+            Trigger::FallingEdge | Trigger::HighLevel => bit << 24,
+        };
+        let noer = if erase { 0 } else { bit };
+        self.rb.cr2.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(bit << 24 | bit << 16 | bit) | trg | noer)
+        });
+        self.rb.cr1.modify(|r, w| unsafe { w.bits(r.bits() | bit) });
+    }
+
+    /// Disables tamper detection on `pin`
+    pub fn disable_tamper(&mut self, pin: TamperPin) {
+        self.rb
+            .cr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << pin as u32)) });
+    }
+
+    /// Configures level detection for both inputs
+    ///
+    /// `precharge` is the input precharge duration in RTCCLK cycles, 1, 2,
+    /// 4 or 8, and is ignored with `pull_up` cleared.
+    pub fn set_filter(
+        &mut self,
+        filter: Filter,
+        freq: SampleFrequency,
+        precharge: u8,
+        pull_up: bool,
+    ) {
+        let prch = match precharge {
+            0..=1 => 0,
+            2 => 1,
+            3..=4 => 2,
+            _ => 3,
+        };
+        // TAMPPUDIS disables the precharge
+        let pudis = if pull_up { 0 } else { 1 << 7 };
+        self.rb.fltcr.write(|w| unsafe {
+            w.bits(freq as u32 | (filter as u32) << 3 | prch << 5 | pudis)
+        });
+    }
+
+    /// Starts listening for an interrupt event
+    ///
+    /// Tamper events are routed to EXTI line 21.
+    pub fn listen(&mut self, event: Event) {
+        self.rb
+            .ier
+            .modify(|r, w| unsafe { w.bits(r.bits() | event as u32) });
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        self.rb
+            .ier
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(event as u32)) });
+    }
+
+    /// Returns `true` if `event` is pending
+    pub fn is_pending(&self, event: Event) -> bool {
+        self.rb.sr.read().bits() & event as u32 != 0
+    }
+
+    /// Clears a pending event
+    pub fn unpend(&mut self, event: Event) {
+        self.rb.scr.write(|w| unsafe { w.bits(event as u32) });
+    }
+
+    pub fn release(self) -> TAMP {
+        self.rb
+    }
+}
+
+pub trait TampExt {
+    fn constrain(self, rcc: &mut Rcc) -> Tamp;
+}
+
+impl TampExt for TAMP {
+    fn constrain(self, rcc: &mut Rcc) -> Tamp {
+        Tamp::new(self, rcc)
+    }
+}
+
+fn backup_register(idx: usize) -> *mut u32 {
+    unsafe { (TAMP::ptr() as *mut u32).add(BKP0R + idx) }
+}
@@ -0,0 +1,189 @@
+//! # Thermal shutdown
+//!
+//! `ThermalGuard` samples a temperature sensor, disables the guarded PWM
+//! outputs once the trip threshold is reached and enables them again after
+//! the temperature fell below the release threshold. Call `poll` from a
+//! periodic timer interrupt or the main loop.
+use crate::analog::adc::Adc;
+use crate::timer::pwm::{ComplementaryPwmPin, PwmPin};
+use hal::adc::{Channel, OneShot};
+
+/// Temperature source in degrees Celsius
+pub trait TemperatureSensor {
+    fn read_temperature(&mut self, adc: &mut Adc) -> i16;
+}
+
+/// Internal junction temperature sensor
+pub struct InternalSensor;
+
+impl TemperatureSensor for InternalSensor {
+    fn read_temperature(&mut self, adc: &mut Adc) -> i16 {
+        adc.read_temperature()
+    }
+}
+
+/// External NTC on an analog pin
+///
+/// `curve` converts the raw conversion result to degrees Celsius, usually
+/// a lookup table for the NTC and divider in use.
+pub struct Ntc<PIN, F> {
+    pin: PIN,
+    curve: F,
+}
+
+impl<PIN, F> Ntc<PIN, F>
+where
+    PIN: Channel<Adc, ID = u8>,
+    F: FnMut(u16) -> i16,
+{
+    pub fn new(pin: PIN, curve: F) -> Self {
+        Ntc { pin, curve }
+    }
+
+    pub fn release(self) -> PIN {
+        self.pin
+    }
+}
+
+impl<PIN, F> TemperatureSensor for Ntc<PIN, F>
+where
+    PIN: Channel<Adc, ID = u8>,
+    F: FnMut(u16) -> i16,
+{
+    fn read_temperature(&mut self, adc: &mut Adc) -> i16 {
+        let raw: u16 = nb::block!(adc.read(&mut self.pin)).unwrap_or(0);
+        (self.curve)(raw)
+    }
+}
+
+/// Output disabled on thermal shutdown
+pub trait Shutdown {
+    fn shutdown(&mut self);
+    fn restore(&mut self);
+}
+
+impl<TIM, CH, PIN> Shutdown for PwmPin<TIM, CH, PIN>
+where
+    PwmPin<TIM, CH, PIN>: hal::PwmPin,
+{
+    fn shutdown(&mut self) {
+        hal::PwmPin::disable(self);
+    }
+
+    fn restore(&mut self) {
+        hal::PwmPin::enable(self);
+    }
+}
+
+impl<TIM, CH, PIN, NPIN> Shutdown for ComplementaryPwmPin<TIM, CH, PIN, NPIN>
+where
+    ComplementaryPwmPin<TIM, CH, PIN, NPIN>: hal::PwmPin,
+{
+    fn shutdown(&mut self) {
+        hal::PwmPin::disable(self);
+    }
+
+    fn restore(&mut self) {
+        hal::PwmPin::enable(self);
+    }
+}
+
+macro_rules! shutdown_tuple {
+    ($(($($T:ident: $idx:tt),+),)+) => {
+        $(
+            impl<$($T: Shutdown),+> Shutdown for ($($T,)+) {
+                fn shutdown(&mut self) {
+                    $(self.$idx.shutdown();)+
+                }
+
+                fn restore(&mut self) {
+                    $(self.$idx.restore();)+
+                }
+            }
+        )+
+    };
+}
+
+shutdown_tuple! {
+    (A: 0, B: 1),
+    (A: 0, B: 1, C: 2),
+    (A: 0, B: 1, C: 2, D: 3),
+}
+
+/// Thermal state change
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ThermalEvent {
+    /// Outputs were disabled at the given temperature
+    Tripped(i16),
+    /// Outputs were enabled again at the given temperature
+    Restored(i16),
+}
+
+/// Disables outputs above a temperature threshold with hysteresis
+pub struct ThermalGuard<S, O> {
+    sensor: S,
+    outputs: O,
+    trip: i16,
+    release: i16,
+    temperature: i16,
+    tripped: bool,
+}
+
+impl<S, O> ThermalGuard<S, O>
+where
+    S: TemperatureSensor,
+    O: Shutdown,
+{
+    /// Guards `outputs`, tripping at `trip` and releasing at `release` degrees Celsius
+    pub fn new(sensor: S, outputs: O, trip: i16, release: i16) -> Self {
+        assert!(release < trip);
+        ThermalGuard {
+            sensor,
+            outputs,
+            trip,
+            release,
+            temperature: 0,
+            tripped: false,
+        }
+    }
+
+    /// Samples the sensor and updates the outputs
+    pub fn poll(&mut self, adc: &mut Adc) -> Option<ThermalEvent> {
+        let temperature = self.sensor.read_temperature(adc);
+        self.temperature = temperature;
+        if !self.tripped && temperature >= self.trip {
+            self.tripped = true;
+            self.outputs.shutdown();
+            Some(ThermalEvent::Tripped(temperature))
+        } else if self.tripped && temperature <= self.release {
+            self.tripped = false;
+            self.outputs.restore();
+            Some(ThermalEvent::Restored(temperature))
+        } else {
+            None
+        }
+    }
+
+    /// Last sampled temperature in degrees Celsius
+    pub fn temperature(&self) -> i16 {
+        self.temperature
+    }
+
+    /// Returns `true` while the outputs are shut down
+    pub fn is_tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// Guarded outputs, e.g. to update duty cycles
+    ///
+    /// Outputs must not be enabled while the guard is tripped.
+    pub fn outputs(&mut self) -> &mut O {
+        &mut self.outputs
+    }
+
+    /// Releases sensor and outputs, outputs are left as they are
+    pub fn release(self) -> (S, O) {
+        (self.sensor, self.outputs)
+    }
+}
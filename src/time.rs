@@ -2,53 +2,71 @@ use core::ops::{Add, Div};
 
 /// A measurement of a monotonically nondecreasing clock
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Instant(pub u32);
 
 /// Baudrate
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Bps(pub u32);
 
 /// Hertz
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Hertz(pub u32);
 
 /// Microseconds
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct MicroSecond(pub u32);
 
+/// Milliseconds
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MilliSecond(pub u32);
+
 /// Seconds
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Second(pub u32);
 
 /// Minutes
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Minute(pub u32);
 
 /// Hours
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Hour(pub u32);
 
 /// WeekDay (1-7)
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct WeekDay(pub u32);
 
 /// Date (1-31)
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct MonthDay(pub u32);
 
 /// Week (1-52)
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Week(pub u32);
 
 /// Month (1-12)
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Month(pub u32);
 
 /// Year
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Year(pub u32);
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Time {
     pub hours: u32,
     pub minutes: u32,
@@ -68,6 +86,7 @@ impl Time {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Date {
     pub day: u32,
     pub month: u32,
@@ -103,6 +122,9 @@ pub trait U32Ext {
     /// Wrap in `MicroSecond`
     fn ms(self) -> MicroSecond;
 
+    /// Wrap in `MilliSecond`
+    fn millis(self) -> MilliSecond;
+
     /// Seconds
     fn seconds(self) -> Second;
 
@@ -149,6 +171,10 @@ impl U32Ext for u32 {
         MicroSecond(self)
     }
 
+    fn millis(self) -> MilliSecond {
+        MilliSecond(self)
+    }
+
     fn seconds(self) -> Second {
         Second(self)
     }
@@ -232,9 +258,21 @@ impl Into<Hertz> for MicroSecond {
     }
 }
 
+impl Into<MicroSecond> for MilliSecond {
+    fn into(self) -> MicroSecond {
+        MicroSecond(self.0.saturating_mul(1_000))
+    }
+}
+
+impl Into<MilliSecond> for Second {
+    fn into(self) -> MilliSecond {
+        MilliSecond(self.0.saturating_mul(1_000))
+    }
+}
+
 impl Into<MicroSecond> for Second {
     fn into(self) -> MicroSecond {
-        MicroSecond(self.0 * 1000)
+        MicroSecond(self.0.saturating_mul(1_000_000))
     }
 }
 
@@ -0,0 +1,223 @@
+//! # PWM audio
+//!
+//! Plays samples on a high frequency PWM channel. A circular DMA burst
+//! reloads the compare register on every update event while the application
+//! refills the half of the sample buffer that was just played from the DMA
+//! interrupt, or from `poll()` in the main loop. An RC low-pass filter on
+//! the pin turns the PWM into a simple DAC for alert tones and short voice
+//! prompts.
+//!
+//! Sample rate is the PWM frequency divided by `repetition + 1`, e.g. 8-bit
+//! samples at 8kHz on a 64MHz timer clock: 250kHz PWM, `repetition = 30`.
+use core::pin::Pin;
+use core::slice;
+
+use crate::dma::{DmaChannel, Event};
+use crate::stm32::*;
+use crate::timer::burst::{BurstChannel, DmaBurst};
+use crate::timer::pwm::PwmPin;
+use crate::timer::*;
+use hal::PwmPin as _;
+use void::Void;
+
+/// Audio sample format
+pub trait Sample: Copy {
+    /// Scales sample to PWM duty cycle
+    fn to_duty(self, max_duty: u16) -> u16;
+}
+
+impl Sample for u8 {
+    fn to_duty(self, max_duty: u16) -> u16 {
+        ((self as u32 * (max_duty as u32 + 1)) >> 8) as u16
+    }
+}
+
+impl Sample for u16 {
+    fn to_duty(self, max_duty: u16) -> u16 {
+        ((self as u32 * (max_duty as u32 + 1)) >> 16) as u16
+    }
+}
+
+impl Sample for i16 {
+    fn to_duty(self, max_duty: u16) -> u16 {
+        (((self as i32 + 0x8000) as u32 * (max_duty as u32 + 1)) >> 16) as u16
+    }
+}
+
+/// Half of the sample buffer to be refilled
+pub struct Block<'a> {
+    duty: &'a mut [u16],
+    pos: usize,
+    max_duty: u16,
+}
+
+impl<'a> Block<'a> {
+    /// Number of samples that still fit into the block
+    pub fn remaining(&self) -> usize {
+        self.duty.len() - self.pos
+    }
+
+    /// Appends samples, returns number of samples written
+    pub fn write<S: Sample>(&mut self, samples: &[S]) -> usize {
+        let count = samples.len().min(self.remaining());
+        for (duty, sample) in self.duty[self.pos..].iter_mut().zip(samples) {
+            *duty = sample.to_duty(self.max_duty);
+        }
+        self.pos += count;
+        count
+    }
+
+    /// Fills the rest of the block with silence
+    pub fn fill_silence(&mut self) {
+        let silence = self.max_duty / 2;
+        for duty in self.duty[self.pos..].iter_mut() {
+            *duty = silence;
+        }
+        self.pos = self.duty.len();
+    }
+}
+
+/// Audio playback in progress
+pub struct Audio<TIM, CH, CHANNEL, PIN> {
+    burst: DmaBurst<TIM, CHANNEL>,
+    pin: PwmPin<TIM, CH, PIN>,
+    buffer: *mut u16,
+    len: usize,
+    max_duty: u16,
+    // offset of the next half to refill
+    next: usize,
+}
+
+// The buffer is exclusively borrowed for 'static by the playback
+unsafe impl<TIM, CH, CHANNEL: Send, PIN: Send> Send for Audio<TIM, CH, CHANNEL, PIN> {}
+
+impl<TIM, CH, CHANNEL: DmaChannel, PIN> Audio<TIM, CH, CHANNEL, PIN> {
+    /// Refills the half of the buffer that was just played
+    ///
+    /// Call from the DMA channel interrupt. Samples not written by `refill`
+    /// are replaced by silence.
+    pub fn on_interrupt<F>(&mut self, refill: F)
+    where
+        F: FnOnce(&mut Block),
+    {
+        self.burst.channel.clear_interrupts();
+        let half = self.len / 2;
+        let remaining = self.burst.channel.get_transfer_remaining() as usize;
+        // DMA plays the first half while more than half of the samples remain
+        let offset = if remaining > half { half } else { 0 };
+        self.fill_half(offset, refill);
+    }
+
+    /// Refills a played half of the buffer, without the DMA interrupt
+    ///
+    /// Must be called at least once per half buffer period.
+    pub fn poll<F>(&mut self, refill: F) -> nb::Result<(), Void>
+    where
+        F: FnOnce(&mut Block),
+    {
+        let half = self.len / 2;
+        let remaining = self.burst.channel.get_transfer_remaining() as usize;
+        let active = if remaining > half { 0 } else { half };
+        if active == self.next {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.fill_half(self.next, refill);
+        Ok(())
+    }
+
+    fn fill_half<F>(&mut self, offset: usize, refill: F)
+    where
+        F: FnOnce(&mut Block),
+    {
+        let half = self.len / 2;
+        self.next = half - offset;
+        let duty = unsafe { slice::from_raw_parts_mut(self.buffer.add(offset), half) };
+        let mut block = Block {
+            duty,
+            pos: 0,
+            max_duty: self.max_duty,
+        };
+        refill(&mut block);
+        block.fill_silence();
+    }
+}
+
+macro_rules! audio {
+    ($($TIM:ident: [$($CH:ident),+],)+) => {
+        $(
+            $(
+                impl<PIN> PwmPin<$TIM, $CH, PIN> {
+                    /// Starts audio playback from a circular buffer of duty cycles
+                    ///
+                    /// The buffer length must be even, it starts out silent. Each
+                    /// sample is held for `repetition + 1` PWM periods.
+                    pub fn audio<CHANNEL>(
+                        mut self,
+                        mut channel: CHANNEL,
+                        buffer: &'static mut [u16],
+                        repetition: u8,
+                    ) -> Audio<$TIM, $CH, CHANNEL, PIN>
+                    where
+                        CHANNEL: DmaChannel,
+                    {
+                        assert!(buffer.len() >= 2 && buffer.len() % 2 == 0);
+                        let max_duty = self.get_max_duty();
+                        for duty in buffer.iter_mut() {
+                            *duty = max_duty / 2;
+                        }
+                        self.set_duty(max_duty / 2);
+                        self.enable();
+
+                        let tim = unsafe { &*$TIM::ptr() };
+                        tim.rcr.write(|w| unsafe { w.bits(repetition as u32) });
+                        tim.egr.write(|w| w.ug().set_bit());
+
+                        let (ptr, len) = (buffer.as_mut_ptr(), buffer.len());
+                        let samples: &'static [u16] = unsafe { slice::from_raw_parts(ptr, len) };
+                        channel.clear_interrupts();
+                        channel.listen(Event::HalfTransfer);
+                        channel.listen(Event::TransferComplete);
+                        let ccr = <$CH as BurstChannel>::CCR;
+                        let transfer = DmaBurst::<$TIM, CHANNEL>::start(
+                            channel,
+                            ccr,
+                            1,
+                            Pin::new(samples),
+                            true,
+                        );
+                        Audio {
+                            burst: transfer.channel,
+                            pin: self,
+                            buffer: ptr,
+                            len,
+                            max_duty,
+                            next: 0,
+                        }
+                    }
+                }
+
+                impl<CHANNEL: DmaChannel, PIN> Audio<$TIM, $CH, CHANNEL, PIN> {
+                    /// Stops playback, returning PWM pin, DMA channel and buffer
+                    pub fn release(self) -> (PwmPin<$TIM, $CH, PIN>, CHANNEL, &'static mut [u16]) {
+                        let mut channel = crate::timer::burst::BurstControl::release(self.burst);
+                        channel.unlisten(Event::HalfTransfer);
+                        channel.unlisten(Event::TransferComplete);
+                        let buffer = unsafe { slice::from_raw_parts_mut(self.buffer, self.len) };
+                        (self.pin, channel, buffer)
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+audio! {
+    TIM1: [Channel1, Channel2, Channel3, Channel4],
+    TIM16: [Channel1],
+    TIM17: [Channel1],
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+audio! {
+    TIM15: [Channel1],
+}
@@ -0,0 +1,247 @@
+//! # Timer DMA burst
+//!
+//! DMA burst mode lets a DMA channel rewrite a block of timer registers
+//! (e.g. ARR, CCRx) through the DMAR register on every update event, so PWM
+//! waveforms can be played back entirely in hardware.
+use core::marker::PhantomData;
+use core::ops;
+use core::pin::Pin;
+use core::sync::atomic::{self, Ordering};
+
+use as_slice::AsSlice;
+
+use crate::dma::{DmaChannel, DmaMuxIndex, Transfer, TransferDirection, WordSize};
+use crate::stm32::*;
+use crate::timer::pwm::{Pwm, PwmPin};
+use crate::timer::*;
+
+/// First timer register updated by a DMA burst
+#[derive(Clone, Copy, PartialEq)]
+pub enum BurstRegister {
+    Cr1 = 0,
+    Cr2 = 1,
+    Smcr = 2,
+    Dier = 3,
+    Sr = 4,
+    Egr = 5,
+    Ccmr1 = 6,
+    Ccmr2 = 7,
+    Ccer = 8,
+    Cnt = 9,
+    Psc = 10,
+    Arr = 11,
+    Rcr = 12,
+    Ccr1 = 13,
+    Ccr2 = 14,
+    Ccr3 = 15,
+    Ccr4 = 16,
+    Bdtr = 17,
+}
+
+/// Timer channel compare register
+pub trait BurstChannel {
+    const CCR: BurstRegister;
+}
+
+impl BurstChannel for Channel1 {
+    const CCR: BurstRegister = BurstRegister::Ccr1;
+}
+
+impl BurstChannel for Channel2 {
+    const CCR: BurstRegister = BurstRegister::Ccr2;
+}
+
+impl BurstChannel for Channel3 {
+    const CCR: BurstRegister = BurstRegister::Ccr3;
+}
+
+impl BurstChannel for Channel4 {
+    const CCR: BurstRegister = BurstRegister::Ccr4;
+}
+
+/// DMA burst in progress
+pub struct DmaBurst<TIM, CHANNEL> {
+    pub(crate) channel: CHANNEL,
+    tim: PhantomData<TIM>,
+}
+
+/// PWM waveform playback in progress
+pub struct Waveform<TIM, CH, CHANNEL, PIN> {
+    burst: DmaBurst<TIM, CHANNEL>,
+    pin: PwmPin<TIM, CH, PIN>,
+}
+
+impl<TIM, CH, CHANNEL, PIN> Waveform<TIM, CH, CHANNEL, PIN>
+where
+    DmaBurst<TIM, CHANNEL>: BurstControl<CHANNEL>,
+{
+    /// Returns `true` once all samples were loaded
+    pub fn is_done(&self) -> bool {
+        self.burst.is_done()
+    }
+
+    /// Stops playback, returning PWM pin and DMA channel
+    pub fn release(self) -> (PwmPin<TIM, CH, PIN>, CHANNEL) {
+        (self.pin, self.burst.release())
+    }
+}
+
+/// DMA burst control
+pub trait BurstControl<CHANNEL> {
+    /// Returns `true` once the whole buffer was transferred
+    fn is_done(&self) -> bool;
+
+    /// Stops DMA requests, returning the DMA channel
+    fn release(self) -> CHANNEL;
+}
+
+macro_rules! dma_burst {
+    ($($TIM:ident: ($req:ident, [$($CH:ident),+]),)+) => {
+        $(
+            impl<CHANNEL: DmaChannel> BurstControl<CHANNEL> for DmaBurst<$TIM, CHANNEL> {
+                fn is_done(&self) -> bool {
+                    self.channel.get_transfer_remaining() == 0
+                }
+
+                fn release(mut self) -> CHANNEL {
+                    let tim = unsafe { &*$TIM::ptr() };
+                    tim.dier.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 8)) });
+                    self.channel.stop();
+                    self.channel
+                }
+            }
+
+            impl Pwm<$TIM> {
+                /// Rewrites `count` consecutive registers starting at `base` on every update event
+                ///
+                /// The buffer holds `count` half-words per update event.
+                pub fn dma_burst<CHANNEL, B>(
+                    &mut self,
+                    channel: CHANNEL,
+                    base: BurstRegister,
+                    count: u8,
+                    buffer: Pin<B>,
+                    circular: bool,
+                ) -> Transfer<DmaBurst<$TIM, CHANNEL>, Pin<B>>
+                where
+                    CHANNEL: DmaChannel,
+                    B: ops::Deref + 'static,
+                    B::Target: AsSlice<Element = u16>,
+                {
+                    DmaBurst::<$TIM, CHANNEL>::start(channel, base, count, buffer, circular)
+                }
+            }
+
+            impl<CHANNEL: DmaChannel> DmaBurst<$TIM, CHANNEL> {
+                pub(crate) fn start<B>(
+                    mut channel: CHANNEL,
+                    base: BurstRegister,
+                    count: u8,
+                    buffer: Pin<B>,
+                    circular: bool,
+                ) -> Transfer<Self, Pin<B>>
+                where
+                    B: ops::Deref + 'static,
+                    B::Target: AsSlice<Element = u16>,
+                {
+                    assert!(count > 0 && count <= 18);
+                    let tim = unsafe { &*$TIM::ptr() };
+                    let slice = buffer.as_slice();
+                    let (ptr, len) = (slice.as_ptr(), slice.len());
+
+                    tim.dcr.write(|w| unsafe {
+                        w.bits(((count as u32 - 1) << 8) | base as u32)
+                    });
+                    channel.set_direction(TransferDirection::MemoryToPeriph);
+                    channel.set_circular_mode(circular);
+                    channel.set_word_size(WordSize::Bits16);
+                    channel.select_peripheral(DmaMuxIndex::$req);
+                    channel.set_peripheral_address(&tim.dmar as *const _ as u32, false);
+                    channel.set_memory_address(ptr as u32, true);
+                    channel.set_transfer_length(len);
+
+                    atomic::compiler_fence(Ordering::SeqCst);
+                    channel.start();
+                    tim.dier.modify(|r, w| unsafe { w.bits(r.bits() | 1 << 8) });
+
+                    Transfer {
+                        buffer,
+                        channel: DmaBurst {
+                            channel,
+                            tim: PhantomData,
+                        },
+                    }
+                }
+            }
+
+            $(
+                impl<PIN> PwmPin<$TIM, $CH, PIN> {
+                    /// Plays back duty cycle samples, one per PWM period
+                    ///
+                    /// The last sample stays active once the playback is done.
+                    pub fn dma_waveform<CHANNEL, B>(
+                        self,
+                        channel: CHANNEL,
+                        buffer: Pin<B>,
+                    ) -> Transfer<Waveform<$TIM, $CH, CHANNEL, PIN>, Pin<B>>
+                    where
+                        CHANNEL: DmaChannel,
+                        B: ops::Deref + 'static,
+                        B::Target: AsSlice<Element = u16>,
+                    {
+                        self.waveform(channel, buffer, false)
+                    }
+
+                    /// Plays back duty cycle samples in a loop
+                    pub fn dma_waveform_repeat<CHANNEL, B>(
+                        self,
+                        channel: CHANNEL,
+                        buffer: Pin<B>,
+                    ) -> Transfer<Waveform<$TIM, $CH, CHANNEL, PIN>, Pin<B>>
+                    where
+                        CHANNEL: DmaChannel,
+                        B: ops::Deref + 'static,
+                        B::Target: AsSlice<Element = u16>,
+                    {
+                        self.waveform(channel, buffer, true)
+                    }
+
+                    fn waveform<CHANNEL, B>(
+                        self,
+                        channel: CHANNEL,
+                        buffer: Pin<B>,
+                        circular: bool,
+                    ) -> Transfer<Waveform<$TIM, $CH, CHANNEL, PIN>, Pin<B>>
+                    where
+                        CHANNEL: DmaChannel,
+                        B: ops::Deref + 'static,
+                        B::Target: AsSlice<Element = u16>,
+                    {
+                        let ccr = <$CH as BurstChannel>::CCR;
+                        let transfer = DmaBurst::<$TIM, CHANNEL>::start(channel, ccr, 1, buffer, circular);
+                        Transfer {
+                            buffer: transfer.buffer,
+                            channel: Waveform {
+                                burst: transfer.channel,
+                                pin: self,
+                            },
+                        }
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+dma_burst! {
+    TIM1: (TIM1_UP, [Channel1, Channel2, Channel3, Channel4]),
+    TIM2: (TIM2_UP, [Channel1, Channel2, Channel3, Channel4]),
+    TIM3: (TIM3_UP, [Channel1, Channel2, Channel3, Channel4]),
+    TIM16: (TIM16_UP, [Channel1]),
+    TIM17: (TIM17_UP, [Channel1]),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+dma_burst! {
+    TIM15: (TIM15_UP, [Channel1]),
+}
@@ -0,0 +1,370 @@
+//! # Input Capture
+use core::marker::PhantomData;
+
+use crate::gpio::SignalEdge;
+use crate::rcc::Rcc;
+use crate::stm32::*;
+use crate::time::{Hertz, MicroSecond, U32Ext};
+use crate::timer::pins::TimerPin;
+use crate::timer::*;
+use void::Void;
+
+/// Input capture prescaler
+#[derive(Clone, Copy, PartialEq)]
+pub enum Prescaler {
+    /// Capture on every edge
+    Div1 = 0b00,
+    /// Capture once every 2 edges
+    Div2 = 0b01,
+    /// Capture once every 4 edges
+    Div4 = 0b10,
+    /// Capture once every 8 edges
+    Div8 = 0b11,
+}
+
+/// Input capture timer
+pub struct InputCapture<TIM> {
+    clk: Hertz,
+    tim: PhantomData<TIM>,
+}
+
+/// Input capture channel
+pub struct CapturePin<TIM, CH> {
+    tim: PhantomData<TIM>,
+    channel: PhantomData<CH>,
+}
+
+/// PWM input: measures frequency and duty cycle of a signal on channel 1
+pub struct PwmInput<TIM, PIN> {
+    clk: Hertz,
+    tim: TIM,
+    pin: PIN,
+}
+
+pub trait CaptureExt: Sized {
+    /// Configures timer as a free running counter with the given tick `resolution`
+    fn capture<T>(self, resolution: T, rcc: &mut Rcc) -> InputCapture<Self>
+    where
+        T: Into<Hertz>;
+}
+
+pub trait PwmInputExt: Sized {
+    /// Configures timer to measure an external PWM signal with the given tick `resolution`
+    fn pwm_input<PIN, T>(self, pin: PIN, resolution: T, rcc: &mut Rcc) -> PwmInput<Self, PIN>
+    where
+        PIN: TimerPin<Self, Channel = Channel1>,
+        T: Into<Hertz>;
+}
+
+impl<TIM> InputCapture<TIM> {
+    pub fn bind_pin<PIN>(&self, pin: PIN) -> CapturePin<TIM, PIN::Channel>
+    where
+        PIN: TimerPin<TIM>,
+    {
+        pin.setup();
+        CapturePin {
+            tim: PhantomData,
+            channel: PhantomData,
+        }
+    }
+
+    /// Counter tick frequency
+    pub fn clock(&self) -> Hertz {
+        self.clk
+    }
+}
+
+macro_rules! capture {
+    ($($TIMX:ident: ($timX:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $max:expr),)+) => {
+        $(
+            impl CaptureExt for $TIMX {
+                fn capture<T>(self, resolution: T, rcc: &mut Rcc) -> InputCapture<Self>
+                where
+                    T: Into<Hertz>,
+                {
+                    $timX(self, resolution, rcc)
+                }
+            }
+
+            fn $timX<T>(tim: $TIMX, resolution: T, rcc: &mut Rcc) -> InputCapture<$TIMX>
+            where
+                T: Into<Hertz>,
+            {
+                rcc.rb.$apbenr.modify(|_, w| w.$timXen().set_bit());
+                rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().set_bit());
+                rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().clear_bit());
+
+                let psc = rcc.clocks.apb_tim_clk / resolution.into();
+                assert!(psc > 0 && psc <= 0x1_0000);
+                tim.psc.write(|w| unsafe { w.psc().bits((psc - 1) as u16) });
+                tim.arr.write(|w| unsafe { w.bits($max) });
+                tim.egr.write(|w| w.ug().set_bit());
+                tim.cr1.write(|w| w.cen().set_bit());
+
+                InputCapture {
+                    clk: (rcc.clocks.apb_tim_clk.0 / psc).hz(),
+                    tim: PhantomData,
+                }
+            }
+
+            impl InputCapture<$TIMX> {
+                /// Gets timer counter current value
+                pub fn get_current(&self) -> u32 {
+                    unsafe { (*$TIMX::ptr()).cnt.read().bits() & $max }
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! capture_hal {
+    ($($TIMX:ident:
+        ($CH:ty, $ccmrx_input:ident, $ccxs:ident, $offset:expr,
+            $ccxe:ident, $ccxp:ident, $ccxnp:ident, $ccxie:ident, $ccxif:ident, $ccxof:ident, $ccrx:ident),)+
+    ) => {
+        $(
+            impl CapturePin<$TIMX, $CH> {
+                /// Enables capture on the given edge
+                pub fn enable(&mut self, edge: SignalEdge) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    let (p, np) = match edge {
+                        SignalEdge::Rising => (false, false),
+                        SignalEdge::Falling => (true, false),
+                        SignalEdge::All => (true, true),
+                    };
+                    tim.ccer.modify(|_, w| w.$ccxe().clear_bit());
+                    tim.$ccmrx_input().modify(|_, w| unsafe { w.$ccxs().bits(0b01) });
+                    tim.ccer.modify(|_, w| w.$ccxp().bit(p).$ccxnp().bit(np).$ccxe().set_bit());
+                }
+
+                /// Disables capture
+                pub fn disable(&mut self) {
+                    unsafe {
+                        (*$TIMX::ptr()).ccer.modify(|_, w| w.$ccxe().clear_bit());
+                    }
+                }
+
+                /// Sets input filter (0 - no filter, 15 - strongest filtering)
+                pub fn set_filter(&mut self, filter: u8) {
+                    assert!(filter < 16);
+                    // ICxF, not generated for all timers by the PAC
+                    let shift = $offset + 4;
+                    unsafe {
+                        (*$TIMX::ptr()).$ccmrx_input().modify(|r, w| {
+                            w.bits(r.bits() & !(0b1111 << shift) | (filter as u32) << shift)
+                        });
+                    }
+                }
+
+                /// Sets input capture prescaler
+                pub fn set_prescaler(&mut self, psc: Prescaler) {
+                    // ICxPSC, named differently across timers by the PAC
+                    let shift = $offset + 2;
+                    unsafe {
+                        (*$TIMX::ptr()).$ccmrx_input().modify(|r, w| {
+                            w.bits(r.bits() & !(0b11 << shift) | (psc as u32) << shift)
+                        });
+                    }
+                }
+
+                /// Starts listening for capture events
+                pub fn listen(&mut self) {
+                    unsafe {
+                        (*$TIMX::ptr()).dier.modify(|_, w| w.$ccxie().set_bit());
+                    }
+                }
+
+                /// Stops listening for capture events
+                pub fn unlisten(&mut self) {
+                    unsafe {
+                        (*$TIMX::ptr()).dier.modify(|_, w| w.$ccxie().clear_bit());
+                    }
+                }
+
+                /// Clears capture and overcapture flags
+                pub fn clear_irq(&mut self) {
+                    unsafe {
+                        (*$TIMX::ptr()).sr.modify(|_, w| w.$ccxif().clear_bit().$ccxof().clear_bit());
+                    }
+                }
+
+                /// Returns `true` if a capture was lost since the last read
+                pub fn is_overcaptured(&self) -> bool {
+                    unsafe { (*$TIMX::ptr()).sr.read().$ccxof().bit_is_set() }
+                }
+
+                /// Returns captured counter value
+                pub fn get_capture(&mut self) -> nb::Result<u32, Void> {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    if tim.sr.read().$ccxif().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    tim.sr.modify(|_, w| w.$ccxof().clear_bit());
+                    // NOTE reading CCR clears CCxIF
+                    Ok(tim.$ccrx.read().bits())
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pwm_input {
+    ($($TIMX:ident: ($timX:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $max:expr),)+) => {
+        $(
+            impl PwmInputExt for $TIMX {
+                fn pwm_input<PIN, T>(self, pin: PIN, resolution: T, rcc: &mut Rcc) -> PwmInput<Self, PIN>
+                where
+                    PIN: TimerPin<Self, Channel = Channel1>,
+                    T: Into<Hertz>,
+                {
+                    PwmInput::$timX(self, pin, resolution, rcc)
+                }
+            }
+
+            impl<PIN> PwmInput<$TIMX, PIN> where PIN: TimerPin<$TIMX, Channel = Channel1> {
+                fn $timX<T>(tim: $TIMX, pin: PIN, resolution: T, rcc: &mut Rcc) -> Self
+                where
+                    T: Into<Hertz>,
+                {
+                    pin.setup();
+                    rcc.rb.$apbenr.modify(|_, w| w.$timXen().set_bit());
+                    rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().set_bit());
+                    rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().clear_bit());
+
+                    let psc = rcc.clocks.apb_tim_clk / resolution.into();
+                    assert!(psc > 0 && psc <= 0x1_0000);
+                    tim.psc.write(|w| unsafe { w.psc().bits((psc - 1) as u16) });
+                    tim.arr.write(|w| unsafe { w.bits($max) });
+
+                    // TI1 is mapped both on IC1 (rising edge) and IC2 (falling edge)
+                    tim.ccmr1_input().write(|w| unsafe { w.cc1s().bits(0b01).cc2s().bits(0b10) });
+                    tim.ccer.write(|w| {
+                        w.cc1p()
+                            .clear_bit()
+                            .cc1np()
+                            .clear_bit()
+                            .cc2p()
+                            .set_bit()
+                            .cc2np()
+                            .clear_bit()
+                            .cc1e()
+                            .set_bit()
+                            .cc2e()
+                            .set_bit()
+                    });
+
+                    // Reset counter on every rising edge of TI1FP1
+                    tim.smcr.write(|w| unsafe { w.ts().bits(0b101).sms().bits(0b100) });
+
+                    // Only counter overflow sets the update flag
+                    tim.cr1.write(|w| w.urs().set_bit());
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    PwmInput {
+                        clk: (rcc.clocks.apb_tim_clk.0 / psc).hz(),
+                        tim,
+                        pin,
+                    }
+                }
+
+                /// Signal period in timer ticks
+                pub fn period_ticks(&self) -> u32 {
+                    self.tim.ccr1.read().bits() & $max
+                }
+
+                /// Signal high time in timer ticks
+                pub fn high_ticks(&self) -> u32 {
+                    self.tim.ccr2.read().bits() & $max
+                }
+
+                /// Returns `true` if no edge was detected during a full counter period
+                pub fn is_signal_lost(&mut self) -> bool {
+                    let lost = self.tim.sr.read().uif().bit_is_set();
+                    self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    lost
+                }
+
+                /// Signal frequency
+                pub fn frequency(&self) -> Option<Hertz> {
+                    match self.period_ticks() {
+                        0 => None,
+                        period => Some((self.clk.0 / period).hz()),
+                    }
+                }
+
+                /// Signal period
+                pub fn period(&self) -> MicroSecond {
+                    self.clk.duration(self.period_ticks())
+                }
+
+                /// Signal pulse width
+                pub fn pulse_width(&self) -> MicroSecond {
+                    self.clk.duration(self.high_ticks())
+                }
+
+                /// Duty cycle in permille
+                pub fn duty_cycle(&self) -> Option<u16> {
+                    match self.period_ticks() {
+                        0 => None,
+                        period => Some((self.high_ticks() as u64 * 1000 / period as u64) as u16),
+                    }
+                }
+
+                /// Counter tick frequency
+                pub fn clock(&self) -> Hertz {
+                    self.clk
+                }
+
+                /// Releases the TIM peripheral and pin
+                pub fn release(self) -> ($TIMX, PIN) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pin)
+                }
+            }
+        )+
+    };
+}
+
+capture_hal! {
+    TIM1: (Channel1, ccmr1_input, cc1s, 0, cc1e, cc1p, cc1np, cc1ie, cc1if, cc1of, ccr1),
+    TIM1: (Channel2, ccmr1_input, cc2s, 8, cc2e, cc2p, cc2np, cc2ie, cc2if, cc2of, ccr2),
+    TIM1: (Channel3, ccmr2_input, cc3s, 0, cc3e, cc3p, cc3np, cc3ie, cc3if, cc3of, ccr3),
+    TIM1: (Channel4, ccmr2_input, cc4s, 8, cc4e, cc4p, cc4np, cc4ie, cc4if, cc4of, ccr4),
+    TIM2: (Channel1, ccmr1_input, cc1s, 0, cc1e, cc1p, cc1np, cc1ie, cc1if, cc1of, ccr1),
+    TIM2: (Channel2, ccmr1_input, cc2s, 8, cc2e, cc2p, cc2np, cc2ie, cc2if, cc2of, ccr2),
+    TIM2: (Channel3, ccmr2_input, cc3s, 0, cc3e, cc3p, cc3np, cc3ie, cc3if, cc3of, ccr3),
+    TIM2: (Channel4, ccmr2_input, cc4s, 8, cc4e, cc4p, cc4np, cc4ie, cc4if, cc4of, ccr4),
+    TIM3: (Channel1, ccmr1_input, cc1s, 0, cc1e, cc1p, cc1np, cc1ie, cc1if, cc1of, ccr1),
+    TIM3: (Channel2, ccmr1_input, cc2s, 8, cc2e, cc2p, cc2np, cc2ie, cc2if, cc2of, ccr2),
+    TIM3: (Channel3, ccmr2_input, cc3s, 0, cc3e, cc3p, cc3np, cc3ie, cc3if, cc3of, ccr3),
+    TIM3: (Channel4, ccmr2_input, cc4s, 8, cc4e, cc4p, cc4np, cc4ie, cc4if, cc4of, ccr4),
+    TIM14: (Channel1, ccmr1_input, cc1s, 0, cc1e, cc1p, cc1np, cc1ie, cc1if, cc1of, ccr1),
+    TIM16: (Channel1, ccmr1_input, cc1s, 0, cc1e, cc1p, cc1np, cc1ie, cc1if, cc1of, ccr1),
+    TIM17: (Channel1, ccmr1_input, cc1s, 0, cc1e, cc1p, cc1np, cc1ie, cc1if, cc1of, ccr1),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+capture_hal! {
+    TIM15: (Channel1, ccmr1_input, cc1s, 0, cc1e, cc1p, cc1np, cc1ie, cc1if, cc1of, ccr1),
+}
+
+capture! {
+    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2, 0xffff),
+    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1, 0xffff_ffff),
+    TIM3: (tim3, tim3en, tim3rst, apbenr1, apbrstr1, 0xffff),
+    TIM14: (tim14, tim14en, tim14rst, apbenr2, apbrstr2, 0xffff),
+    TIM16: (tim16, tim16en, tim16rst, apbenr2, apbrstr2, 0xffff),
+    TIM17: (tim17, tim17en, tim17rst, apbenr2, apbrstr2, 0xffff),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+capture! {
+    TIM15: (tim15, tim15en, tim15rst, apbenr2, apbrstr2, 0xffff),
+}
+
+pwm_input! {
+    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2, 0xffff),
+    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1, 0xffff_ffff),
+    TIM3: (tim3, tim3en, tim3rst, apbenr1, apbrstr1, 0xffff),
+}
@@ -0,0 +1,175 @@
+//! # LED dimmer
+//!
+//! Drives a set of PWM channels as LED zones with 16 bit perceptual
+//! brightness. Brightness is gamma corrected (γ = 2.2) before it is written
+//! as duty cycle, fades are linear in perceptual brightness and advanced by
+//! `tick()`, which should be called from a periodic timer interrupt.
+//! Zones can be assigned to groups to dim several channels at once.
+use as_slice::AsMutSlice;
+use hal::PwmPin;
+
+/// γ = 2.2 curve sampled at 33 points over the 16 bit range
+const GAMMA: [u16; 33] = [
+    0, 32, 147, 359, 676, 1104, 1648, 2314, 3104, 4022, 5072, 6255, 7574, 9033, 10632, 12375,
+    14263, 16298, 18482, 20816, 23303, 25943, 28739, 31692, 34802, 38072, 41503, 45097, 48853,
+    52774, 56860, 61114, 65535,
+];
+
+/// Gamma corrects perceptual `brightness` to linear light output
+pub fn gamma(brightness: u16) -> u16 {
+    let idx = (brightness >> 11) as usize;
+    let frac = (brightness & 0x7ff) as u32;
+    let low = GAMMA[idx] as u32;
+    let high = GAMMA[idx + 1] as u32;
+    (low + (((high - low) * frac) >> 11)) as u16
+}
+
+/// A dimmed PWM channel
+pub struct Zone<'a> {
+    pin: &'a mut dyn PwmPin<Duty = u16>,
+    group: u8,
+    // brightness in 16.16 fixed point
+    level: u32,
+    target: u16,
+    step: i32,
+    ticks: u32,
+}
+
+impl<'a> Zone<'a> {
+    /// Creates a zone belonging to `group`
+    pub fn new(pin: &'a mut dyn PwmPin<Duty = u16>, group: u8) -> Self {
+        Zone {
+            pin,
+            group,
+            level: 0,
+            target: 0,
+            step: 0,
+            ticks: 0,
+        }
+    }
+
+    /// Current perceptual brightness
+    pub fn brightness(&self) -> u16 {
+        (self.level >> 16) as u16
+    }
+
+    pub fn group(&self) -> u8 {
+        self.group
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.ticks > 0
+    }
+
+    fn set(&mut self, brightness: u16) {
+        self.level = (brightness as u32) << 16;
+        self.target = brightness;
+        self.ticks = 0;
+        self.update();
+    }
+
+    fn fade(&mut self, brightness: u16, ticks: u32) {
+        if ticks == 0 {
+            self.set(brightness);
+            return;
+        }
+        let delta = ((brightness as i64) << 16) - self.level as i64;
+        self.target = brightness;
+        self.step = (delta / ticks as i64) as i32;
+        self.ticks = ticks;
+    }
+
+    fn tick(&mut self) {
+        if self.ticks == 0 {
+            return;
+        }
+        self.ticks -= 1;
+        if self.ticks == 0 {
+            self.level = (self.target as u32) << 16;
+        } else {
+            self.level = (self.level as i64 + self.step as i64) as u32;
+        }
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let max = self.pin.get_max_duty() as u32;
+        let duty = gamma(self.brightness()) as u32 * max / 0xffff;
+        self.pin.set_duty(duty as u16);
+    }
+}
+
+/// Multi-zone LED dimmer
+pub struct LedDimmer<ZONES> {
+    zones: ZONES,
+    tick_ms: u32,
+}
+
+impl<'a, ZONES> LedDimmer<ZONES>
+where
+    ZONES: AsMutSlice<Element = Zone<'a>>,
+{
+    /// Takes over `zones`, `tick()` is called every `tick_ms` milliseconds
+    ///
+    /// All zones start dark with their outputs enabled.
+    pub fn new(mut zones: ZONES, tick_ms: u32) -> Self {
+        assert!(tick_ms > 0);
+        for zone in zones.as_mut_slice() {
+            zone.set(0);
+            zone.pin.enable();
+        }
+        LedDimmer { zones, tick_ms }
+    }
+
+    /// Sets zone brightness immediately
+    pub fn set(&mut self, zone: usize, brightness: u16) {
+        self.zones.as_mut_slice()[zone].set(brightness);
+    }
+
+    /// Fades zone to `brightness` within `duration_ms`
+    pub fn fade(&mut self, zone: usize, brightness: u16, duration_ms: u32) {
+        let ticks = duration_ms / self.tick_ms;
+        self.zones.as_mut_slice()[zone].fade(brightness, ticks);
+    }
+
+    /// Sets brightness of all zones in `group` immediately
+    pub fn set_group(&mut self, group: u8, brightness: u16) {
+        for zone in self.zones.as_mut_slice() {
+            if zone.group == group {
+                zone.set(brightness);
+            }
+        }
+    }
+
+    /// Fades all zones in `group` to `brightness` within `duration_ms`
+    pub fn fade_group(&mut self, group: u8, brightness: u16, duration_ms: u32) {
+        let ticks = duration_ms / self.tick_ms;
+        for zone in self.zones.as_mut_slice() {
+            if zone.group == group {
+                zone.fade(brightness, ticks);
+            }
+        }
+    }
+
+    /// Current perceptual brightness of a zone
+    pub fn brightness(&mut self, zone: usize) -> u16 {
+        self.zones.as_mut_slice()[zone].brightness()
+    }
+
+    /// Returns `true` while any zone is fading
+    pub fn is_fading(&mut self) -> bool {
+        self.zones.as_mut_slice().iter().any(Zone::is_fading)
+    }
+
+    /// Advances fades, call every `tick_ms` milliseconds
+    pub fn tick(&mut self) {
+        for zone in self.zones.as_mut_slice() {
+            zone.tick();
+        }
+    }
+
+    /// Releases the zones, outputs keep their current duty cycle
+    pub fn release(self) -> ZONES {
+        self.zones
+    }
+}
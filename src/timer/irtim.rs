@@ -0,0 +1,196 @@
+//! # Infrared transmitter
+//!
+//! IRTIM drives IR_OUT with the TIM17 carrier gated by the TIM16 envelope.
+//! The envelope channel is forced active or inactive and timed by TIM16 in
+//! one-pulse mode, so marks and spaces have microsecond resolution.
+//!
+//! ```ignore
+//! let mut syscfg = dp.SYSCFG.constrain(&mut rcc);
+//! let mut ir = IrTx::new(dp.TIM16, dp.TIM17, gpiob.pb9, 38.khz(), &mut syscfg, &mut rcc);
+//! ir.send_nec(0x00, 0x45);
+//! ```
+use crate::gpio::gpioa::PA13;
+use crate::gpio::gpiob::PB9;
+use crate::gpio::{AltFunction, Alternate, DefaultMode, AF0, AF1};
+use crate::rcc::{Enable, Rcc, Reset};
+use crate::stm32::{TIM16, TIM17};
+use crate::syscfg::{IrEnvelope, SysCfg};
+use crate::time::Hertz;
+
+// CR1
+const CEN: u32 = 1;
+const URS: u32 = 1 << 2;
+const OPM: u32 = 1 << 3;
+// SR
+const UIF: u32 = 1;
+// EGR
+const UG: u32 = 1;
+// CCMR1
+const OC1PE: u32 = 1 << 3;
+const OC1M_FORCE_INACTIVE: u32 = 0b100 << 4;
+const OC1M_FORCE_ACTIVE: u32 = 0b101 << 4;
+const OC1M_PWM1: u32 = 0b110 << 4;
+// CCER
+const CC1E: u32 = 1;
+// BDTR
+const MOE: u32 = 1 << 15;
+
+// NEC protocol timings in microseconds
+const NEC_LEADER_MARK: u16 = 9000;
+const NEC_LEADER_SPACE: u16 = 4500;
+const NEC_REPEAT_SPACE: u16 = 2250;
+const NEC_BIT_MARK: u16 = 562;
+const NEC_ZERO_SPACE: u16 = 562;
+const NEC_ONE_SPACE: u16 = 1687;
+
+/// IR_OUT pin
+pub trait IrPin {
+    fn setup(&self);
+}
+
+impl IrPin for PA13<DefaultMode> {
+    fn setup(&self) {
+        self.set_alt_mode(AltFunction::AF1);
+    }
+}
+
+impl IrPin for PB9<DefaultMode> {
+    fn setup(&self) {
+        self.set_alt_mode(AltFunction::AF0);
+    }
+}
+
+impl IrPin for PA13<Alternate<AF1>> {
+    fn setup(&self) {}
+}
+
+impl IrPin for PB9<Alternate<AF0>> {
+    fn setup(&self) {}
+}
+
+/// Infrared transmitter
+pub struct IrTx<PIN> {
+    envelope: TIM16,
+    carrier: TIM17,
+    pin: PIN,
+}
+
+impl<PIN: IrPin> IrTx<PIN> {
+    /// Configures TIM17 as carrier at `carrier_freq`, 1/3 duty, and TIM16 as envelope
+    ///
+    /// Timings are only in microseconds with a timer clock of at least 1 MHz.
+    pub fn new<T>(
+        envelope: TIM16,
+        carrier: TIM17,
+        pin: PIN,
+        carrier_freq: T,
+        syscfg: &mut SysCfg,
+        rcc: &mut Rcc,
+    ) -> Self
+    where
+        T: Into<Hertz>,
+    {
+        TIM16::enable(rcc);
+        TIM16::reset(rcc);
+        TIM17::enable(rcc);
+        TIM17::reset(rcc);
+
+        let clk = rcc.clocks.apb_tim_clk.0;
+        let period = clk / carrier_freq.into().0;
+        assert!((1..=0x1_0000).contains(&period));
+        carrier.arr.write(|w| unsafe { w.bits(period - 1) });
+        carrier.ccr1.write(|w| unsafe { w.bits(period / 3) });
+        carrier
+            .ccmr1_output()
+            .write(|w| unsafe { w.bits(OC1M_PWM1 | OC1PE) });
+        carrier.ccer.write(|w| unsafe { w.bits(CC1E) });
+        carrier.bdtr.write(|w| unsafe { w.bits(MOE) });
+        carrier.egr.write(|w| unsafe { w.bits(UG) });
+        carrier.cr1.write(|w| unsafe { w.bits(CEN) });
+
+        // 1 MHz envelope ticks, timer clock ticks below 1 MHz
+        envelope
+            .psc
+            .write(|w| unsafe { w.bits((clk / 1_000_000).saturating_sub(1)) });
+        envelope
+            .ccmr1_output()
+            .write(|w| unsafe { w.bits(OC1M_FORCE_INACTIVE) });
+        envelope.ccer.write(|w| unsafe { w.bits(CC1E) });
+        envelope.bdtr.write(|w| unsafe { w.bits(MOE) });
+        envelope.cr1.write(|w| unsafe { w.bits(URS | OPM) });
+
+        syscfg.set_ir_modulation(IrEnvelope::Tim16, false);
+        pin.setup();
+
+        IrTx {
+            envelope,
+            carrier,
+            pin,
+        }
+    }
+
+    /// Sends alternating marks and spaces in microseconds, starting with a mark
+    pub fn send_raw(&mut self, timings: &[u16]) {
+        for (i, us) in timings.iter().enumerate() {
+            self.pulse(i % 2 == 0, *us);
+        }
+        self.set_envelope(false);
+    }
+
+    /// Sends an NEC frame
+    pub fn send_nec(&mut self, address: u8, command: u8) {
+        self.pulse(true, NEC_LEADER_MARK);
+        self.pulse(false, NEC_LEADER_SPACE);
+        let frame = u32::from_le_bytes([address, !address, command, !command]);
+        for bit in 0..32 {
+            self.pulse(true, NEC_BIT_MARK);
+            if frame & (1 << bit) != 0 {
+                self.pulse(false, NEC_ONE_SPACE);
+            } else {
+                self.pulse(false, NEC_ZERO_SPACE);
+            }
+        }
+        self.pulse(true, NEC_BIT_MARK);
+        self.set_envelope(false);
+    }
+
+    /// Sends an NEC repeat code, to be sent every 108ms while a key is held
+    pub fn send_nec_repeat(&mut self) {
+        self.send_raw(&[NEC_LEADER_MARK, NEC_REPEAT_SPACE, NEC_BIT_MARK]);
+    }
+
+    /// Stops both timers and disables their clocks
+    pub fn release(self, rcc: &mut Rcc) -> (TIM16, TIM17, PIN) {
+        self.envelope.cr1.reset();
+        self.carrier.cr1.reset();
+        TIM16::disable(rcc);
+        TIM17::disable(rcc);
+        (self.envelope, self.carrier, self.pin)
+    }
+
+    fn set_envelope(&mut self, active: bool) {
+        let oc1m = if active {
+            OC1M_FORCE_ACTIVE
+        } else {
+            OC1M_FORCE_INACTIVE
+        };
+        self.envelope
+            .ccmr1_output()
+            .write(|w| unsafe { w.bits(oc1m) });
+    }
+
+    /// Holds the envelope for `us` microseconds
+    fn pulse(&mut self, mark: bool, us: u16) {
+        if us == 0 {
+            return;
+        }
+        self.set_envelope(mark);
+        self.envelope.arr.write(|w| unsafe { w.bits(us as u32) });
+        self.envelope.egr.write(|w| unsafe { w.bits(UG) });
+        self.envelope
+            .cr1
+            .write(|w| unsafe { w.bits(URS | OPM | CEN) });
+        while self.envelope.sr.read().bits() & UIF == 0 {}
+        self.envelope.sr.write(|w| unsafe { w.bits(!UIF) });
+    }
+}
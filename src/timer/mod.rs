@@ -1,23 +1,64 @@
 //! Timers
 use cortex_m::peripheral::syst::SystClkSource;
 use cortex_m::peripheral::SYST;
-use hal::timer::{CountDown, Periodic};
+use hal::timer::{Cancel, CountDown, Periodic};
 use nb;
 use void::Void;
-use crate::rcc::Rcc;
+use crate::rcc::{Enable, Rcc};
 use crate::stm32::*;
-use crate::time::{Hertz, MicroSecond};
-
+use crate::time::{Hertz, MicroSecond, MilliSecond};
+
+pub mod audio;
+pub mod burst;
+pub mod capture;
+pub mod dimmer;
+pub mod irtim;
+pub mod monotonic;
 pub mod opm;
 pub mod pwm;
 pub mod qei;
+pub mod ramp;
+pub mod recorder;
 pub mod stopwatch;
+pub mod sync;
 pub mod pins;
 
+// CR1
+const OPM: u32 = 1 << 3;
+
 /// Hardware timers
 pub struct Timer<TIM> {
-    clk: Hertz,
-    tim: TIM,
+    pub(crate) clk: Hertz,
+    pub(crate) tim: TIM,
+}
+
+/// Count down timer taking millisecond timeouts
+///
+/// Reaches timeouts beyond the `MicroSecond` range of `Timer`, e.g. days with
+/// the 32-bit TIM2.
+pub struct MilliTimer<TIM> {
+    timer: Timer<TIM>,
+}
+
+/// Count down timer stopping on the first timeout
+pub struct OneShotTimer<TIM> {
+    timer: Timer<TIM>,
+}
+
+/// Timer errors
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The timer is not running
+    Disabled,
+}
+
+/// Timer interrupt event
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// Count down timeout (update event)
+    TimeOut,
 }
 
 pub struct Channel1;
@@ -84,12 +125,27 @@ impl TimerExt<SYST> for SYST {
     }
 }
 
+impl Cancel for Timer<SYST> {
+    type Error = Error;
+
+    fn cancel(&mut self) -> Result<(), Error> {
+        if !self.tim.is_counter_enabled() {
+            return Err(Error::Disabled);
+        }
+        self.tim.disable_counter();
+        Ok(())
+    }
+}
+
 impl Periodic for Timer<SYST> {}
 
 macro_rules! timers {
-    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $cnt:ident $(,$cnt_h:ident)*),)+) => {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $irq:ident, $max_arr:expr, $cnt:ident $(,$cnt_h:ident)*),)+) => {
         $(
             impl Timer<$TIM> {
+                /// NVIC interrupt line of the timer update event
+                pub const INTERRUPT: Interrupt = Interrupt::$irq;
+
                 /// Configures a TIM peripheral as a periodic count down timer
                 pub fn $tim<T>(tim: $TIM, rcc: &mut Rcc) -> Self {
                     rcc.rb.$apbenr.modify(|_, w| w.$timXen().set_bit());
@@ -112,14 +168,19 @@ macro_rules! timers {
                     self.tim.cr1.modify(|_, w| w.cen().set_bit());
                 }
 
-                /// Starts listening
+                /// Starts listening for the update (timeout) interrupt
                 pub fn listen(&mut self) {
-                    self.tim.dier.write(|w| w.uie().set_bit());
+                    self.tim.dier.modify(|_, w| w.uie().set_bit());
                 }
 
                 /// Stops listening
                 pub fn unlisten(&mut self) {
-                    self.tim.dier.write(|w| w.uie().clear_bit());
+                    self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                }
+
+                /// Returns `true` if the timeout interrupt flag is set
+                pub fn is_irq_pending(&self) -> bool {
+                    self.tim.sr.read().uif().bit_is_set()
                 }
 
                 /// Clears interrupt flag
@@ -127,6 +188,18 @@ macro_rules! timers {
                     self.tim.sr.modify(|_, w| w.uif().clear_bit());
                 }
 
+                /// Clears the flag of a pending `event`
+                pub fn clear_interrupt(&mut self, event: Event) {
+                    match event {
+                        Event::TimeOut => self.clear_irq(),
+                    }
+                }
+
+                /// Returns the NVIC interrupt line, shared with other peripherals on some timers
+                pub fn interrupt(&self) -> Interrupt {
+                    Self::INTERRUPT
+                }
+
                 /// Resets counter value
                 pub fn reset(&mut self) {
                     self.tim.cnt.reset();
@@ -146,6 +219,116 @@ macro_rules! timers {
                 pub fn release(self) -> $TIM {
                     self.tim
                 }
+
+                /// Stops the timer, disables its clock and releases the TIM peripheral
+                pub fn release_and_disable(self, rcc: &mut Rcc) -> $TIM {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.dier.reset();
+                    $TIM::disable(rcc);
+                    self.tim
+                }
+
+                /// Counts down in milliseconds
+                pub fn into_millis(self) -> MilliTimer<$TIM> {
+                    MilliTimer { timer: self }
+                }
+
+                /// Stops counting on the first timeout, applies to the next `start`
+                pub fn into_one_shot(self) -> OneShotTimer<$TIM> {
+                    self.tim.cr1.modify(|r, w| unsafe { w.bits(r.bits() | OPM) });
+                    OneShotTimer { timer: self }
+                }
+
+                /// Restarts the counter with a timeout of `cycles` timer clocks
+                fn start_cycles(&mut self, cycles: u64) {
+                    // pause
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    // reset counter
+                    self.tim.cnt.reset();
+
+                    // Calculate counter configuration
+                    let psc = cycles / $max_arr;
+                    assert!(psc <= 0xffff, "timeout out of range");
+                    let arr = cycles / (psc + 1);
+
+                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc as u16) });
+                    self.tim.arr.write(|w| unsafe { w.bits(arr as u32) });
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit().urs().set_bit());
+                }
+            }
+
+            impl MilliTimer<$TIM> {
+                /// Counts down in microseconds again
+                pub fn into_micros(self) -> Timer<$TIM> {
+                    self.timer
+                }
+
+                /// Underlying timer, e.g. to listen for the timeout interrupt
+                pub fn timer(&mut self) -> &mut Timer<$TIM> {
+                    &mut self.timer
+                }
+            }
+
+            impl CountDown for MilliTimer<$TIM> {
+                type Time = MilliSecond;
+
+                fn start<T>(&mut self, timeout: T)
+                where
+                    T: Into<MilliSecond>,
+                {
+                    let cycles = self.timer.clk.0 as u64 * timeout.into().0 as u64 / 1_000;
+                    self.timer.start_cycles(cycles);
+                }
+
+                fn wait(&mut self) -> nb::Result<(), Void> {
+                    self.timer.wait()
+                }
+            }
+
+            impl Cancel for MilliTimer<$TIM> {
+                type Error = Error;
+
+                fn cancel(&mut self) -> Result<(), Error> {
+                    self.timer.cancel()
+                }
+            }
+
+            impl Periodic for MilliTimer<$TIM> {}
+
+            impl OneShotTimer<$TIM> {
+                /// Reloads and keeps counting after each timeout again
+                pub fn into_periodic(self) -> Timer<$TIM> {
+                    self.timer.tim.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !OPM) });
+                    self.timer
+                }
+
+                /// Underlying timer, e.g. to listen for the timeout interrupt
+                pub fn timer(&mut self) -> &mut Timer<$TIM> {
+                    &mut self.timer
+                }
+            }
+
+            impl CountDown for OneShotTimer<$TIM> {
+                type Time = MicroSecond;
+
+                fn start<T>(&mut self, timeout: T)
+                where
+                    T: Into<MicroSecond>,
+                {
+                    self.timer.start(timeout);
+                }
+
+                fn wait(&mut self) -> nb::Result<(), Void> {
+                    self.timer.wait()
+                }
+            }
+
+            impl Cancel for OneShotTimer<$TIM> {
+                type Error = Error;
+
+                fn cancel(&mut self) -> Result<(), Error> {
+                    self.timer.cancel()
+                }
             }
 
             impl TimerExt<$TIM> for $TIM {
@@ -161,19 +344,8 @@ macro_rules! timers {
                 where
                     T: Into<MicroSecond>,
                 {
-                    // pause
-                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
-                    // reset counter
-                    self.tim.cnt.reset();
-
-                    // Calculate counter configuration
-                    let cycles = timeout.into().cycles(self.clk);
-                    let psc = cycles / 0xffff;
-                    let arr = cycles / (psc + 1);
-
-                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc as u16) });
-                    self.tim.arr.write(|w| unsafe { w.bits(arr) });
-                    self.tim.cr1.modify(|_, w| w.cen().set_bit().urs().set_bit());
+                    let cycles = self.clk.0 as u64 * timeout.into().0 as u64 / 1_000_000;
+                    self.start_cycles(cycles);
                 }
 
                 fn wait(&mut self) -> nb::Result<(), Void> {
@@ -186,23 +358,49 @@ macro_rules! timers {
                 }
             }
 
+            impl Cancel for Timer<$TIM> {
+                type Error = Error;
+
+                fn cancel(&mut self) -> Result<(), Error> {
+                    if self.tim.cr1.read().cen().bit_is_clear() {
+                        return Err(Error::Disabled);
+                    }
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    Ok(())
+                }
+            }
+
             impl Periodic for Timer<$TIM> {}
         )+
     }
 }
 
+#[cfg(any(
+    feature = "stm32g030",
+    feature = "stm32g031",
+    feature = "stm32g041"
+))]
+timers! {
+    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2, TIM1_BRK_UP_TRG_COM, 0xffff, cnt),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+timers! {
+    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2, TIM1_BRK_UP_TRG_COMP, 0xffff, cnt),
+}
+
 timers! {
-    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2, cnt),
-    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1, cnt_l, cnt_h),
-    TIM3: (tim3, tim3en, tim3rst, apbenr1, apbrstr1, cnt_l, cnt_h),
-    TIM14: (tim14, tim14en, tim14rst, apbenr2, apbrstr2, cnt),
-    TIM16: (tim16, tim16en, tim16rst, apbenr2, apbrstr2, cnt),
-    TIM17: (tim17, tim17en, tim17rst, apbenr2, apbrstr2, cnt),
+    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1, TIM2, 0xffff_ffff, cnt_l, cnt_h),
+    TIM3: (tim3, tim3en, tim3rst, apbenr1, apbrstr1, TIM3, 0xffff, cnt_l, cnt_h),
+    TIM14: (tim14, tim14en, tim14rst, apbenr2, apbrstr2, TIM14, 0xffff, cnt),
+    TIM16: (tim16, tim16en, tim16rst, apbenr2, apbrstr2, TIM16, 0xffff, cnt),
+    TIM17: (tim17, tim17en, tim17rst, apbenr2, apbrstr2, TIM17, 0xffff, cnt),
 }
 
 #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 timers! {
-    TIM6: (tim6, tim6en, tim6rst, apbenr1, apbrstr1, cnt),
-    TIM7: (tim7, tim7en, tim7rst, apbenr1, apbrstr1, cnt),
-    TIM15: (tim15, tim15en, tim15rst, apbenr2, apbrstr2, cnt),
+    TIM6: (tim6, tim6en, tim6rst, apbenr1, apbrstr1, TIM6_DAC_LPTIM1, 0xffff, cnt),
+    TIM7: (tim7, tim7en, tim7rst, apbenr1, apbrstr1, TIM7_LPTIM2, 0xffff, cnt),
+    TIM15: (tim15, tim15en, tim15rst, apbenr2, apbrstr2, TIM15, 0xffff, cnt),
 }
\ No newline at end of file
@@ -0,0 +1,253 @@
+//! # Monotonic timebase
+//!
+//! Free-running timer counting microseconds, extended in software to 64 bits.
+//! Counter overflows are collected by `now()` or by the timer interrupt
+//! handler calling `on_interrupt()`; one of them must run at least once per
+//! counter period (~71 minutes for TIM2, ~65 ms for 16-bit timers).
+use core::cell::Cell;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+use cortex_m::interrupt;
+
+use crate::rcc::Rcc;
+use crate::stm32::*;
+use crate::time::MicroSecond;
+
+/// Span of time with microsecond resolution
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Duration(u64);
+
+impl Duration {
+    pub const fn from_micros(us: u64) -> Self {
+        Duration(us)
+    }
+
+    pub const fn from_millis(ms: u64) -> Self {
+        Duration(ms * 1_000)
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Duration(secs * 1_000_000)
+    }
+
+    pub fn as_micros(&self) -> u64 {
+        self.0
+    }
+
+    pub fn as_millis(&self) -> u64 {
+        self.0 / 1_000
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        self.0 / 1_000_000
+    }
+
+    pub fn checked_add(self, other: Duration) -> Option<Duration> {
+        self.0.checked_add(other.0).map(Duration)
+    }
+
+    pub fn checked_sub(self, other: Duration) -> Option<Duration> {
+        self.0.checked_sub(other.0).map(Duration)
+    }
+
+    pub fn saturating_sub(self, other: Duration) -> Duration {
+        Duration(self.0.saturating_sub(other.0))
+    }
+}
+
+impl From<MicroSecond> for Duration {
+    fn from(us: MicroSecond) -> Self {
+        Duration(us.0 as u64)
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, other: Duration) -> Duration {
+        Duration(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, other: Duration) -> Duration {
+        self.saturating_sub(other)
+    }
+}
+
+/// Point in time measured by a `MonoTimer`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Microseconds since the timer was started
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns duration since `earlier`, or `None` if `earlier` is later
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration)
+    }
+
+    /// Returns duration since `earlier`, or zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration.0).map(Instant)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration.0).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration.0))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_sub(duration.0))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// 64-bit monotonic microsecond timer
+pub struct MonoTimer<TIM> {
+    tim: TIM,
+    overflows: Cell<u32>,
+}
+
+pub trait MonoTimerExt: Sized {
+    fn monotonic(self, rcc: &mut Rcc) -> MonoTimer<Self>;
+}
+
+macro_rules! monotonic {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $bits:expr, $cnt:ident $(,$cnt_h:ident)*),)+) => {
+        $(
+            impl MonoTimerExt for $TIM {
+                fn monotonic(self, rcc: &mut Rcc) -> MonoTimer<Self> {
+                    MonoTimer::$tim(self, rcc)
+                }
+            }
+
+            impl MonoTimer<$TIM> {
+                /// Starts free-running timer ticking at 1MHz
+                pub fn $tim(tim: $TIM, rcc: &mut Rcc) -> Self {
+                    let clk = rcc.clocks.apb_tim_clk.0;
+                    assert!(clk % 1_000_000 == 0);
+                    rcc.rb.$apbenr.modify(|_, w| w.$timXen().set_bit());
+                    rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().set_bit());
+                    rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().clear_bit());
+
+                    tim.psc.write(|w| unsafe { w.psc().bits((clk / 1_000_000 - 1) as u16) });
+                    tim.arr.write(|w| unsafe { w.bits(u32::MAX >> (32 - $bits)) });
+                    // only counter overflow sets UIF
+                    tim.cr1.modify(|_, w| w.urs().set_bit());
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+                    MonoTimer {
+                        tim,
+                        overflows: Cell::new(0),
+                    }
+                }
+
+                /// Returns current time
+                pub fn now(&self) -> Instant {
+                    interrupt::free(|_| {
+                        let mut cnt = self.counter();
+                        if self.tim.sr.read().uif().bit_is_set() {
+                            self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                            self.overflows.set(self.overflows.get().wrapping_add(1));
+                            cnt = self.counter();
+                        }
+                        Instant(((self.overflows.get() as u64) << $bits) | cnt as u64)
+                    })
+                }
+
+                /// Returns time elapsed since `since`
+                pub fn elapsed(&self, since: Instant) -> Duration {
+                    self.now().duration_since(since)
+                }
+
+                /// Enables overflow interrupt
+                pub fn listen(&mut self) {
+                    self.tim.dier.modify(|_, w| w.uie().set_bit());
+                }
+
+                /// Disables overflow interrupt
+                pub fn unlisten(&mut self) {
+                    self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                }
+
+                /// Collects counter overflow, call from the timer interrupt handler
+                pub fn on_interrupt(&self) {
+                    self.now();
+                }
+
+                /// Releases the TIM peripheral
+                pub fn release(self) -> $TIM {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
+
+                fn counter(&self) -> u32 {
+                    let _high = 0;
+                    $(
+                        let _high = self.tim.cnt.read().$cnt_h().bits() as u32;
+                    )*
+                    let low = self.tim.cnt.read().$cnt().bits() as u32;
+                    low | (_high << 16)
+                }
+            }
+        )+
+    }
+}
+
+monotonic! {
+    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2, 16, cnt),
+    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1, 32, cnt_l, cnt_h),
+    TIM3: (tim3, tim3en, tim3rst, apbenr1, apbrstr1, 16, cnt_l),
+    TIM14: (tim14, tim14en, tim14rst, apbenr2, apbrstr2, 16, cnt),
+    TIM16: (tim16, tim16en, tim16rst, apbenr2, apbrstr2, 16, cnt),
+    TIM17: (tim17, tim17en, tim17rst, apbenr2, apbrstr2, 16, cnt),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+monotonic! {
+    TIM6: (tim6, tim6en, tim6rst, apbenr1, apbrstr1, 16, cnt),
+    TIM7: (tim7, tim7en, tim7rst, apbenr1, apbrstr1, 16, cnt),
+    TIM15: (tim15, tim15en, tim15rst, apbenr2, apbrstr2, 16, cnt),
+}
@@ -1,5 +1,7 @@
 //! # One-pulse Mode
+use core::any::TypeId;
 use core::marker::PhantomData;
+use crate::gpio::SignalEdge;
 use crate::prelude::*;
 use crate::rcc::Rcc;
 use crate::stm32::*;
@@ -18,9 +20,28 @@ pub struct Opm<TIM, CHANNEL> {
     clk: Hertz,
     pulse_width: MicroSecond,
     delay: MicroSecond,
+    retriggerable: bool,
     _channel: PhantomData<CHANNEL>,
 }
 
+/// Timer input usable as a slave mode trigger
+pub trait TriggerInput {
+    /// Trigger selection bits
+    const TS: u8;
+    /// Capture/compare channel index
+    const INDEX: u8;
+}
+
+impl TriggerInput for Channel1 {
+    const TS: u8 = 0b101;
+    const INDEX: u8 = 0;
+}
+
+impl TriggerInput for Channel2 {
+    const TS: u8 = 0b110;
+    const INDEX: u8 = 1;
+}
+
 macro_rules! opm {
     ($($TIMX:ident: ($apbXenr:ident, $apbXrstr:ident, $timX:ident, $timXen:ident, $timXrst:ident),)+) => {
         $(
@@ -46,6 +67,7 @@ macro_rules! opm {
                     clk: rcc.clocks.apb_tim_clk,
                     pulse_width,
                     delay: 0.us(),
+                    retriggerable: false,
                     _channel: PhantomData,
                 }
             }
@@ -55,7 +77,7 @@ macro_rules! opm {
 
 macro_rules! opm_hal {
     ($($TIMX:ident:
-        ($CH:ty, $ccxe:ident, $ccmrx_output:ident, $ocxm:ident, $ocxfe:ident, $ccrx:ident, $arr:ident $(,$arr_h:ident)*),)+
+        ($CH:ty, $ccxe:ident, $ccmrx_output:ident, $ocxm:ident, $ocxm_3:expr, $ocxfe:ident, $ccrx:ident, $arr:ident $(,$arr_h:ident)*),)+
     ) => {
         $(
             impl Opm<$TIMX, $CH> {
@@ -108,8 +130,75 @@ macro_rules! opm_hal {
                         $(
                             self.rb.arr.modify(|_, w| w.$arr_h().bits((reload >> 16) as u16));
                         )*
-                        self.rb.$ccmrx_output().modify(|_, w| w.$ocxm().bits(7).$ocxfe().set_bit());
+                        // PWM mode 2 or retriggerable OPM mode 2
+                        let (mode, mode_3) = if self.retriggerable {
+                            (0b001, 1)
+                        } else {
+                            (0b111, 0)
+                        };
+                        self.rb.$ccmrx_output().modify(|_, w| w.$ocxm().bits(mode).$ocxfe().set_bit());
+                        self.rb.$ccmrx_output().modify(|r, w| {
+                            w.bits(r.bits() & !(1 << $ocxm_3) | (mode_3 << $ocxm_3))
+                        });
+                    }
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! opm_trigger {
+    ($($TIMX:ident: $CH:ty,)+) => {
+        $(
+            impl Opm<$TIMX, $CH> {
+                /// Starts the pulse on an external edge instead of `generate()`
+                ///
+                /// The trigger pin must belong to a channel other than the output channel.
+                pub fn trigger_on<PIN>(&mut self, pin: PIN, edge: SignalEdge)
+                where
+                    PIN: TimerPin<$TIMX>,
+                    PIN::Channel: TriggerInput + 'static,
+                {
+                    assert!(TypeId::of::<$CH>() != TypeId::of::<PIN::Channel>());
+                    pin.setup();
+                    let ccmr_offset = 8 * PIN::Channel::INDEX;
+                    let ccer_offset = 4 * PIN::Channel::INDEX;
+                    let (p, np) = match edge {
+                        SignalEdge::Rising => (0, 0),
+                        SignalEdge::Falling => (1, 0),
+                        SignalEdge::All => (1, 1),
+                    };
+                    unsafe {
+                        // Map CCx on TIx input
+                        self.rb.ccmr1_input().modify(|r, w| {
+                            w.bits(r.bits() & !(0b11 << ccmr_offset) | (0b01 << ccmr_offset))
+                        });
+                        self.rb.ccer.modify(|r, w| {
+                            w.bits(
+                                r.bits() & !(0b1011 << ccer_offset)
+                                    | (p << (ccer_offset + 1))
+                                    | (np << (ccer_offset + 3)),
+                            )
+                        });
+                        self.rb.smcr.modify(|_, w| w.ts().bits(PIN::Channel::TS));
                     }
+                    self.set_slave_mode();
+                    self.rb.cr1.modify(|_, w| w.opm().set_bit());
+                }
+
+                /// Restarts the pulse on every trigger while it is still running
+                pub fn set_retriggerable(&mut self, retriggerable: bool) {
+                    self.retriggerable = retriggerable;
+                    self.set_slave_mode();
+                    self.setup();
+                }
+
+                fn set_slave_mode(&mut self) {
+                    // Trigger mode or combined reset + trigger mode
+                    let sms = if self.retriggerable { 1 << 16 } else { 0b110 };
+                    self.rb.smcr.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0b111 | 1 << 16) | sms)
+                    });
                 }
             }
         )+
@@ -117,21 +206,36 @@ macro_rules! opm_hal {
 }
 
 opm_hal! {
-    TIM1: (Channel1, cc1e, ccmr1_output, oc1m, oc1fe, ccr1, arr),
-    TIM1: (Channel2, cc2e, ccmr1_output, oc2m, oc2fe, ccr2, arr),
-    TIM1: (Channel3, cc3e, ccmr2_output, oc3m, oc3fe, ccr3, arr),
-    TIM1: (Channel4, cc4e, ccmr2_output, oc4m, oc4fe, ccr4, arr),
-    TIM2: (Channel1, cc1e, ccmr1_output, oc1m, oc1fe, ccr1, arr_l, arr_h),
-    TIM2: (Channel2, cc2e, ccmr1_output, oc2m, oc2fe, ccr2, arr_l, arr_h),
-    TIM2: (Channel3, cc3e, ccmr2_output, oc3m, oc3fe, ccr3, arr_l, arr_h),
-    TIM2: (Channel4, cc4e, ccmr2_output, oc4m, oc4fe, ccr4, arr_l, arr_h),
-    TIM3: (Channel1, cc1e, ccmr1_output, oc1m, oc1fe, ccr1, arr_l, arr_h),
-    TIM3: (Channel2, cc2e, ccmr1_output, oc2m, oc2fe, ccr2, arr_l, arr_h),
-    TIM3: (Channel3, cc3e, ccmr2_output, oc3m, oc3fe, ccr3, arr_l, arr_h),
-    TIM3: (Channel4, cc4e, ccmr2_output, oc4m, oc4fe, ccr4, arr_l, arr_h),
-    TIM14: (Channel1, cc1e, ccmr1_output, oc1m, oc1fe, ccr1, arr),
-    TIM16: (Channel1, cc1e, ccmr1_output, oc1m, oc1fe, ccr1, arr),
-    TIM17: (Channel1, cc1e, ccmr1_output, oc1m, oc1fe, ccr1, arr),
+    TIM1: (Channel1, cc1e, ccmr1_output, oc1m, 16, oc1fe, ccr1, arr),
+    TIM1: (Channel2, cc2e, ccmr1_output, oc2m, 24, oc2fe, ccr2, arr),
+    TIM1: (Channel3, cc3e, ccmr2_output, oc3m, 16, oc3fe, ccr3, arr),
+    TIM1: (Channel4, cc4e, ccmr2_output, oc4m, 24, oc4fe, ccr4, arr),
+    TIM2: (Channel1, cc1e, ccmr1_output, oc1m, 16, oc1fe, ccr1, arr_l, arr_h),
+    TIM2: (Channel2, cc2e, ccmr1_output, oc2m, 24, oc2fe, ccr2, arr_l, arr_h),
+    TIM2: (Channel3, cc3e, ccmr2_output, oc3m, 16, oc3fe, ccr3, arr_l, arr_h),
+    TIM2: (Channel4, cc4e, ccmr2_output, oc4m, 24, oc4fe, ccr4, arr_l, arr_h),
+    TIM3: (Channel1, cc1e, ccmr1_output, oc1m, 16, oc1fe, ccr1, arr_l, arr_h),
+    TIM3: (Channel2, cc2e, ccmr1_output, oc2m, 24, oc2fe, ccr2, arr_l, arr_h),
+    TIM3: (Channel3, cc3e, ccmr2_output, oc3m, 16, oc3fe, ccr3, arr_l, arr_h),
+    TIM3: (Channel4, cc4e, ccmr2_output, oc4m, 24, oc4fe, ccr4, arr_l, arr_h),
+    TIM14: (Channel1, cc1e, ccmr1_output, oc1m, 16, oc1fe, ccr1, arr),
+    TIM16: (Channel1, cc1e, ccmr1_output, oc1m, 16, oc1fe, ccr1, arr),
+    TIM17: (Channel1, cc1e, ccmr1_output, oc1m, 16, oc1fe, ccr1, arr),
+}
+
+opm_trigger! {
+    TIM1: Channel1,
+    TIM1: Channel2,
+    TIM1: Channel3,
+    TIM1: Channel4,
+    TIM2: Channel1,
+    TIM2: Channel2,
+    TIM2: Channel3,
+    TIM2: Channel4,
+    TIM3: Channel1,
+    TIM3: Channel2,
+    TIM3: Channel3,
+    TIM3: Channel4,
 }
 
 opm! {
@@ -3,7 +3,8 @@ use crate::gpio::gpiob::*;
 use crate::gpio::gpioc::*;
 use crate::gpio::gpiod::*;
 use crate::gpio::gpiof::*;
-use crate::gpio::{AltFunction, DefaultMode};
+use crate::gpio::{AltFunction, Alternate, DefaultMode};
+use crate::gpio::{AF0, AF1, AF2, AF4, AF5, AF6};
 use crate::stm32::*;
 use crate::timer::*;
 
@@ -14,83 +15,184 @@ pub trait TimerPin<TIM> {
 }
 
 macro_rules! timer_pins {
-    ($TIMX:ident, [ $(($ch:ty, $pin:ty, $af_mode:expr),)+ ]) => {
+    ($TIMX:ident, [ $(($ch:ty, $pin:ident, $af:ident),)+ ]) => {
         $(
-            impl TimerPin<$TIMX> for $pin {
+            impl TimerPin<$TIMX> for $pin<DefaultMode> {
                 type Channel = $ch;
 
                 fn setup(&self) {
-                    self.set_alt_mode($af_mode);
+                    self.set_alt_mode(AltFunction::$af);
                 }
             }
+
+            impl TimerPin<$TIMX> for $pin<Alternate<$af>> {
+                type Channel = $ch;
+
+                fn setup(&self) {}
+            }
         )+
     };
 }
 
 timer_pins!(TIM1, [
-    (Channel1, PA8<DefaultMode>, AltFunction::AF2),
-    (Channel1, PC8<DefaultMode>, AltFunction::AF2),
-    (Channel2, PA9<DefaultMode>, AltFunction::AF2),
-    (Channel2, PB3<DefaultMode>, AltFunction::AF1),
-    (Channel2, PC9<DefaultMode>, AltFunction::AF2),
-    (Channel3, PA10<DefaultMode>, AltFunction::AF2),
-    (Channel3, PB6<DefaultMode>, AltFunction::AF1),
-    (Channel3, PC10<DefaultMode>, AltFunction::AF2),
-    (Channel4, PA11<DefaultMode>, AltFunction::AF2),
-    (Channel4, PC11<DefaultMode>, AltFunction::AF2),
+    (Channel1, PA8, AF2),
+    (Channel1, PC8, AF2),
+    (Channel2, PA9, AF2),
+    (Channel2, PB3, AF1),
+    (Channel2, PC9, AF2),
+    (Channel3, PA10, AF2),
+    (Channel3, PB6, AF1),
+    (Channel3, PC10, AF2),
+    (Channel4, PA11, AF2),
+    (Channel4, PC11, AF2),
 ]);
 
 timer_pins!(TIM2, [
-    (Channel1, PA0<DefaultMode>, AltFunction::AF2),
-    (Channel1, PA5<DefaultMode>, AltFunction::AF2),
-    (Channel1, PA15<DefaultMode>, AltFunction::AF2),
-    (Channel1, PC4<DefaultMode>, AltFunction::AF2),
-    (Channel2, PA1<DefaultMode>, AltFunction::AF2),
-    (Channel2, PB3<DefaultMode>, AltFunction::AF2),
-    (Channel2, PC5<DefaultMode>, AltFunction::AF2),
-    (Channel3, PA2<DefaultMode>, AltFunction::AF2),
-    (Channel3, PB10<DefaultMode>, AltFunction::AF2),
-    (Channel4, PA3<DefaultMode>, AltFunction::AF2),
-    (Channel4, PB11<DefaultMode>, AltFunction::AF2),
-    (Channel4, PC7<DefaultMode>, AltFunction::AF2),
+    (Channel1, PA0, AF2),
+    (Channel1, PA5, AF2),
+    (Channel1, PA15, AF2),
+    (Channel1, PC4, AF2),
+    (Channel2, PA1, AF2),
+    (Channel2, PB3, AF2),
+    (Channel2, PC5, AF2),
+    (Channel3, PA2, AF2),
+    (Channel3, PB10, AF2),
+    (Channel4, PA3, AF2),
+    (Channel4, PB11, AF2),
+    (Channel4, PC7, AF2),
 ]);
 
 timer_pins!(TIM3, [
-    (Channel1, PA6<DefaultMode>, AltFunction::AF1),
-    (Channel1, PB4<DefaultMode>, AltFunction::AF1),
-    (Channel1, PC6<DefaultMode>, AltFunction::AF1),
-    (Channel2, PA7<DefaultMode>, AltFunction::AF1),
-    (Channel2, PB5<DefaultMode>, AltFunction::AF1),
-    (Channel2, PC7<DefaultMode>, AltFunction::AF1),
-    (Channel3, PB0<DefaultMode>, AltFunction::AF1),
-    (Channel3, PC8<DefaultMode>, AltFunction::AF1),
-    (Channel4, PB1<DefaultMode>, AltFunction::AF1),
-    (Channel4, PC9<DefaultMode>, AltFunction::AF1),
+    (Channel1, PA6, AF1),
+    (Channel1, PB4, AF1),
+    (Channel1, PC6, AF1),
+    (Channel2, PA7, AF1),
+    (Channel2, PB5, AF1),
+    (Channel2, PC7, AF1),
+    (Channel3, PB0, AF1),
+    (Channel3, PC8, AF1),
+    (Channel4, PB1, AF1),
+    (Channel4, PC9, AF1),
 ]);
 
 timer_pins!(TIM14, [
-    (Channel1, PA4<DefaultMode>, AltFunction::AF4),
-    (Channel1, PA7<DefaultMode>, AltFunction::AF4),
-    (Channel1, PB1<DefaultMode>, AltFunction::AF0),
-    (Channel1, PC12<DefaultMode>, AltFunction::AF2),
-    (Channel1, PF0<DefaultMode>, AltFunction::AF2),
+    (Channel1, PA4, AF4),
+    (Channel1, PA7, AF4),
+    (Channel1, PB1, AF0),
+    (Channel1, PC12, AF2),
+    (Channel1, PF0, AF2),
 ]);
 
 #[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
 timer_pins!(TIM15, [
-    (Channel1, PA2<DefaultMode>, AltFunction::AF5),
-    (Channel1, PB14<DefaultMode>, AltFunction::AF5),
-    (Channel1, PC1<DefaultMode>, AltFunction::AF2),
+    (Channel1, PA2, AF5),
+    (Channel1, PB14, AF5),
+    (Channel1, PC1, AF2),
+    (Channel2, PA3, AF5),
+    (Channel2, PB15, AF5),
+    (Channel2, PC2, AF2),
 ]);
 
 timer_pins!(TIM16, [
-    (Channel1, PA6<DefaultMode>, AltFunction::AF5),
-    (Channel1, PB8<DefaultMode>, AltFunction::AF2),
-    (Channel1, PD0<DefaultMode>, AltFunction::AF2),
+    (Channel1, PA6, AF5),
+    (Channel1, PB8, AF2),
+    (Channel1, PD0, AF2),
 ]);
 
 timer_pins!(TIM17, [
-    (Channel1, PA7<DefaultMode>, AltFunction::AF6),
-    (Channel1, PB9<DefaultMode>, AltFunction::AF2),
-    (Channel1, PD1<DefaultMode>, AltFunction::AF2),
+    (Channel1, PA7, AF6),
+    (Channel1, PB9, AF2),
+    (Channel1, PD1, AF2),
+]);
+
+/// Break input 1
+pub struct Bkin;
+/// Break input 2
+pub struct Bkin2;
+
+pub trait TimerNPin<TIM> {
+    type Channel;
+
+    fn setup(&self);
+}
+
+pub trait BreakPin<TIM> {
+    type Input;
+
+    fn setup(&self);
+}
+
+macro_rules! timer_npins {
+    ($TIMX:ident, [ $(($ch:ty, $pin:ident, $af:ident),)+ ]) => {
+        $(
+            impl TimerNPin<$TIMX> for $pin<DefaultMode> {
+                type Channel = $ch;
+
+                fn setup(&self) {
+                    self.set_alt_mode(AltFunction::$af);
+                }
+            }
+
+            impl TimerNPin<$TIMX> for $pin<Alternate<$af>> {
+                type Channel = $ch;
+
+                fn setup(&self) {}
+            }
+        )+
+    };
+}
+
+macro_rules! break_pins {
+    ($TIMX:ident, [ $(($input:ty, $pin:ident, $af:ident),)+ ]) => {
+        $(
+            impl BreakPin<$TIMX> for $pin<DefaultMode> {
+                type Input = $input;
+
+                fn setup(&self) {
+                    self.set_alt_mode(AltFunction::$af);
+                }
+            }
+
+            impl BreakPin<$TIMX> for $pin<Alternate<$af>> {
+                type Input = $input;
+
+                fn setup(&self) {}
+            }
+        )+
+    };
+}
+
+timer_npins!(TIM1, [
+    (Channel1, PA7, AF2),
+    (Channel1, PB13, AF2),
+    (Channel1, PD2, AF2),
+    (Channel2, PB0, AF2),
+    (Channel2, PB14, AF2),
+    (Channel2, PD3, AF2),
+    (Channel3, PB1, AF2),
+    (Channel3, PB15, AF2),
+    (Channel3, PD4, AF2),
+]);
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+timer_npins!(TIM15, [
+    (Channel1, PA1, AF5),
+    (Channel1, PB13, AF5),
+    (Channel1, PB15, AF4),
+    (Channel1, PF1, AF2),
+]);
+
+timer_npins!(TIM16, [
+    (Channel1, PB6, AF2),
+]);
+
+timer_npins!(TIM17, [
+    (Channel1, PB7, AF2),
+]);
+
+break_pins!(TIM1, [
+    (Bkin, PA6, AF2),
+    (Bkin, PB12, AF2),
+    (Bkin, PC13, AF2),
+    (Bkin2, PA11, AF5),
 ]);
@@ -1,20 +1,119 @@
 //! # Pulse Width Modulation
 use core::marker::PhantomData;
 
-use crate::rcc::Rcc;
+use crate::rcc::{Enable, Rcc};
 use crate::stm32::*;
-use crate::time::Hertz;
-use crate::timer::pins::TimerPin;
+use crate::time::{Hertz, MicroSecond};
+use crate::timer::pins::{BreakPin, Bkin, Bkin2, TimerNPin, TimerPin};
 use crate::timer::*;
 use hal;
 
+/// Counter alignment mode
+#[derive(Clone, Copy, PartialEq)]
+pub enum Alignment {
+    /// Counter counts up, outputs are edge-aligned
+    Edge = 0b00,
+    /// Center-aligned, compare flags set while counting down
+    Center1 = 0b01,
+    /// Center-aligned, compare flags set while counting up
+    Center2 = 0b10,
+    /// Center-aligned, compare flags set while counting up and down
+    Center3 = 0b11,
+}
+
+// CCMRx output mode of the first channel, the second is 8 bits higher
+const OCXPE: u32 = 1 << 3;
+const OCXM_SHIFT: u32 = 4;
+const OCXM_3: u32 = 1 << 16;
+const OCXM_MASK: u32 = 0b111 << OCXM_SHIFT | OCXM_3;
+const MODE_PWM1: u32 = 0b0110;
+const MODE_PWM2: u32 = 0b0111;
+const MODE_COMBINED1: u32 = 0b1100;
+const MODE_COMBINED2: u32 = 0b1101;
+
+/// Output polarity (CCxP, CCxNP)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// Output level while the main output is disabled (OISx, OISxN)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IdleState {
+    Low,
+    High,
+}
+
+/// Break input polarity
+#[derive(Clone, Copy, PartialEq)]
+pub enum BreakPolarity {
+    ActiveLow,
+    ActiveHigh,
+}
+
+/// Break input configuration
+#[derive(Clone, Copy)]
+pub struct BreakConfig {
+    polarity: BreakPolarity,
+    filter: u8,
+    auto_reenable: bool,
+}
+
+impl BreakConfig {
+    pub fn new(polarity: BreakPolarity) -> Self {
+        BreakConfig {
+            polarity,
+            filter: 0,
+            auto_reenable: false,
+        }
+    }
+
+    /// Digital filter applied to the break input (0 - no filter, 15 - strongest filtering)
+    pub fn filter(mut self, filter: u8) -> Self {
+        assert!(filter < 16);
+        self.filter = filter;
+        self
+    }
+
+    /// Re-enable outputs automatically on the next update event once the break is released
+    pub fn auto_reenable(mut self, auto_reenable: bool) -> Self {
+        self.auto_reenable = auto_reenable;
+        self
+    }
+}
+
+impl Default for BreakConfig {
+    fn default() -> Self {
+        BreakConfig::new(BreakPolarity::ActiveLow)
+    }
+}
+
 pub struct Pwm<TIM> {
-    tim: PhantomData<TIM>,
+    clk: Hertz,
+    tim: TIM,
 }
 
-pub struct PwmPin<TIM, CH> {
+/// PWM channel bound to `PIN`
+pub struct PwmPin<TIM, CH, PIN> {
    tim: PhantomData<TIM>,
    channel: PhantomData<CH>,
+   pin: PIN,
+}
+
+/// PWM channel driving both CHx and CHxN outputs
+pub struct ComplementaryPwmPin<TIM, CH, PIN, NPIN> {
+    pin: PwmPin<TIM, CH, PIN>,
+    npin: NPIN,
+}
+
+/// PWM channel combined with its paired channel, the pulse can start anywhere
+/// in the period
+pub struct PhasedPwmPin<TIM, CH, PIN, PAIR> {
+    pin: PwmPin<TIM, CH, PIN>,
+    pair: PAIR,
 }
 
 pub trait PwmExt: Sized {
@@ -24,7 +123,7 @@ pub trait PwmExt: Sized {
 }
 
 impl<TIM> Pwm<TIM> {
-    pub fn bind_pin<PIN>(&self, pin: PIN) -> PwmPin<TIM, PIN::Channel>
+    pub fn bind_pin<PIN>(&self, pin: PIN) -> PwmPin<TIM, PIN::Channel, PIN>
     where
         PIN: TimerPin<TIM>
     {
@@ -32,8 +131,195 @@ impl<TIM> Pwm<TIM> {
         PwmPin {
             tim: PhantomData,
             channel: PhantomData,
+            pin,
         }
     }
+
+    /// Timer kernel clock
+    pub fn clock(&self) -> Hertz {
+        self.clk
+    }
+}
+
+impl<TIM, CH, PIN> PwmPin<TIM, CH, PIN> {
+    /// Binds complementary output pin to the channel
+    pub fn with_complementary<NPIN>(self, npin: NPIN) -> ComplementaryPwmPin<TIM, CH, PIN, NPIN>
+    where
+        NPIN: TimerNPin<TIM, Channel = CH>,
+    {
+        npin.setup();
+        ComplementaryPwmPin { pin: self, npin }
+    }
+}
+
+impl<TIM, CH, PIN> PwmPin<TIM, CH, PIN>
+where
+    Self: hal::PwmPin,
+{
+    /// Disables the channel and returns the pin
+    ///
+    /// The pin stays in its alternate function until reconfigured.
+    pub fn release(mut self) -> PIN {
+        hal::PwmPin::disable(&mut self);
+        self.pin
+    }
+}
+
+impl<TIM, CH, PIN, NPIN> ComplementaryPwmPin<TIM, CH, PIN, NPIN> {
+    /// Releases the main channel and the complementary output pin
+    pub fn release(self) -> (PwmPin<TIM, CH, PIN>, NPIN) {
+        (self.pin, self.npin)
+    }
+}
+
+impl Pwm<TIM1> {
+    /// Places the ADC sampling instant within the PWM period
+    ///
+    /// Channel 4 compare triggers ADC conversions (`adc::Trigger::Tim1Cc4`),
+    /// so channel 4 can't drive a pin at the same time. `phase` is a fraction
+    /// of the period in 1/65536 steps, e.g. `0x8000` samples mid-period.
+    pub fn set_adc_trigger_phase(&mut self, phase: u16) {
+        let tim = unsafe { &*TIM1::ptr() };
+        let period = tim.arr.read().bits() + 1;
+        let ccr = (period * phase as u32) >> 16;
+        tim.ccr4.write(|w| unsafe { w.bits(ccr) });
+        // OC4M PWM mode 1, OC4PE
+        tim.ccmr2_output().modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b111 << 12 | 1 << 24) | 0b110 << 12 | 1 << 11)
+        });
+        // CC4E
+        tim.ccer.modify(|r, w| unsafe { w.bits(r.bits() | 1 << 12) });
+    }
+
+    /// Sets dead time inserted between complementary outputs
+    ///
+    /// Panics if the dead time is out of range for the timer clock.
+    pub fn set_dead_time(&mut self, dead_time_ns: u32) {
+        let mut ticks = (self.clk.0 as u64 * dead_time_ns as u64 / 1_000_000_000) as u32;
+        let mut ckd = 0;
+        let dtg = loop {
+            if let Some(dtg) = dead_time_bits(ticks) {
+                break dtg;
+            }
+            assert!(ckd < 2, "dead time is out of range");
+            ckd += 1;
+            ticks /= 2;
+        };
+        unsafe {
+            let tim = &*TIM1::ptr();
+            tim.cr1.modify(|_, w| w.ckd().bits(ckd));
+            tim.bdtr.modify(|_, w| w.dtg().bits(dtg));
+        }
+    }
+
+    /// Configures break input
+    pub fn enable_break<PIN>(&mut self, pin: PIN, cfg: BreakConfig)
+    where
+        PIN: BreakPin<TIM1>,
+        PIN::Input: BreakInput,
+    {
+        pin.setup();
+        PIN::Input::configure(cfg);
+    }
+
+    /// Disables break input
+    pub fn disable_break<IN>(&mut self)
+    where
+        IN: BreakInput,
+    {
+        IN::disable();
+    }
+
+    /// Starts listening for break events
+    pub fn listen_break(&mut self) {
+        unsafe {
+            (*TIM1::ptr()).dier.modify(|_, w| w.bie().set_bit());
+        }
+    }
+
+    /// Stops listening for break events
+    pub fn unlisten_break(&mut self) {
+        unsafe {
+            (*TIM1::ptr()).dier.modify(|_, w| w.bie().clear_bit());
+        }
+    }
+
+    /// Returns `true` if a break event has occurred
+    pub fn is_break_pending(&self) -> bool {
+        let sr = unsafe { (*TIM1::ptr()).sr.read() };
+        sr.bif().bit_is_set() || sr.b2if().bit_is_set()
+    }
+
+    /// Clears break flags and re-enables the main output
+    pub fn clear_break(&mut self) {
+        unsafe {
+            let tim = &*TIM1::ptr();
+            tim.sr.modify(|_, w| w.bif().clear_bit().b2if().clear_bit());
+            tim.bdtr.modify(|_, w| w.moe().set_bit());
+        }
+    }
+}
+
+/// Break input configuration
+pub trait BreakInput {
+    fn configure(cfg: BreakConfig);
+    fn disable();
+}
+
+impl BreakInput for Bkin {
+    fn configure(cfg: BreakConfig) {
+        unsafe {
+            (*TIM1::ptr()).bdtr.modify(|_, w| {
+                w.bkf()
+                    .bits(cfg.filter)
+                    .bkp()
+                    .bit(cfg.polarity == BreakPolarity::ActiveHigh)
+                    .aoe()
+                    .bit(cfg.auto_reenable)
+                    .bke()
+                    .set_bit()
+            });
+        }
+    }
+
+    fn disable() {
+        unsafe {
+            (*TIM1::ptr()).bdtr.modify(|_, w| w.bke().clear_bit());
+        }
+    }
+}
+
+impl BreakInput for Bkin2 {
+    fn configure(cfg: BreakConfig) {
+        unsafe {
+            (*TIM1::ptr()).bdtr.modify(|_, w| {
+                w.bk2f()
+                    .bits(cfg.filter)
+                    .bk2p()
+                    .bit(cfg.polarity == BreakPolarity::ActiveHigh)
+                    .aoe()
+                    .bit(cfg.auto_reenable)
+                    .bk2e()
+                    .set_bit()
+            });
+        }
+    }
+
+    fn disable() {
+        unsafe {
+            (*TIM1::ptr()).bdtr.modify(|_, w| w.bk2e().clear_bit());
+        }
+    }
+}
+
+fn dead_time_bits(ticks: u32) -> Option<u8> {
+    match ticks {
+        0..=127 => Some(ticks as u8),
+        128..=255 => Some(0b1000_0000 | (ticks / 2 - 64) as u8),
+        256..=511 => Some(0b1100_0000 | (ticks / 8 - 32) as u8),
+        512..=1023 => Some(0b1110_0000 | (ticks / 16 - 32) as u8),
+        _ => None,
+    }
 }
 
 macro_rules! pwm {
@@ -65,7 +351,74 @@ macro_rules! pwm {
                 )*
                 tim.cr1.write(|w| w.cen().set_bit());
                 Pwm {
-                    tim: PhantomData
+                    clk: rcc.clocks.apb_tim_clk,
+                    tim,
+                }
+            }
+
+            impl Pwm<$TIMX> {
+                /// Changes the PWM frequency and restarts the period
+                ///
+                /// Duty cycles are kept in ticks, rescale them to the new period.
+                pub fn set_freq<T>(&mut self, freq: T)
+                where
+                    T: Into<Hertz>,
+                {
+                    let cycles = self.clk.0 / freq.into().0;
+                    self.set_period_cycles(cycles);
+                }
+
+                /// Changes the PWM period and restarts it
+                pub fn set_period<T>(&mut self, period: T)
+                where
+                    T: Into<MicroSecond>,
+                {
+                    let cycles = self.clk.0 as u64 * period.into().0 as u64 / 1_000_000;
+                    assert!(cycles <= u32::MAX as u64, "period out of range");
+                    self.set_period_cycles(cycles as u32);
+                }
+
+                /// Actual PWM frequency
+                pub fn freq(&self) -> Hertz {
+                    let psc = self.tim.psc.read().bits() + 1;
+                    Hertz(self.clk.0 / psc / self.period_ticks())
+                }
+
+                /// Counter ticks per period, the duty in ticks of an always on channel
+                pub fn period_ticks(&self) -> u32 {
+                    self.tim.arr.read().bits() + 1
+                }
+
+                /// Delays compare value updates until `resume_updates`
+                ///
+                /// With preloaded compare registers, duty and phase changes of all
+                /// channels take effect together at the next period.
+                pub fn hold_updates(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.udis().set_bit());
+                }
+
+                pub fn resume_updates(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.udis().clear_bit());
+                }
+
+                fn set_period_cycles(&mut self, cycles: u32) {
+                    assert!(cycles > 1, "period out of range");
+                    let psc = (cycles - 1) / 0x1_0000;
+                    assert!(psc <= 0xffff, "period out of range");
+                    let arr = cycles / (psc + 1) - 1;
+                    self.tim.psc.write(|w| unsafe { w.bits(psc) });
+                    self.tim.arr.write(|w| unsafe { w.bits(arr) });
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                }
+
+                /// Stops the timer, disables its clock and releases the TIM peripheral
+                ///
+                /// Bound pins are returned by `PwmPin::release`.
+                pub fn release(self, rcc: &mut Rcc) -> $TIMX {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.ccer.reset();
+                    $TIMX::disable(rcc);
+                    self.tim
                 }
             }
         )+
@@ -77,7 +430,7 @@ macro_rules! pwm_hal {
         ($CH:ty, $ccxe:ident, $ccmrx_output:ident, $ocxpe:ident, $ocxm:ident, $ccrx:ident, $ccrx_l:ident, $ccrx_h:ident),)+
     ) => {
         $(
-            impl hal::PwmPin for PwmPin<$TIMX, $CH> {
+            impl<PIN> hal::PwmPin for PwmPin<$TIMX, $CH, PIN> {
                 type Duty = u32;
 
                 fn disable(&mut self) {
@@ -106,6 +459,17 @@ macro_rules! pwm_hal {
                     unsafe { (*$TIMX::ptr()).$ccrx.write(|w| w.bits(duty)) }
                 }
             }
+
+            impl<PIN> PwmPin<$TIMX, $CH, PIN> {
+                /// Sets the duty in counter ticks, up to `Pwm::period_ticks`
+                pub fn set_duty_ticks(&mut self, ticks: u32) {
+                    unsafe { (*$TIMX::ptr()).$ccrx.write(|w| w.bits(ticks)) }
+                }
+
+                pub fn duty_ticks(&self) -> u32 {
+                    unsafe { (*$TIMX::ptr()).$ccrx.read().bits() }
+                }
+            }
         )+
     };
 
@@ -113,7 +477,7 @@ macro_rules! pwm_hal {
         ($CH:ty, $ccxe:ident, $ccmrx_output:ident, $ocxpe:ident, $ocxm:ident, $ccrx:ident $(,$moe:ident)*),)+
     ) => {
         $(
-            impl hal::PwmPin for PwmPin<$TIMX, $CH> {
+            impl<PIN> hal::PwmPin for PwmPin<$TIMX, $CH, PIN> {
                 type Duty = u16;
 
                 fn disable(&mut self) {
@@ -145,10 +509,281 @@ macro_rules! pwm_hal {
                     unsafe { (*$TIMX::ptr()).$ccrx.write(|w| w.$ccrx().bits(duty)) }
                 }
             }
+
+            impl<PIN> PwmPin<$TIMX, $CH, PIN> {
+                /// Sets the duty in counter ticks, up to `Pwm::period_ticks`
+                pub fn set_duty_ticks(&mut self, ticks: u32) {
+                    assert!(ticks <= 0x1_0000);
+                    unsafe { (*$TIMX::ptr()).$ccrx.write(|w| w.bits(ticks)) }
+                }
+
+                pub fn duty_ticks(&self) -> u32 {
+                    unsafe { (*$TIMX::ptr()).$ccrx.read().bits() }
+                }
+            }
         )+
     };
 }
 
+macro_rules! pwm_alignment {
+    ($($TIMX:ident,)+) => {
+        $(
+            impl Pwm<$TIMX> {
+                /// Sets counter alignment
+                ///
+                /// In center-aligned modes the counter counts up and down, so the PWM
+                /// frequency is half of the configured one.
+                pub fn set_alignment(&mut self, align: Alignment) {
+                    unsafe {
+                        let tim = &*$TIMX::ptr();
+                        tim.cr1.modify(|_, w| w.cen().clear_bit());
+                        tim.cr1.modify(|_, w| w.cms().bits(align as u8));
+                        tim.cr1.modify(|_, w| w.cen().set_bit());
+                    }
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pwm_complementary {
+    ($($TIMX:ident: ($CH:ty, $ccxe:ident, $ccxne:ident),)+) => {
+        $(
+            impl<PIN, NPIN> hal::PwmPin for ComplementaryPwmPin<$TIMX, $CH, PIN, NPIN> {
+                type Duty = u16;
+
+                fn disable(&mut self) {
+                    unsafe {
+                        (*$TIMX::ptr()).ccer.modify(|_, w| w.$ccxe().clear_bit().$ccxne().clear_bit());
+                    }
+                }
+
+                fn enable(&mut self) {
+                    hal::PwmPin::enable(&mut self.pin);
+                    unsafe {
+                        (*$TIMX::ptr()).ccer.modify(|_, w| w.$ccxne().set_bit());
+                    }
+                }
+
+                fn get_duty(&self) -> u16 {
+                    hal::PwmPin::get_duty(&self.pin)
+                }
+
+                fn get_max_duty(&self) -> u16 {
+                    hal::PwmPin::get_max_duty(&self.pin)
+                }
+
+                fn set_duty(&mut self, duty: u16) {
+                    hal::PwmPin::set_duty(&mut self.pin, duty)
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pwm_phase {
+    ($($TIMX:ident: ($CH:ty, $PAIR:ty, $ccmrx_output:ident, $ccrx:ident, $ccry:ident, $ccxe:ident
+        $(,$moe:ident)*),)+) => {
+        $(
+            impl<PIN> PwmPin<$TIMX, $CH, PIN> {
+                /// Combines the channel with `pair` to shift the pulse within the period
+                ///
+                /// The paired channel output is not usable while combined.
+                pub fn with_phase<PPIN>(
+                    self,
+                    pair: PwmPin<$TIMX, $PAIR, PPIN>,
+                ) -> PhasedPwmPin<$TIMX, $CH, PIN, PwmPin<$TIMX, $PAIR, PPIN>> {
+                    PhasedPwmPin { pin: self, pair }
+                }
+            }
+
+            impl<PIN, PPIN> PhasedPwmPin<$TIMX, $CH, PIN, PwmPin<$TIMX, $PAIR, PPIN>> {
+                /// Places a pulse of `width` ticks starting `offset` ticks into the period
+                ///
+                /// Pulses crossing the end of the period wrap around. Channels with
+                /// different offsets produce phase shifted outputs of the same timer.
+                pub fn set_pulse(&mut self, offset: u32, width: u32) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    let period = tim.arr.read().bits() + 1;
+                    assert!(offset < period && width <= period);
+                    let end = offset + width;
+                    // Active between CCRx and CCRy, or outside of them when wrapping
+                    let (mode, pair_mode, ccrx, ccry) = if end <= period {
+                        (MODE_COMBINED2, MODE_PWM1, offset, end)
+                    } else {
+                        (MODE_COMBINED1, MODE_PWM2, end - period, offset)
+                    };
+                    tim.$ccrx.write(|w| unsafe { w.bits(ccrx) });
+                    tim.$ccry.write(|w| unsafe { w.bits(ccry) });
+                    tim.$ccmrx_output().modify(|r, w| unsafe {
+                        let mask = (OCXM_MASK | OCXPE) * 0x101;
+                        w.bits(r.bits() & !mask | output_mode(mode) | output_mode(pair_mode) << 8)
+                    });
+                }
+
+                pub fn enable(&mut self) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    tim.ccer.modify(|_, w| w.$ccxe().set_bit());
+                    $(
+                        tim.bdtr.modify(|_, w| w.$moe().set_bit());
+                    )*
+                }
+
+                pub fn disable(&mut self) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    tim.ccer.modify(|_, w| w.$ccxe().clear_bit());
+                }
+
+                /// Returns the channel to plain PWM, the paired channel is released
+                pub fn release(self) -> (PwmPin<$TIMX, $CH, PIN>, PwmPin<$TIMX, $PAIR, PPIN>) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    tim.$ccmrx_output().modify(|r, w| unsafe {
+                        let mask = (OCXM_MASK | OCXPE) * 0x101;
+                        w.bits(r.bits() & !mask | output_mode(MODE_PWM1) * 0x101)
+                    });
+                    (self.pin, self.pair)
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pwm_polarity {
+    ($($TIMX:ident: ($CH:ty, $index:expr),)+) => {
+        $(
+            impl<PIN> PwmPin<$TIMX, $CH, PIN> {
+                pub fn set_polarity(&mut self, polarity: Polarity) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    let ccxp = 1 << (4 * $index + 1);
+                    tim.ccer.modify(|r, w| unsafe {
+                        w.bits(set_bits(r.bits(), ccxp, polarity == Polarity::ActiveLow))
+                    });
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pwm_idle_state {
+    ($($TIMX:ident: ($CH:ty, $index:expr),)+) => {
+        $(
+            impl<PIN> PwmPin<$TIMX, $CH, PIN> {
+                /// Sets the output level after a break or with the main output disabled
+                pub fn set_idle_state(&mut self, state: IdleState) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    let oisx = 1 << (8 + 2 * $index);
+                    tim.cr2.modify(|r, w| unsafe {
+                        w.bits(set_bits(r.bits(), oisx, state == IdleState::High))
+                    });
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pwm_complementary_polarity {
+    ($($TIMX:ident: ($CH:ty, $index:expr),)+) => {
+        $(
+            impl<PIN, NPIN> ComplementaryPwmPin<$TIMX, $CH, PIN, NPIN> {
+                /// Sets the polarity of the CHx and CHxN outputs
+                pub fn set_polarity(&mut self, main: Polarity, complementary: Polarity) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    let ccxp = 1 << (4 * $index + 1);
+                    let ccxnp = 1 << (4 * $index + 3);
+                    tim.ccer.modify(|r, w| unsafe {
+                        let bits = set_bits(r.bits(), ccxp, main == Polarity::ActiveLow);
+                        w.bits(set_bits(bits, ccxnp, complementary == Polarity::ActiveLow))
+                    });
+                }
+
+                /// Sets the CHx and CHxN levels after a break
+                ///
+                /// Both idle states should not be active at once on a bridge.
+                pub fn set_idle_state(&mut self, main: IdleState, complementary: IdleState) {
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    let oisx = 1 << (8 + 2 * $index);
+                    let oisxn = 1 << (9 + 2 * $index);
+                    tim.cr2.modify(|r, w| unsafe {
+                        let bits = set_bits(r.bits(), oisx, main == IdleState::High);
+                        w.bits(set_bits(bits, oisxn, complementary == IdleState::High))
+                    });
+                }
+            }
+        )+
+    };
+}
+
+fn set_bits(bits: u32, mask: u32, set: bool) -> u32 {
+    if set {
+        bits | mask
+    } else {
+        bits & !mask
+    }
+}
+
+/// Preloaded output compare mode bits of the first channel of a CCMR register
+fn output_mode(mode: u32) -> u32 {
+    ((mode & 0b111) << OCXM_SHIFT) | ((mode >> 3) * OCXM_3) | OCXPE
+}
+
+pwm_phase! {
+    TIM1: (Channel1, Channel2, ccmr1_output, ccr1, ccr2, cc1e, moe),
+    TIM1: (Channel3, Channel4, ccmr2_output, ccr3, ccr4, cc3e, moe),
+    TIM2: (Channel1, Channel2, ccmr1_output, ccr1, ccr2, cc1e),
+    TIM2: (Channel3, Channel4, ccmr2_output, ccr3, ccr4, cc3e),
+    TIM3: (Channel1, Channel2, ccmr1_output, ccr1, ccr2, cc1e),
+    TIM3: (Channel3, Channel4, ccmr2_output, ccr3, ccr4, cc3e),
+}
+
+pwm_polarity! {
+    TIM1: (Channel1, 0),
+    TIM1: (Channel2, 1),
+    TIM1: (Channel3, 2),
+    TIM1: (Channel4, 3),
+    TIM2: (Channel1, 0),
+    TIM2: (Channel2, 1),
+    TIM2: (Channel3, 2),
+    TIM2: (Channel4, 3),
+    TIM3: (Channel1, 0),
+    TIM3: (Channel2, 1),
+    TIM3: (Channel3, 2),
+    TIM3: (Channel4, 3),
+    TIM14: (Channel1, 0),
+    TIM16: (Channel1, 0),
+    TIM17: (Channel1, 0),
+}
+
+pwm_idle_state! {
+    TIM1: (Channel1, 0),
+    TIM1: (Channel2, 1),
+    TIM1: (Channel3, 2),
+    TIM1: (Channel4, 3),
+    TIM16: (Channel1, 0),
+    TIM17: (Channel1, 0),
+}
+
+pwm_complementary_polarity! {
+    TIM1: (Channel1, 0),
+    TIM1: (Channel2, 1),
+    TIM1: (Channel3, 2),
+    TIM16: (Channel1, 0),
+    TIM17: (Channel1, 0),
+}
+
+pwm_alignment! {
+    TIM1,
+    TIM2,
+    TIM3,
+}
+
+pwm_complementary! {
+    TIM1: (Channel1, cc1e, cc1ne),
+    TIM1: (Channel2, cc2e, cc2ne),
+    TIM1: (Channel3, cc3e, cc3ne),
+    TIM16: (Channel1, cc1e, cc1ne),
+    TIM17: (Channel1, cc1e, cc1ne),
+}
+
 pwm_hal! {
     TIM1:  (Channel1, cc1e, ccmr1_output, oc1pe, oc1m, ccr1, moe),
     TIM1:  (Channel2, cc2e, ccmr1_output, oc2pe, oc2m, ccr2, moe),
@@ -175,6 +810,86 @@ pwm_hal! {
     TIM15: (Channel1, cc1e, ccmr1_output, oc1pe, oc1m, ccr1, moe),
 }
 
+// The PAC shares the TIM16 register block with TIM15, channel 2 is written by raw bits
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+const TIM15_CCR2_OFFSET: usize = 0x38;
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+fn tim15_ccr2() -> *mut u32 {
+    unsafe { (TIM15::ptr() as *mut u8).add(TIM15_CCR2_OFFSET) as *mut u32 }
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+impl<PIN> hal::PwmPin for PwmPin<TIM15, Channel2, PIN> {
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        unsafe {
+            (*TIM15::ptr())
+                .ccer
+                .modify(|r, w| w.bits(r.bits() & !(1 << 4)));
+        }
+    }
+
+    fn enable(&mut self) {
+        unsafe {
+            let tim = &*TIM15::ptr();
+            tim.ccmr1_output().modify(|r, w| {
+                w.bits(r.bits() & !(OCXM_MASK << 8) | (OCXPE | MODE_PWM1 << OCXM_SHIFT) << 8)
+            });
+            tim.ccer.modify(|r, w| w.bits(r.bits() | 1 << 4));
+            tim.bdtr.modify(|_, w| w.moe().set_bit());
+        }
+    }
+
+    fn get_duty(&self) -> u16 {
+        unsafe { core::ptr::read_volatile(tim15_ccr2()) as u16 }
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        unsafe { (*TIM15::ptr()).arr.read().arr().bits() }
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        unsafe { core::ptr::write_volatile(tim15_ccr2(), duty as u32) }
+    }
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+impl<PIN> PwmPin<TIM15, Channel2, PIN> {
+    /// Sets the duty in counter ticks, up to `Pwm::period_ticks`
+    pub fn set_duty_ticks(&mut self, ticks: u32) {
+        assert!(ticks <= 0x1_0000);
+        unsafe { core::ptr::write_volatile(tim15_ccr2(), ticks) }
+    }
+
+    pub fn duty_ticks(&self) -> u32 {
+        unsafe { core::ptr::read_volatile(tim15_ccr2()) }
+    }
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pwm_polarity! {
+    TIM15: (Channel1, 0),
+    TIM15: (Channel2, 1),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pwm_idle_state! {
+    TIM15: (Channel1, 0),
+    TIM15: (Channel2, 1),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pwm_complementary! {
+    TIM15: (Channel1, cc1e, cc1ne),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+pwm_complementary_polarity! {
+    TIM15: (Channel1, 0),
+}
+
 pwm! {
     TIM1: (apbenr2, apbrstr2, tim1, tim1en, tim1rst, arr),
     TIM2: (apbenr1, apbrstr1, tim2, tim2en, tim2rst, arr_l, arr_h),
@@ -0,0 +1,134 @@
+//! # Soft-start PWM ramp
+//!
+//! Ramps a PWM duty cycle from zero to the target over a configured time,
+//! e.g. to limit inrush current of motors and solenoids. An ADC analog
+//! watchdog on a current sense channel can pause the ramp while the current
+//! exceeds the limit. `tick()` should be called from a periodic timer
+//! interrupt.
+use crate::analog::adc::{Adc, AnalogWatchdog, Continuous, Event};
+use hal::adc::Channel;
+use hal::PwmPin;
+
+/// Ramp progress
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RampState {
+    /// Duty cycle is increasing
+    Ramping,
+    /// Ramp is paused, current above the limit
+    Limited,
+    /// Target duty cycle reached
+    Done,
+}
+
+/// PWM output ramping up to a target duty cycle
+pub struct SoftStart<P> {
+    pin: P,
+    // duty in 16.16 fixed point
+    level: u32,
+    step: u32,
+    target: u16,
+    limit: Option<(Adc<Continuous>, AnalogWatchdog)>,
+}
+
+impl<P> SoftStart<P>
+where
+    P: PwmPin<Duty = u16>,
+{
+    /// Starts ramping `pin` to `target` duty within `ramp_ms`
+    ///
+    /// `tick()` must be called every `tick_ms` milliseconds.
+    pub fn new(mut pin: P, target: u16, ramp_ms: u32, tick_ms: u32) -> Self {
+        assert!(tick_ms > 0);
+        let ticks = (ramp_ms / tick_ms).max(1);
+        pin.set_duty(0);
+        pin.enable();
+        SoftStart {
+            pin,
+            level: 0,
+            step: ((target as u32) << 16) / ticks,
+            target,
+            limit: None,
+        }
+    }
+
+    /// Pauses the ramp while the current sense reading exceeds `limit`
+    ///
+    /// Converts `sense` continuously and guards it with the analog
+    /// watchdog `awd`. `limit` is a 12 bit ADC reading. The ADC is returned
+    /// by `release`.
+    pub fn with_current_limit<PIN>(
+        mut self,
+        mut adc: Adc,
+        sense: &mut PIN,
+        awd: AnalogWatchdog,
+        limit: u16,
+    ) -> Self
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        adc.watchdog_channel(awd, &*sense);
+        adc.set_watchdog_thresholds(awd, 0, limit);
+        adc.unpend(watchdog_event(awd));
+        self.limit = Some((adc.start_continuous(sense), awd));
+        self
+    }
+
+    /// Advances the ramp
+    pub fn tick(&mut self) -> RampState {
+        let target = (self.target as u32) << 16;
+        if self.level >= target {
+            return RampState::Done;
+        }
+
+        if let Some((adc, awd)) = &mut self.limit {
+            // The watchdog flag is set by any conversion above the limit
+            // since the last tick
+            let event = watchdog_event(*awd);
+            if adc.is_pending(event) {
+                adc.clear_interrupt(event);
+                return RampState::Limited;
+            }
+        }
+
+        self.level = self.level.saturating_add(self.step).min(target);
+        self.pin.set_duty((self.level >> 16) as u16);
+        if self.level >= target {
+            RampState::Done
+        } else {
+            RampState::Ramping
+        }
+    }
+
+    /// Changes the target duty, ramping up or jumping down to it
+    pub fn set_target(&mut self, target: u16) {
+        self.target = target;
+        if (self.level >> 16) as u16 > target {
+            self.level = (target as u32) << 16;
+            self.pin.set_duty(target);
+        }
+    }
+
+    /// Current duty cycle
+    pub fn duty(&self) -> u16 {
+        (self.level >> 16) as u16
+    }
+
+    /// Stops the current limit conversions, returns the PWM pin and the ADC
+    pub fn release(self) -> (P, Option<Adc>) {
+        let adc = self.limit.map(|(adc, awd)| {
+            let mut adc = adc.into_single_shot();
+            adc.watchdog_disable(awd);
+            adc
+        });
+        (self.pin, adc)
+    }
+}
+
+fn watchdog_event(awd: AnalogWatchdog) -> Event {
+    match awd {
+        AnalogWatchdog::AWD1 => Event::AnalogWatchdog1,
+        AnalogWatchdog::AWD2 => Event::AnalogWatchdog2,
+        AnalogWatchdog::AWD3 => Event::AnalogWatchdog3,
+    }
+}
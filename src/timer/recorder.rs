@@ -0,0 +1,194 @@
+//! # Edge recorder
+//!
+//! Records the timestamps of both edges of a signal on a timer channel 1
+//! pin with 1µs resolution. Input capture requests DMA on every edge, so
+//! no edges are lost to interrupt latency until the buffer is full. The
+//! recording is read back as `(level, duration)` pairs, e.g. to dump an
+//! unknown signal over the debug UART.
+//!
+//! Gaps between edges must stay below the counter range: 65ms for 16 bit
+//! timers, 71 minutes for TIM2.
+use core::fmt;
+
+use crate::dma::{DmaChannel, DmaMuxIndex, TransferDirection, WordSize};
+use crate::rcc::Rcc;
+use crate::stm32::*;
+use crate::timer::pins::TimerPin;
+use crate::timer::Channel1;
+use hal::digital::v2::InputPin;
+
+/// Timestamps of recorded edges
+pub struct EdgeRecorder<TIM, PIN, CHANNEL, WORD: 'static> {
+    tim: TIM,
+    pin: PIN,
+    channel: CHANNEL,
+    buffer: &'static mut [WORD],
+    initial: bool,
+}
+
+/// Recorded `(level, duration in µs)` pairs
+pub struct Edges<'a, WORD> {
+    timestamps: &'a [WORD],
+    level: bool,
+    idx: usize,
+}
+
+pub trait EdgeRecorderExt<PIN, WORD>: Sized {
+    /// Starts recording edges of `pin` into `buffer`
+    fn edge_recorder<CHANNEL>(
+        self,
+        pin: PIN,
+        channel: CHANNEL,
+        buffer: &'static mut [WORD],
+        rcc: &mut Rcc,
+    ) -> EdgeRecorder<Self, PIN, CHANNEL, WORD>
+    where
+        CHANNEL: DmaChannel;
+}
+
+impl<TIM, PIN, CHANNEL, WORD> EdgeRecorder<TIM, PIN, CHANNEL, WORD>
+where
+    CHANNEL: DmaChannel,
+    WORD: Copy + Into<u32>,
+{
+    /// Number of edges recorded so far
+    pub fn recorded(&self) -> usize {
+        self.buffer.len() - self.channel.get_transfer_remaining() as usize
+    }
+
+    /// Returns `true` once the buffer is full
+    pub fn is_full(&self) -> bool {
+        self.channel.get_transfer_remaining() == 0
+    }
+
+    /// Level before the first recorded edge
+    pub fn initial_level(&self) -> bool {
+        self.initial
+    }
+
+    /// Iterates over `(level, duration in µs)` pairs between recorded edges
+    pub fn edges(&self) -> Edges<'_, WORD> {
+        Edges {
+            timestamps: &self.buffer[..self.recorded()],
+            // The level after the first edge
+            level: !self.initial,
+            idx: 0,
+        }
+    }
+
+    /// Writes one `level duration_us` line per recorded pair
+    pub fn dump<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        for (level, duration) in self.edges() {
+            writeln!(out, "{} {}", level as u8, duration)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, WORD> Iterator for Edges<'a, WORD>
+where
+    WORD: Copy + Into<u32>,
+{
+    type Item = (bool, u32);
+
+    fn next(&mut self) -> Option<(bool, u32)> {
+        let start: u32 = (*self.timestamps.get(self.idx)?).into();
+        let end: u32 = (*self.timestamps.get(self.idx + 1)?).into();
+        let level = self.level;
+        self.level = !self.level;
+        self.idx += 1;
+        Some((level, end.wrapping_sub(start) & max_count::<WORD>()))
+    }
+}
+
+/// Counter mask for the timestamp word size
+fn max_count<WORD>() -> u32 {
+    match core::mem::size_of::<WORD>() {
+        2 => 0xffff,
+        _ => 0xffff_ffff,
+    }
+}
+
+macro_rules! edge_recorder {
+    ($($TIMX:ident: ($timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $word:ty, $size:ident, $req:ident),)+) => {
+        $(
+            impl<PIN> EdgeRecorderExt<PIN, $word> for $TIMX
+            where
+                PIN: TimerPin<$TIMX, Channel = Channel1> + InputPin,
+            {
+                fn edge_recorder<CHANNEL>(
+                    self,
+                    pin: PIN,
+                    mut channel: CHANNEL,
+                    buffer: &'static mut [$word],
+                    rcc: &mut Rcc,
+                ) -> EdgeRecorder<$TIMX, PIN, CHANNEL, $word>
+                where
+                    CHANNEL: DmaChannel,
+                {
+                    assert!(buffer.len() >= 2);
+                    rcc.rb.$apbenr.modify(|_, w| w.$timXen().set_bit());
+                    rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().set_bit());
+                    rcc.rb.$apbrstr.modify(|_, w| w.$timXrst().clear_bit());
+
+                    // 1MHz counter
+                    let psc = rcc.clocks.apb_tim_clk.0 / 1_000_000;
+                    assert!(psc > 0 && psc <= 0x1_0000);
+                    let tim = self;
+                    tim.psc.write(|w| unsafe { w.bits(psc - 1) });
+                    tim.arr.write(|w| unsafe { w.bits(max_count::<$word>()) });
+                    tim.egr.write(|w| w.ug().set_bit());
+                    // CC1S: TI1
+                    tim.ccmr1_input().write(|w| unsafe { w.bits(0b01) });
+
+                    channel.set_direction(TransferDirection::PeriphToMemory);
+                    channel.set_word_size(WordSize::$size);
+                    channel.set_peripheral_address(&tim.ccr1 as *const _ as u32, false);
+                    channel.set_memory_address(buffer.as_ptr() as u32, true);
+                    channel.set_transfer_length(buffer.len());
+                    channel.select_peripheral(DmaMuxIndex::$req);
+                    channel.start();
+
+                    pin.setup();
+                    let initial = pin.is_high().unwrap_or(false);
+                    // CC1DE, then CC1E on both edges (CC1P, CC1NP)
+                    tim.dier.write(|w| unsafe { w.bits(1 << 9) });
+                    tim.ccer.write(|w| unsafe { w.bits(1 | 1 << 1 | 1 << 3) });
+                    tim.cr1.write(|w| w.cen().set_bit());
+
+                    EdgeRecorder {
+                        tim,
+                        pin,
+                        channel,
+                        buffer,
+                        initial,
+                    }
+                }
+            }
+
+            impl<PIN, CHANNEL: DmaChannel> EdgeRecorder<$TIMX, PIN, CHANNEL, $word> {
+                /// Stops recording, returning timer, pin, DMA channel and buffer
+                pub fn release(mut self) -> ($TIMX, PIN, CHANNEL, &'static mut [$word]) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.dier.write(|w| unsafe { w.bits(0) });
+                    self.tim.ccer.write(|w| unsafe { w.bits(0) });
+                    self.channel.stop();
+                    (self.tim, self.pin, self.channel, self.buffer)
+                }
+            }
+        )+
+    };
+}
+
+edge_recorder! {
+    TIM1: (tim1en, tim1rst, apbenr2, apbrstr2, u16, Bits16, TIM1_CH1),
+    TIM2: (tim2en, tim2rst, apbenr1, apbrstr1, u32, Bits32, TIM2_CH1),
+    TIM3: (tim3en, tim3rst, apbenr1, apbrstr1, u16, Bits16, TIM3_CH1),
+    TIM16: (tim16en, tim16rst, apbenr2, apbrstr2, u16, Bits16, TIM16_CH1),
+    TIM17: (tim17en, tim17rst, apbenr2, apbrstr2, u16, Bits16, TIM17_CH1),
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+edge_recorder! {
+    TIM15: (tim15en, tim15rst, apbenr2, apbrstr2, u16, Bits16, TIM15_CH1),
+}
@@ -0,0 +1,196 @@
+//! # Timer synchronization
+//!
+//! Master timers drive their TRGO output, slave timers select another timer
+//! as trigger input (ITRx) and follow it in reset, gated or triggered mode.
+//! Only valid master/slave pairs implement `InternalTrigger`. TIM1, TIM2,
+//! TIM3 and TIM15 have a slave mode controller, TIM16 and TIM17 only drive
+//! their internal trigger.
+use crate::stm32::*;
+use crate::timer::capture::InputCapture;
+use crate::timer::pwm::Pwm;
+use crate::timer::Timer;
+use core::ptr;
+
+const SMCR_OFFSET: usize = 0x08;
+
+/// Trigger output (TRGO) source
+#[derive(Clone, Copy, PartialEq)]
+pub enum MasterMode {
+    /// UG bit of the master
+    Reset = 0b000,
+    /// Counter enable
+    Enable = 0b001,
+    /// Update event
+    Update = 0b010,
+    /// Capture/compare 1 match
+    ComparePulse = 0b011,
+    /// OC1REF signal
+    Compare1 = 0b100,
+    /// OC2REF signal
+    Compare2 = 0b101,
+    /// OC3REF signal
+    Compare3 = 0b110,
+    /// OC4REF signal
+    Compare4 = 0b111,
+}
+
+/// Slave mode controller configuration
+#[derive(Clone, Copy, PartialEq)]
+pub enum SlaveMode {
+    /// Slave mode controller disabled
+    Disabled = 0b0000,
+    /// Trigger rising edge reinitializes the counter
+    Reset = 0b0100,
+    /// Counter runs while trigger input is high
+    Gated = 0b0101,
+    /// Trigger rising edge starts the counter
+    Trigger = 0b0110,
+    /// Trigger rising edges clock the counter
+    ExternalClock = 0b0111,
+    /// Trigger rising edge reinitializes and starts the counter
+    ResetTrigger = 0b1000,
+}
+
+/// Internal trigger connection from `MASTER` timer
+pub trait InternalTrigger<MASTER> {
+    const ITR: u8;
+}
+
+/// Timer driving the trigger output
+pub trait MasterTimer {
+    /// Selects TRGO source
+    fn set_master_mode(&mut self, mode: MasterMode);
+}
+
+/// Timer following another timer
+pub trait SlaveTimer<TIM> {
+    /// Selects `MASTER` timer as trigger input and sets slave mode
+    fn set_slave_mode<MASTER>(&mut self, mode: SlaveMode)
+    where
+        TIM: InternalTrigger<MASTER>;
+
+    /// Disables slave mode controller
+    fn disable_slave_mode(&mut self);
+}
+
+macro_rules! internal_triggers {
+    ($($TIM:ident: [$($MASTER:ident: $itr:expr,)+],)+) => {
+        $(
+            $(
+                impl InternalTrigger<$MASTER> for $TIM {
+                    const ITR: u8 = $itr;
+                }
+            )+
+        )+
+    }
+}
+
+macro_rules! master_timers {
+    ($($TIM:ident: [$($owner:ty,)+],)+) => {
+        $(
+            $(
+                impl MasterTimer for $owner {
+                    fn set_master_mode(&mut self, mode: MasterMode) {
+                        let tim = unsafe { &*$TIM::ptr() };
+                        tim.cr2.modify(|r, w| unsafe {
+                            w.bits((r.bits() & !(0b111 << 4)) | ((mode as u32) << 4))
+                        });
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+macro_rules! slave_timers {
+    ($($TIM:ident: [$($owner:ty,)+],)+) => {
+        $(
+            $(
+                impl SlaveTimer<$TIM> for $owner {
+                    fn set_slave_mode<MASTER>(&mut self, mode: SlaveMode)
+                    where
+                        $TIM: InternalTrigger<MASTER>,
+                    {
+                        let sms = mode as u32;
+                        let ts = <$TIM as InternalTrigger<MASTER>>::ITR as u32;
+                        // SMS[3] lives at bit 16, TS[4:3] at bits 21:20
+                        let mask = 0b111 | (0b111 << 4) | (1 << 16) | (0b11 << 20);
+                        let bits = (sms & 0b111) | ((sms >> 3) << 16) | (ts << 4);
+                        modify_smcr($TIM::ptr() as *const u8, |smcr| (smcr & !mask) | bits);
+                    }
+
+                    fn disable_slave_mode(&mut self) {
+                        modify_smcr($TIM::ptr() as *const u8, |smcr| smcr & !(0b111 | (1 << 16)));
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+impl Timer<TIM1> {
+    /// Selects TRGO2 source, used to trigger ADC conversions
+    pub fn set_master_mode2(&mut self, mode: MasterMode) {
+        set_tim1_master_mode2(mode)
+    }
+}
+
+impl Pwm<TIM1> {
+    /// Selects TRGO2 source, used to trigger ADC conversions
+    pub fn set_master_mode2(&mut self, mode: MasterMode) {
+        set_tim1_master_mode2(mode)
+    }
+}
+
+/// SMCR is written by offset, the PAC shares the TIM16 register block with TIM15
+fn modify_smcr<F: FnOnce(u32) -> u32>(tim: *const u8, f: F) {
+    unsafe {
+        let smcr = tim.add(SMCR_OFFSET) as *mut u32;
+        ptr::write_volatile(smcr, f(ptr::read_volatile(smcr)));
+    }
+}
+
+fn set_tim1_master_mode2(mode: MasterMode) {
+    let tim = unsafe { &*TIM1::ptr() };
+    tim.cr2.modify(|r, w| unsafe {
+        w.bits((r.bits() & !(0b1111 << 20)) | ((mode as u32) << 20))
+    });
+}
+
+internal_triggers! {
+    TIM1: [TIM2: 1, TIM3: 2, TIM17: 3,],
+    TIM2: [TIM1: 0, TIM3: 2, TIM14: 3,],
+    TIM3: [TIM1: 0, TIM2: 1, TIM14: 3,],
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+internal_triggers! {
+    TIM1: [TIM15: 0,],
+    TIM2: [TIM15: 1,],
+    TIM3: [TIM15: 2,],
+    TIM15: [TIM2: 0, TIM3: 1, TIM16: 2, TIM17: 3,],
+}
+
+master_timers! {
+    TIM1: [Timer<TIM1>, Pwm<TIM1>, InputCapture<TIM1>,],
+    TIM2: [Timer<TIM2>, Pwm<TIM2>, InputCapture<TIM2>,],
+    TIM3: [Timer<TIM3>, Pwm<TIM3>, InputCapture<TIM3>,],
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+master_timers! {
+    TIM6: [Timer<TIM6>,],
+    TIM7: [Timer<TIM7>,],
+    TIM15: [Timer<TIM15>, Pwm<TIM15>, InputCapture<TIM15>,],
+}
+
+slave_timers! {
+    TIM1: [Timer<TIM1>, Pwm<TIM1>, InputCapture<TIM1>,],
+    TIM2: [Timer<TIM2>, Pwm<TIM2>, InputCapture<TIM2>,],
+    TIM3: [Timer<TIM3>, Pwm<TIM3>, InputCapture<TIM3>,],
+}
+
+#[cfg(any(feature = "stm32g07x", feature = "stm32g081"))]
+slave_timers! {
+    TIM15: [Timer<TIM15>, Pwm<TIM15>, InputCapture<TIM15>,],
+}
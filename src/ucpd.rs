@@ -0,0 +1,647 @@
+//! # USB Type-C / Power Delivery interface
+//!
+//! UCPD handles the CC lines of a USB-C port: it applies Rp or Rd, reports
+//! the voltage state of both CC lines and sends and receives USB-PD
+//! messages with BMC coding and hardware CRC. GoodCRC replies and message
+//! IDs are up to software, see [`SinkPolicy`] for a minimal sink that
+//! negotiates a fixed voltage contract.
+//!
+//! The kernel clock is HSI16, divided to 8MHz with a ~300kbit/s bit rate.
+use crate::rcc::Rcc;
+use crate::stm32::{SYSCFG_VREFBUF, UCPD1, UCPD2};
+
+// CFG1
+const HBITCLKDIV: u32 = 13;
+const IFRGAP: u32 = 16 << 6;
+const TRANSWIN: u32 = 7 << 11;
+const PSC_DIV2: u32 = 1 << 17;
+// SOP and Hard Reset
+const RXORDSETEN: u32 = 0b1001 << 20;
+const UCPDEN: u32 = 1 << 31;
+
+// CR
+const TXSEND: u32 = 1 << 2;
+const TXHRST: u32 = 1 << 3;
+const PHYRXEN: u32 = 1 << 5;
+const PHYCCSEL: u32 = 1 << 6;
+const ANASUBMODE_MASK: u32 = 0b11 << 7;
+const ANAMODE: u32 = 1 << 9;
+const CCENABLE_MASK: u32 = 0b11 << 10;
+
+// SR / ICR
+const TXIS: u32 = 1 << 0;
+const TXMSGDISC: u32 = 1 << 1;
+const TXMSGSENT: u32 = 1 << 2;
+const TXMSGABT: u32 = 1 << 3;
+const HRSTDISC: u32 = 1 << 4;
+const HRSTSENT: u32 = 1 << 5;
+const TXUND: u32 = 1 << 6;
+const RXNE: u32 = 1 << 8;
+const RXHRSTDET: u32 = 1 << 10;
+const RXOVR: u32 = 1 << 11;
+const RXMSGEND: u32 = 1 << 12;
+const RXERR: u32 = 1 << 13;
+
+/// SOP ordered set: Sync-1, Sync-1, Sync-1, Sync-2
+const ORDSET_SOP: u32 = 0x18 | 0x18 << 5 | 0x18 << 10 | 0x11 << 15;
+
+/// Header plus up to 7 data objects
+const MAX_MESSAGE_LEN: usize = 30;
+
+/// CC line
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CcLine {
+    Cc1,
+    Cc2,
+}
+
+/// Rp current advertisement of a source
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RpCurrent {
+    /// Default USB power (500mA/900mA)
+    Default = 1,
+    /// 1.5A
+    Current1A5 = 2,
+    /// 3A
+    Current3A0 = 3,
+}
+
+/// CC line voltage state seen by a sink
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CcState {
+    /// Nothing attached or Ra
+    Open,
+    /// Source advertising default USB power
+    Default,
+    /// Source advertising 1.5A
+    Current1A5,
+    /// Source advertising 3A
+    Current3A0,
+}
+
+/// UCPD errors
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Transmission discarded by an incoming message
+    Discarded,
+    /// Transmission aborted
+    Aborted,
+    /// Transmit data register was not written in time
+    Underrun,
+    /// Received message failed the CRC check
+    Crc,
+    /// Received data was not read in time
+    Overrun,
+    /// Hard reset received
+    HardReset,
+    /// Received message has an invalid length
+    InvalidLength,
+}
+
+/// UCPD interrupt events
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// Transmit data register empty
+    TxEmpty = 1 << 0,
+    /// Message sent
+    TxMessageSent = 1 << 2,
+    /// Receive data register not empty
+    RxNotEmpty = 1 << 8,
+    /// Hard reset received
+    RxHardReset = 1 << 10,
+    /// Message received
+    RxMessageEnd = 1 << 12,
+    /// CC1 state changed
+    TypeC1 = 1 << 14,
+    /// CC2 state changed
+    TypeC2 = 1 << 15,
+}
+
+/// USB-PD message
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message {
+    pub header: u16,
+    pub objects: [u32; 7],
+}
+
+impl Message {
+    /// Control message without data objects
+    pub fn control(message_type: u8, id: u8, revision: u8) -> Self {
+        Message::data(message_type, id, revision, &[])
+    }
+
+    /// Data message, sent by a sink in the UFP data role
+    pub fn data(message_type: u8, id: u8, revision: u8, objects: &[u32]) -> Self {
+        assert!(objects.len() <= 7);
+        let mut msg = Message {
+            header: (message_type as u16 & 0x1f)
+                | (revision as u16 & 0b11) << 6
+                | (id as u16 & 0b111) << 9
+                | (objects.len() as u16) << 12,
+            objects: [0; 7],
+        };
+        msg.objects[..objects.len()].copy_from_slice(objects);
+        msg
+    }
+
+    pub fn message_type(&self) -> u8 {
+        (self.header & 0x1f) as u8
+    }
+
+    pub fn message_id(&self) -> u8 {
+        (self.header >> 9 & 0b111) as u8
+    }
+
+    /// Specification revision, 1 for USB-PD 2.0, 2 for USB-PD 3.0
+    pub fn revision(&self) -> u8 {
+        (self.header >> 6 & 0b11) as u8
+    }
+
+    pub fn num_objects(&self) -> usize {
+        (self.header >> 12 & 0b111) as usize
+    }
+
+    /// Returns `true` for control messages
+    pub fn is_control(&self) -> bool {
+        self.num_objects() == 0
+    }
+
+    /// Data objects
+    pub fn data_objects(&self) -> &[u32] {
+        &self.objects[..self.num_objects()]
+    }
+
+    fn len(&self) -> usize {
+        2 + 4 * self.num_objects()
+    }
+
+    fn byte(&self, idx: usize) -> u8 {
+        match idx {
+            0 => self.header as u8,
+            1 => (self.header >> 8) as u8,
+            _ => (self.objects[(idx - 2) / 4] >> (8 * ((idx - 2) % 4))) as u8,
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        // 16-bit header followed by whole 32-bit data objects
+        if bytes.len() < 2 || (bytes.len() - 2) & 3 != 0 {
+            return Err(Error::InvalidLength);
+        }
+        let mut msg = Message {
+            header: u16::from_le_bytes([bytes[0], bytes[1]]),
+            objects: [0; 7],
+        };
+        if msg.len() != bytes.len() {
+            return Err(Error::InvalidLength);
+        }
+        for (obj, chunk) in msg.objects.iter_mut().zip(bytes[2..].chunks(4)) {
+            *obj = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        Ok(msg)
+    }
+}
+
+/// UCPD port
+pub struct Ucpd<UCPD> {
+    rb: UCPD,
+    rx: [u8; MAX_MESSAGE_LEN],
+    rx_len: usize,
+}
+
+pub trait UcpdExt: Sized {
+    fn constrain(self, rcc: &mut Rcc) -> Ucpd<Self>;
+}
+
+macro_rules! ucpd {
+    ($($UCPDX:ident: ($ucpdXen:ident, $ucpdXrst:ident, $strobe:expr),)+) => {
+        $(
+            impl UcpdExt for $UCPDX {
+                fn constrain(self, rcc: &mut Rcc) -> Ucpd<$UCPDX> {
+                    rcc.rb.apbenr1.modify(|_, w| w.$ucpdXen().set_bit());
+                    rcc.rb.apbrstr1.modify(|_, w| w.$ucpdXrst().set_bit());
+                    rcc.rb.apbrstr1.modify(|_, w| w.$ucpdXrst().clear_bit());
+                    rcc.rb.apbenr2.modify(|_, w| w.syscfgen().set_bit());
+
+                    self.cfg1.write(|w| unsafe {
+                        w.bits(HBITCLKDIV | IFRGAP | TRANSWIN | PSC_DIV2 | RXORDSETEN)
+                    });
+                    self.cfg1.modify(|r, w| unsafe { w.bits(r.bits() | UCPDEN) });
+
+                    Ucpd {
+                        rb: self,
+                        rx: [0; MAX_MESSAGE_LEN],
+                        rx_len: 0,
+                    }
+                }
+            }
+
+            impl Ucpd<$UCPDX> {
+                /// Presents Rd on both CC lines
+                ///
+                /// Also releases the dead battery pull-downs so the UCPD
+                /// takes over the CC lines.
+                pub fn sink(&mut self) {
+                    self.rb.cr.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !ANASUBMODE_MASK | ANAMODE | CCENABLE_MASK)
+                    });
+                    let syscfg = unsafe { &(*SYSCFG_VREFBUF::ptr()) };
+                    syscfg.cfgr1.modify(|r, w| unsafe { w.bits(r.bits() | 1 << $strobe) });
+                }
+
+                /// Presents Rp advertising `current` on both CC lines
+                pub fn source(&mut self, current: RpCurrent) {
+                    self.rb.cr.modify(|r, w| unsafe {
+                        w.bits(
+                            r.bits() & !(ANASUBMODE_MASK | ANAMODE)
+                                | (current as u32) << 7
+                                | CCENABLE_MASK,
+                        )
+                    });
+                    let syscfg = unsafe { &(*SYSCFG_VREFBUF::ptr()) };
+                    syscfg.cfgr1.modify(|r, w| unsafe { w.bits(r.bits() | 1 << $strobe) });
+                }
+
+                /// Disconnects the pull resistors from both CC lines
+                pub fn detach(&mut self) {
+                    self.disable_phy();
+                    self.rb
+                        .cr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !CCENABLE_MASK) });
+                }
+
+                /// Voltage state of a CC line when acting as sink
+                pub fn cc_state(&self, line: CcLine) -> CcState {
+                    let shift = match line {
+                        CcLine::Cc1 => 16,
+                        CcLine::Cc2 => 18,
+                    };
+                    match self.rb.sr.read().bits() >> shift & 0b11 {
+                        0 => CcState::Open,
+                        1 => CcState::Default,
+                        2 => CcState::Current1A5,
+                        _ => CcState::Current3A0,
+                    }
+                }
+
+                /// CC line a source is attached to, if any
+                pub fn attached(&self) -> Option<CcLine> {
+                    if self.cc_state(CcLine::Cc1) != CcState::Open {
+                        Some(CcLine::Cc1)
+                    } else if self.cc_state(CcLine::Cc2) != CcState::Open {
+                        Some(CcLine::Cc2)
+                    } else {
+                        None
+                    }
+                }
+
+                /// Enables the PD transceiver on `line`
+                pub fn enable_phy(&mut self, line: CcLine) {
+                    self.rx_len = 0;
+                    self.rb.icr.write(|w| unsafe { w.bits(RXHRSTDET | RXOVR | RXMSGEND) });
+                    self.rb.cr.modify(|r, w| unsafe {
+                        let bits = r.bits() & !PHYCCSEL | PHYRXEN;
+                        w.bits(match line {
+                            CcLine::Cc1 => bits,
+                            CcLine::Cc2 => bits | PHYCCSEL,
+                        })
+                    });
+                }
+
+                /// Disables the PD transceiver
+                pub fn disable_phy(&mut self) {
+                    self.rb
+                        .cr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !PHYRXEN) });
+                }
+
+                /// Sends `msg` on the SOP channel, blocking until it is sent
+                ///
+                /// The CRC is appended by hardware. Does not wait for the
+                /// GoodCRC reply.
+                pub fn transmit(&mut self, msg: &Message) -> Result<(), Error> {
+                    self.rb.tx_ordset.write(|w| unsafe { w.bits(ORDSET_SOP) });
+                    self.rb.tx_paysz.write(|w| unsafe { w.bits(msg.len() as u32) });
+                    // TXMODE: SOP* packet
+                    self.rb
+                        .cr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !0b11 | TXSEND) });
+
+                    let mut idx = 0;
+                    loop {
+                        let sr = self.rb.sr.read().bits();
+                        if sr & TXIS != 0 && idx < msg.len() {
+                            self.rb.txdr.write(|w| unsafe { w.bits(msg.byte(idx) as u32) });
+                            idx += 1;
+                        }
+                        let done = sr & (TXMSGSENT | TXMSGDISC | TXMSGABT | TXUND);
+                        if done != 0 {
+                            self.rb.icr.write(|w| unsafe { w.bits(done) });
+                            return if done & TXMSGDISC != 0 {
+                                Err(Error::Discarded)
+                            } else if done & TXUND != 0 {
+                                Err(Error::Underrun)
+                            } else if done & TXMSGABT != 0 {
+                                Err(Error::Aborted)
+                            } else {
+                                Ok(())
+                            };
+                        }
+                    }
+                }
+
+                /// Sends a hard reset, blocking until it is sent
+                pub fn send_hard_reset(&mut self) -> Result<(), Error> {
+                    self.rb.cr.modify(|r, w| unsafe { w.bits(r.bits() | TXHRST) });
+                    loop {
+                        let sr = self.rb.sr.read().bits() & (HRSTSENT | HRSTDISC);
+                        if sr != 0 {
+                            self.rb.icr.write(|w| unsafe { w.bits(sr) });
+                            self.rx_len = 0;
+                            return if sr & HRSTSENT != 0 {
+                                Ok(())
+                            } else {
+                                Err(Error::Discarded)
+                            };
+                        }
+                    }
+                }
+
+                /// Receives a message on the SOP channel
+                ///
+                /// Must be polled often enough to read each byte before the
+                /// next one arrives, or from the `RxNotEmpty` interrupt.
+                pub fn receive(&mut self) -> nb::Result<Message, Error> {
+                    let sr = self.rb.sr.read().bits();
+                    if sr & RXHRSTDET != 0 {
+                        self.rb.icr.write(|w| unsafe { w.bits(RXHRSTDET) });
+                        self.rx_len = 0;
+                        return Err(nb::Error::Other(Error::HardReset));
+                    }
+                    if sr & RXOVR != 0 {
+                        self.rb.icr.write(|w| unsafe { w.bits(RXOVR) });
+                        self.rx_len = 0;
+                        return Err(nb::Error::Other(Error::Overrun));
+                    }
+                    if sr & RXNE != 0 {
+                        let byte = self.rb.rxdr.read().bits() as u8;
+                        if self.rx_len < MAX_MESSAGE_LEN {
+                            self.rx[self.rx_len] = byte;
+                        }
+                        self.rx_len += 1;
+                    }
+                    if sr & RXMSGEND == 0 || self.rb.sr.read().bits() & RXNE != 0 {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    self.rb.icr.write(|w| unsafe { w.bits(RXMSGEND) });
+                    let len = core::mem::replace(&mut self.rx_len, 0);
+                    if sr & RXERR != 0 {
+                        Err(nb::Error::Other(Error::Crc))
+                    } else if len > MAX_MESSAGE_LEN {
+                        Err(nb::Error::Other(Error::InvalidLength))
+                    } else {
+                        Message::parse(&self.rx[..len]).map_err(nb::Error::Other)
+                    }
+                }
+
+                /// Starts listening for an interrupt event
+                pub fn listen(&mut self, event: Event) {
+                    self.rb
+                        .imr
+                        .modify(|r, w| unsafe { w.bits(r.bits() | event as u32) });
+                }
+
+                /// Stops listening for an interrupt event
+                pub fn unlisten(&mut self, event: Event) {
+                    self.rb
+                        .imr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !(event as u32)) });
+                }
+
+                /// Returns `true` if `event` is pending
+                pub fn is_pending(&self, event: Event) -> bool {
+                    self.rb.sr.read().bits() & event as u32 != 0
+                }
+
+                /// Clears a pending Type-C or message event
+                pub fn unpend(&mut self, event: Event) {
+                    self.rb.icr.write(|w| unsafe { w.bits(event as u32) });
+                }
+
+                /// Disables the port and returns the peripheral
+                pub fn release(mut self) -> $UCPDX {
+                    self.detach();
+                    self.rb
+                        .cfg1
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !UCPDEN) });
+                    self.rb
+                }
+            }
+
+            impl PdPort for Ucpd<$UCPDX> {
+                fn transmit(&mut self, msg: &Message) -> Result<(), Error> {
+                    Ucpd::<$UCPDX>::transmit(self, msg)
+                }
+
+                fn receive(&mut self) -> nb::Result<Message, Error> {
+                    Ucpd::<$UCPDX>::receive(self)
+                }
+            }
+        )+
+    };
+}
+
+ucpd! {
+    UCPD1: (ucpd1en, ucpd1rst, 9),
+    UCPD2: (ucpd2en, ucpd2rst, 10),
+}
+
+/// Message transport used by [`SinkPolicy`]
+pub trait PdPort {
+    fn transmit(&mut self, msg: &Message) -> Result<(), Error>;
+    fn receive(&mut self) -> nb::Result<Message, Error>;
+}
+
+// Control message types
+const GOOD_CRC: u8 = 1;
+const ACCEPT: u8 = 3;
+const REJECT: u8 = 4;
+const PS_RDY: u8 = 6;
+// Data message types
+const SOURCE_CAPABILITIES: u8 = 1;
+const REQUEST: u8 = 2;
+
+/// Negotiated power contract
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Contract {
+    pub millivolts: u16,
+    pub milliamps: u16,
+}
+
+/// Sink policy events
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SinkEvent {
+    /// Source capabilities received, a request was sent
+    Requested(Contract),
+    /// Source switched to the requested contract
+    Contract(Contract),
+    /// Source rejected the request
+    Rejected,
+    /// Port was hard reset, VBUS returns to 5V
+    HardReset,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum SinkState {
+    WaitCapabilities,
+    WaitAccept,
+    WaitPsRdy,
+    Ready,
+}
+
+/// Minimal USB-PD sink policy engine
+///
+/// Requests the fixed supply PDO matching the target voltage, or 5V if the
+/// source does not offer it. `poll()` must be called often enough to reply
+/// GoodCRC within 195µs of each received message.
+pub struct SinkPolicy {
+    millivolts: u16,
+    max_milliamps: u16,
+    state: SinkState,
+    tx_id: u8,
+    rx_id: Option<u8>,
+    revision: u8,
+    requested: Contract,
+    contract: Option<Contract>,
+}
+
+impl SinkPolicy {
+    /// Sink asking for `millivolts` at up to `max_milliamps`
+    pub fn new(millivolts: u16, max_milliamps: u16) -> Self {
+        SinkPolicy {
+            millivolts,
+            max_milliamps,
+            state: SinkState::WaitCapabilities,
+            tx_id: 0,
+            rx_id: None,
+            revision: 1,
+            requested: Contract {
+                millivolts: 5000,
+                milliamps: 0,
+            },
+            contract: None,
+        }
+    }
+
+    /// Established contract, if any
+    pub fn contract(&self) -> Option<Contract> {
+        self.contract
+    }
+
+    /// Handles received messages
+    pub fn poll<P: PdPort>(&mut self, port: &mut P) -> Option<SinkEvent> {
+        let msg = match port.receive() {
+            Ok(msg) => msg,
+            Err(nb::Error::Other(Error::HardReset)) => {
+                self.reset();
+                return Some(SinkEvent::HardReset);
+            }
+            Err(_) => return None,
+        };
+
+        if msg.is_control() && msg.message_type() == GOOD_CRC {
+            if msg.message_id() == self.tx_id {
+                self.tx_id = (self.tx_id + 1) & 0b111;
+            }
+            return None;
+        }
+
+        let reply = Message::control(GOOD_CRC, msg.message_id(), msg.revision());
+        if port.transmit(&reply).is_err() {
+            return None;
+        }
+        // Retransmission of a message already handled
+        if self.rx_id == Some(msg.message_id()) {
+            return None;
+        }
+        self.rx_id = Some(msg.message_id());
+
+        match (msg.is_control(), msg.message_type(), self.state) {
+            (false, SOURCE_CAPABILITIES, _) => {
+                // Capabilities are resent after a soft reset with IDs restarting
+                self.revision = msg.revision().clamp(1, 2);
+                let (position, contract, max_current) = self.select(msg.data_objects());
+                let rdo = (position as u32) << 28
+                    // No USB suspend
+                    | 1 << 24
+                    | (contract.milliamps as u32 / 10) << 10
+                    | max_current;
+                let request = Message::data(REQUEST, self.tx_id, self.revision, &[rdo]);
+                if port.transmit(&request).is_err() {
+                    return None;
+                }
+                self.requested = contract;
+                self.state = SinkState::WaitAccept;
+                Some(SinkEvent::Requested(contract))
+            }
+            (true, ACCEPT, SinkState::WaitAccept) => {
+                self.state = SinkState::WaitPsRdy;
+                None
+            }
+            (true, REJECT, SinkState::WaitAccept) => {
+                self.state = if self.contract.is_some() {
+                    SinkState::Ready
+                } else {
+                    SinkState::WaitCapabilities
+                };
+                Some(SinkEvent::Rejected)
+            }
+            (true, PS_RDY, SinkState::WaitPsRdy) => {
+                self.state = SinkState::Ready;
+                self.contract = Some(self.requested);
+                Some(SinkEvent::Contract(self.requested))
+            }
+            _ => None,
+        }
+    }
+
+    /// Forgets the contract and message IDs after a hard reset or detach
+    pub fn reset(&mut self) {
+        self.state = SinkState::WaitCapabilities;
+        self.tx_id = 0;
+        self.rx_id = None;
+        self.contract = None;
+    }
+
+    /// Picks a fixed supply PDO, returns its 1-based position, the contract
+    /// and the max current field of the request
+    fn select(&self, pdos: &[u32]) -> (usize, Contract, u32) {
+        let mut choice = (1, 5000, pdos.first().map_or(0, |pdo| pdo & 0x3ff));
+        for (idx, pdo) in pdos.iter().enumerate() {
+            // Fixed supply only
+            if pdo >> 30 != 0 {
+                continue;
+            }
+            let millivolts = (pdo >> 10 & 0x3ff) * 50;
+            if millivolts == self.millivolts as u32 {
+                choice = (idx + 1, millivolts, pdo & 0x3ff);
+            }
+        }
+        let (position, millivolts, max_current) = choice;
+        let milliamps = (max_current * 10).min(self.max_milliamps as u32);
+        let contract = Contract {
+            millivolts: millivolts as u16,
+            milliamps: milliamps as u16,
+        };
+        (position, contract, milliamps / 10)
+    }
+}
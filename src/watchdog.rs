@@ -1,5 +1,6 @@
+use crate::dbg::{self, Freeze};
 use crate::prelude::*;
-use crate::rcc::Rcc;
+use crate::rcc::{Rcc, LSI_FREQ};
 use crate::stm32::{IWDG, WWDG};
 use crate::time::{Hertz, MicroSecond};
 use hal::watchdog;
@@ -21,22 +22,45 @@ impl watchdog::WatchdogEnable for IndependedWatchdog {
     where
         T: Into<MicroSecond>,
     {
-        let mut cycles = period.into().cycles(16_384.hz());
+        self.configure(period.into(), None);
+    }
+}
+
+impl IndependedWatchdog {
+    /// Starts the watchdog, feeding it sooner than `min_interval` after the
+    /// previous feed resets the MCU as well
+    pub fn start_windowed<T, U>(&mut self, period: T, min_interval: U)
+    where
+        T: Into<MicroSecond>,
+        U: Into<MicroSecond>,
+    {
+        self.configure(period.into(), Some(min_interval.into()));
+    }
+
+    /// Stops the watchdog counter while the core is halted by a debugger
+    pub fn freeze_on_debug(&mut self, rcc: &mut Rcc) {
+        dbg::freeze_on_debug(Freeze::Iwdg, rcc);
+    }
+
+    fn configure(&mut self, period: MicroSecond, min_interval: Option<MicroSecond>) {
+        // LSI divided by the minimum prescaler of 4
+        let clk = (LSI_FREQ / 4).hz();
+        let mut cycles = period.cycles(clk);
+        let mut min_cycles = min_interval.map_or(0, |interval| interval.cycles(clk));
         let mut psc = 0;
-        let mut reload = 0;
-        while psc < 6 {
-            reload = cycles;
-            if reload <= 0xfff {
-                break;
-            }
+        while psc < 6 && cycles > 0xfff {
             psc += 1;
             cycles /= 2;
+            min_cycles /= 2;
         }
+        let reload = cycles.min(0xfff);
+        // Feeding is allowed once the counter is below the window
+        let window = reload - min_cycles.min(reload);
 
         // Enable watchdog
         self.iwdg.kr.write(|w| unsafe { w.key().bits(0xcccc) });
 
-        // Enable access to RLR/PR
+        // Enable access to RLR/PR/WINR
         self.iwdg.kr.write(|w| unsafe { w.key().bits(0x5555) });
 
         self.iwdg.pr.write(|w| unsafe { w.pr().bits(psc) });
@@ -46,7 +70,15 @@ impl watchdog::WatchdogEnable for IndependedWatchdog {
 
         while self.iwdg.sr.read().bits() > 0 {}
 
-        self.iwdg.kr.write(|w| unsafe { w.key().bits(0xaaaa) });
+        if min_interval.is_some() {
+            // Writing WINR reloads the counter
+            self.iwdg.winr.write(|w| unsafe { w.bits(window) });
+            while self.iwdg.sr.read().bits() > 0 {}
+        } else {
+            self.iwdg.winr.write(|w| unsafe { w.bits(0xfff) });
+            while self.iwdg.sr.read().bits() > 0 {}
+            self.iwdg.kr.write(|w| unsafe { w.key().bits(0xaaaa) });
+        }
     }
 }
 